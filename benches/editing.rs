@@ -0,0 +1,136 @@
+// 編集の基本的な処理のベンチマーク
+// 大きなJSONと全角文字の多いテキストを一時ディレクトリに作って使う
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use deci::{Document, Editor, Position, Row, SearchDirection, SearchOptions, Terminal};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+
+// 長い行の文字数
+const LONG_LINE_CHARS: usize = 10_000;
+// 大きなファイルの行数
+const LARGE_FILE_LINES: usize = 50_000;
+// 画面を描画するときの端末のサイズ
+const SCREEN_WIDTH: u16 = 120;
+const SCREEN_HEIGHT: u16 = 40;
+
+fn ascii_line() -> String {
+    "abcdefghij".repeat(LONG_LINE_CHARS / 10)
+}
+
+fn cjk_line() -> String {
+    "日本語の文章と漢字かな交じり"
+        .chars()
+        .cycle()
+        .take(LONG_LINE_CHARS)
+        .collect()
+}
+
+// 1行に1つのオブジェクトを並べた大きなJSON(最後の行にだけ検索語句を含む)
+fn huge_json() -> String {
+    let mut json = String::from("[\n");
+    for index in 0..LARGE_FILE_LINES {
+        let _ = writeln!(
+            json,
+            "  {{\"id\": {index}, \"name\": \"item {index}\", \"tags\": [\"a\", \"b\"], \"price\": {index}.5, \"active\": true}},"
+        );
+    }
+    json.push_str("  {\"id\": -1, \"name\": \"needle\"}\n]\n");
+    json
+}
+
+fn cjk_text() -> String {
+    let mut text = String::new();
+    for index in 0..LARGE_FILE_LINES {
+        let _ = writeln!(
+            text,
+            "{index}行目: 吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。"
+        );
+    }
+    text
+}
+
+// 一時ディレクトリにファイルを書いて、読み込み終わるまで待ったドキュメントを返す
+fn open_fixture(name: &str, contents: &str) -> Document {
+    let path = env::temp_dir().join(format!("deci-bench-{name}"));
+    if fs::read_to_string(&path).ok().as_deref() != Some(contents) {
+        fs::write(&path, contents).expect("fixture should be writable");
+    }
+    let mut document = Document::open(&path.to_string_lossy()).expect("fixture should be readable");
+    while document.loading_progress().is_some() {
+        document.receive_rows().expect("fixture should be loaded");
+    }
+    document
+}
+
+fn row(c: &mut Criterion) {
+    for (name, line) in [("ascii", ascii_line()), ("cjk", cjk_line())] {
+        c.bench_function(&format!("row_insert_{name}"), |b| {
+            b.iter_batched(
+                || Row::from(&line[..]),
+                |mut row| row.insert(black_box(LONG_LINE_CHARS / 2), 'x'),
+                BatchSize::SmallInput,
+            );
+        });
+        c.bench_function(&format!("row_delete_{name}"), |b| {
+            b.iter_batched(
+                || Row::from(&line[..]),
+                |mut row| row.delete(black_box(LONG_LINE_CHARS / 2)),
+                BatchSize::SmallInput,
+            );
+        });
+        // 同じ行でカーソル位置の表示幅を何度も求める(索引は最初の1回だけ作る)
+        let row = Row::from(&line[..]);
+        c.bench_function(&format!("row_cursor_width_{name}"), |b| {
+            b.iter(|| {
+                let half = row.full2half_width(0, black_box(LONG_LINE_CHARS / 2));
+                row.half2full_position(0, black_box(half))
+            });
+        });
+    }
+}
+
+fn document(c: &mut Criterion) {
+    let json = huge_json();
+    let cjk = cjk_text();
+    let document = open_fixture("huge.json", &json);
+    c.bench_function("document_find_json", |b| {
+        b.iter(|| {
+            document.find(
+                black_box("needle"),
+                &Position::default(),
+                SearchDirection::Forward,
+                SearchOptions::default(),
+            )
+        });
+    });
+    for (name, contents) in [("huge.json", &json), ("cjk.txt", &cjk)] {
+        c.bench_function(&format!("highlight_all_{name}"), |b| {
+            b.iter_batched(
+                || open_fixture(name, contents),
+                |mut document| {
+                    let len = document.len();
+                    document.highlight(None, SearchOptions::default(), 0, len);
+                    document
+                },
+                BatchSize::LargeInput,
+            );
+        });
+        c.bench_function(&format!("render_frame_{name}"), |b| {
+            b.iter_batched(
+                || {
+                    let terminal = Terminal::headless(SCREEN_WIDTH, SCREEN_HEIGHT, Vec::new());
+                    Editor::headless(terminal, open_fixture(name, contents))
+                },
+                |mut editor| {
+                    editor.run_headless().expect("rendering should not fail");
+                    Terminal::take_output()
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+}
+
+criterion_group!(benches, row, document);
+criterion_main!(benches);
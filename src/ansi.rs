@@ -0,0 +1,108 @@
+// ページャで表示するテキストに含まれるANSIのエスケープシーケンスを解釈する
+// 文字色(SGRの30-37、90-97、38;5;n、38;2;r;g;b)のみ再現し、それ以外の制御は取り除く
+
+// 16色の標準のパレット(xtermの色)
+const BASE_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+// 文字色(デフォルトの色の場合はNone)
+pub type Color = Option<(u8, u8, u8)>;
+
+// テキストを行に分け、エスケープシーケンスを取り除いた行と各文字の色を返す(色は行を跨いで引き継ぐ)
+pub fn parse(text: &str) -> Vec<(String, Vec<Color>)> {
+    let mut color = None;
+    text.lines()
+        .map(|line| {
+            let mut string = String::new();
+            let mut colors = Vec::new();
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                if c != '\u{1b}' {
+                    string.push(c);
+                    colors.push(color);
+                    continue;
+                }
+                // CSIでなければ続く1文字とともに捨てる
+                if chars.next_if_eq(&'[').is_none() {
+                    chars.next();
+                    continue;
+                }
+                let mut params = String::new();
+                while let Some(c) = chars.next_if(|c| !('\u{40}'..='\u{7e}').contains(c)) {
+                    params.push(c);
+                }
+                if chars.next() == Some('m') {
+                    color = select_graphic_rendition(&params, color);
+                }
+            }
+            (string, colors)
+        })
+        .collect()
+}
+
+// SGRの引数を順に解釈して、新しい文字色を返す
+pub fn select_graphic_rendition(params: &str, mut color: Color) -> Color {
+    let numbers: Vec<u8> = params
+        .split(';')
+        .map(|param| param.parse().unwrap_or_default())
+        .collect();
+    let mut numbers = numbers.into_iter();
+    while let Some(number) = numbers.next() {
+        color = match number {
+            0 | 39 => None,
+            30..=37 => Some(palette(number.saturating_sub(30))),
+            90..=97 => Some(palette(number.saturating_sub(82))),
+            38 => match numbers.next() {
+                Some(5) => numbers.next().map(palette),
+                Some(2) => Some((
+                    numbers.next().unwrap_or_default(),
+                    numbers.next().unwrap_or_default(),
+                    numbers.next().unwrap_or_default(),
+                )),
+                _ => color,
+            },
+            _ => color,
+        };
+    }
+    color
+}
+
+// 256色のパレットの番号の色
+#[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+fn palette(index: u8) -> (u8, u8, u8) {
+    if let Some(color) = BASE_COLORS.get(usize::from(index)) {
+        return *color;
+    }
+    // 16から231は6x6x6の色の立方体、232から255は灰色の階調
+    let level = |value: u8| {
+        if value == 0 {
+            0
+        } else {
+            value.saturating_mul(40).saturating_add(55)
+        }
+    };
+    if let Some(cube) = index.checked_sub(16).filter(|cube| *cube < 216) {
+        return (level(cube / 36), level(cube / 6 % 6), level(cube % 6));
+    }
+    let gray = index
+        .saturating_sub(232)
+        .saturating_mul(10)
+        .saturating_add(8);
+    (gray, gray, gray)
+}
@@ -0,0 +1,49 @@
+use crate::marks;
+use crate::Position;
+
+// 記録する変更位置の最大数
+const MAX_CHANGES: usize = 100;
+
+// ドキュメントを変更した位置の履歴(g;とg,で辿る)
+#[derive(Default)]
+pub struct ChangeList {
+    // 古い順に並べた変更位置
+    positions: Vec<Position>,
+    // 次にg;で移動する位置の1つ後ろ(変更があると末尾の次に戻る)
+    index: usize,
+}
+
+impl ChangeList {
+    // 変更した位置を記録する
+    // 直前の変更と同じ行の場合は新しい位置で置き換える
+    pub fn record(&mut self, at: &Position) {
+        if self.positions.last().is_some_and(|last| last.y == at.y) {
+            self.positions.pop();
+        }
+        self.positions.push(at.clone());
+        if self.positions.len() > MAX_CHANGES {
+            self.positions.remove(0);
+        }
+        self.index = self.positions.len();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+    // 1つ古い変更位置に移動する。先頭に達していればNoneを返す
+    pub fn older(&mut self) -> Option<Position> {
+        self.index = self.index.checked_sub(1)?;
+        self.positions.get(self.index).cloned()
+    }
+    // 1つ新しい変更位置に移動する。末尾に達していればNoneを返す
+    pub fn newer(&mut self) -> Option<Position> {
+        let position = self.positions.get(self.index.saturating_add(1))?.clone();
+        self.index = self.index.saturating_add(1);
+        Some(position)
+    }
+    // y行目の後ろにdelta行挿入(負の場合は削除)したときに記録した位置を更新する
+    pub fn edit(&mut self, y: usize, delta: isize) {
+        for position in &mut self.positions {
+            marks::shift(position, y, delta);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+// 行内の文字検索(f、t、F、Tに続けて入力した文字の位置に移動する)
+// ;と,で繰り返せるように、エディタは最後の検索を覚えておく
+#[derive(Clone, Copy)]
+pub struct CharSearch {
+    forward: bool,
+    // tとTの場合は見つけた文字の手前で止まる
+    till: bool,
+    target: char,
+}
+
+impl CharSearch {
+    // f、t、F、Tと続く文字から検索を作る
+    pub fn new(kind: char, target: char) -> Option<Self> {
+        let (forward, till) = match kind {
+            'f' => (true, false),
+            't' => (true, true),
+            'F' => (false, false),
+            'T' => (false, true),
+            _ => return None,
+        };
+        Some(Self {
+            forward,
+            till,
+            target,
+        })
+    }
+    // ,で繰り返すときの逆向きの検索
+    pub fn reversed(self) -> Self {
+        Self {
+            forward: !self.forward,
+            ..self
+        }
+    }
+    // 行の書記素のat番目から検索した移動先の位置を返す
+    // 書記素の最初の文字で比べるので、結合文字が付いた文字も見つかる
+    // repeatの場合、tとTはすぐ隣の文字を飛ばして探す(;で同じ位置に止まり続けないように)
+    pub fn find(self, graphemes: &[&str], at: usize, repeat: bool) -> Option<usize> {
+        let matches = |grapheme: &&str| grapheme.starts_with(self.target);
+        let skip = usize::from(self.till && repeat);
+        if self.forward {
+            let start = at.saturating_add(1).saturating_add(skip);
+            let found = graphemes
+                .get(start..)?
+                .iter()
+                .position(matches)?
+                .saturating_add(start);
+            Some(if self.till {
+                found.saturating_sub(1)
+            } else {
+                found
+            })
+        } else {
+            let end = at.saturating_sub(skip).min(graphemes.len());
+            let found = graphemes.get(..end)?.iter().rposition(matches)?;
+            Some(if self.till {
+                found.saturating_add(1)
+            } else {
+                found
+            })
+        }
+    }
+    // at番目から移動先までのうち、操作(dやc)の対象にする範囲を書記素の位置で返す(endは範囲の次の位置)
+    // 前方への検索は移動先の文字を含み、後方への検索はカーソル位置の文字を含まない
+    pub fn range(self, at: usize, found: usize) -> (usize, usize) {
+        if self.forward {
+            (at, found.saturating_add(1))
+        } else {
+            (found, at)
+        }
+    }
+}
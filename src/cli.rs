@@ -0,0 +1,108 @@
+// コマンドラインの引数
+// --の後の引数はオプションとみなさずにファイル名とする
+use crate::location::{self, Location};
+use std::path::Path;
+
+/// 解析したコマンドラインの引数
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Default)]
+pub struct Cli {
+    /// 開くファイル(ディレクトリ、標準入力を表す-、末尾に:line:colを付けたものも含む)
+    pub file: Option<String>,
+    /// +Nで指定した行番号(1から始まる)
+    pub line: Option<usize>,
+    /// -p: ファイルまたは標準入力を読み取り専用で表示する
+    pub pager: bool,
+    /// -R: ファイルを編集できないように開く
+    pub readonly: bool,
+    /// 色を付けずに表示する
+    pub no_color: bool,
+    /// -x: 状態のファイルを暗号化するパスフレーズを起動時に尋ねる
+    pub encrypt: bool,
+    /// 異常終了したときに書き出したファイル(暗号化されていれば復号して、ファイル名の無いバッファに読み込む)
+    pub recover: Option<String>,
+    /// 起動時に実行する:のコマンドを書いたファイル
+    pub config: Option<String>,
+    /// 画面を使わずに実行する:のコマンド
+    pub batch: Option<String>,
+    /// -h、--help: 使い方を表示して終了する
+    pub help: bool,
+    /// -V、--version: バージョンを表示して終了する
+    pub version: bool,
+}
+
+impl Cli {
+    /// プログラム名を除いた引数を解析する
+    ///
+    /// # Errors
+    ///
+    /// 知らないオプションや2つ目のファイル名があればそのメッセージを返す
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut cli = Self::default();
+        let mut args = args.into_iter();
+        let mut only_files = false;
+        while let Some(arg) = args.next() {
+            if only_files || arg == "-" || !(arg.starts_with('-') || arg.starts_with('+')) {
+                if cli.file.is_some() {
+                    return Err(format!("unexpected argument: {arg}"));
+                }
+                cli.file = Some(arg);
+                continue;
+            }
+            // --config=pathのように値を=で続けて書いてもよい
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (&arg[..], None),
+            };
+            match name {
+                "--" => only_files = true,
+                "-p" | "--pager" => cli.pager = true,
+                "-R" | "--readonly" => cli.readonly = true,
+                "-x" | "--encrypt" => cli.encrypt = true,
+                "--no-color" => cli.no_color = true,
+                "-h" | "--help" => cli.help = true,
+                "-V" | "--version" => cli.version = true,
+                "--config" => cli.config = Some(option_value(name, value, &mut args)?),
+                "--recover" => cli.recover = Some(option_value(name, value, &mut args)?),
+                "--batch" => cli.batch = Some(option_value(name, value, &mut args)?),
+                _ => {
+                    let Some(line) = arg.strip_prefix('+') else {
+                        return Err(format!("unknown option: {arg}"));
+                    };
+                    let line = line
+                        .parse()
+                        .map_err(|_| format!("invalid line number: {arg}"))?;
+                    cli.line = Some(line);
+                }
+            }
+        }
+        Ok(cli)
+    }
+    // 開くファイルと位置
+    // 存在しないファイル名の末尾の:line:colは位置とみなし、+Nで指定した行を優先する
+    pub(crate) fn location(&self) -> Option<Location> {
+        let file_name = self.file.as_ref()?;
+        let mut location = Some(file_name)
+            .filter(|file_name| !Path::new(file_name).exists())
+            .and_then(|file_name| location::parse(file_name))
+            .filter(|location| location.line.is_some())
+            .unwrap_or_else(|| Location {
+                path: file_name.clone(),
+                line: None,
+                column: None,
+            });
+        location.line = self.line.or(location.line);
+        Some(location)
+    }
+}
+
+// 値を取るオプションの値(=で続けて書いていなければ次の引数)
+fn option_value(
+    name: &str,
+    value: Option<String>,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String, String> {
+    value
+        .or_else(|| args.next())
+        .ok_or_else(|| format!("{name} requires a value"))
+}
@@ -0,0 +1,39 @@
+// 行コメントの記号を付けたり外したりする
+// 空白だけの行を除く全ての行がコメントなら外し、そうでなければ最も浅いインデントの位置に付ける
+pub fn toggle(lines: &[String], prefix: &str) -> Vec<String> {
+    let body = |line: &str| line.trim_start_matches([' ', '\t']).len();
+    let is_blank = |line: &str| line.trim().is_empty();
+    let commented = lines
+        .iter()
+        .filter(|line| !is_blank(line))
+        .all(|line| line.trim_start_matches([' ', '\t']).starts_with(prefix));
+    if commented {
+        return lines.iter().map(|line| uncomment(line, prefix)).collect();
+    }
+    let column = lines
+        .iter()
+        .filter(|line| !is_blank(line))
+        .map(|line| line.len().saturating_sub(body(line)))
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| {
+            if is_blank(line) {
+                return line.clone();
+            }
+            let (indent, rest) = line.split_at(column.min(line.len()));
+            format!("{indent}{prefix} {rest}")
+        })
+        .collect()
+}
+
+// 行頭の空白に続くコメントの記号と、その後ろの空白1つを取り除く
+fn uncomment(line: &str, prefix: &str) -> String {
+    let rest = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len().saturating_sub(rest.len())];
+    match rest.strip_prefix(prefix) {
+        Some(text) => format!("{indent}{}", text.strip_prefix(' ').unwrap_or(text)),
+        None => line.to_string(),
+    }
+}
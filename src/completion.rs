@@ -0,0 +1,59 @@
+// 挿入モードで入力中の単語を補完する候補の一覧
+use crate::Document;
+
+// 一度に集める候補の最大数
+const MAX_CANDIDATES: usize = 50;
+
+#[derive(Clone)]
+pub struct Completion {
+    // 補完する入力中の単語(カーソルの直前の部分)
+    pub prefix: String,
+    pub candidates: Vec<String>,
+    // 選択中の候補(自動で表示した直後は何も選択しない)
+    pub selected: Option<usize>,
+}
+
+impl Completion {
+    // 選択を次(前)の候補に移す(端では反対の端に戻る)
+    pub fn select(&mut self, forward: bool) {
+        let len = self.candidates.len();
+        self.selected = Some(match (self.selected, forward) {
+            (None, true) => 0,
+            (None, false) => len.saturating_sub(1),
+            (Some(index), true) => index.saturating_add(1).checked_rem(len).unwrap_or(0),
+            (Some(index), false) => index.checked_sub(1).unwrap_or(len.saturating_sub(1)),
+        });
+    }
+    pub fn selected_candidate(&self) -> Option<&str> {
+        self.candidates.get(self.selected?).map(String::as_str)
+    }
+}
+
+// 単語を構成する文字か
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// ドキュメントに現れる単語のうち、prefixで始まりprefixより長いものを現れた順に重複無く集める
+pub fn candidates<'a>(prefix: &str, documents: impl Iterator<Item = &'a Document>) -> Vec<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    for document in documents {
+        for y in 0..document.len() {
+            let Some(row) = document.row(y) else {
+                continue;
+            };
+            for word in row.as_str().split(|c| !is_word_char(c)) {
+                if word.len() > prefix.len()
+                    && word.starts_with(prefix)
+                    && !candidates.iter().any(|candidate| candidate == word)
+                {
+                    candidates.push(word.to_string());
+                    if candidates.len() >= MAX_CANDIDATES {
+                        return candidates;
+                    }
+                }
+            }
+        }
+    }
+    candidates
+}
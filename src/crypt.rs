@@ -0,0 +1,145 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::Sha256;
+use std::io::{Error, ErrorKind};
+
+// 暗号化したファイルの先頭(続けてソルト、ノンス、暗号文を置く)
+const HEADER: &[u8] = b"deci-encrypted 1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+// パスフレーズから鍵を作るときのPBKDF2の繰り返し回数
+#[cfg(not(test))]
+const ITERATIONS: u32 = 600_000;
+// 最適化しないテストのビルドでは鍵を作るのに何秒もかかるので減らす
+#[cfg(test)]
+const ITERATIONS: u32 = 1_000;
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(HEADER)
+}
+
+// パスフレーズと、それからソルトごとに作った鍵
+// PBKDF2は遅いので、読み込みと保存のたびに作り直さず、一度作った鍵をセッションの間使い回す
+pub struct Keyring {
+    passphrase: String,
+    keys: Vec<([u8; SALT_LEN], Key)>,
+}
+
+impl Keyring {
+    pub fn new(passphrase: String) -> Self {
+        Self {
+            passphrase,
+            keys: Vec::new(),
+        }
+    }
+    // 最初に作った鍵で暗号化する(ソルトは鍵と一緒に使い回し、ノンスは毎回作り直す)
+    pub fn encrypt(&mut self, plain: &[u8]) -> Result<Vec<u8>, Error> {
+        let salt = if let Some((salt, _)) = self.keys.first() {
+            *salt
+        } else {
+            let mut salt = [0_u8; SALT_LEN];
+            random(&mut salt)?;
+            salt
+        };
+        let mut nonce = [0_u8; NONCE_LEN];
+        random(&mut nonce)?;
+        let cipher = ChaCha20Poly1305::new(&self.key(salt));
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .map_err(|_| Error::other("Could not encrypt"))?;
+        let mut data = HEADER.to_vec();
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+        data.extend(ciphertext);
+        Ok(data)
+    }
+    // 暗号化したデータを復号する(パスフレーズが違う場合や改ざんされている場合はErrを返す)
+    pub fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "Wrong passphrase or corrupted file");
+        let rest = data.strip_prefix(HEADER).ok_or_else(invalid)?;
+        let (salt, rest) = rest.split_at_checked(SALT_LEN).ok_or_else(invalid)?;
+        let (nonce, ciphertext) = rest.split_at_checked(NONCE_LEN).ok_or_else(invalid)?;
+        let salt = salt.try_into().map_err(|_| invalid())?;
+        let cipher = ChaCha20Poly1305::new(&self.key(salt));
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid())
+    }
+    // ソルトに対する鍵(まだ作っていなければ作って覚えておく)
+    fn key(&mut self, salt: [u8; SALT_LEN]) -> Key {
+        if let Some((_, key)) = self.keys.iter().find(|(known, _)| *known == salt) {
+            return *key;
+        }
+        let mut key = Key::default();
+        pbkdf2::pbkdf2_hmac::<Sha256>(self.passphrase.as_bytes(), &salt, ITERATIONS, &mut key);
+        self.keys.push((salt, key));
+        key
+    }
+}
+
+fn random(buffer: &mut [u8]) -> Result<(), Error> {
+    getrandom::getrandom(buffer).map_err(|error| Error::other(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decrypts_what_it_encrypted() {
+        let mut keyring = Keyring::new("secret".to_string());
+        let data = keyring.encrypt(b"recent\t1\t2\t/tmp/a").unwrap();
+        assert!(is_encrypted(&data));
+        assert!(!data.windows(6).any(|window| window == b"recent"));
+        assert_eq!(keyring.decrypt(&data).unwrap(), b"recent\t1\t2\t/tmp/a");
+        // 別のセッション(鍵を覚えていない状態)でも同じパスフレーズなら復号できる
+        let mut other = Keyring::new("secret".to_string());
+        assert_eq!(other.decrypt(&data).unwrap(), b"recent\t1\t2\t/tmp/a");
+    }
+
+    #[test]
+    fn derives_the_key_once_per_salt() {
+        let mut keyring = Keyring::new("secret".to_string());
+        let first = keyring.encrypt(b"one").unwrap();
+        let second = keyring.encrypt(b"two").unwrap();
+        assert_eq!(keyring.keys.len(), 1);
+        // ソルトは使い回し、ノンスは毎回変わる
+        let salt_end = HEADER.len().saturating_add(SALT_LEN);
+        let nonce_end = salt_end.saturating_add(NONCE_LEN);
+        assert_eq!(first.get(..salt_end), second.get(..salt_end));
+        assert_ne!(
+            first.get(salt_end..nonce_end),
+            second.get(salt_end..nonce_end)
+        );
+        assert_eq!(keyring.decrypt(&second).unwrap(), b"two");
+        assert_eq!(keyring.keys.len(), 1);
+    }
+
+    #[test]
+    fn rejects_a_wrong_passphrase() {
+        let data = Keyring::new("secret".to_string()).encrypt(b"text").unwrap();
+        let error = Keyring::new("guess".to_string())
+            .decrypt(&data)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_tampered_or_truncated_data() {
+        let mut keyring = Keyring::new("secret".to_string());
+        let data = keyring.encrypt(b"text").unwrap();
+        let last = data.len().saturating_sub(1);
+        // ソルト、ノンス、暗号文のどこを書き換えても復号できない
+        for index in [HEADER.len(), HEADER.len().saturating_add(SALT_LEN), last] {
+            let mut tampered = data.clone();
+            if let Some(byte) = tampered.get_mut(index) {
+                *byte ^= 1;
+            }
+            assert!(keyring.decrypt(&tampered).is_err());
+        }
+        for end in [last, HEADER.len().saturating_add(4)] {
+            assert!(keyring.decrypt(data.get(..end).unwrap()).is_err());
+        }
+        assert!(keyring.decrypt(b"text").is_err());
+    }
+}
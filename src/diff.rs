@@ -0,0 +1,178 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+// 共通部分を探す表の大きさの上限(超える場合は全ての行を置き換えたとみなす)
+const MAX_TABLE_SIZE: usize = 4_000_000;
+
+// 行単位の差分の1行(変更前と変更後の行の位置)
+#[derive(Clone, Copy, PartialEq)]
+pub enum Line {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// 変更前の行と変更後の行の差分を求める
+pub fn diff(old: &[String], new: &[String]) -> Vec<Line> {
+    // 先頭と末尾の共通部分は表を作らずに一致とする
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let old_rest = old.get(prefix..).unwrap_or_default();
+    let new_rest = new.get(prefix..).unwrap_or_default();
+    let suffix = old_rest
+        .iter()
+        .rev()
+        .zip(new_rest.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = old_rest
+        .get(..old_rest.len().saturating_sub(suffix))
+        .unwrap_or_default();
+    let new_middle = new_rest
+        .get(..new_rest.len().saturating_sub(suffix))
+        .unwrap_or_default();
+    let mut lines: Vec<Line> = (0..prefix).map(|i| Line::Equal(i, i)).collect();
+    lines.extend(
+        diff_middle(old_middle, new_middle)
+            .into_iter()
+            .map(|line| match line {
+                Line::Equal(i, j) => {
+                    Line::Equal(i.saturating_add(prefix), j.saturating_add(prefix))
+                }
+                Line::Delete(i) => Line::Delete(i.saturating_add(prefix)),
+                Line::Insert(j) => Line::Insert(j.saturating_add(prefix)),
+            }),
+    );
+    let old_end = prefix.saturating_add(old_middle.len());
+    let new_end = prefix.saturating_add(new_middle.len());
+    lines.extend(
+        (0..suffix).map(|k| Line::Equal(old_end.saturating_add(k), new_end.saturating_add(k))),
+    );
+    lines
+}
+
+// 最長共通部分列の表から差分を求める
+#[allow(clippy::arithmetic_side_effects, clippy::indexing_slicing)]
+fn diff_middle(old: &[String], new: &[String]) -> Vec<Line> {
+    let (n, m) = (old.len(), new.len());
+    if n.saturating_mul(m) > MAX_TABLE_SIZE {
+        return (0..n)
+            .map(Line::Delete)
+            .chain((0..m).map(Line::Insert))
+            .collect();
+    }
+    // table[i * (m + 1) + j]はold[i..]とnew[j..]の最長共通部分列の長さ
+    let width = m + 1;
+    let mut table = vec![0_u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i * width + j] = if old[i] == new[j] {
+                table[(i + 1) * width + j + 1] + 1
+            } else {
+                table[(i + 1) * width + j].max(table[i * width + j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            lines.push(Line::Equal(i, j));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || table[(i + 1) * width + j] >= table[i * width + j + 1]) {
+            lines.push(Line::Delete(i));
+            i += 1;
+        } else {
+            lines.push(Line::Insert(j));
+            j += 1;
+        }
+    }
+    lines
+}
+
+// 変更前と変更後の行を書記素単位で比べ、変わった書記素の(変更前, 変更後)の位置を返す
+pub fn changed_graphemes(old: &str, new: &str) -> (Vec<usize>, Vec<usize>) {
+    let split = |line: &str| -> Vec<String> { line.graphemes(true).map(str::to_string).collect() };
+    let mut deleted = Vec::new();
+    let mut inserted = Vec::new();
+    for line in diff(&split(old), &split(new)) {
+        match line {
+            Line::Equal(..) => (),
+            Line::Delete(i) => deleted.push(i),
+            Line::Insert(j) => inserted.push(j),
+        }
+    }
+    (deleted, inserted)
+}
+
+// 差分をunified形式の行にする(変更の前後context行を含める)
+pub fn unified(old: &[String], new: &[String], context: usize) -> Vec<String> {
+    let lines = diff(old, new);
+    let mut output = Vec::new();
+    let mut index = 0;
+    while let Some(offset) = lines
+        .iter()
+        .skip(index)
+        .position(|line| !matches!(line, Line::Equal(..)))
+    {
+        // 変更の前後context行を含む範囲を、次の変更の範囲と重なる間は広げる
+        let mut last = index.saturating_add(offset);
+        let start = last.saturating_sub(context);
+        while let Some(next) = lines
+            .iter()
+            .skip(last.saturating_add(1))
+            .take(context.saturating_mul(2).saturating_add(1))
+            .position(|line| !matches!(line, Line::Equal(..)))
+        {
+            last = last.saturating_add(next).saturating_add(1);
+        }
+        let end = last
+            .saturating_add(context)
+            .saturating_add(1)
+            .min(lines.len());
+        let hunk = lines.get(start..end).unwrap_or_default();
+        output.push(hunk_header(&lines, start, hunk));
+        for line in hunk {
+            output.push(match *line {
+                Line::Equal(i, _) => format!(" {}", old.get(i).map_or("", String::as_str)),
+                Line::Delete(i) => format!("-{}", old.get(i).map_or("", String::as_str)),
+                Line::Insert(j) => format!("+{}", new.get(j).map_or("", String::as_str)),
+            });
+        }
+        index = end;
+    }
+    output
+}
+
+// @@ -変更前の開始行,行数 +変更後の開始行,行数 @@
+fn hunk_header(lines: &[Line], start: usize, hunk: &[Line]) -> String {
+    let before = lines.get(..start).unwrap_or_default();
+    let old_start = before
+        .iter()
+        .filter(|line| !matches!(line, Line::Insert(_)))
+        .count();
+    let new_start = before
+        .iter()
+        .filter(|line| !matches!(line, Line::Delete(_)))
+        .count();
+    let old_count = hunk
+        .iter()
+        .filter(|line| !matches!(line, Line::Insert(_)))
+        .count();
+    let new_count = hunk
+        .iter()
+        .filter(|line| !matches!(line, Line::Delete(_)))
+        .count();
+    // 行数が0の場合は直前の行を開始行とする
+    let line_number = |start: usize, count: usize| {
+        if count == 0 {
+            start
+        } else {
+            start.saturating_add(1)
+        }
+    };
+    format!(
+        "@@ -{},{old_count} +{},{new_count} @@",
+        line_number(old_start, old_count),
+        line_number(new_start, new_count)
+    )
+}
@@ -1,53 +1,540 @@
+use crate::ansi;
+use crate::changelist::ChangeList;
+use crate::comment;
+use crate::diff;
+use crate::filetype::EditingOptions;
+use crate::fold::Folds;
+use crate::hexdump;
+use crate::highlighting;
+use crate::indent::{self, Indent};
+use crate::list;
+use crate::loader::Loader;
+use crate::marks::Marks;
+use crate::spell::Dictionary;
+use crate::syntax::Definitions;
+use crate::table;
+use crate::undo::{Change, History};
+use crate::width;
 use crate::FileType;
 use crate::Position;
 use crate::Row;
 use crate::SearchDirection;
+use crate::SearchOptions;
 use std::fs;
+use std::io::BufWriter;
 use std::io::Error;
+use std::io::ErrorKind;
+use std::io::IntoInnerError;
 use std::io::Write;
+use std::iter;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
 
+// 対応する括弧の組
+const BRACKETS: [(&str, &str); 3] = [("(", ")"), ("[", "]"), ("{", "}")];
+
+// これより大きいファイルは別スレッドで読み込む
+const ASYNC_LOAD_SIZE: u64 = 1024 * 1024;
+// 出力バッファに保持する最大行数
+const MAX_OUTPUT_LINES: usize = 10000;
+
+/// バッファの種類
+#[derive(Default, PartialEq, Clone)]
+pub enum BufferKind {
+    /// ファイルを編集する通常のバッファ
+    #[default]
+    File,
+    /// ディレクトリ内のファイル一覧
+    Explorer(PathBuf),
+    /// path:line:col形式の行からなる検索結果などの一覧(タイトルを持つ)
+    QuickFix(String),
+    /// コマンドの出力などを末尾に追記していく読み取り専用のバッファ(タイトルを持つ)
+    Output(String),
+    /// キーボードマクロを文字列で編集するバッファ(保存するとレジスタに書き戻す)
+    Macro(char),
+    /// 編集できるようにした検索結果の一覧(タイトルと編集前の行を持ち、保存すると元のファイルに書き戻す)
+    GrepEdit(String, Vec<String>),
+    /// q:で開くコマンドの履歴を編集するバッファ(Enterでカーソル行のコマンドを実行する)
+    CommandLine,
+}
+
+/// g Ctrl-Gで表示するドキュメントまたは選択範囲の統計(改行も1文字、1バイトとして数える)
+#[derive(Default)]
+pub struct Statistics {
+    pub lines: usize,
+    pub words: usize,
+    pub graphemes: usize,
+    pub bytes: usize,
+}
+
+impl Statistics {
+    /// 選択範囲などの行ごとの文字列を数える
+    /// 行の間の改行を数え、`trailing_newline`がtrueの場合は最後の行の改行も数える
+    pub fn of_lines(lines: &[String], trailing_newline: bool) -> Self {
+        let newlines = lines.len().saturating_sub(usize::from(!trailing_newline));
+        let statistics = lines.iter().map(|line| Row::from(&line[..])).fold(
+            Self {
+                lines: lines.len(),
+                ..Self::default()
+            },
+            |statistics, row| Self {
+                words: statistics.words.saturating_add(row.word_count()),
+                graphemes: statistics.graphemes.saturating_add(row.len()),
+                bytes: statistics.bytes.saturating_add(row.as_bytes().len()),
+                ..statistics
+            },
+        );
+        Self {
+            graphemes: statistics.graphemes.saturating_add(newlines),
+            bytes: statistics.bytes.saturating_add(newlines),
+            ..statistics
+        }
+    }
+}
+
+/// 行の一覧と、編集の履歴や折りたたみなどのバッファごとの状態を持つドキュメント
 #[derive(Default)]
 pub struct Document {
     rows: Vec<Row>,
+    /// 保存先のファイル名(無名のバッファではNone)
     pub file_name: Option<String>,
     // ローカルのファイルに対し更新があればtrue、無ければfalse
     dirty: bool,
     file_type: FileType,
+    // ファイルタイプの判定に使うユーザ定義のファイルタイプ(エディタが読み込んだものを共有する)
+    syntax: Rc<Definitions>,
+    kind: BufferKind,
+    folds: Folds,
+    changes: ChangeList,
+    marks: Marks,
+    // 別スレッドで読み込み中の場合はその読み込み
+    loader: Option<Loader>,
+    history: History,
+    // この行より前の行はハイライトが更新済み
+    unhighlighted_from: usize,
+    // 最後に読み込みまたは保存したときのファイルの更新日時
+    modified_time: Option<SystemTime>,
+    indent: Indent,
+    // 行ごとの一致位置のキャッシュを求めた検索語句と、語句が変わるたびに上げる世代
+    match_query: Option<(String, SearchOptions)>,
+    match_generation: u64,
+    // :setで指定したバッファごとの設定の(名前, 設定)(表示の状態として保存する)
+    local_options: Vec<(&'static str, String)>,
+    // 内容を変更するたびに上げる回数(言語サーバに変更を送るかの判断に使う)
+    edit_count: u64,
+    // 書き込みが許可されていないファイルを開いた場合はtrue(編集も保存もできない)
+    read_only: bool,
 }
 
 impl Document {
-    // 指定したファイル内容の取得に失敗したらエラーを返す
+    /// ファイルを開き、大きいファイルは別スレッドで読み込み始める
+    ///
+    /// # Errors
+    ///
+    /// 指定したファイル内容の取得に失敗したらエラーを返す
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
+        let metadata = fs::metadata(filename)?;
+        let modified_time = metadata.modified().ok();
+        let read_only = metadata.permissions().readonly();
+        // 大きいファイルは読み込み終わる前から表示できるように別スレッドで読み込む
+        // 最初の行によるファイルタイプの判定とインデントの推測は読み込み終わってから行う
+        let size = metadata.len();
+        if size > ASYNC_LOAD_SIZE {
+            return Ok(Self {
+                file_name: Some(filename.to_string()),
+                file_type: FileType::from(filename),
+                loader: Some(Loader::start(fs::File::open(filename)?, size)),
+                modified_time,
+                read_only,
+                ..Self::default()
+            });
+        }
         // 指定したファイルの中身を読み込む
         let contents = fs::read_to_string(filename)?;
-        let file_type = FileType::from(filename);
         let mut rows = Vec::new();
         // 一行ずつ保存する
         for value in contents.lines() {
             rows.push(Row::from(value));
         }
+        let indent = indent::guess(contents.lines()).unwrap_or_default();
+        // 最初の行のシバンやモードラインも使って判定する
+        let first_line = contents.lines().next().unwrap_or_default();
+        let file_type = FileType::detect(filename, first_line, &Definitions::default());
         Ok(Self {
             rows,
             file_name: Some(filename.to_string()),
             dirty: false,
             file_type,
+            syntax: Rc::default(),
+            kind: BufferKind::File,
+            folds: Folds::default(),
+            changes: ChangeList::default(),
+            marks: Marks::default(),
+            loader: None,
+            history: History::default(),
+            unhighlighted_from: 0,
+            modified_time,
+            indent,
+            match_query: None,
+            match_generation: 0,
+            local_options: Vec::new(),
+            edit_count: 0,
+            read_only,
         })
     }
-    // ファイルタイプ名を返す
+    /// まだ存在しないファイルを編集する空のドキュメントを作る(保存するとそのファイルを作る)
+    pub fn new_file(filename: &str) -> Self {
+        Self {
+            file_name: Some(filename.to_string()),
+            file_type: FileType::from(filename),
+            ..Self::default()
+        }
+    }
+    /// UTF-8として読めないファイルの内容を16進数で表示する読み取り専用のドキュメントを作る
+    pub fn hex_view(filename: &str, bytes: &[u8]) -> Self {
+        Self {
+            rows: hexdump::lines(bytes)
+                .iter()
+                .map(|line| Row::from(&line[..]))
+                .collect(),
+            kind: BufferKind::Output(format!("hex {filename}")),
+            ..Self::default()
+        }
+    }
+    /// 種類を指定して、与えられた行からファイルと結び付かないドキュメントを作る
+    pub fn from_lines(lines: &[String], kind: BufferKind) -> Self {
+        Self {
+            rows: lines.iter().map(|line| Row::from(&line[..])).collect(),
+            kind,
+            ..Self::default()
+        }
+    }
+    /// ANSIのエスケープシーケンスで色を付けたテキストから、色を再現したドキュメントを作る
+    pub fn from_ansi(text: &str, kind: BufferKind) -> Self {
+        let mut document = Self {
+            kind,
+            ..Self::default()
+        };
+        document.set_colored_lines(ansi::parse(text));
+        document
+    }
+    /// 全ての行を色付きの行で置き換える(:terminalの画面を表示する出力バッファに使う)
+    pub fn set_colored_lines(&mut self, lines: Vec<(String, Vec<ansi::Color>)>) {
+        self.rows = lines
+            .into_iter()
+            .map(|(line, colors)| Row::with_ansi_colors(&line, colors))
+            .collect();
+        self.unhighlighted_from = 0;
+    }
+    /// 編集も保存もできないドキュメントであればtrue
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    /// 書き込みが許可されていても編集と保存をできないようにする(-R)
+    pub fn set_read_only(&mut self) {
+        self.read_only = true;
+    }
+    /// 内容を変更するたびに上がる回数
+    pub fn edit_count(&self) -> u64 {
+        self.edit_count
+    }
+    /// バッファの種類
+    pub fn kind(&self) -> &BufferKind {
+        &self.kind
+    }
+    /// 別スレッドで読み込んだ行を末尾に追加し、表示を更新する必要があればtrueを返す
+    ///
+    /// # Errors
+    ///
+    /// 読み込みに失敗した場合はエラーを返す(UTF-8として読めなかった場合は16進数で表示するドキュメントに置き換えてからエラーを返す)
+    pub fn receive_rows(&mut self) -> Result<bool, Error> {
+        let Some(loader) = &mut self.loader else {
+            return Ok(false);
+        };
+        let len = self.rows.len();
+        match loader.receive(&mut self.rows) {
+            Ok(false) => Ok(self.rows.len() != len),
+            Ok(true) => self.finish_loading(Ok(())).map(|()| true),
+            Err(error) => self.finish_loading(Err(error)).map(|()| true),
+        }
+    }
+    /// 最後まで読み込むのを待つ
+    ///
+    /// # Errors
+    ///
+    /// 読み込みに失敗した場合はエラーを返す
+    pub fn wait_rows(&mut self) -> Result<(), Error> {
+        let Some(loader) = &mut self.loader else {
+            return Ok(());
+        };
+        let result = loader.wait(&mut self.rows);
+        self.finish_loading(result)
+    }
+    // 別スレッドでの読み込みが終わったら、同期して読み込んだ場合と同じく最初の行も使ってファイルタイプを判定し、インデントを推測する
+    // UTF-8として読めなかった場合は、途中までの内容を編集して保存できないように16進数で表示する読み取り専用のドキュメントに置き換える
+    // それ以外の理由で失敗した場合は、途中までの内容で上書きしないようにファイルとの結び付きを外す
+    fn finish_loading(&mut self, result: Result<(), Error>) -> Result<(), Error> {
+        self.loader = None;
+        let Some(file_name) = self.file_name.clone() else {
+            return result;
+        };
+        match result {
+            Ok(()) => {
+                self.detect_file_type();
+                self.guess_indent();
+                Ok(())
+            }
+            Err(error) => {
+                match fs::read(&file_name) {
+                    Ok(bytes) if error.kind() == ErrorKind::InvalidData => {
+                        *self = Self::hex_view(&file_name, &bytes);
+                    }
+                    _ => self.file_name = None,
+                }
+                Err(error)
+            }
+        }
+    }
+    /// インデントの設定
+    pub fn indent(&self) -> &Indent {
+        &self.indent
+    }
+    /// インデントの設定を明示的に変更する(以降はファイルの内容から推測しない)
+    pub fn set_indent(&mut self, use_tabs: Option<bool>, width: Option<usize>) {
+        self.indent.use_tabs = use_tabs.unwrap_or(self.indent.use_tabs);
+        self.indent.width = width.unwrap_or(self.indent.width);
+        self.indent.source = indent::Source::Explicit;
+    }
+    // 明示的に指定されていなければ、ファイルの内容からインデントの設定を推測する
+    fn guess_indent(&mut self) {
+        if self.indent.source == indent::Source::Explicit {
+            return;
+        }
+        if let Some(indent) = indent::guess(self.rows.iter().map(Row::as_str)) {
+            self.indent = indent;
+        }
+    }
+    /// 読み込み中の場合は読み込んだ割合(%)を返す
+    pub fn loading_progress(&self) -> Option<u64> {
+        self.loader.as_ref().map(Loader::progress)
+    }
+    /// バッファの種類を変更する
+    pub fn set_kind(&mut self, kind: BufferKind) {
+        self.kind = kind;
+    }
+    /// 全ての行の文字列を返す
+    pub fn all_lines(&self) -> Vec<String> {
+        self.lines(0, self.rows.len())
+    }
+    /// 出力バッファの末尾に行を追加する
+    /// 最大行数を超えた場合は超えた分の行を先頭からまとめて捨て、捨てた行数を返す
+    pub fn append_output(&mut self, lines: &[String]) -> usize {
+        self.rows
+            .extend(lines.iter().map(|line| Row::from(line.as_str())));
+        let excess = self.rows.len().saturating_sub(MAX_OUTPUT_LINES);
+        if excess > 0 {
+            self.rows.drain(..excess);
+            self.shift_lines(0, 0_isize.saturating_sub_unsigned(excess));
+            self.unhighlighted_from = self.unhighlighted_from.saturating_sub(excess);
+        }
+        excess
+    }
+    /// ファイルとマクロを編集するバッファ、編集できるようにした検索結果のみ編集できる
+    /// 書き込みが許可されていないファイルは編集できない
+    /// 読み込み中のファイルは読み込み終わるまで編集できない
+    pub fn is_editable(&self) -> bool {
+        self.loader.is_none()
+            && !self.read_only
+            && matches!(
+                self.kind,
+                BufferKind::File
+                    | BufferKind::Macro(_)
+                    | BufferKind::GrepEdit(..)
+                    | BufferKind::CommandLine
+            )
+    }
+    /// 全ての行を改行で繋いだ文字列を返す
+    pub fn contents(&self) -> String {
+        self.all_lines().join("\n")
+    }
+    /// ファイル以外に書き戻したときに更新フラグを下ろす
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+    }
+    /// ファイルタイプ名を返す
     pub fn file_type(&self) -> String {
         self.file_type.name()
     }
-    // 指定された行が存在すればその行をSomeで包んで、なければNoneを返す
+    /// ファイルタイプごとの編集の設定
+    pub fn editing_options(&self) -> &EditingOptions {
+        self.file_type.editing_options()
+    }
+    /// ファイル名を変更し、ファイル名と最初の行からファイルタイプを判定し直す
+    pub fn set_file_name(&mut self, file_name: String) {
+        let first_line = self.rows.first().map_or("", Row::as_str);
+        self.set_file_type(FileType::detect(&file_name, first_line, &self.syntax));
+        self.file_name = Some(file_name);
+    }
+    /// ユーザが定義したファイルタイプも使って、ファイルタイプを判定し直す
+    pub fn set_syntax(&mut self, syntax: Rc<Definitions>) {
+        self.syntax = syntax;
+        self.detect_file_type();
+    }
+    // ファイル名と最初の行からファイルタイプを判定し、変わった場合のみハイライトし直す
+    fn detect_file_type(&mut self) {
+        let Some(file_name) = &self.file_name else {
+            return;
+        };
+        let first_line = self.rows.first().map(Row::as_str).unwrap_or_default();
+        let file_type = FileType::detect(file_name, first_line, &self.syntax);
+        if file_type.name() != self.file_type.name() {
+            self.set_file_type(file_type);
+        }
+    }
+    /// ファイルタイプを変更し、全ての行をハイライトし直す
+    pub fn set_file_type(&mut self, file_type: FileType) {
+        self.file_type = file_type;
+        for row in &mut self.rows {
+            row.is_highlighted = false;
+        }
+        self.unhighlight_rows(0);
+    }
+    /// 指定された行が存在すればその行をSomeで包んで、なければNoneを返す
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
     }
+    /// 行が1つも無ければtrue
     pub fn is_empty(&self) -> bool {
         self.rows.is_empty()
     }
-    // ドキュメントの総行数を返す
+    /// ドキュメントの総行数を返す
     pub fn len(&self) -> usize {
         self.rows.len()
     }
+    /// 折りたたみの一覧
+    pub fn folds(&self) -> &Folds {
+        &self.folds
+    }
+    /// 変更位置の履歴を古い方に辿る
+    ///
+    /// # Errors
+    ///
+    /// 履歴が空か、最も古い位置にいる場合はメッセージを返す
+    pub fn older_change(&mut self) -> Result<Position, &'static str> {
+        if self.changes.is_empty() {
+            return Err("Change list is empty.");
+        }
+        let position = self.changes.older().ok_or("At start of change list.")?;
+        Ok(self.clamp(&position))
+    }
+    /// 変更位置の履歴を新しい方に辿る
+    ///
+    /// # Errors
+    ///
+    /// 履歴が空か、最も新しい位置にいる場合はメッセージを返す
+    pub fn newer_change(&mut self) -> Result<Position, &'static str> {
+        if self.changes.is_empty() {
+            return Err("Change list is empty.");
+        }
+        let position = self.changes.newer().ok_or("At end of change list.")?;
+        Ok(self.clamp(&position))
+    }
+    /// マークを設定する
+    pub fn set_mark(&mut self, mark: char, at: &Position) {
+        self.marks.set(mark, at);
+    }
+    /// マークの位置をドキュメント内に収めて返す
+    pub fn mark(&self, mark: char) -> Option<Position> {
+        self.marks.get(mark).map(|position| self.clamp(position))
+    }
+    /// 変更後に無くなった位置をドキュメント内に収める
+    pub fn clamp(&self, position: &Position) -> Position {
+        let y = position.y.min(self.rows.len().saturating_sub(1));
+        let x = position.x.min(self.rows.get(y).map_or(0, Row::len));
+        Position { x, y }
+    }
+    /// 保存されていた折りたたみを閉じる(ドキュメントの範囲外の場合は何もしない)
+    pub fn close_fold(&mut self, start: usize, end: usize) {
+        if end <= self.rows.len() {
+            self.folds.close(start, end);
+        }
+    }
+    /// :setで指定したバッファごとの設定を記録する(同じ名前の設定は最後のものだけ残す)
+    pub fn record_option(&mut self, name: &'static str, option: String) {
+        self.local_options.retain(|(old, _)| *old != name);
+        self.local_options.push((name, option));
+    }
+    /// 記録したバッファごとの設定の一覧
+    pub fn local_options(&self) -> Vec<String> {
+        self.local_options
+            .iter()
+            .map(|(_, option)| option.clone())
+            .collect()
+    }
+    /// 指定行を含む折りたたみを開く
+    pub fn open_fold(&mut self, y: usize) -> bool {
+        self.folds.open(y)
+    }
+    /// 指定行の折りたたみを開閉し、折りたたみの先頭行を返す(折りたためる範囲が無ければNone)
+    /// 行の下にインデントの深いブロックがあればそれを、無ければ行を含むブロックを折りたたむ
+    pub fn toggle_fold(&mut self, y: usize) -> Option<usize> {
+        if self.folds.end_of(y).is_some() {
+            self.folds.open(y);
+            return Some(y);
+        }
+        // 空行の場合は直前の空行でない行を基準にする
+        let y = (0..=y).rev().find(|y| self.indent_of(*y).is_some())?;
+        let (start, end) = self.fold_range(y).or_else(|| {
+            let indent = self.indent_of(y)?;
+            let parent = (0..y)
+                .rev()
+                .find(|y| self.indent_of(*y).is_some_and(|i| i < indent))?;
+            self.fold_range(parent)
+        })?;
+        self.folds.close(start, end);
+        Some(start)
+    }
+    // 行のインデントの幅を返す(空行の場合はNone)
+    fn indent_of(&self, y: usize) -> Option<usize> {
+        let row = self.rows.get(y)?.as_str();
+        let text = row.trim_start();
+        (!text.is_empty()).then(|| row.len().saturating_sub(text.len()))
+    }
+    /// 指定行の最初の空白でない文字の位置を返す
+    pub fn first_non_blank(&self, y: usize) -> usize {
+        self.rows.get(y).map_or(0, |row| {
+            row.as_str()
+                .graphemes(true)
+                .take_while(|grapheme| grapheme.trim().is_empty())
+                .count()
+        })
+    }
+    // 指定行の下に続くインデントの深いブロックを(開始行, 終了行の次の行)で返す
+    // ブロックを閉じる同じインデントの括弧の行も含める
+    fn fold_range(&self, y: usize) -> Option<(usize, usize)> {
+        let indent = self.indent_of(y)?;
+        let mut last = y;
+        for next in y.saturating_add(1)..self.rows.len() {
+            match self.indent_of(next) {
+                Some(i) if i > indent => last = next,
+                Some(_) => break,
+                // 空行はブロックの途中であれば含める
+                None => (),
+            }
+        }
+        let closer = last.saturating_add(1);
+        if self.indent_of(closer) == Some(indent)
+            && self
+                .rows
+                .get(closer)
+                .is_some_and(|row| row.as_str().trim_start().starts_with([')', ']', '}']))
+        {
+            last = closer;
+        }
+        (last > y).then_some((y, last.saturating_add(1)))
+    }
     // ドキュメントに行を挿入
     fn insert_newline(&mut self, at: &Position) {
         if at.y > self.rows.len() {
@@ -62,17 +549,20 @@ impl Document {
             let current_row = &mut self.rows[at.y];
             let new_row = current_row.split(at.x);
             // 後半行を挿入
-            #[allow(clippy::integer_arithmetic)]
+            #[allow(clippy::arithmetic_side_effects)]
             self.rows.insert(at.y + 1, new_row);
+            self.shift_lines(at.y, 1);
         }
     }
-    // 指定した位置の後ろに1文字挿入
+    /// 指定した位置の後ろに1文字挿入
     pub fn insert(&mut self, at: &Position, c: char) {
         if at.y > self.rows.len() {
             return;
         }
         // 更新フラグを立てる
         self.dirty = true;
+        self.changes.record(at);
+        let before = self.begin_edit(at.y, 1);
         // Enterキーが押された時
         if c == '\n' {
             // 指定位置の下に空行を挿入
@@ -82,23 +572,69 @@ impl Document {
             #[allow(clippy::indexing_slicing)]
             let row = &mut self.rows[at.y];
             row.insert(at.x, c);
+            self.folds.open(at.y);
         } else {
             // ドキュメント末尾に入力された文字を含んだ新しい行を追加
             let mut row = Row::default();
             row.insert(0, c);
             self.rows.push(row);
         }
+        self.end_edit(at, before);
         // 挿入位置以降のハイライトを未更新にする
         self.unhighlight_rows(at.y);
     }
+    /// 指定した位置に文字列(改行を含んでもよい)を挿入し、挿入した文字列の末尾の位置を返す
+    /// 1文字ずつinsertするのと同じ結果になるが、行はまとめて作り、1回の変更として記録する
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        if at.y > self.rows.len() || text.is_empty() {
+            return at.clone();
+        }
+        self.dirty = true;
+        self.changes.record(at);
+        let before = self.begin_edit(at.y, 1);
+        if at.y == self.rows.len() {
+            self.rows.push(Row::default());
+        }
+        let mut lines = text.split('\n');
+        let first = lines.next().unwrap_or_default();
+        let mut rows: Vec<Row> = lines.map(Row::from).collect();
+        let added = rows.len();
+        let Some(row) = self.rows.get_mut(at.y) else {
+            return at.clone();
+        };
+        let end = if let Some(last) = rows.last_mut() {
+            // 挿入位置より後ろは最後の行の後ろに付ける
+            let tail = row.split(at.x);
+            row.insert_str(row.len(), first);
+            let x = last.len();
+            last.append(&tail);
+            Position {
+                x,
+                y: at.y.saturating_add(added),
+            }
+        } else {
+            let len = row.len();
+            row.insert_str(at.x, first);
+            Position {
+                x: at.x.saturating_add(row.len()).saturating_sub(len),
+                y: at.y,
+            }
+        };
+        let next = at.y.saturating_add(1);
+        self.rows.splice(next..next, rows);
+        self.shift_lines(at.y, isize::try_from(added).unwrap_or_default());
+        self.folds.open(at.y);
+        self.end_edit(at, before);
+        self.unhighlight_rows(at.y);
+        end
+    }
     // 指定した位置以降の行をハイライト未更新にする
+    // 変更した行自体は未更新になっているので、後ろの行は次にハイライトするときに前の行から続くコメントの状態が変わったものだけハイライトし直す
     fn unhighlight_rows(&mut self, start: usize) {
-        let start = start.saturating_sub(1);
-        for row in self.rows.iter_mut().skip(start) {
-            row.is_highlighted = false;
-        }
+        self.unhighlighted_from = self.unhighlighted_from.min(start);
     }
-    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    /// 指定位置の文字を削除する(行末の場合は次の行を連結する)
+    #[allow(clippy::arithmetic_side_effects, clippy::indexing_slicing)]
     pub fn delete(&mut self, at: &Position) {
         let len = self.rows.len();
         // 指定位置がドキュメントからはみ出している時
@@ -108,6 +644,8 @@ impl Document {
         }
         // 更新フラグを立てる
         self.dirty = true;
+        self.changes.record(at);
+        let before = self.begin_edit(at.y, 2);
         // 指定位置が行の末尾にあり、かつ次の行が存在した時
         if at.x == self.rows[at.y].len() && at.y + 1 < len {
             // 指定位置の次の行を削除
@@ -116,34 +654,796 @@ impl Document {
             let row = &mut self.rows[at.y];
             // 結合
             row.append(&next_row);
+            self.shift_lines(at.y, -1);
+            self.end_edit(at, before);
         } else {
             let row = &mut self.rows[at.y];
             row.delete(at.x);
+            self.folds.open(at.y);
+            self.end_edit(at, before);
         }
         self.unhighlight_rows(at.y);
     }
-    pub fn save(&mut self) -> Result<(), Error> {
+    /// startからendの手前までの文字列(行をまたいでもよい)を1回の変更として削除し、削除した文字列を返す
+    /// 行をまたぐ場合はstartの行の前半とendの行の後半を結合する
+    #[allow(clippy::indexing_slicing)]
+    pub fn delete_range(&mut self, start: &Position, end: &Position) -> String {
+        let (start, end) = if (end.y, end.x) < (start.y, start.x) {
+            (end, start)
+        } else {
+            (start, end)
+        };
+        let Some(last) = self.rows.len().checked_sub(1) else {
+            return String::new();
+        };
+        if start.y > last || (start.y == end.y && start.x >= end.x) {
+            return String::new();
+        }
+        // ドキュメントより後ろまでの場合は最後の行の末尾までとする
+        let (end_y, end_x) = if end.y > last {
+            (last, self.rows[last].len())
+        } else {
+            (end.y, end.x)
+        };
+        self.dirty = true;
+        self.changes.record(start);
+        let count = end_y.saturating_sub(start.y);
+        let before = self.begin_edit(start.y, count.saturating_add(1));
+        let tail = self.rows[end_y].split(end_x);
+        let middle: Vec<Row> = self.rows.drain(start.y.saturating_add(1)..=end_y).collect();
+        let row = &mut self.rows[start.y];
+        let first = row.split(start.x);
+        row.append(&tail);
+        let deleted: Vec<&str> = iter::once(&first).chain(&middle).map(Row::as_str).collect();
+        self.shift_lines(start.y, 0_isize.saturating_sub_unsigned(count));
+        self.folds.open(start.y);
+        self.end_edit(start, before);
+        self.unhighlight_rows(start.y);
+        deleted.join("\n")
+    }
+    /// startからendの手前までの文字列(`delete_range`で削除する範囲と同じ)を行ごとに返す
+    pub fn text_range(&self, start: &Position, end: &Position) -> Vec<String> {
+        let (start, end) = if (end.y, end.x) < (start.y, start.x) {
+            (end, start)
+        } else {
+            (start, end)
+        };
+        self.rows
+            .iter()
+            .enumerate()
+            .take(end.y.saturating_add(1))
+            .skip(start.y)
+            .map(|(y, row)| {
+                let first = if y == start.y { start.x } else { 0 };
+                let last = if y == end.y { end.x } else { row.len() };
+                row.substring(first, last)
+            })
+            .collect()
+    }
+    // 指定行から始まる行を、与えられた文字列の行で置き換える
+    fn replace_rows(&mut self, start: usize, lines: Vec<String>) {
+        let before = self.begin_edit(start, lines.len());
+        for (index, line) in lines.into_iter().enumerate() {
+            if let Some(row) = self.rows.get_mut(start.saturating_add(index)) {
+                *row = Row::from(&line[..]);
+                self.folds.open(start.saturating_add(index));
+            }
+        }
+        self.dirty = true;
+        self.changes.record(&Position { x: 0, y: start });
+        self.end_edit(&Position { x: 0, y: start }, before);
+        self.unhighlight_rows(start);
+    }
+    /// start行からend行の手前までを与えられた行で置き換える(行数は変わってもよい)
+    pub fn replace_lines(&mut self, start: usize, end: usize, lines: &[String]) {
+        let count = end.saturating_sub(start);
+        let before = self.begin_edit(start, count);
+        self.splice_rows(start, count, lines);
+        self.end_edit(&Position { x: 0, y: start }, before);
+    }
+    /// 全ての行を整形した結果などの行に置き換える(差分の部分のみ置き換えて、折りたたみや印を保つ)
+    /// atの位置が置き換えた後に移った位置を返す
+    pub fn replace_all_lines(&mut self, lines: &[String], at: &Position) -> Position {
+        let old = self.all_lines();
+        // 変更のまとまり(変更前の開始行と行数、変更後の開始行と行数)
+        let mut hunks: Vec<(usize, usize, usize, usize)> = Vec::new();
+        let mut in_hunk = false;
+        let (mut next_old, mut next_new) = (0, 0);
+        let mut moved_y = None;
+        for line in diff::diff(&old, lines) {
+            if !in_hunk && !matches!(line, diff::Line::Equal(..)) {
+                hunks.push((next_old, 0, next_new, 0));
+            }
+            in_hunk = !matches!(line, diff::Line::Equal(..));
+            match (line, hunks.last_mut()) {
+                (diff::Line::Equal(i, j), _) => {
+                    if i == at.y {
+                        moved_y = Some(j);
+                    }
+                    next_old = i.saturating_add(1);
+                    next_new = j.saturating_add(1);
+                }
+                (diff::Line::Delete(i), Some(hunk)) => {
+                    hunk.1 = hunk.1.saturating_add(1);
+                    next_old = i.saturating_add(1);
+                }
+                (diff::Line::Insert(j), Some(hunk)) => {
+                    hunk.3 = hunk.3.saturating_add(1);
+                    next_new = j.saturating_add(1);
+                }
+                _ => (),
+            }
+        }
+        // 変わった行の中では同じ行数だけ下の行に、インデントからの位置を保って移る
+        let moved = moved_y.map_or_else(
+            || {
+                let (old_start, _, new_start, new_count) = hunks
+                    .iter()
+                    .find(|(start, count, _, _)| {
+                        *start <= at.y && at.y < start.saturating_add(*count)
+                    })
+                    .copied()
+                    .unwrap_or_default();
+                let offset = at.y.saturating_sub(old_start);
+                (
+                    new_start.saturating_add(offset.min(new_count.saturating_sub(1))),
+                    false,
+                )
+            },
+            |y| (y, true),
+        );
+        let indent = self.first_non_blank(at.y);
+        // 下の変更から置き換えると、上の変更の行の位置は変わらない
+        for (old_start, old_count, new_start, new_count) in hunks.into_iter().rev() {
+            let new_lines = lines
+                .get(new_start..new_start.saturating_add(new_count))
+                .unwrap_or_default();
+            self.replace_lines(old_start, old_start.saturating_add(old_count), new_lines);
+        }
+        let (y, unchanged) = moved;
+        let x = if unchanged {
+            at.x
+        } else {
+            self.first_non_blank(y)
+                .saturating_add(at.x.saturating_sub(indent))
+        };
+        self.clamp(&Position { x, y })
+    }
+    /// y行目の表示幅が`text_width`を超えていれば、収まる範囲の最後の空白で次の行に折り返す
+    /// 続きの行にはインデントとコメントの記号(commentの行の場合)、またはリストの行頭部分と同じ幅の空白を付ける
+    /// 折り返した場合はatが移動した先の位置を返す
+    pub fn wrap_line(
+        &mut self,
+        at: &Position,
+        text_width: usize,
+        comment: Option<&str>,
+    ) -> Option<Position> {
+        let line = self.rows.get(at.y)?.as_str().to_string();
+        if text_width == 0 || width::str_width(&line) <= text_width {
+            return None;
+        }
+        let (leader, body_start) = wrap_leader(&line, comment);
+        // 折り返す前の部分が収まる最後の空白を探す
+        let mut line_width: usize = 0;
+        let mut break_at = None;
+        for (index, grapheme) in line.grapheme_indices(true) {
+            if index >= body_start && grapheme.trim().is_empty() {
+                break_at = Some(index);
+            }
+            line_width = line_width.saturating_add(width::str_width(grapheme));
+            if line_width > text_width {
+                break;
+            }
+        }
+        let break_at = break_at?;
+        let head = line.get(..break_at)?.trim_end();
+        let rest = line.get(break_at..)?.trim_start();
+        if head.len() <= body_start || rest.is_empty() {
+            return None;
+        }
+        let before = self.begin_edit(at.y, 1);
+        let next = format!("{leader}{rest}");
+        self.rows
+            .splice(at.y..=at.y, [Row::from(head), Row::from(&next[..])]);
+        self.shift_lines(at.y, 1);
+        self.dirty = true;
+        self.changes.record(at);
+        self.end_edit(&Position { x: 0, y: at.y }, before);
+        self.unhighlight_rows(at.y);
+        // 折り返した部分にあった位置は次の行の対応する位置に移す
+        let rest_start = line.len().saturating_sub(rest.len());
+        let rest_x = line.get(..rest_start)?.graphemes(true).count();
+        let head_len = head.graphemes(true).count();
+        Some(if at.x >= rest_x {
+            Position {
+                x: at
+                    .x
+                    .saturating_sub(rest_x)
+                    .saturating_add(leader.graphemes(true).count()),
+                y: at.y.saturating_add(1),
+            }
+        } else {
+            Position {
+                x: at.x.min(head_len),
+                y: at.y,
+            }
+        })
+    }
+    /// start行目からcount行のインデントを1段深く(deeperがfalseの場合は浅く)する
+    pub fn shift_indent(&mut self, start: usize, count: usize, deeper: bool) {
+        let before = self.begin_edit(start, count);
+        for row in self.rows.iter_mut().skip(start).take(count) {
+            if deeper {
+                row.indent(&self.indent);
+            } else {
+                row.dedent(&self.indent);
+            }
+        }
+        self.dirty = true;
+        self.changes.record(&Position { x: 0, y: start });
+        self.end_edit(&Position { x: 0, y: start }, before);
+        self.unhighlight_rows(start);
+    }
+    /// start行目からcount行のコメントを切り替える(ファイルタイプに行コメントが無い場合はfalseを返す)
+    pub fn toggle_comment(&mut self, start: usize, count: usize) -> bool {
+        let Some(prefix) = self.file_type.editing_options().line_comment() else {
+            return false;
+        };
+        let lines = self.lines(start, start.saturating_add(count));
+        let toggled = comment::toggle(&lines, prefix);
+        if toggled != lines {
+            self.replace_rows(start, toggled);
+        }
+        true
+    }
+    /// y行目に次の行を結合し、結合した位置を返す(次の行が無い場合はNoneを返す)
+    #[allow(clippy::indexing_slicing)]
+    pub fn join_lines(&mut self, y: usize, with_space: bool) -> Option<Position> {
+        if y.saturating_add(1) >= self.rows.len() {
+            return None;
+        }
+        let before = self.begin_edit(y, 2);
+        let next_row = self.rows.remove(y.saturating_add(1));
+        let x = self.rows[y].join(&next_row, with_space);
+        self.shift_lines(y, -1);
+        self.dirty = true;
+        let at = Position { x, y };
+        self.changes.record(&at);
+        self.end_edit(&Position { x: 0, y }, before);
+        self.unhighlight_rows(y);
+        Some(at)
+    }
+    /// y行目と次の行を入れ替える(次の行が無い場合はfalseを返す)
+    pub fn swap_rows(&mut self, y: usize) -> bool {
+        let mut lines = self.lines(y, y.saturating_add(2));
+        if lines.len() < 2 {
+            return false;
+        }
+        lines.swap(0, 1);
+        self.replace_rows(y, lines);
+        true
+    }
+    /// start行目からend行目の手前までの行をまとめて1行上(upがfalseの場合は下)の行と入れ替える
+    /// 上または下に入れ替える行が無い場合はfalseを返す
+    pub fn move_rows(&mut self, start: usize, end: usize, up: bool) -> bool {
+        let (first, last) = if up {
+            (start.checked_sub(1), end)
+        } else {
+            (Some(start), end.saturating_add(1))
+        };
+        let Some(first) = first.filter(|_| start < end && last <= self.rows.len()) else {
+            return false;
+        };
+        let mut lines = self.lines(first, last);
+        if up {
+            lines.rotate_left(1);
+        } else {
+            lines.rotate_right(1);
+        }
+        self.replace_rows(first, lines);
+        true
+    }
+    /// y行目を複製して次の行に挿入する
+    pub fn duplicate_row(&mut self, y: usize) {
+        self.duplicate_rows(y, y.saturating_add(1));
+    }
+    /// start行目からend行目の手前までの行を複製して後ろに挿入し、複製した行数を返す
+    pub fn duplicate_rows(&mut self, start: usize, end: usize) -> usize {
+        let lines = self.lines(start, end);
+        if !lines.is_empty() {
+            self.insert_lines(start.saturating_add(lines.len()), &lines);
+        }
+        lines.len()
+    }
+    /// 全ての行の行末の空白とタブを削除し、変更した行数を返す
+    pub fn trim_trailing_whitespace(&mut self) -> usize {
+        let trim = |line: &String| line.trim_end_matches([' ', '\t']).len() != line.len();
+        let lines = self.all_lines();
+        let (Some(first), Some(last)) = (lines.iter().position(trim), lines.iter().rposition(trim))
+        else {
+            return 0;
+        };
+        let trimmed: Vec<String> = lines
+            .iter()
+            .take(last.saturating_add(1))
+            .skip(first)
+            .map(|line| line.trim_end_matches([' ', '\t']).to_string())
+            .collect();
+        self.replace_rows(first, trimmed);
+        lines.iter().filter(|line| trim(line)).count()
+    }
+    /// start行目からcount行を削除し、削除した行を返す
+    pub fn delete_lines(&mut self, start: usize, count: usize) -> Vec<String> {
+        let before = self.begin_edit(start, count);
+        let deleted = before.0.clone();
+        self.splice_rows(start, count, &[]);
+        self.end_edit(&Position { x: 0, y: start }, before);
+        deleted
+    }
+    /// y行目の前に行を挿入する
+    pub fn insert_lines(&mut self, y: usize, lines: &[String]) {
+        let y = y.min(self.rows.len());
+        let before = self.begin_edit(y, 0);
+        self.splice_rows(y, 0, lines);
+        self.end_edit(&Position { x: 0, y }, before);
+    }
+    // y行目の後ろにdelta行挿入(負の場合は削除)したときに折りたたみや印の行を更新する
+    fn shift_lines(&mut self, y: usize, delta: isize) {
+        self.folds.edit(y, delta);
+        self.changes.edit(y, delta);
+        self.marks.edit(y, delta);
+    }
+    // y行目からcount行を変更する前に、変更前の行とドキュメントの行数を返す
+    // 変更後にend_editに渡して取り消し履歴に記録する
+    fn begin_edit(&self, y: usize, count: usize) -> (Vec<String>, usize) {
+        (self.lines(y, y.saturating_add(count)), self.rows.len())
+    }
+    fn end_edit(&mut self, at: &Position, (old, len): (Vec<String>, usize)) {
+        let new_len = old
+            .len()
+            .saturating_add(self.rows.len())
+            .saturating_sub(len);
+        let new = self.lines(at.y, at.y.saturating_add(new_len));
+        self.edit_count = self.edit_count.saturating_add(1);
+        self.history.record(Change {
+            at: at.clone(),
+            old,
+            new,
+        });
+    }
+    // start行からcount行を与えられた行で置き換える(取り消しとやり直しで使う)
+    fn splice_rows(&mut self, start: usize, count: usize, lines: &[String]) {
+        let start = start.min(self.rows.len());
+        let end = start.saturating_add(count).min(self.rows.len());
+        self.rows
+            .splice(start..end, lines.iter().map(|line| Row::from(&line[..])));
+        let delta = isize::try_from(lines.len())
+            .unwrap_or_default()
+            .saturating_sub(isize::try_from(end.saturating_sub(start)).unwrap_or_default());
+        self.shift_lines(start, delta);
+        for y in start..start.saturating_add(lines.len()) {
+            self.folds.open(y);
+        }
+        self.edit_count = self.edit_count.saturating_add(1);
+        self.dirty = true;
+        self.changes.record(&Position { x: 0, y: start });
+        self.unhighlight_rows(start);
+    }
+    /// 直前の取り消し単位の変更を取り消し、変更した位置を返す
+    pub fn undo(&mut self) -> Option<Position> {
+        let changes = self.history.take_undo()?;
+        for change in changes.iter().rev() {
+            self.splice_rows(change.at.y, change.new.len(), &change.old);
+        }
+        let at = changes.first().map(|change| change.at.clone());
+        self.history.push_redo(changes);
+        at.map(|at| self.clamp(&at))
+    }
+    /// 取り消した変更をやり直し、変更した位置を返す
+    pub fn redo(&mut self) -> Option<Position> {
+        let changes = self.history.take_redo()?;
+        for change in &changes {
+            self.splice_rows(change.at.y, change.old.len(), &change.new);
+        }
+        let at = changes.first().map(|change| change.at.clone());
+        self.history.push_undo(changes);
+        at.map(|at| self.clamp(&at))
+    }
+    /// ここまでの変更を1回の取り消しの単位として区切る
+    /// 区切った変更があればtrueを返す
+    pub fn commit_undo(&mut self) -> bool {
+        let changed = self.history.has_uncommitted();
+        self.history.commit();
+        changed
+    }
+    /// 次に区切る変更を直前の取り消しの単位にまとめる
+    /// 複数回に分けて変更する処理で、変更ごとに取り消しの単位が分かれないようにするために使う
+    pub fn undo_join(&mut self) {
+        self.history.join();
+    }
+    /// 指定行がMarkdownの表の行か判定する
+    pub fn is_table_row(&self, y: usize) -> bool {
+        self.rows
+            .get(y)
+            .is_some_and(|row| table::is_table_row(row.as_str()))
+    }
+    // 指定行を含む表の範囲を(開始行, 終了行の次の行)で返す
+    fn table_range(&self, y: usize) -> Option<(usize, usize)> {
+        if !self.is_table_row(y) {
+            return None;
+        }
+        let mut start = y;
+        while start > 0 && self.is_table_row(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
+        }
+        let mut end = y;
+        while self.is_table_row(end) {
+            end = end.saturating_add(1);
+        }
+        Some((start, end))
+    }
+    /// 指定範囲の行の文字列を取得する
+    /// start行目からend行目の手前までの行の文字列を返す
+    pub fn lines(&self, start: usize, end: usize) -> Vec<String> {
+        self.rows
+            .iter()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .map(|row| row.as_str().to_string())
+            .collect()
+    }
+    /// 指定行を含む表の縦線を揃える
+    pub fn format_table(&mut self, y: usize) -> bool {
+        if let Some((start, end)) = self.table_range(y) {
+            let lines = table::format(&self.lines(start, end));
+            self.replace_rows(start, lines);
+            return true;
+        }
+        false
+    }
+    /// 指定行の下に同じ列数の空行を挿入して表を整形する
+    pub fn insert_table_row(&mut self, y: usize) -> bool {
+        if let Some(row) = self.rows.get(y) {
+            if table::is_table_row(row.as_str()) {
+                let new_row = Row::from(&table::empty_row(row.as_str())[..]);
+                let before = self.begin_edit(y, 1);
+                self.rows.insert(y.saturating_add(1), new_row);
+                self.shift_lines(y, 1);
+                self.end_edit(&Position { x: 0, y }, before);
+                return self.format_table(y);
+            }
+        }
+        false
+    }
+    /// 指定位置のセルの右に空の列を挿入して表を整形する
+    pub fn insert_table_column(&mut self, at: &Position) -> bool {
+        if let Some((start, end)) = self.table_range(at.y) {
+            let column = self
+                .rows
+                .get(at.y)
+                .map_or(0, |row| table::cell_index(row.as_str(), at.x));
+            let lines = table::insert_column(&self.lines(start, end), column);
+            self.replace_rows(start, table::format(&lines));
+            return true;
+        }
+        false
+    }
+    /// 表を整形し、指定位置の次(前)のセルの位置を返す
+    pub fn move_table_cell(&mut self, at: &Position, forward: bool) -> Option<Position> {
+        let row = self.rows.get(at.y)?;
+        let column = table::cell_index(row.as_str(), at.x);
+        if !self.format_table(at.y) {
+            return None;
+        }
+        let mut y = at.y;
+        let mut column = if forward {
+            column.saturating_add(1)
+        } else if column > 0 {
+            column.saturating_sub(1)
+        } else {
+            // 行頭のセルより前は前の行の最後のセル
+            y = y.checked_sub(1)?;
+            usize::MAX
+        };
+        // 区切り行を飛ばしながら移動先の行を探す
+        loop {
+            let line = self.rows.get(y)?.as_str();
+            if !table::is_table_row(line) {
+                return None;
+            }
+            let count = table::cell_count(line);
+            if !table::is_separator_line(line) {
+                if column == usize::MAX {
+                    column = count.saturating_sub(1);
+                }
+                if column < count {
+                    let x = table::cell_start(line, column)?;
+                    return Some(Position { x, y });
+                }
+            }
+            // 行の最後のセルより後ろは次の行の先頭のセル
+            if forward {
+                y = y.saturating_add(1);
+                column = 0;
+            } else {
+                y = y.checked_sub(1)?;
+                column = usize::MAX;
+            }
+        }
+    }
+    // 指定行を含むリストの範囲を(開始行, 終了行の次の行)で返す
+    fn list_range(&self, y: usize) -> Option<(usize, usize)> {
+        let is_item = |y: usize| {
+            self.rows
+                .get(y)
+                .is_some_and(|row| list::parse(row.as_str()).is_some())
+        };
+        if !is_item(y) {
+            return None;
+        }
+        let mut start = y;
+        while start > 0 && is_item(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
+        }
+        let mut end = y;
+        while is_item(end) {
+            end = end.saturating_add(1);
+        }
+        Some((start, end))
+    }
+    /// 指定行の項目のチェックボックスの状態を切り替える
+    pub fn toggle_checkbox(&mut self, y: usize) -> bool {
+        let Some(mut item) = self.rows.get(y).and_then(|row| list::parse(row.as_str())) else {
+            return false;
+        };
+        item.toggle();
+        self.replace_rows(y, vec![item.to_line()]);
+        true
+    }
+    /// 指定行を含む番号付きリストの番号を振り直す
+    pub fn renumber_list(&mut self, y: usize) -> bool {
+        let Some((start, end)) = self.list_range(y) else {
+            return false;
+        };
+        let lines = self.lines(start, end);
+        let renumbered = list::renumber(&lines);
+        // 番号が変わらなければ更新しない
+        if renumbered != lines {
+            self.replace_rows(start, renumbered);
+        }
+        true
+    }
+    // 検索語句が変わっていれば世代を上げ、各行のキャッシュを使えなくする
+    fn use_match_cache(&mut self, query: &str, options: SearchOptions) -> u64 {
+        let unchanged = self
+            .match_query
+            .as_ref()
+            .is_some_and(|(cached, cached_options)| cached == query && *cached_options == options);
+        if !unchanged {
+            self.match_query = Some((query.to_string(), options));
+            self.match_generation = self.match_generation.wrapping_add(1);
+        }
+        self.match_generation
+    }
+    /// 指定範囲の行で一致した(行数, 個数)を返す
+    pub fn count_matches(
+        &mut self,
+        start: usize,
+        end: usize,
+        query: &str,
+        options: SearchOptions,
+    ) -> (usize, usize) {
+        let generation = self.use_match_cache(query, options);
+        self.rows
+            .iter_mut()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .map(|row| row.cached_matches(generation, query, options).len())
+            .filter(|count| *count > 0)
+            .fold((0, 0), |(lines, matches), count| {
+                (lines.saturating_add(1), matches.saturating_add(count))
+            })
+    }
+    /// 指定範囲の行で文字列を置換し、置換した(行数, 個数)を返す
+    pub fn substitute(
+        &mut self,
+        start: usize,
+        end: usize,
+        query: &str,
+        replacement: &str,
+        global: bool,
+        options: SearchOptions,
+    ) -> (usize, usize) {
+        let mut lines = 0_usize;
+        let mut matches = 0_usize;
+        let replaced: Vec<String> = self
+            .rows
+            .iter()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .map(|row| {
+                let (line, count) = row.replace(query, replacement, global, options);
+                if count > 0 {
+                    lines = lines.saturating_add(1);
+                    matches = matches.saturating_add(count);
+                }
+                line
+            })
+            .collect();
+        if matches > 0 {
+            self.replace_rows(start, replaced);
+        }
+        (lines, matches)
+    }
+    /// リストの項目で改行したときに次の項目の行頭部分を補い、改行後のカーソル位置を返す
+    /// リストの項目でなければ何もせずNoneを返す
+    pub fn insert_list_newline(&mut self, at: &Position) -> Option<Position> {
+        let item = list::parse(self.rows.get(at.y)?.as_str())?;
+        // 空の項目で改行した場合は行頭部分を消してリストを終える
+        if item.text().is_empty() {
+            self.replace_rows(at.y, vec![String::new()]);
+            return Some(Position { x: 0, y: at.y });
+        }
+        // 行頭部分の途中で改行した場合は通常の改行とする
+        if at.x < item.prefix().graphemes(true).count() {
+            return None;
+        }
+        self.insert(at, '\n');
+        let y = at.y.saturating_add(1);
+        let before = self.begin_edit(y, 1);
+        let row = self.rows.get_mut(y)?;
+        for (index, c) in item.next().prefix().chars().enumerate() {
+            row.insert(index, c);
+        }
+        self.end_edit(&Position { x: 0, y }, before);
+        Some(Position {
+            x: self.list_prefix_len(y).unwrap_or_default(),
+            y,
+        })
+    }
+    /// y行目がリストの項目であれば、行頭部分(記号や番号と続く空白)の書記素の数を返す
+    pub fn list_prefix_len(&self, y: usize) -> Option<usize> {
+        let item = list::parse(self.rows.get(y)?.as_str())?;
+        Some(item.prefix().graphemes(true).count())
+    }
+    /// ファイルに保存する(`trim_trailing`がtrueの場合は保存する前に行末の空白を削除する)
+    ///
+    /// # Errors
+    ///
+    /// ファイルに書き込めなかった場合はエラーを返す
+    pub fn save(&mut self, trim_trailing: bool) -> Result<(), Error> {
+        if trim_trailing && self.file_name.is_some() {
+            self.trim_trailing_whitespace();
+        }
         // ファイル名取得
         if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
+            let mut file = BufWriter::new(fs::File::create(file_name)?);
             // 一行ずつ保存
             for row in &mut self.rows {
                 file.write_all(row.as_bytes())?;
                 file.write_all(b"\n")?;
             }
+            // 保存したと表示した直後に終了しても内容が失われないようにディスクまで書き込む
+            file.into_inner()
+                .map_err(IntoInnerError::into_error)?
+                .sync_all()?;
             // 更新フラグを下ろす
             self.dirty = false;
+            self.modified_time = fs::metadata(file_name)?.modified().ok();
         }
         Ok(())
     }
+    /// 読み込みまたは保存した後に、他のプログラムによってファイルが更新されたか
+    pub fn changed_on_disk(&self) -> bool {
+        if self.kind != BufferKind::File || self.loader.is_some() {
+            return false;
+        }
+        let Some(file_name) = &self.file_name else {
+            return false;
+        };
+        // ファイルが削除された場合などは更新されていないとみなす
+        let modified_time = fs::metadata(file_name).and_then(|metadata| metadata.modified());
+        modified_time.is_ok_and(|time| Some(time) != self.modified_time)
+    }
+    /// ディスク上のファイルの更新を確認したことにして、次の更新まで通知しない
+    pub fn ignore_disk_change(&mut self) {
+        if let Some(file_name) = &self.file_name {
+            self.modified_time = fs::metadata(file_name)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+        }
+    }
+    /// ファイルを読み込み直す(読み込み直す前の内容には取り消しで戻せる)
+    ///
+    /// # Errors
+    ///
+    /// ファイルを読み込めなかった場合はエラーを返す
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let Some(file_name) = &self.file_name else {
+            return Ok(());
+        };
+        let contents = fs::read_to_string(file_name)?;
+        let modified_time = fs::metadata(file_name)?.modified().ok();
+        self.history.commit();
+        let before = self.begin_edit(0, self.rows.len());
+        self.rows = contents.lines().map(Row::from).collect();
+        self.end_edit(&Position::default(), before);
+        self.history.commit();
+        self.folds = Folds::default();
+        self.dirty = false;
+        self.modified_time = modified_time;
+        self.unhighlight_rows(0);
+        Ok(())
+    }
+    /// 保存したときのファイルの行数、単語数、文字数、バイト数を数える
+    pub fn statistics(&self) -> Statistics {
+        self.rows
+            .iter()
+            .fold(Statistics::default(), |statistics, row| Statistics {
+                lines: statistics.lines.saturating_add(1),
+                words: statistics.words.saturating_add(row.word_count()),
+                graphemes: statistics
+                    .graphemes
+                    .saturating_add(row.len())
+                    .saturating_add(1),
+                bytes: statistics
+                    .bytes
+                    .saturating_add(row.as_bytes().len())
+                    .saturating_add(1),
+            })
+    }
+    /// コマンドの履歴のバッファは保存するものが無いので変更があっても未保存としない
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        self.dirty && self.kind != BufferKind::CommandLine
+    }
+    /// n、Nで使う、カーソルの次(前)の一致位置と、ドキュメントの端で折り返したかを返す
+    /// 一致位置は行ごとのキャッシュを使い、同じ語句で繰り返し移動するときは変更した行のみ検索し直す
+    #[allow(clippy::arithmetic_side_effects)]
+    pub fn find_next(
+        &mut self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+        options: SearchOptions,
+    ) -> Option<(Position, bool)> {
+        let len = self.rows.len();
+        if query.is_empty() || at.y >= len {
+            return None;
+        }
+        let generation = self.use_match_cache(query, options);
+        // カーソルの行から始めて、一周してカーソルの行の反対側まで探す
+        for step in 0..=len {
+            let (y, wrapped) = if direction == SearchDirection::Forward {
+                ((at.y + step) % len, at.y + step >= len)
+            } else {
+                ((at.y + len - step) % len, step > at.y)
+            };
+            let Some(row) = self.rows.get_mut(y) else {
+                continue;
+            };
+            let matches = row.cached_matches(generation, query, options);
+            let found = match (direction, step) {
+                (SearchDirection::Forward, 0) => matches.iter().find(|x| **x > at.x),
+                (SearchDirection::Backward, 0) => matches.iter().rev().find(|x| **x < at.x),
+                (SearchDirection::Forward, _) => matches.first(),
+                (SearchDirection::Backward, _) => matches.last(),
+            };
+            if let Some(x) = found {
+                return Some((Position { x: *x, y }, wrapped));
+            }
+        }
+        None
     }
-    // 指定された位置から引数の文字列を検索し、見つかった時は全角文字単位の位置を返す
-    // queryに空文字列を指定するとNoneを返す
+    /// 指定された位置から引数の文字列を検索し、見つかった時は全角文字単位の位置を返す
+    /// queryに空文字列を指定するとNoneを返す
     #[allow(clippy::indexing_slicing)]
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+    pub fn find(
+        &self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+        options: SearchOptions,
+    ) -> Option<Position> {
         // atがドキュメントの範囲外の時は何もしない
         if at.y >= self.rows.len() {
             return None;
@@ -164,7 +1464,7 @@ impl Document {
             // 一行取り出す
             if let Some(row) = self.rows.get(position.y) {
                 // 行内検索で見つかったらその位置を返す
-                if let Some(x) = row.find(query, position.x, direction) {
+                if let Some(x) = row.find(query, position.x, direction, options) {
                     position.x = x;
                     return Some(position);
                 }
@@ -185,26 +1485,242 @@ impl Document {
         }
         None
     }
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
-        let mut start_with_comment = false;
-        // ハイライトを実行する範囲の最後
-        let until = if let Some(until) = until {
-            if until.saturating_add(1) < self.rows.len() {
-                until.saturating_add(1)
+    /// 指定位置の括弧に対応する括弧の位置を返す
+    /// 括弧上でなければ行内でその位置より後ろにある最初の括弧を対象とする
+    pub fn matching_bracket(&self, at: &Position) -> Option<Position> {
+        let row = self.rows.get(at.y)?;
+        let x = row
+            .as_str()
+            .graphemes(true)
+            .enumerate()
+            .skip(at.x)
+            .find(|(_, grapheme)| bracket_pair(grapheme).is_some())
+            .map(|(x, _)| x)?;
+        let bracket = row.grapheme(x)?;
+        let (open, close, forward) = bracket_pair(bracket)?;
+        let (target, nested) = if forward {
+            (close, open)
+        } else {
+            (open, close)
+        };
+        // 入れ子になった括弧の深さを数えながら、行をまたいで探す
+        let mut depth: usize = 0;
+        let mut y = at.y;
+        let mut start = Some(x);
+        loop {
+            let graphemes: Vec<&str> = self.rows.get(y)?.as_str().graphemes(true).collect();
+            let positions: Box<dyn Iterator<Item = usize>> = if forward {
+                let from = start.map_or(0, |x| x.saturating_add(1));
+                Box::new(from..graphemes.len())
             } else {
-                self.rows.len()
+                let to = start.unwrap_or(graphemes.len());
+                Box::new((0..to).rev())
+            };
+            for position in positions {
+                match graphemes.get(position) {
+                    Some(grapheme) if *grapheme == nested => depth = depth.saturating_add(1),
+                    Some(grapheme) if *grapheme == target => {
+                        if depth == 0 {
+                            return Some(Position { x: position, y });
+                        }
+                        depth = depth.saturating_sub(1);
+                    }
+                    _ => (),
+                }
             }
-        } else {
-            self.rows.len()
+            y = if forward {
+                y.saturating_add(1)
+            } else {
+                y.checked_sub(1)?
+            };
+            start = None;
+        }
+    }
+    /// カーソル位置の括弧とそれに対応する括弧をハイライトする
+    pub fn highlight_matching_bracket(&mut self, at: &Position) {
+        let on_bracket = self
+            .rows
+            .get(at.y)
+            .and_then(|row| row.grapheme(at.x))
+            .is_some_and(|grapheme| bracket_pair(grapheme).is_some());
+        if !on_bracket {
+            return;
+        }
+        if let Some(matching) = self.matching_bracket(at) {
+            for position in [at, &matching] {
+                if let Some(row) = self.rows.get_mut(position.y) {
+                    row.set_highlighting(position.x, highlighting::Type::MatchingBracket);
+                }
+            }
+        }
+    }
+    /// カーソル位置の単語と同じ単語をstart行からend行の手前までハイライトする
+    pub fn highlight_cursor_word(&mut self, at: &Position, start: usize, end: usize) {
+        let Some(word) = self.rows.get(at.y).and_then(|row| row.word_at(at.x)) else {
+            return;
+        };
+        let options = SearchOptions {
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let word_len = word.graphemes(true).count();
+        for (y, row) in self
+            .rows
+            .iter_mut()
+            .enumerate()
+            .skip(start)
+            .take(end.saturating_sub(start))
+        {
+            for found in row.find_all(&word, options) {
+                let word_end = found.saturating_add(word_len);
+                // カーソル位置の単語自体はハイライトしない
+                if y == at.y && (found..word_end).contains(&at.x) {
+                    continue;
+                }
+                for x in found..word_end {
+                    row.set_highlighting(x, highlighting::Type::CursorWord);
+                }
+            }
+        }
+    }
+    /// 文章を書くファイルタイプの場合、start行からend行の手前までの辞書に無い単語をハイライトする
+    /// 辞書がNoneの場合や文章でない場合はスペルチェックのハイライトを消す
+    pub fn highlight_spelling(
+        &mut self,
+        dictionary: Option<&Dictionary>,
+        start: usize,
+        end: usize,
+    ) {
+        let dictionary = dictionary.filter(|_| self.editing_options().is_prose());
+        for row in self.rows.iter_mut().take(end).skip(start) {
+            row.highlight_spelling(dictionary);
+        }
+    }
+    // 差分のstart行からend行までのうち、変更前と変更後の行の組になっている行で変わった文字を強調する
+    fn highlight_inline_diff(&mut self, start: usize, end: usize) {
+        for y in start..end {
+            let Some((old, new)) = self.diff_pair(y) else {
+                continue;
+            };
+            let line = |y: usize| {
+                self.rows
+                    .get(y)
+                    .map_or("", |row| row.as_str().get(1..).unwrap_or_default())
+            };
+            let (deleted, inserted) = diff::changed_graphemes(line(old), line(new));
+            let changed = if y == old { deleted } else { inserted };
+            if let Some(row) = self.rows.get_mut(y) {
+                // 行頭の記号の分ずらす
+                for x in changed {
+                    row.set_highlighting(x.saturating_add(1), highlighting::Type::DiffText);
+                }
+            }
+        }
+    }
+    // y行目が連続した-の行と続く+の行のいずれかの場合は、同じ順番にある(-の行, +の行)を返す
+    fn diff_pair(&self, y: usize) -> Option<(usize, usize)> {
+        let starts_with = |y: usize, marker: &str| {
+            self.rows
+                .get(y)
+                .is_some_and(|row| row.as_str().starts_with(marker))
+        };
+        // ---と+++のファイル名の行は除く
+        let is_header = |y: usize| {
+            (starts_with(y, "--- ") && starts_with(y.saturating_add(1), "+++ "))
+                || (starts_with(y, "+++ ")
+                    && y.checked_sub(1).is_some_and(|y| starts_with(y, "--- ")))
+        };
+        let is_change = |y: usize, marker: &str| starts_with(y, marker) && !is_header(y);
+        // markerの行が続く範囲の先頭と末尾の次の行
+        let block = |y: usize, marker: &str| {
+            let mut first = y;
+            while first.checked_sub(1).is_some_and(|y| is_change(y, marker)) {
+                first = first.saturating_sub(1);
+            }
+            let mut last = y;
+            while is_change(last, marker) {
+                last = last.saturating_add(1);
+            }
+            (first, last)
         };
-        // 指定された行までハイライトを実行
-        #[allow(clippy::indexing_slicing)]
-        for row in &mut self.rows[..until] {
-            start_with_comment = row.highlight(
-                self.file_type.highlighting_options(),
-                word,
-                start_with_comment,
-            );
+        if is_change(y, "-") {
+            let (first, last) = block(y, "-");
+            let (_, added_last) = block(last, "+");
+            let new = last.saturating_add(y.saturating_sub(first));
+            (new < added_last).then_some((y, new))
+        } else if is_change(y, "+") {
+            let (first, _) = block(y, "+");
+            let deleted_last = first.checked_sub(1).filter(|y| is_change(*y, "-"))?;
+            let (deleted_first, _) = block(deleted_last, "-");
+            let old = deleted_first.saturating_add(y.saturating_sub(first));
+            (old <= deleted_last).then_some((old, y))
+        } else {
+            None
+        }
+    }
+    /// 画面に表示するstart行からend行までをハイライトする
+    /// 変更のあった行からstart行までは、複数行コメントの状態を求めるために検索語句なしでハイライトする
+    pub fn highlight(
+        &mut self,
+        word: Option<&String>,
+        search_options: SearchOptions,
+        start: usize,
+        end: usize,
+    ) {
+        let word = word.map(|word| (self.use_match_cache(word, search_options), word));
+        let opts = self.file_type.highlighting_options();
+        let is_diff = opts.diff();
+        // 1行先までハイライトする
+        let end = end.saturating_add(1).min(self.rows.len());
+        // コメントが無ければ前の行の状態は関係ないので画面に表示する行のみハイライトする
+        let first = if opts.comments() {
+            self.unhighlighted_from.min(start)
+        } else {
+            start
         }
+        .min(end);
+        let mut start_with_comment = first
+            .checked_sub(1)
+            .and_then(|y| self.rows.get(y))
+            .is_some_and(Row::ends_in_comment);
+        for (y, row) in self.rows.iter_mut().enumerate().take(end).skip(first) {
+            let word = if y < start { None } else { word };
+            start_with_comment = row.highlight(opts, word, search_options, start_with_comment);
+        }
+        self.unhighlighted_from = self.unhighlighted_from.max(end);
+        if is_diff {
+            self.highlight_inline_diff(start, end);
+        }
+    }
+}
+
+// 括弧であれば(開き括弧, 閉じ括弧, 開き括弧か)を返す
+fn bracket_pair(grapheme: &str) -> Option<(&'static str, &'static str, bool)> {
+    BRACKETS.iter().find_map(|(open, close)| {
+        if grapheme == *open {
+            Some((*open, *close, true))
+        } else if grapheme == *close {
+            Some((*open, *close, false))
+        } else {
+            None
+        }
+    })
+}
+
+// 折り返した続きの行の行頭部分と、元の行で折り返さない行頭部分のバイト数を返す
+fn wrap_leader(line: &str, comment: Option<&str>) -> (String, usize) {
+    if let Some(item) = list::parse(line) {
+        let prefix = item.prefix();
+        return (" ".repeat(width::str_width(&prefix)), prefix.len());
     }
+    let body = line.trim_start_matches([' ', '\t']);
+    let indent_len = line.len().saturating_sub(body.len());
+    let prefix_len = comment
+        .and_then(|comment| body.strip_prefix(comment))
+        .map_or(indent_len, |text| {
+            line.len()
+                .saturating_sub(text.trim_start_matches(' ').len())
+        });
+    let leader = line.get(..prefix_len).unwrap_or_default();
+    (leader.to_string(), prefix_len)
 }
@@ -1,53 +1,241 @@
+use crate::charsearch::CharSearch;
+use crate::cli::Cli;
+use crate::completion::{self, Completion};
+use crate::diff;
+use crate::document::{BufferKind, Statistics};
+use crate::error::Error;
+use crate::explorer;
+use crate::filter;
+use crate::fold::Folds;
+use crate::format;
+use crate::grep;
+use crate::highlighting::Overlay;
+use crate::keys;
+use crate::location;
+use crate::location::Location;
+use crate::lsp;
+use crate::make::{self, Job};
+use crate::options::Options;
+use crate::plugin::{self, Hook, Notification, Plugin, Reply};
+use crate::prompt::{self, Source};
+use crate::pty::Pty;
+use crate::registers::{Register, Registers};
+use crate::selection::Selection;
+use crate::spell::{self, Dictionary};
+use crate::state::{self, State};
+use crate::statusline::{self, GitBranch};
+use crate::syntax::{Definition, Definitions};
+use crate::textobject::TextObject;
+use crate::theme::{Theme, THEME_NAMES};
+use crate::view::{self, View};
+use crate::vt;
+use crate::width;
 use crate::Document;
+use crate::FileType;
 use crate::Row;
 use crate::Terminal;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitStatus};
+use std::rc::Rc;
 use std::time::Duration;
 use std::time::Instant;
-use termion::color;
-use termion::event::Key;
+use termion::color::Rgb;
+use termion::event::{Event, Key, MouseButton, MouseEvent};
 use unicode_segmentation::UnicodeSegmentation;
 
-// ステータスバー文字色
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(13, 13, 13);
-// ステータスバー背景色
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
-// 行番号背景色
-const LINE_NUMBER_BG_COLOR: color::Rgb = color::Rgb(53, 53, 53);
 // コンパイル時にバージョン情報を取得
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-// 行頭の行番号の最大表示桁数 4桁+半角スペース1桁
-const LINE_NUMBER_SPACES: usize = 5;
 // 変更を未保存のまま終了するときの終了コマンド回数
 const QUIT_TIMES: u8 = 3;
+/// 引数の#を直前に開いていたファイル名に置き換えるコマンド
+pub const FILE_COMMANDS: [&str; 5] = ["edit", "e", "split", "sp", "rename"];
+// ノーマルモードで後に続くキーを待つコマンドの最初のキー
+const PREFIX_KEYS: &str = "gzqmydc<>\"@'`ftFT";
+// .で繰り返さないコマンドの最初のキー
+const NOT_REPEATABLE_KEYS: [Key; 5] = [
+    Key::Char(':'),
+    Key::Char('/'),
+    Key::Char('u'),
+    Key::Ctrl('r'),
+    Key::Ctrl('s'),
+];
+// マクロの実行で溜められるキーの最大数(再帰するマクロを止める)
+const MAX_QUEUED_KEYS: usize = 100_000;
+// ダブルクリックとみなすクリックの間隔
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(500);
+// メッセージバーの右端に入力途中のコマンドを表示する幅
+const SHOWCMD_WIDTH: usize = 10;
+// ウィンドウ操作のコマンドの最初のキー(Ctrl-W)
+const WINDOW_PREFIX: char = '\u{17}';
+// 入力が無いときに時間経過による処理を行う間隔
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+// メッセージバーにメッセージを表示する時間
+const MESSAGE_DURATION: Duration = Duration::from_secs(5);
+// :messagesで表示するために覚えておくメッセージの行数
+const MAX_MESSAGE_HISTORY: usize = 200;
+// メッセージバーに収まらないメッセージを上に重ねて表示する最大の行数
+const MAX_MESSAGE_LINES: usize = 5;
+// 開いているファイルが他のプログラムによって更新されたか確認する間隔
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// 入力が止まってからカーソル位置の単語をハイライトするまでの時間
+const CURSOR_WORD_DELAY: Duration = Duration::from_millis(300);
+// 描画の期限を過ぎても必ず描画する、カーソル行の上下の行数
+const NEARBY_ROWS: usize = 2;
+// 補完の候補を自動で表示する単語の最小の文字数
+const AUTO_COMPLETE_LENGTH: usize = 3;
+// 補完の候補を一度に表示する最大の行数
+const MAX_COMPLETION_ROWS: usize = 8;
+// --batchで使う仮の画面の大きさ
+const BATCH_WIDTH: u16 = 80;
+const BATCH_HEIGHT: u16 = 24;
+// --batchや--configのコマンドで失敗とみなし、メッセージバーで折り返して表示するメッセージの始まり
+const COMMAND_ERRORS: [&str; 8] = [
+    "ERR",
+    "Error",
+    "Usage:",
+    "Not an editor command",
+    "Cannot",
+    "Mark not set",
+    "File is",
+    "No write since last change",
+];
+// 続けられないエラーで終了するときに保存していない変更を書き出すファイル名の接尾辞
+const RECOVERY_SUFFIX: &str = ".deci-recover";
+// :setで空白を含められるように、残り全てを値とする設定(ステータスバーの書式とコマンド)
+const WHOLE_ARGUMENT_OPTIONS: [&str; 8] = [
+    "statusline=",
+    "stl=",
+    "formatprg=",
+    "fp=",
+    "makeprg=",
+    "mp=",
+    "lsp=",
+    "plugins=",
+];
+// :makeの出力バッファのタイトルの接頭辞(後ろに実行したコマンドを付ける)
+const MAKE_TITLE: &str = "make: ";
+// :terminalの出力バッファのタイトルの接頭辞(後ろに実行したコマンドを付ける)
+const TERMINAL_TITLE: &str = "term: ";
 
+/// 検索する方向
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection {
+    /// ファイルの末尾に向かって検索する
     Forward,
+    /// ファイルの先頭に向かって検索する
     Backward,
 }
 
+/// 検索時の大文字と小文字の区別
+#[derive(PartialEq, Copy, Clone, Default)]
+pub enum CaseSensitivity {
+    /// 区別する
+    #[default]
+    Sensitive,
+    /// 区別しない
+    Insensitive,
+    /// 検索文字列に大文字が含まれる場合のみ区別する
+    Smart,
+}
+
+/// 検索語句の一致の判定方法
+#[derive(PartialEq, Copy, Clone, Default)]
+pub struct SearchOptions {
+    /// 大文字と小文字の区別
+    pub case: CaseSensitivity,
+    /// 単語単位で検索する
+    pub whole_word: bool,
+}
+impl SearchOptions {
+    /// 検索文字列に対して大文字と小文字を区別しないか
+    pub fn ignore_case(self, query: &[&str]) -> bool {
+        match self.case {
+            CaseSensitivity::Sensitive => false,
+            CaseSensitivity::Insensitive => true,
+            CaseSensitivity::Smart => !query
+                .iter()
+                .any(|grapheme| grapheme.chars().any(char::is_uppercase)),
+        }
+    }
+    // 大文字と小文字の区別を smart -> 区別する -> 区別しない の順に切り替える
+    fn toggle_case(&mut self) {
+        self.case = match self.case {
+            CaseSensitivity::Smart => CaseSensitivity::Sensitive,
+            CaseSensitivity::Sensitive => CaseSensitivity::Insensitive,
+            CaseSensitivity::Insensitive => CaseSensitivity::Smart,
+        };
+    }
+    // プロンプトに表示する有効なオプション
+    fn indicator(self) -> String {
+        let case = match self.case {
+            CaseSensitivity::Smart => "[smartcase]",
+            CaseSensitivity::Sensitive => "[case]",
+            CaseSensitivity::Insensitive => "[nocase]",
+        };
+        let word = if self.whole_word { "[word]" } else { "" };
+        format!("{case}{word} ")
+    }
+}
+
+/// ドキュメント内の位置
 #[derive(Default, Clone)]
 pub struct Position {
+    /// 行頭からの書記素の数
     pub x: usize,
+    /// 0から数えた行番号
     pub y: usize,
 }
 
 struct StatusMessage {
     text: String,
     time: Instant,
+    // :messagesの履歴に記録したか
+    logged: bool,
 }
 impl StatusMessage {
     fn from(message: String) -> Self {
         Self {
             time: Instant::now(),
             text: message,
+            logged: false,
         }
     }
 }
 
+// 画面を分割したときの、アクティブでないウィンドウの状態
+struct Window {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
+    pinned: bool,
+    scrollbind: bool,
+    alternate_file: Option<String>,
+}
+
+// :s/pattern/replacement/flagsの内容
+struct Substitute {
+    pattern: String,
+    replacement: String,
+    flags: String,
+}
+
+/// 開いているドキュメントとウィンドウ、端末への描画やキー入力の処理をまとめたエディタ
+#[allow(clippy::struct_excessive_bools)]
 pub struct Editor {
     should_quit: bool,
+    // 終了ステータス(:cqで終了した場合は1)
+    exit_status: i32,
     // trueはノーマルモード、falseはインサートモード
     vim_normal_mode: bool,
     terminal: Terminal,
@@ -58,88 +246,515 @@ pub struct Editor {
     status_message: StatusMessage,
     quit_times: u8,
     highlighted_word: Option<String>,
+    // 複数キーからなるノーマルモードのコマンドで、入力途中のキー
+    pending_keys: String,
+    // ビジュアルモードの選択(ビジュアルモードでなければNone)
+    selection: Option<Selection>,
+    // ;と,で繰り返す最後の文字検索(f、t、F、T)
+    last_char_search: Option<CharSearch>,
+    // 最後にクリックした時刻と画面上の位置
+    last_click: Option<(Instant, u16, u16)>,
+    // trueの場合はウィンドウに表示するバッファを切り替えない
+    pinned: bool,
+    // アクティブでないウィンドウ(画面の上から順)
+    windows: Vec<Window>,
+    // 全てのウィンドウの中でのアクティブなウィンドウの位置
+    window_index: usize,
+    // trueの場合は同期スクロールが有効な他のウィンドウも一緒にスクロールする
+    scrollbind: bool,
+    // 同期スクロールで横方向もスクロールさせる
+    search_options: SearchOptions,
+    // 入力中のプロンプトの前に表示する文字列
+    prompt_indicator: String,
+    // プロンプトの入力中はメッセージバーでのカーソルの表示位置
+    prompt_cursor: Option<usize>,
+    // プロンプトでTabを押して補完中の候補
+    prompt_completion: Option<Completion>,
+    options: Options,
+    theme: Theme,
+    // 設定ファイルで定義されたファイルタイプ(開いたドキュメントと共有する)
+    syntax: Rc<Definitions>,
+    // 起動したプラグイン
+    plugins: Vec<Plugin>,
+    // 最後にプラグインに知らせたカーソル位置(行, 列)とモード
+    notified_cursor: (usize, usize),
+    notified_mode: &'static str,
+    // 端末より先に読み込むキー(マクロの実行で使う)
+    input_queue: VecDeque<Key>,
+    // 続けて届いた文字をまとめて読んだときに、文字の後に読んだ入力(次に読む)
+    pending_event: Option<Event>,
+    // レジスタごとに記録したキーボードマクロ
+    macros: HashMap<char, Vec<Key>>,
+    // 記録中のマクロのレジスタとキー
+    recording: Option<(char, Vec<Key>)>,
+    // 最後に実行したマクロのレジスタ(@@で使う)
+    last_macro: Option<char>,
+    // 最後に入力があった時刻(入力が止まった後の処理を済ませたらNone)
+    last_input: Option<Instant>,
+    // ヤンクや削除したテキスト
+    registers: Registers,
+    // 最後にドキュメントを変更したコマンドのキー(.で繰り返す)と、入力中のコマンドのキー
+    last_change: Vec<Key>,
+    change_keys: Vec<Key>,
+    // アクティブなウィンドウで直前に開いていたファイル(Ctrl-^で切り替える)
+    alternate_file: Option<String>,
+    // 最後にキーが入力された時刻(複数キーのコマンドの続きを待つ時間を計る)
+    last_key_time: Instant,
+    // 最後にファイルの更新を確認した時刻
+    last_disk_check: Instant,
+    // 終了後も引き継ぐ状態
+    state: State,
+    // ページャとして起動したか(qで終了する)
+    pager: bool,
+    // :abbreviateで登録した略語と展開後の文字列
+    abbreviations: HashMap<String, String>,
+    // 挿入モードでカーソルの直前の単語が略語のとき、その略語(行末に展開後の文字列を薄く表示し、Tabで展開する)
+    abbreviation_preview: Option<String>,
+    // ファイルタイプごとに起動した言語サーバ(起動できなかった場合はNone)
+    language_servers: HashMap<String, Option<lsp::Client>>,
+    // 言語サーバに内容を送ったファイルと、そのときのドキュメントの変更回数
+    lsp_synced: HashMap<PathBuf, u64>,
+    // 応答を待っている言語サーバへの要求(ファイルタイプ、要求のID、種類)
+    lsp_request: Option<(String, u64, lsp::Request)>,
+    // Kで表示したホバーの内容(次のキー入力で閉じる)
+    hover: Vec<String>,
+    // 挿入モードで表示している補完の候補
+    completion: Option<Completion>,
+    // 前回の描画で期限を過ぎて描画しなかった行があるか(入力が止まったら全て描画する)
+    deferred_rows: bool,
+    // 前回までの描画でウィンドウの画面上の各行に出力した内容(Noneの行は次の描画で必ず出力する)
+    // 内容が変わらない行は出力し直さない
+    drawn_rows: RefCell<Vec<Option<String>>>,
+    // :set spellで読み込んだ辞書
+    dictionary: Option<Dictionary>,
+    // z=で表示したカーソル位置の単語の修正候補(カーソルは単語の先頭に置く)
+    spell_suggestions: Option<Completion>,
+    // :makeや:!で実行中のコマンドと、その出力を追記する出力バッファのタイトルと、コマンドの文字列
+    job: Option<(Job, String, String)>,
+    // :terminalで実行中のプログラムと、その画面と、画面を表示する出力バッファのタイトル
+    shell: Option<(Pty, vt::Screen, String)>,
+    // 起動時に指定された、行を読み込んだらカーソルを移動する位置
+    pending_location: Option<Location>,
+    // ステータスバーに表示するgitのブランチ名
+    git_branch: GitBranch,
+    // これまでに表示したメッセージ(古いものから順に、:messagesで表示する)
+    messages: VecDeque<String>,
 }
 
 impl Editor {
-    pub fn run(&mut self) {
+    /// 終了するまで入力を処理し、終了ステータスを返す
+    ///
+    /// # Errors
+    ///
+    /// 端末の入出力に失敗した場合やパニックした場合はエラーを返す(保存していない変更は`write_recovery_files`で書き出す)
+    pub fn run(&mut self) -> Result<i32, Error> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.run_loop())).unwrap_or(Err(Error::Panic))
+    }
+    fn run_loop(&mut self) -> Result<i32, Error> {
+        let mut redraw = true;
         loop {
-            if let Err(error) = self.refresh_screen() {
-                die(&error);
+            if redraw {
+                self.refresh_screen()?;
             }
             // 終了フラグが立っていたらループを抜ける
             if self.should_quit {
-                break;
+                self.save_state();
+                return Ok(self.exit_status);
+            }
+            // 入力が無い間も一定間隔で時間経過による処理を行う
+            redraw = self.process_event()?;
+        }
+    }
+    /// 保存していない変更のあるファイルの内容を、元のファイル名に.deci-recoverを付けたファイルに書き出す
+    /// ファイル名の無いバッファは一時ディレクトリに書き出し、書き出したファイル名を返す
+    /// 状態に残さないファイルは書き出さず、状態のファイルを暗号化していれば同じ鍵で暗号化する
+    pub fn write_recovery_files(&mut self) -> Vec<String> {
+        let documents: Vec<&Document> = iter::once(&self.document)
+            .chain(self.windows.iter().map(|window| &window.document))
+            .filter(|document| document.is_dirty() && *document.kind() == BufferKind::File)
+            .collect();
+        let mut written = Vec::new();
+        for (index, document) in documents.into_iter().enumerate() {
+            let path = match &document.file_name {
+                Some(file_name) if state::is_excluded(Path::new(file_name)) => continue,
+                Some(file_name) => PathBuf::from(format!("{file_name}{RECOVERY_SUFFIX}")),
+                // 除外するファイルを開いていた場合は、ファイル名の無いバッファにその内容があるかもしれない
+                None if self.state.private => continue,
+                None => {
+                    let name = format!("deci-{}-{index}{RECOVERY_SUFFIX}", process::id());
+                    env::temp_dir().join(name)
+                }
+            };
+            let path_name = path.to_string_lossy().to_string();
+            // 同じファイルを複数のウィンドウで開いている場合は最初のウィンドウの内容のみ書き出す
+            if written.contains(&path_name) {
+                continue;
             }
-            // キー入力が無い間は入力待ち状態で処理は進まない
-            if let Err(error) = self.process_keypress() {
-                die(&error);
+            let is_temporary = document.file_name.is_none();
+            let written_ok = self
+                .state
+                .encrypt(document.contents().as_bytes())
+                .and_then(|contents| write_private_file(&path, &contents, is_temporary));
+            if written_ok.is_ok() {
+                written.push(path_name);
             }
         }
+        written
     }
-    pub fn default() -> Self {
-        // コマンドの引数を取得
-        let args: Vec<String> = env::args().collect();
+    /// コマンドラインの引数で指定されたファイルを開き、端末に表示するエディタを作る
+    ///
+    /// # Errors
+    ///
+    /// 端末を使う準備に失敗した場合や、状態のファイルか復元するファイルを読み込めなかった場合はエラーを返す
+    pub fn new(cli: &Cli) -> Result<Self, Error> {
+        // 状態のファイルや復元するファイルが暗号化されているか-xが指定されていたら、画面を使う前にパスフレーズを尋ねる
+        // 状態のファイルがまだ暗号化されていなければ、打ち間違えないように確認する
+        let encrypted = state::is_encrypted();
+        let recover_encrypted =
+            (cli.recover.as_deref()).is_some_and(|path| state::is_encrypted_file(Path::new(path)));
+        let passphrase = if cli.encrypt || encrypted || recover_encrypted {
+            state::ask_passphrase(!encrypted).map_err(Error::State)?
+        } else {
+            None
+        };
+        let mut state = State::load(passphrase).map_err(Error::State)?;
+        // --no-colorが指定されたら色を付けずに表示する
+        Terminal::init_color_support(cli.no_color);
+        // -pが指定されたらファイルまたは標準入力を読み取り専用で表示する
+        let pager = cli.pager;
         // 起動直後にステータスバーに表示するメッセージ
-        let mut initial_status =
-            String::from("HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
-        // 引数でファイル名が指定されていたら
-        let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(file_name);
-            // 指定されたファイル名が開ければその内容を保存
-            if let Ok(doc) = doc {
-                doc
-            } else {
-                // 失敗したらエラーメッセージを出してから、ファイル名を指定しなかったときと同じ動作をする
-                initial_status = format!("ERR: Could not open file: {file_name}");
+        let mut initial_status = if pager {
+            String::from("HELP: / = find | : = command | q = quit")
+        } else {
+            String::from("HELP: / = find | : = command | Ctrl-S = save | Ctrl-Q = quit")
+        };
+        // ユーザが定義したファイルタイプはドキュメントを開く前に読み込む
+        let (syntax, errors) = Definitions::load();
+        if let Some(error) = errors.first() {
+            initial_status = format!("ERR: Could not load syntax: {error}");
+        }
+        // 引数でファイル名が指定されていたら(位置も指定されていれば読み込んだ後に移動する)
+        let location = cli.location();
+        let file_name = location.as_ref().map(|location| &location.path);
+        let mut document = if let Some(path) = &cli.recover {
+            // 復元したファイルは元のファイルと区別するため、ファイル名の無いバッファに読み込む
+            let bytes = fs::read(path).and_then(|bytes| state.decrypt(&bytes));
+            unnamed_document(&bytes.map_err(Error::State)?)
+        } else if pager {
+            pager_document(file_name.filter(|name| *name != "-")).unwrap_or_else(|error| {
+                initial_status = format!("ERR: Could not read input: {error}");
                 Document::default()
+            })
+        } else if file_name.is_some_and(|name| name == "-") {
+            // -が指定されたら標準入力をファイル名の無いバッファに読み込む(保存時にファイル名を尋ねる)
+            stdin_document().unwrap_or_else(|error| {
+                initial_status = format!("ERR: Could not read input: {error}");
+                Document::default()
+            })
+        } else if let Some(file_name) = file_name {
+            match open_path(file_name) {
+                Ok((document, message)) => {
+                    initial_status = message.unwrap_or(initial_status);
+                    document
+                }
+                // 失敗したらエラーメッセージを出してから、ファイル名を指定しなかったときと同じ動作をする
+                Err(message) => {
+                    initial_status = message;
+                    Document::default()
+                }
             }
         } else {
             // 中身を空とする
             Document::default()
         };
-        Self {
+        // -Rが指定されたら変更も保存もできないようにする
+        if cli.readonly {
+            document.set_read_only();
+        }
+        let mut editor = Self::with_terminal(
+            Terminal::default().map_err(Error::Init)?,
+            document,
+            initial_status,
+            state,
+            syntax,
+        );
+        editor.pager = pager;
+        if let Some(path) = &cli.config {
+            editor.load_config(path);
+        }
+        // --configで:set pluginsを指定できるように、設定を読んだ後で起動する
+        if !pager {
+            editor.start_plugins(&plugin::commands(editor.options.plugins.as_deref()));
+        }
+        editor.pending_location = location.filter(|location| location.line.is_some());
+        editor.move_to_pending_location();
+        Ok(editor)
+    }
+    // 端末と最初に表示するドキュメント、ステータスバーのメッセージ、引き継ぐ状態、ユーザ定義のファイルタイプからエディタを作る
+    fn with_terminal(
+        terminal: Terminal,
+        mut document: Document,
+        initial_status: String,
+        state: State,
+        syntax: Definitions,
+    ) -> Self {
+        let syntax = Rc::new(syntax);
+        document.set_syntax(Rc::clone(&syntax));
+        let mut editor = Self {
             should_quit: false,
+            exit_status: 0,
             vim_normal_mode: true,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
+            terminal,
             cursor_position: Position::default(),
             document,
             offset: Position::default(),
             status_message: StatusMessage::from(initial_status),
             quit_times: QUIT_TIMES,
             highlighted_word: None,
+            pending_keys: String::new(),
+            selection: None,
+            last_char_search: None,
+            last_click: None,
+            pinned: false,
+            windows: Vec::new(),
+            window_index: 0,
+            scrollbind: false,
+            search_options: SearchOptions {
+                case: CaseSensitivity::Smart,
+                whole_word: false,
+            },
+            prompt_indicator: String::new(),
+            prompt_cursor: None,
+            prompt_completion: None,
+            options: Options::default(),
+            theme: Theme::default(),
+            syntax,
+            plugins: Vec::new(),
+            notified_cursor: (0, 0),
+            notified_mode: "normal",
+            input_queue: VecDeque::new(),
+            pending_event: None,
+            macros: state
+                .registers
+                .iter()
+                .map(|(register, text)| (*register, keys::parse(text)))
+                .collect(),
+            recording: None,
+            last_macro: None,
+            last_input: None,
+            registers: Registers::default(),
+            last_change: Vec::new(),
+            change_keys: Vec::new(),
+            alternate_file: None,
+            last_key_time: Instant::now(),
+            last_disk_check: Instant::now(),
+            state,
+            pager: false,
+            abbreviations: HashMap::new(),
+            abbreviation_preview: None,
+            language_servers: HashMap::new(),
+            lsp_synced: HashMap::new(),
+            lsp_request: None,
+            hover: Vec::new(),
+            completion: None,
+            deferred_rows: false,
+            drawn_rows: RefCell::new(Vec::new()),
+            dictionary: None,
+            spell_suggestions: None,
+            job: None,
+            shell: None,
+            pending_location: None,
+            git_branch: GitBranch::default(),
+            messages: VecDeque::new(),
+        };
+        editor.check_private();
+        editor.restore_position();
+        editor
+    }
+    /// 端末の代わりに与えられた入力を処理するエディタを作る(状態は読み込まず、保存もしない)
+    pub fn headless(terminal: Terminal, document: Document) -> Self {
+        Self::with_terminal(
+            terminal,
+            document,
+            String::new(),
+            State::default(),
+            Definitions::default(),
+        )
+    }
+    /// ユーザ定義のファイルタイプを登録し、開いているドキュメントのファイルタイプを判定し直す
+    pub fn register_syntax(&mut self, definition: Definition) {
+        Rc::make_mut(&mut self.syntax).register(definition);
+        self.document.set_syntax(Rc::clone(&self.syntax));
+        for window in &mut self.windows {
+            window.document.set_syntax(Rc::clone(&self.syntax));
+        }
+    }
+    /// 画面を使わずにファイルに対して:のコマンドを1行ずつ順に実行する(--batch)
+    /// メッセージは標準エラー出力に書き、失敗したコマンドがあれば1を返す
+    pub fn batch(file_name: &str, commands: &str) -> i32 {
+        let mut document = match open_path(file_name) {
+            Ok((document, _)) => document,
+            Err(message) => {
+                eprintln!("deci: {message}");
+                return 1;
+            }
+        };
+        if let Err(error) = document.wait_rows() {
+            eprintln!("deci: ERR: Could not read file: {error}");
+            return 1;
+        }
+        let terminal = Terminal::headless(BATCH_WIDTH, BATCH_HEIGHT, Vec::new());
+        let mut editor = Self::headless(terminal, document);
+        let errors = editor.execute_commands(commands, |message| eprintln!("{message}"));
+        editor.exit_status.max(i32::from(!errors.is_empty()))
+    }
+    // 1行に1つずつ書いた:のコマンドを終了するまで順に実行し、失敗したコマンドのメッセージを返す
+    // 空行と"で始まる行は無視し、各コマンドのメッセージはreportに渡す
+    fn execute_commands(&mut self, commands: &str, mut report: impl FnMut(&str)) -> Vec<String> {
+        let mut errors = Vec::new();
+        for command in commands.lines() {
+            let command = command.trim_start().trim_start_matches(':');
+            if command.trim().is_empty() || command.starts_with('"') {
+                continue;
+            }
+            self.status_message = StatusMessage::from(String::new());
+            self.execute_command(command);
+            let message = &self.status_message.text;
+            if !message.is_empty() {
+                report(message);
+            }
+            if COMMAND_ERRORS
+                .iter()
+                .any(|prefix| message.starts_with(prefix))
+            {
+                errors.push(message.clone());
+            }
+            if self.should_quit {
+                break;
+            }
+        }
+        errors
+    }
+    // --configで指定されたファイルのコマンドを実行する(失敗したら最初のエラーを表示する)
+    fn load_config(&mut self, path: &str) {
+        let initial_status =
+            mem::replace(&mut self.status_message, StatusMessage::from(String::new()));
+        self.status_message = match fs::read_to_string(path) {
+            Ok(commands) => self
+                .execute_commands(&commands, |_| ())
+                .first()
+                .map_or(initial_status, |error| {
+                    StatusMessage::from(format!("ERR: {path}: {error}"))
+                }),
+            Err(error) => StatusMessage::from(format!("ERR: Could not read {path}: {error}")),
+        };
+    }
+    /// 入力が尽きるか終了するまで、入力を処理するたびに画面を描画する
+    ///
+    /// # Errors
+    ///
+    /// 画面の描画に失敗した場合はエラーを返す
+    pub fn run_headless(&mut self) -> Result<(), std::io::Error> {
+        self.refresh_screen()?;
+        while !self.should_quit && self.process_event().is_ok() {
+            self.refresh_screen()?;
         }
+        // 入力が終わったら実行中のコマンドの終了を待ち、残した行や最後の入力の後に受け取った出力も描画する
+        self.wait_job();
+        self.wait_shell();
+        self.render(None)?;
+        Ok(())
     }
+    // 入力の直後は描画にかける時間に期限を設け、入力が止まってから全ての行を描画する
     fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
+        let deadline = self
+            .last_input
+            .and_then(|_| Instant::now().checked_add(self.options.render_budget));
+        self.render(deadline)
+    }
+    fn render(&mut self, deadline: Option<Instant>) -> Result<(), std::io::Error> {
+        // 入力以外で表示したメッセージも履歴に残す
+        self.log_message();
         Terminal::cursor_hide();
         // カーソルを行頭に戻す
         Terminal::cursor_position(&Position::default());
         // 終了時には画面をクリアして、メッセージを出力
         if self.should_quit {
             Terminal::clear_screen();
-            println!("エディタを終了します。さようなら。\r");
+            Terminal::print(format_args!("エディタを終了します。さようなら。\r\n"));
         } else {
             // 画面に表示されている部分とその前をハイライト
+            let heights = self.window_heights();
+            let end = self
+                .document
+                .folds()
+                .visible_below(self.offset.y, self.window_height());
             self.document.highlight(
-                &self.highlighted_word,
-                Some(
-                    self.offset
-                        .y
-                        .saturating_add(self.terminal.size().height as usize),
-                ),
+                self.highlighted_word.as_ref(),
+                self.search_options,
+                self.offset.y,
+                end,
             );
-            self.draw_rows();
+            self.load_dictionary();
+            let dictionary = self.dictionary.as_ref().filter(|_| self.options.spell);
+            self.document
+                .highlight_spelling(dictionary, self.offset.y, end);
+            self.document
+                .highlight_matching_bracket(&self.cursor_position);
+            // 入力が止まるまではカーソル位置の単語をハイライトしない
+            if self.options.cursor_word && self.last_input.is_none() {
+                self.document
+                    .highlight_cursor_word(&self.cursor_position, self.offset.y, end);
+            }
+            for (window, height) in self.windows.iter_mut().zip(
+                heights
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index != self.window_index),
+            ) {
+                let end = window
+                    .document
+                    .folds()
+                    .visible_below(window.offset.y, *height.1);
+                window
+                    .document
+                    .highlight(None, SearchOptions::default(), window.offset.y, end);
+                window
+                    .document
+                    .highlight_spelling(dictionary, window.offset.y, end);
+            }
+            self.deferred_rows = self.draw_rows(deadline);
             self.draw_status_bar();
             self.draw_message_bar();
+            self.draw_prompt_completion();
             // カーソルの画面上の位置を求めて、カーソルを表示する
-            let char_pos = if let Some(row) = self.document.row(self.cursor_position.y) {
-                row.full2half_width(self.offset.x, self.cursor_position.x)
-            } else {
-                0
+            let folds = self.document.folds();
+            let char_pos = match self.document.row(self.cursor_position.y) {
+                // 折りたたまれた行では行頭に表示する
+                _ if folds.end_of(self.cursor_position.y).is_some() => 0,
+                Some(row) => row.full2half_width(self.offset.x, self.cursor_position.x),
+                None => 0,
             };
-            Terminal::cursor_position(&Position {
-                x: (char_pos).saturating_add(LINE_NUMBER_SPACES),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
+            let cursor_row = folds
+                .to_visible(self.cursor_position.y)
+                .saturating_sub(folds.to_visible(self.offset.y));
+            let cursor = Position {
+                x: (char_pos).saturating_add(self.gutter_width(&self.document)),
+                y: self.window_top().saturating_add(cursor_row),
+            };
+            // ホバーは文書の行の上に重ねて描画する
+            self.draw_hover(&cursor);
+            self.draw_completion(&cursor);
+            self.draw_spell_suggestions(&cursor);
+            // プロンプトの入力中はメッセージバーの入力位置にカーソルを表示する
+            let cursor = self.prompt_cursor.map_or(cursor, |x| Position {
+                x: x.min(
+                    (self.terminal.size().width as usize)
+                        .saturating_sub(SHOWCMD_WIDTH.saturating_add(1)),
+                ),
+                y: usize::from(self.terminal.size().height).saturating_add(1),
             });
+            Terminal::cursor_position(&cursor);
         }
         Terminal::cursor_show();
         // バッファの内容を出力
@@ -147,143 +762,3867 @@ impl Editor {
     }
     // ファイルに保存
     fn save(&mut self) {
+        if self.document.loading_progress().is_some() {
+            self.status_message = StatusMessage::from("File is still loading.".to_string());
+            return;
+        }
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::from("File is read-only.".to_string());
+            return;
+        }
+        // ファイル一覧やコマンドの履歴などは保存できない
+        if !self.document.is_editable() || *self.document.kind() == BufferKind::CommandLine {
+            self.status_message = StatusMessage::from("Cannot save this buffer.".to_string());
+            return;
+        }
+        // マクロの編集バッファはレジスタに書き戻す
+        if let BufferKind::Macro(register) = self.document.kind() {
+            let register = *register;
+            self.macros
+                .insert(register, keys::parse(&self.document.contents()));
+            self.document.mark_saved();
+            self.status_message = StatusMessage::from(format!("Macro @{register} updated."));
+            return;
+        }
+        if let BufferKind::GrepEdit(..) = self.document.kind() {
+            self.write_grep_results();
+            return;
+        }
         // エディタ起動時にファイル名が指定されていなかった場合
         if self.document.file_name.is_none() {
             // ファイル名入力を促す
-            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
+            let input = prompt::Input::new("", Vec::new(), Source::File);
+            let new_name = self
+                .prompt_with("Save as: ", input, |_, _, _| {})
+                .unwrap_or(None);
             // ファイル名が入力されなければ
-            if new_name.is_none() {
+            let Some(new_name) = new_name else {
                 // メッセージを表示して保存はしない
                 self.status_message = StatusMessage::from("Save aborted.".to_string());
                 return;
+            };
+            self.document.set_file_name(new_name);
+        }
+
+        let (position, message) = run_hook(
+            &mut self.plugins,
+            Hook::PreSave,
+            &mut self.document,
+            &self.cursor_position,
+        );
+        self.cursor_position = position;
+        // プラグインがエラーを返しても保存はする
+        let plugin_error = message.filter(|message| message.starts_with("ERR: "));
+        // 整形に失敗しても保存はする
+        let format_error = if self.options.format_on_save
+            && format::command(
+                self.options.formatters.as_deref(),
+                &self.document.file_type(),
+            )
+            .is_some()
+        {
+            self.format_document().err()
+        } else {
+            None
+        };
+        if self.document.save(self.options.trim_on_save).is_ok() {
+            // 行末の空白を削除した場合はカーソルが行からはみ出さないようにする
+            self.cursor_position = self.document.clamp(&self.cursor_position);
+            // 成功
+            self.status_message = StatusMessage::from(
+                format_error
+                    .map(|message| format!("ERR: {message} (saved without formatting)"))
+                    .or(plugin_error)
+                    .unwrap_or_else(|| "File saved successfully.".to_string()),
+            );
+        } else {
+            // 失敗
+            self.status_message = StatusMessage::from("Error writing file!".to_string());
+        }
+    }
+    // 各ウィンドウの行数を画面の上から順に返す(ウィンドウの間には区切り行が入る)
+    fn window_heights(&self) -> Vec<usize> {
+        let count = self.windows.len().saturating_add(1);
+        let available =
+            (self.terminal.size().height as usize).saturating_sub(count.saturating_sub(1));
+        #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+        let height = (available / count).max(1);
+        let mut heights = vec![height; count];
+        // 割り切れなかった分は最後のウィンドウに割り当てる
+        if let Some(last) = heights.last_mut() {
+            *last = available
+                .saturating_sub(height.saturating_mul(count.saturating_sub(1)))
+                .max(1);
+        }
+        heights
+    }
+    // アクティブなウィンドウの行数
+    fn window_height(&self) -> usize {
+        self.window_heights()
+            .get(self.window_index)
+            .copied()
+            .unwrap_or(1)
+    }
+    // アクティブなウィンドウの画面上の開始行
+    fn window_top(&self) -> usize {
+        self.window_heights()
+            .iter()
+            .take(self.window_index)
+            .map(|height| height.saturating_add(1))
+            .sum()
+    }
+    // 画面上の行が何番目のウィンドウの何行目にあたるかを返す(区切り行などの場合はNone)
+    fn window_at(&self, terminal_y: usize) -> Option<(usize, usize)> {
+        let mut top: usize = 0;
+        for (index, height) in self.window_heights().into_iter().enumerate() {
+            if terminal_y < top {
+                return None;
+            }
+            if terminal_y < top.saturating_add(height) {
+                return Some((index, terminal_y.saturating_sub(top)));
+            }
+            top = top.saturating_add(height).saturating_add(1);
+        }
+        None
+    }
+    // アクティブなウィンドウの状態を取り出す
+    fn take_window(&mut self) -> Window {
+        // 選択はアクティブなウィンドウを離れたら解除する
+        self.selection = None;
+        Window {
+            document: mem::take(&mut self.document),
+            cursor_position: mem::take(&mut self.cursor_position),
+            offset: mem::take(&mut self.offset),
+            pinned: self.pinned,
+            scrollbind: self.scrollbind,
+            alternate_file: self.alternate_file.take(),
+        }
+    }
+    // 指定したウィンドウの状態をアクティブなウィンドウとして設定する
+    fn set_window(&mut self, window: Window) {
+        self.document = window.document;
+        self.cursor_position = window.cursor_position;
+        self.offset = window.offset;
+        self.pinned = window.pinned;
+        self.scrollbind = window.scrollbind;
+        self.alternate_file = window.alternate_file;
+    }
+    // 指定した位置のウィンドウをアクティブにする
+    fn focus_window(&mut self, index: usize) {
+        if index == self.window_index || index > self.windows.len() {
+            return;
+        }
+        let current = self.take_window();
+        self.windows.insert(self.window_index, current);
+        let target = self.windows.remove(index);
+        self.set_window(target);
+        self.window_index = index;
+    }
+    // 上(下)のウィンドウをアクティブにする(端では反対側に移る)
+    fn focus_next_window(&mut self, forward: bool) {
+        let count = self.windows.len().saturating_add(1);
+        let index = if forward {
+            self.window_index
+                .saturating_add(1)
+                .checked_rem(count)
+                .unwrap_or(0)
+        } else {
+            self.window_index
+                .checked_sub(1)
+                .unwrap_or(count.saturating_sub(1))
+        };
+        self.focus_window(index);
+    }
+    // 画面を分割し、上の新しいウィンドウで指定したファイル(省略時は現在のファイル)を開く
+    fn split_window(&mut self, file_name: Option<&str>) {
+        let file_name = file_name
+            .map(str::to_string)
+            .or_else(|| self.document.file_name.clone());
+        let document = match file_name.as_deref() {
+            Some(file_name) => {
+                // 未保存の変更があるファイルを読み直すと内容が食い違う
+                if file_name == self.document.file_name.as_deref().unwrap_or_default()
+                    && self.document.is_dirty()
+                {
+                    self.status_message =
+                        StatusMessage::from("No write since last change.".to_string());
+                    return;
+                }
+                let Some(document) = self.load_document(file_name) else {
+                    return;
+                };
+                document
+            }
+            None => Document::default(),
+        };
+        self.split_window_with(document);
+    }
+    // 画面を分割し、上の新しいウィンドウで指定したドキュメントを表示する
+    fn split_window_with(&mut self, document: Document) -> bool {
+        // ウィンドウの行数が足りない場合は分割しない
+        if self.window_height() < 3 {
+            self.status_message = StatusMessage::from("Not enough room.".to_string());
+            return false;
+        }
+        // 分割元のファイルを新しいウィンドウの直前のファイルとする
+        let alternate_file = self
+            .document
+            .file_name
+            .clone()
+            .filter(|name| document.file_name.as_ref() != Some(name))
+            .or_else(|| self.alternate_file.clone());
+        let current = self.take_window();
+        self.windows.insert(self.window_index, current);
+        self.set_window(Window {
+            document,
+            cursor_position: Position::default(),
+            offset: Position::default(),
+            pinned: false,
+            scrollbind: false,
+            alternate_file,
+        });
+        true
+    }
+    // アクティブなウィンドウを閉じて、下(最後のウィンドウの場合は上)のウィンドウをアクティブにする
+    fn close_window(&mut self) {
+        if self.windows.is_empty() {
+            self.status_message = StatusMessage::from("Cannot close last window.".to_string());
+            return;
+        }
+        if self.document.is_dirty() {
+            self.status_message = StatusMessage::from("No write since last change.".to_string());
+            return;
+        }
+        if self.window_index >= self.windows.len() {
+            self.window_index = self.window_index.saturating_sub(1);
+        }
+        self.remember_position();
+        let window = self.windows.remove(self.window_index);
+        self.set_window(window);
+    }
+    // アクティブなウィンドウ以外を閉じる
+    fn only_window(&mut self) {
+        if self.windows.iter().any(|window| window.document.is_dirty()) {
+            self.status_message = StatusMessage::from("Other window contains changes.".to_string());
+            return;
+        }
+        for window in &self.windows {
+            if let Some(file_name) = &window.document.file_name {
+                self.state.recent.record(file_name, &window.cursor_position);
+            }
+        }
+        self.windows.clear();
+        self.window_index = 0;
+    }
+    // 同期スクロールが有効なウィンドウを、アクティブなウィンドウと同じだけスクロールさせる
+    fn sync_scroll(&mut self, old_offset: &Position) {
+        if !self.scrollbind {
+            return;
+        }
+        let heights: Vec<usize> = self
+            .window_heights()
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| *index != self.window_index)
+            .map(|(_, height)| height)
+            .collect();
+        for (window, height) in self.windows.iter_mut().zip(heights) {
+            if !window.scrollbind {
+                continue;
+            }
+            let offset = &mut window.offset;
+            offset.y = offset
+                .y
+                .saturating_add(self.offset.y)
+                .saturating_sub(old_offset.y)
+                .min(window.document.len());
+            if self.options.scrollbind_horizontal {
+                offset.x = offset
+                    .x
+                    .saturating_add(self.offset.x)
+                    .saturating_sub(old_offset.x);
+            }
+            // カーソルをウィンドウの表示範囲内に収める
+            let bottom = offset
+                .y
+                .saturating_add(height.saturating_sub(1))
+                .min(window.document.len().saturating_sub(1));
+            window.cursor_position.y = window.cursor_position.y.min(bottom).max(offset.y);
+            let width = window
+                .document
+                .row(window.cursor_position.y)
+                .map_or(0, Row::len);
+            window.cursor_position.x = window.cursor_position.x.min(width);
+        }
+    }
+    // :setで指定されたオプションを設定する
+    fn set_option(&mut self, option: &str) {
+        // ウィンドウやバッファごとの設定以外は共通の設定とする
+        match option {
+            "scrollbind" | "scb" => self.scrollbind = true,
+            "noscrollbind" | "noscb" => self.scrollbind = false,
+            "expandtab" | "et" => {
+                self.document.set_indent(Some(false), None);
+                self.document
+                    .record_option("expandtab", "expandtab".to_string());
+            }
+            "noexpandtab" | "noet" => {
+                self.document.set_indent(Some(true), None);
+                self.document
+                    .record_option("expandtab", "noexpandtab".to_string());
+            }
+            "indent?" => {
+                self.status_message = StatusMessage::from(self.document.indent().describe());
+            }
+            _ if option.starts_with("shiftwidth=") || option.starts_with("sw=") => {
+                let value = option.split_once('=').map_or("", |(_, value)| value);
+                match value.parse() {
+                    Ok(width) if width > 0 => {
+                        self.document.set_indent(None, Some(width));
+                        self.document
+                            .record_option("shiftwidth", format!("shiftwidth={width}"));
+                    }
+                    _ => {
+                        self.status_message =
+                            StatusMessage::from(format!("Invalid shiftwidth: {value}"));
+                    }
+                }
+            }
+            _ if option.starts_with("filetype=") || option.starts_with("ft=") => {
+                let name = option.split_once('=').map_or("", |(_, name)| name);
+                if let Some(file_type) = FileType::from_name(name, &self.syntax) {
+                    self.document.set_file_type(file_type);
+                    self.document
+                        .record_option("filetype", format!("filetype={name}"));
+                } else {
+                    self.status_message = StatusMessage::from(format!("Unknown filetype: {name}"));
+                }
+            }
+            _ => {
+                if let Err(message) = self.options.set(option) {
+                    self.status_message = StatusMessage::from(message);
+                }
+            }
+        }
+    }
+    // 表示するドキュメントを切り替える
+    fn open_document(&mut self, document: Document) {
+        self.remember_position();
+        if matches!(self.document.kind(), BufferKind::File)
+            && self.document.file_name.is_some()
+            && self.document.file_name != document.file_name
+        {
+            self.alternate_file = self.document.file_name.take();
+        }
+        self.document = document;
+        self.cursor_position = Position::default();
+        self.offset = Position::default();
+        self.selection = None;
+        self.check_private();
+        self.restore_position();
+    }
+    // 状態に残さないファイルを開いたら、このプロセスのレジスタと履歴も保存しないようにする
+    fn check_private(&mut self) {
+        if let Some(file_name) = &self.document.file_name {
+            if state::is_excluded(Path::new(file_name)) {
+                self.state.private = true;
+            }
+        }
+    }
+    // 表を編集するコマンドを実行する
+    fn table_command(&mut self, name: &str) {
+        let in_table = match name {
+            "table-format" => self.document.format_table(self.cursor_position.y),
+            "table-row" => {
+                let inserted = self.document.insert_table_row(self.cursor_position.y);
+                if inserted {
+                    // 挿入した行の最初のセルに移動
+                    self.cursor_position.y = self.cursor_position.y.saturating_add(1);
+                    self.cursor_position.x = 2;
+                }
+                inserted
+            }
+            "table-col" => {
+                let inserted = self.document.insert_table_column(&self.cursor_position);
+                // 挿入した列に移動
+                if let Some(position) = inserted
+                    .then(|| self.document.move_table_cell(&self.cursor_position, true))
+                    .flatten()
+                {
+                    self.cursor_position = position;
+                }
+                inserted
+            }
+            _ => true,
+        };
+        if !in_table {
+            self.status_message = StatusMessage::from("Not in a table.".to_string());
+        }
+    }
+    // 他にウィンドウがあればアクティブなウィンドウを閉じ、無ければ終了する
+    // forceがfalseの場合は未保存の変更があれば何もしない
+    fn quit(&mut self, force: bool) {
+        if !force && self.document.is_dirty() {
+            self.status_message =
+                StatusMessage::from("No write since last change (add ! to override).".to_string());
+            return;
+        }
+        if self.windows.is_empty() {
+            self.should_quit = true;
+            return;
+        }
+        if force {
+            self.document.mark_saved();
+        }
+        self.close_window();
+    }
+    // Ctrl-Qで終了する(終了した場合はtrueを返す)
+    fn confirm_quit(&mut self) -> bool {
+        // 更新有りで終了しようとしたときは入力を促すメッセージを表示するのみ
+        let dirty = self.document.is_dirty()
+            || self.windows.iter().any(|window| window.document.is_dirty());
+        if self.quit_times > 0 && dirty {
+            self.status_message = StatusMessage::from(format!(
+                "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                self.quit_times
+            ));
+            self.quit_times = self.quit_times.saturating_sub(1);
+            return false;
+        }
+        // 更新無し、またはCtrl-Qを規定回数押されたときは終了
+        self.should_quit = true;
+        true
+    }
+    // 指定したファイル(ディレクトリの場合はファイル一覧)を開く
+    fn edit_file(&mut self, file_name: &str) {
+        if file_name.is_empty() {
+            self.status_message = StatusMessage::from("Usage: :edit file".to_string());
+            return;
+        }
+        if !self.can_abandon() {
+            return;
+        }
+        if Path::new(file_name).is_dir() {
+            self.show_explorer(Path::new(file_name), None);
+        } else if let Some(document) = self.load_document(file_name) {
+            self.open_document(document);
+        }
+    }
+    // ファイルを開き、開いたときのメッセージかエラーメッセージを表示する
+    fn load_document(&mut self, file_name: &str) -> Option<Document> {
+        match open_path(file_name) {
+            Ok((mut document, message)) => {
+                document.set_syntax(Rc::clone(&self.syntax));
+                if let Some(message) = message {
+                    self.status_message = StatusMessage::from(message);
+                }
+                let (_, message) = run_hook(
+                    &mut self.plugins,
+                    Hook::Open,
+                    &mut document,
+                    &Position::default(),
+                );
+                if let Some(message) = message {
+                    self.status_message = StatusMessage::from(message);
+                }
+                Some(document)
+            }
+            Err(message) => {
+                self.status_message = StatusMessage::from(message);
+                None
+            }
+        }
+    }
+    // 直前に開いていたファイルに切り替える
+    fn edit_alternate_file(&mut self) {
+        if let Some(file_name) = self.alternate_file.clone() {
+            self.edit_file(&file_name);
+        } else {
+            self.status_message = StatusMessage::from("No alternate file.".to_string());
+        }
+    }
+    // コマンドの引数の#を直前に開いていたファイル名に置き換える(\#は#のまま)
+    // 置き換えるファイル名が無ければNoneを返す
+    fn expand_alternate(&mut self, text: &str) -> Option<String> {
+        let mut expanded = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'#') => {
+                    expanded.push('#');
+                    chars.next();
+                }
+                '#' => {
+                    let Some(file_name) = &self.alternate_file else {
+                        self.status_message = StatusMessage::from(
+                            "No alternate file name to substitute for '#'.".to_string(),
+                        );
+                        return None;
+                    };
+                    expanded.push_str(file_name);
+                }
+                c => expanded.push(c),
+            }
+        }
+        Some(expanded)
+    }
+    // 表示中のファイルのカーソル位置を最近開いたファイルの履歴に記録し、表示の状態も記録する
+    fn remember_position(&mut self) {
+        if !matches!(self.document.kind(), BufferKind::File) {
+            return;
+        }
+        if let Some(file_name) = &self.document.file_name {
+            self.state.recent.record(file_name, &self.cursor_position);
+            if let Some(view) = view_of(
+                &self.document,
+                &self.offset,
+                self.options.view_file_types.as_deref(),
+            ) {
+                self.state.record_view(file_name, view);
+            }
+        }
+    }
+    // 表示中のファイルを前回開いていたときのカーソル位置に移動する
+    fn restore_position(&mut self) {
+        if !matches!(self.document.kind(), BufferKind::File) {
+            return;
+        }
+        let position = self
+            .document
+            .file_name
+            .as_ref()
+            .and_then(|file_name| self.state.recent.position(file_name));
+        if let Some(position) = position {
+            self.cursor_position = self.document.clamp(&position);
+        }
+        // 折りたたみ、横方向のスクロール位置、バッファごとの設定を戻す
+        let view = self
+            .document
+            .file_name
+            .as_ref()
+            .filter(|_| {
+                view::is_enabled(
+                    self.options.view_file_types.as_deref(),
+                    &self.document.file_type(),
+                )
+            })
+            .and_then(|file_name| self.state.view(file_name))
+            .cloned();
+        if let Some(view) = view {
+            for (start, end) in view.folds {
+                self.document.close_fold(start, end);
+            }
+            self.offset.x = view.offset_x;
+            for option in &view.options {
+                self.set_option(option);
+            }
+        }
+    }
+    // 全てのウィンドウのカーソル位置とレジスタを記録して、状態を保存する
+    fn save_state(&mut self) {
+        self.remember_position();
+        let file_types = self.options.view_file_types.as_deref();
+        for window in &self.windows {
+            if let Some(file_name) = &window.document.file_name {
+                if matches!(window.document.kind(), BufferKind::File) {
+                    self.state.recent.record(file_name, &window.cursor_position);
+                    if let Some(view) = view_of(&window.document, &window.offset, file_types) {
+                        self.state.record_view(file_name, view);
+                    }
+                }
+            }
+        }
+        self.state.registers = self
+            .macros
+            .iter()
+            .map(|(register, keys)| (*register, keys::to_text(keys)))
+            .collect();
+        // 終了する直前なので保存できなくても知らせる方法が無い
+        let _ = mem::take(&mut self.state).save();
+    }
+    // カーソル位置に印を付ける
+    fn set_mark(&mut self, mark: char) {
+        // m<とm>で:'<,'>!のように使う範囲の始まりと終わりを付ける
+        if !mark.is_ascii_alphabetic() && mark != '<' && mark != '>' {
+            return;
+        }
+        if mark.is_ascii_uppercase() {
+            let Some(path) = self
+                .document
+                .file_name
+                .as_ref()
+                .and_then(|name| fs::canonicalize(name).ok())
+            else {
+                self.status_message = StatusMessage::from("No file name.".to_string());
+                return;
+            };
+            self.state
+                .file_marks
+                .insert(mark, (path, self.cursor_position.clone()));
+        }
+        self.document.set_mark(mark, &self.cursor_position);
+    }
+    // 大文字の印を付けたファイルを開き、そのドキュメントに印を設定する
+    // ファイルを開けなかった場合はfalseを返す
+    fn open_file_mark(&mut self, mark: char) -> bool {
+        let Some((path, position)) = self.state.file_marks.get(&mark).cloned() else {
+            return true;
+        };
+        let current = self
+            .document
+            .file_name
+            .as_ref()
+            .and_then(|name| fs::canonicalize(name).ok());
+        if current.as_ref() == Some(&path) {
+            // 開いているファイルの印は編集に合わせて動いているのでそれを使う
+            if self.document.mark(mark).is_none() {
+                self.document.set_mark(mark, &position);
+            }
+            return true;
+        }
+        if !self.can_abandon() {
+            return false;
+        }
+        let file_name = explorer::relative(&path);
+        let Some(document) = self.load_document(&file_name) else {
+            return false;
+        };
+        self.open_document(document);
+        self.document.set_mark(mark, &position);
+        true
+    }
+    // 最近開いたファイルの一覧を表示する(Enterでそのファイルの最後の位置を開く)
+    fn show_recent_files(&mut self) {
+        if !self.can_abandon() {
+            return;
+        }
+        self.remember_position();
+        let lines = self.state.recent.lines();
+        if lines.is_empty() {
+            self.status_message = StatusMessage::from("No recent files.".to_string());
+            return;
+        }
+        self.open_document(Document::from_lines(
+            &lines,
+            BufferKind::QuickFix("recent files".to_string()),
+        ));
+    }
+    // 表示するメッセージを履歴に記録する(古いものから捨てる)
+    fn log_message(&mut self) {
+        let message = &mut self.status_message;
+        if message.logged || message.text.is_empty() {
+            return;
+        }
+        message.logged = true;
+        self.messages
+            .extend(message.text.lines().map(str::to_string));
+        let excess = self.messages.len().saturating_sub(MAX_MESSAGE_HISTORY);
+        self.messages.drain(..excess);
+    }
+    // これまでに表示したメッセージを一覧で開く
+    fn show_messages(&mut self) {
+        if !self.can_abandon() {
+            return;
+        }
+        if self.messages.is_empty() {
+            self.status_message = StatusMessage::from("No messages.".to_string());
+            return;
+        }
+        let lines: Vec<String> = self.messages.iter().cloned().collect();
+        self.open_document(Document::from_lines(
+            &lines,
+            BufferKind::Output("messages".to_string()),
+        ));
+    }
+    // 表示中のバッファを他のバッファに切り替えられなければ警告してfalseを返す
+    fn can_abandon(&mut self) -> bool {
+        // ウィンドウがバッファに固定されている場合
+        if self.pinned {
+            self.status_message = StatusMessage::from(
+                "Window is pinned to its buffer (:unpin to release).".to_string(),
+            );
+            return false;
+        }
+        // 未保存の変更がある場合
+        if self.document.is_dirty() {
+            self.status_message = StatusMessage::from("No write since last change.".to_string());
+            return false;
+        }
+        true
+    }
+    // 指定された位置のファイルを開いてカーソルを移動する
+    fn open_location(&mut self, location: &Location) {
+        // 開いているファイルであればカーソル移動のみ
+        if self.document.file_name.as_ref() != Some(&location.path) {
+            if !self.can_abandon() {
+                return;
+            }
+            // カレントディレクトリに無ければ開いているファイルのディレクトリから探す
+            let mut path = PathBuf::from(&location.path);
+            if !path.exists() {
+                if let Some(dir) = self
+                    .document
+                    .file_name
+                    .as_ref()
+                    .and_then(|name| Path::new(name).parent())
+                {
+                    path = dir.join(&location.path);
+                }
+            }
+            let file_name = path.to_string_lossy().to_string();
+            // 一覧やカーソル位置の名前から新しいファイルは作らない
+            if !path.exists() {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Can't find file: {file_name}"));
+                return;
+            }
+            let Some(document) = self.load_document(&file_name) else {
+                return;
+            };
+            self.open_document(document);
+        }
+        self.move_to_location(location);
+    }
+    // 起動時に指定された位置の行まで読み込んだらカーソルを移動する
+    fn move_to_pending_location(&mut self) {
+        let Some(location) = &self.pending_location else {
+            return;
+        };
+        if self.document.loading_progress().is_some()
+            && self.document.len() < location.line.unwrap_or_default()
+        {
+            return;
+        }
+        if let Some(location) = self.pending_location.take() {
+            self.move_to_location(&location);
+        }
+    }
+    // 行番号と列番号の位置にカーソルを移動する(行番号、列番号は1から始まる)
+    fn move_to_location(&mut self, location: &Location) {
+        if let Some(line) = location.line {
+            let y = line
+                .saturating_sub(1)
+                .min(self.document.len().saturating_sub(1));
+            let width = self.document.row(y).map_or(0, Row::len);
+            let x = location.column.unwrap_or(1).saturating_sub(1).min(width);
+            self.cursor_position = Position { x, y };
+        }
+    }
+    // カーソル位置にあるpath:line:colの形式のファイルを開く
+    fn goto_file(&mut self) {
+        let Some(word) = self
+            .document
+            .row(self.cursor_position.y)
+            .and_then(|row| row.big_word_at(self.cursor_position.x))
+        else {
+            self.status_message = StatusMessage::from("No file name under cursor.".to_string());
+            return;
+        };
+        if location::is_url(&word) {
+            self.status_message = StatusMessage::from(format!("Cannot open URL: {word}"));
+            return;
+        }
+        if let Some(location) = location::parse(&word) {
+            self.open_location(&location);
+        }
+    }
+    // 現在のファイルのあるディレクトリ(一覧表示中は親ディレクトリ)を一覧表示する
+    fn open_explorer(&mut self) {
+        if !self.can_abandon() {
+            return;
+        }
+        // 一覧を開いた後にカーソルを置くエントリ
+        let (dir, current) = match self.document.kind() {
+            BufferKind::Explorer(dir) => (
+                dir.parent().unwrap_or(dir).to_path_buf(),
+                dir.file_name()
+                    .map(|name| format!("{}/", name.to_string_lossy())),
+            ),
+            BufferKind::File
+            | BufferKind::QuickFix(_)
+            | BufferKind::Output(_)
+            | BufferKind::Macro(_)
+            | BufferKind::GrepEdit(..)
+            | BufferKind::CommandLine => self.file_entry(),
+        };
+        self.show_explorer(&dir, current.as_deref());
+    }
+    // 現在のファイルのあるディレクトリと、そのディレクトリ内でのファイル名を返す
+    fn file_entry(&self) -> (PathBuf, Option<String>) {
+        let path = self
+            .document
+            .file_name
+            .as_ref()
+            .and_then(|name| fs::canonicalize(name).ok());
+        (
+            path.as_ref()
+                .and_then(|path| path.parent())
+                .map_or_else(|| PathBuf::from("."), Path::to_path_buf),
+            path.as_ref()
+                .and_then(|path| path.file_name())
+                .map(|name| name.to_string_lossy().to_string()),
+        )
+    }
+    // 現在のファイルを、ファイル一覧のウィンドウで選択した状態で表示する
+    fn reveal(&mut self) {
+        if self.document.file_name.is_none() {
+            self.status_message = StatusMessage::from("No file name.".to_string());
+            return;
+        }
+        let (dir, current) = self.file_entry();
+        // ファイル一覧を表示しているウィンドウがあればそれを使い、無ければ画面を分割する
+        let explorer_window = self
+            .windows
+            .iter()
+            .position(|window| matches!(window.document.kind(), BufferKind::Explorer(_)));
+        if let Some(index) = explorer_window {
+            self.focus_window(index.saturating_add(usize::from(index >= self.window_index)));
+        } else if !matches!(self.document.kind(), BufferKind::Explorer(_))
+            && !self.split_window_with(Document::default())
+        {
+            return;
+        }
+        self.show_explorer(&dir, current.as_deref());
+    }
+    // 指定したディレクトリの一覧を表示し、指定したエントリにカーソルを置く
+    fn show_explorer(&mut self, dir: &Path, current: Option<&str>) {
+        match explorer::open(dir) {
+            Ok(document) => {
+                self.open_document(document);
+                if let Some(current) = current {
+                    self.cursor_position.y = (0..self.document.len())
+                        .find(|y| {
+                            self.document
+                                .row(*y)
+                                .is_some_and(|row| row.as_str() == current)
+                        })
+                        .unwrap_or(0);
+                }
+            }
+            Err(_) => {
+                self.status_message = StatusMessage::from(format!(
+                    "ERR: Could not open directory: {}",
+                    dir.display()
+                ));
+            }
+        }
+    }
+    // 検索結果の一覧を編集できるようにする
+    fn edit_grep_results(&mut self) {
+        let BufferKind::QuickFix(title) = self.document.kind() else {
+            self.status_message = StatusMessage::from("Not in grep results.".to_string());
+            return;
+        };
+        let kind = BufferKind::GrepEdit(title.clone(), self.document.all_lines());
+        self.document.set_kind(kind);
+        self.status_message = StatusMessage::from(
+            "Edit the lines and save to write them back to the files.".to_string(),
+        );
+    }
+    // 編集した検索結果を元のファイルに書き戻す
+    fn write_grep_results(&mut self) {
+        let BufferKind::GrepEdit(title, original) = self.document.kind() else {
+            return;
+        };
+        let edited = self.document.all_lines();
+        // 他のウィンドウで開いているファイルの保存していない変更は上書きしない
+        let dirty: Vec<PathBuf> = (self.windows.iter())
+            .map(|window| &window.document)
+            .filter(|document| document.is_dirty() && *document.kind() == BufferKind::File)
+            .filter_map(|document| document.file_name.as_ref())
+            .map(|file_name| fs::canonicalize(file_name).unwrap_or_else(|_| file_name.into()))
+            .collect();
+        match grep::write_back(original, &edited, &dirty) {
+            Ok((files, lines)) => {
+                // 次に保存するときは書き戻した内容との差分を書き戻す
+                self.document
+                    .set_kind(BufferKind::GrepEdit(title.clone(), edited));
+                self.document.mark_saved();
+                self.status_message =
+                    StatusMessage::from(format!("{lines} lines written to {files} files."));
+            }
+            Err(message) => self.status_message = StatusMessage::from(message),
+        }
+    }
+    // ファイル一覧や検索結果の一覧でカーソルのあるエントリを開く
+    fn open_entry(&mut self) {
+        let Some(row) = self.document.row(self.cursor_position.y) else {
+            return;
+        };
+        let dir = match self.document.kind() {
+            BufferKind::Explorer(dir) => dir,
+            BufferKind::QuickFix(_) | BufferKind::GrepEdit(..) => {
+                if let Some(location) = location::parse(row.as_str()) {
+                    self.open_location(&location);
+                }
+                return;
+            }
+            BufferKind::Output(_) => {
+                // :makeの出力であれば分割元のウィンドウで開く
+                if let Some(location) = location::find(row.as_str()) {
+                    if is_make_output(&self.document) && !self.windows.is_empty() {
+                        self.focus_next_window(true);
+                    }
+                    self.open_location(&location);
+                }
+                return;
+            }
+            BufferKind::File | BufferKind::Macro(_) | BufferKind::CommandLine => return,
+        };
+        let path = explorer::entry(dir, row.as_str());
+        let file_name = explorer::relative(&path);
+        if !self.can_abandon() {
+            return;
+        }
+        if let Some(document) = self.load_document(&file_name) {
+            self.open_document(document);
+        }
+    }
+    // 文字列検索
+    fn search(&mut self) {
+        // 検索開始前にカーソルの位置を保存
+        let old_position = self.cursor_position.clone();
+        let mut direction = SearchDirection::Forward;
+        self.prompt_indicator = self.search_options.indicator();
+        // 検索文字列を取得(上下キーは履歴、Ctrl-G/Ctrl-Tで次と前のマッチに移動する)
+        let input = prompt::Input::new("", self.state.search_history.clone(), Source::None);
+        let query = self
+            .prompt_with(
+                "Search (ESC to cancel, ^G/^T to navigate, Alt-C case, Alt-W word): ",
+                input,
+                |editor, key, query| {
+                    // このコールバック関数は改行またはEscが入力されるまでループ
+                    // 検索対象をずらすためにカーソルをずらしたか
+                    let mut moved = false;
+                    match key {
+                        Key::Ctrl('g') => {
+                            direction = SearchDirection::Forward;
+                            // 現在の位置から検索すると同じ場所でマッチするので1文字右にずらす
+                            editor.move_cursor(Key::Right);
+                            moved = true;
+                        }
+                        Key::Ctrl('t') => direction = SearchDirection::Backward,
+                        // 検索オプションを切り替える
+                        Key::Alt('c') => {
+                            editor.search_options.toggle_case();
+                            direction = SearchDirection::Forward;
+                        }
+                        Key::Alt('w') => {
+                            editor.search_options.whole_word = !editor.search_options.whole_word;
+                            direction = SearchDirection::Forward;
+                        }
+                        _ => direction = SearchDirection::Forward,
+                    }
+                    editor.prompt_indicator = editor.search_options.indicator();
+                    // 検索文字列が見つかった場合
+                    if let Some(position) = editor.document.find(
+                        query,
+                        &editor.cursor_position,
+                        direction,
+                        editor.search_options,
+                    ) {
+                        // 文字が入力されるたびに検索文字列の位置にカーソルをジャンプ
+                        editor.cursor_position = position;
+                        editor.scroll();
+                    } else if moved {
+                        // 検索で見つからなかったらずらしたカーソルを元に戻す
+                        editor.move_cursor(Key::Left);
+                    }
+                    // ハイライトする検索文字列を保存
+                    editor.highlighted_word = Some(query.clone());
+                },
+            )
+            .unwrap_or(None);
+        if let Some(query) = &query {
+            state::add_history(&mut self.state.search_history, query);
+        }
+        // 何も入力されない、またはEscでキャンセルされた場合
+        if query.is_none() {
+            // 検索開始前の位置にカーソルを戻す
+            self.cursor_position = old_position;
+            self.scroll();
+        }
+        // 検索が終わったら検索文字列のハイライトを解除
+        self.highlighted_word = None;
+    }
+    // カーソル行の行コメントを付けたり外したりする
+    fn toggle_comment(&mut self) {
+        let y = self.cursor_position.y;
+        if self.document.toggle_comment(y, 1) {
+            self.cursor_position.x = self.document.first_non_blank(y);
+        } else {
+            self.status_message =
+                StatusMessage::from("No line comment for this filetype".to_string());
+        }
+    }
+    // 行末の空白を削除して、削除した行数を表示する
+    fn trim_trailing_whitespace(&mut self) {
+        if !self.document.is_editable() {
+            return;
+        }
+        let count = self.document.trim_trailing_whitespace();
+        self.cursor_position = self.document.clamp(&self.cursor_position);
+        self.status_message =
+            StatusMessage::from(format!("Trimmed trailing whitespace on {count} lines."));
+    }
+    // :formatでドキュメントを整形し、結果をメッセージで知らせる
+    fn format(&mut self) {
+        self.status_message = StatusMessage::from(match self.format_document() {
+            Ok(true) => "Formatted.".to_string(),
+            Ok(false) => "Already formatted.".to_string(),
+            Err(message) => format!("ERR: {message}"),
+        });
+    }
+    // ファイルタイプの整形コマンドにドキュメントを通して、変わった行を置き換える
+    // 変わった行があればtrueを返し、整形できなければメッセージを返す
+    fn format_document(&mut self) -> Result<bool, String> {
+        if !self.document.is_editable() || *self.document.kind() != BufferKind::File {
+            return Err("Cannot format this buffer".to_string());
+        }
+        if self.document.loading_progress().is_some() {
+            return Err("File is still loading".to_string());
+        }
+        let file_type = self.document.file_type();
+        let command = format::command(self.options.formatters.as_deref(), &file_type)
+            .ok_or_else(|| format!("No formatter for {file_type}"))?;
+        let file_name = self.document.file_name.clone().unwrap_or_default();
+        let lines = self.document.all_lines();
+        let formatted = format::run(&command, &file_name, &lines)?;
+        if formatted == lines {
+            return Ok(false);
+        }
+        self.cursor_position = self
+            .document
+            .replace_all_lines(&formatted, &self.cursor_position);
+        Ok(true)
+    }
+    // カーソル行を上(upがfalseの場合は下)の行と入れ替えて、カーソルも移動する
+    fn move_line(&mut self, up: bool) {
+        let y = self.cursor_position.y;
+        if up {
+            if y > 0 && self.document.swap_rows(y.saturating_sub(1)) {
+                self.cursor_position.y = y.saturating_sub(1);
+            }
+        } else if self.document.swap_rows(y) {
+            self.cursor_position.y = y.saturating_add(1);
+        }
+        self.renumber_list(y);
+    }
+    // カーソル行を複製し、複製した行に移動する
+    fn duplicate_line(&mut self) {
+        self.document.duplicate_row(self.cursor_position.y);
+        self.cursor_position.y = self.cursor_position.y.saturating_add(1);
+        self.renumber_list(self.cursor_position.y);
+    }
+    // Markdownなどの文章を書くファイルタイプで、リストの項目を増やしたり減らしたりした後に番号を振り直す
+    fn renumber_list(&mut self, y: usize) {
+        if self.document.editing_options().is_prose() {
+            self.document.renumber_list(y);
+        }
+    }
+    // カーソル位置の文字を削除し、行末で次の行と結合した場合はリストの番号を振り直す
+    fn delete_at_cursor(&mut self) {
+        let len = self.document.len();
+        self.document.delete(&self.cursor_position);
+        if self.document.len() < len {
+            self.renumber_list(self.cursor_position.y);
+        }
+    }
+    // カーソルを一つ前に移動し、挿入モードの時はその文字を削除する
+    fn backspace(&mut self) {
+        // 空の括弧の間で削除した場合は閉じ記号も削除する
+        if !self.vim_normal_mode && self.in_empty_pair() {
+            self.document.delete(&self.cursor_position);
+        }
+        // カーソルがドキュメントの先頭でなければ
+        if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
+            // カーソルを一つ前に移動
+            self.move_cursor(Key::Left);
+            // 挿入モードの時のみ
+            if !self.vim_normal_mode {
+                // 文字を削除
+                self.delete_at_cursor();
+            }
+        }
+    }
+    // カーソル行と次の行を結合し、結合した位置にカーソルを移動する
+    fn join_lines(&mut self, with_space: bool) {
+        if let Some(position) = self.document.join_lines(self.cursor_position.y, with_space) {
+            self.cursor_position = self.document.clamp(&position);
+            self.renumber_list(position.y);
+        }
+    }
+    // 最後に検索した文字列をカーソルの後ろ(forwardがfalseの場合は前)から探して移動する
+    fn search_next(&mut self, forward: bool) {
+        let Some(query) = self.state.search_history.last().cloned() else {
+            self.status_message = StatusMessage::from("No previous search pattern".to_string());
+            return;
+        };
+        let direction = if forward {
+            SearchDirection::Forward
+        } else {
+            SearchDirection::Backward
+        };
+        match self.document.find_next(
+            &query,
+            &self.cursor_position,
+            direction,
+            self.search_options,
+        ) {
+            Some((position, wrapped)) => {
+                self.cursor_position = position;
+                if wrapped {
+                    let message = if forward {
+                        "search hit BOTTOM, continuing at TOP"
+                    } else {
+                        "search hit TOP, continuing at BOTTOM"
+                    };
+                    self.status_message = StatusMessage::from(message.to_string());
+                }
+            }
+            None => {
+                self.status_message = StatusMessage::from(format!("Pattern not found: {query}"));
+            }
+        }
+    }
+    // コマンドを入力させて実行する
+    fn command(&mut self) {
+        self.command_with("");
+    }
+    // 入力済みの文字列を与えてコマンドを入力させる
+    fn command_with(&mut self, initial: &str) {
+        let input =
+            prompt::Input::new(initial, self.state.command_history.clone(), Source::Command);
+        let command = self.prompt_with(":", input, |_, _, _| {}).unwrap_or(None);
+        if let Some(command) = command {
+            state::add_history(&mut self.state.command_history, &command);
+            self.execute_command(&command);
+        }
+    }
+    // ドキュメントの行数、単語数、文字数、バイト数と、カーソルの位置をメッセージに表示する
+    // ビジュアルモードでは選択している範囲を数えてドキュメント全体と並べて表示する
+    fn show_statistics(&mut self) {
+        let statistics = self.document.statistics();
+        if let Some(selection) = &self.selection {
+            let selected = Statistics::of_lines(&self.selected_lines(), selection.linewise);
+            self.status_message = StatusMessage::from(format!(
+                "Selected {} of {} Lines; {} of {} Words; {} of {} Chars; {} of {} Bytes",
+                selected.lines,
+                statistics.lines,
+                selected.words,
+                statistics.words,
+                selected.graphemes,
+                statistics.graphemes,
+                selected.bytes,
+                statistics.bytes
+            ));
+            return;
+        }
+        self.status_message = StatusMessage::from(format!(
+            "Line {} of {}; Word {}; Char {}; Byte {}",
+            self.cursor_position.y.saturating_add(1),
+            statistics.lines,
+            statistics.words,
+            statistics.graphemes,
+            statistics.bytes
+        ));
+    }
+    // q:でコマンドの履歴を編集できるウィンドウを開き、最後の空行にカーソルを置く
+    fn open_command_window(&mut self) {
+        let mut lines = self.state.command_history.clone();
+        lines.push(String::new());
+        if self.split_window_with(Document::from_lines(&lines, BufferKind::CommandLine)) {
+            self.cursor_position.y = lines.len().saturating_sub(1);
+        }
+    }
+    // コマンドの履歴のウィンドウを閉じてから、カーソル行のコマンドを実行する
+    fn execute_command_line(&mut self) {
+        let command = self
+            .document
+            .row(self.cursor_position.y)
+            .map(|row| row.as_str().trim().to_string())
+            .unwrap_or_default();
+        self.vim_normal_mode = true;
+        self.close_window();
+        if !command.is_empty() {
+            state::add_history(&mut self.state.command_history, &command);
+            self.execute_command(&command);
+        }
+    }
+    // 出力バッファに行を追加する
+    fn append_output(&mut self, lines: &[String]) {
+        // 最終行にカーソルがあれば追加した行に追従する
+        let following = self.cursor_position.y.saturating_add(1) >= self.document.len();
+        let dropped = self.document.append_output(lines);
+        if following {
+            self.cursor_position = Position {
+                x: 0,
+                y: self.document.len().saturating_sub(1),
+            };
+        } else {
+            // 先頭の行が捨てられた分カーソルを上にずらす
+            self.cursor_position.y = self.cursor_position.y.saturating_sub(dropped);
+        }
+        self.scroll();
+    }
+    // シェルコマンドを:makeと同じく別スレッドで実行し、出力を出力バッファに追記していく
+    fn run_shell_command(&mut self, command: &str) {
+        // 出力バッファを表示中であれば追記する
+        if !matches!(self.document.kind(), BufferKind::Output(_)) {
+            if !self.can_abandon() {
+                return;
+            }
+            self.open_document(Document::from_lines(
+                &[],
+                BufferKind::Output(format!("!{command}")),
+            ));
+        }
+        let BufferKind::Output(title) = self.document.kind().clone() else {
+            return;
+        };
+        // 実行中のコマンドは止める
+        self.job = None;
+        self.append_output(&[format!("$ {command}")]);
+        self.status_message = StatusMessage::from(match Job::start(command) {
+            Ok(job) => {
+                self.job = Some((job, title, command.to_string()));
+                format!("Running: {command}")
+            }
+            Err(error) => format!("ERR: Could not run: {command}: {error}"),
+        });
+    }
+    // :makeのコマンドを別スレッドで実行し、出力を分割したウィンドウの出力バッファに追記していく
+    fn make(&mut self, args: &str) {
+        // 実行中のコマンドは止める
+        self.job = None;
+        let command = make::command(self.options.make_program.as_deref(), args);
+        let title = format!("{MAKE_TITLE}{command}");
+        let mut document = Document::from_lines(&[], BufferKind::Output(title.clone()));
+        document.append_output(&[format!("$ {command}")]);
+        if !self.show_make_output(document) {
+            return;
+        }
+        self.status_message = StatusMessage::from(match Job::start(&command) {
+            Ok(job) => {
+                let message = format!("Running: {command}");
+                self.job = Some((job, title, command));
+                message
+            }
+            Err(error) => format!("ERR: Could not run: {command}: {error}"),
+        });
+    }
+    // :makeの出力バッファを表示しているウィンドウがあれば置き換え、無ければ分割して表示する
+    fn show_make_output(&mut self, document: Document) -> bool {
+        if is_make_output(&self.document) {
+            self.document = document;
+            self.cursor_position = Position::default();
+            self.offset = Position::default();
+            return true;
+        }
+        if let Some(window) = self
+            .windows
+            .iter_mut()
+            .find(|window| is_make_output(&window.document))
+        {
+            window.document = document;
+            window.cursor_position = Position::default();
+            window.offset = Position::default();
+            return true;
+        }
+        self.split_window_with(document)
+    }
+    // :makeや:!で実行中のコマンドの出力を受け取って出力バッファに追記する
+    // 画面の再描画が必要な場合はtrueを返す
+    fn receive_job_output(&mut self) -> bool {
+        let Some((job, ..)) = &mut self.job else {
+            return false;
+        };
+        let mut lines = Vec::new();
+        let status = job.receive(&mut lines);
+        self.append_job_output(&lines);
+        if let Some(status) = status {
+            self.finish_job(status);
+            return true;
+        }
+        !lines.is_empty()
+    }
+    // :makeや:!で実行中のコマンドが終了するまで待つ
+    fn wait_job(&mut self) {
+        let Some((job, ..)) = &mut self.job else {
+            return;
+        };
+        let mut lines = Vec::new();
+        let status = job.wait(&mut lines);
+        self.append_job_output(&lines);
+        self.finish_job(status);
+    }
+    fn finish_job(&mut self, status: io::Result<ExitStatus>) {
+        let Some((.., command)) = self.job.take() else {
+            return;
+        };
+        self.status_message = StatusMessage::from(match status {
+            Ok(status) if status.success() => format!("Finished: {command}"),
+            Ok(status) => format!("ERR: {command}: {status}"),
+            Err(error) => format!("ERR: {command}: {error}"),
+        });
+    }
+    // 実行中のコマンドの出力バッファを表示しているウィンドウに行を追記する(閉じられていれば捨てる)
+    fn append_job_output(&mut self, lines: &[String]) {
+        let Some((_, title, _)) = &self.job else {
+            return;
+        };
+        let kind = BufferKind::Output(title.clone());
+        if self.document.kind() == &kind {
+            self.append_output(lines);
+            return;
+        }
+        let Some((_, height)) = self
+            .window_position(&kind)
+            .map(|position| self.text_size_at(position))
+        else {
+            return;
+        };
+        let Some(window) = self
+            .windows
+            .iter_mut()
+            .find(|window| window.document.kind() == &kind)
+        else {
+            return;
+        };
+        // 最終行にカーソルがあれば追加した行に追従する
+        let following = window.cursor_position.y.saturating_add(1) >= window.document.len();
+        let dropped = window.document.append_output(lines);
+        if following {
+            let last = window.document.len().saturating_sub(1);
+            window.cursor_position = Position { x: 0, y: last };
+            window.offset.y = window
+                .offset
+                .y
+                .max(last.saturating_add(1).saturating_sub(height));
+        } else {
+            window.cursor_position.y = window.cursor_position.y.saturating_sub(dropped);
+        }
+    }
+    /// プラグインを起動し、最初に開いたファイルにフックを適用する
+    pub fn start_plugins(&mut self, commands: &[String]) {
+        for command in commands {
+            match Plugin::start(command) {
+                Ok(plugin) => self.plugins.push(plugin),
+                Err(error) => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Could not start plugin: {error}"));
+                }
+            }
+        }
+        let (_, message) = run_hook(
+            &mut self.plugins,
+            Hook::Open,
+            &mut self.document,
+            &Position::default(),
+        );
+        if let Some(message) = message {
+            self.status_message = StatusMessage::from(message);
+        }
+    }
+    // プラグインが登録したコマンドを実行する
+    fn run_plugin_command(&mut self, name: &str, args: &str) {
+        let registered = |plugin: &Plugin| plugin.commands.iter().any(|command| command == name);
+        if !self.plugins.iter().any(registered) {
+            self.status_message = StatusMessage::from(format!("Not an editor command: {name}"));
+            return;
+        }
+        let mut params = plugin_params(&self.document, &self.cursor_position);
+        if let (Some(params), Value::Object(state)) = (params.as_object_mut(), self.plugin_state())
+        {
+            params.extend(state);
+            params.insert("name".to_string(), json!(name));
+            params.insert("args".to_string(), json!(args));
+        }
+        let Some(plugin) = self.plugins.iter_mut().find(|plugin| registered(plugin)) else {
+            return;
+        };
+        match plugin.request("command", &params) {
+            Ok(reply) => self.apply_plugin_reply(reply),
+            Err(error) => self.status_message = StatusMessage::from(format!("ERR: {error}")),
+        }
+    }
+    // ノーマルモードでプラグインが登録したキーが入力されたら、そのコマンドを実行してtrueを返す
+    fn run_plugin_key(&mut self, key: Key) -> bool {
+        if !self.vim_normal_mode || !self.pending_keys.is_empty() {
+            return false;
+        }
+        let Some(command) = self.plugins.iter().find_map(|plugin| {
+            plugin
+                .keys
+                .iter()
+                .find(|(keys, _)| keys::parse(keys) == [key])
+                .map(|(_, command)| command.clone())
+        }) else {
+            return false;
+        };
+        self.run_plugin_command(&command, "");
+        true
+    }
+    // プラグインの応答に従って内容を置き換え、カーソル位置、画面の位置、モードを変更し、メッセージを表示する
+    fn apply_plugin_reply(&mut self, reply: Reply) {
+        if let Some(lines) = reply.lines {
+            if self.document.is_editable() {
+                self.cursor_position = self
+                    .document
+                    .replace_all_lines(&lines, &self.cursor_position);
+            } else {
+                self.status_message = StatusMessage::from("Buffer is read-only.".to_string());
+                return;
+            }
+        }
+        if let Some(message) = reply.message {
+            self.status_message = StatusMessage::from(message);
+        }
+        if let Some((y, x)) = reply.cursor {
+            self.cursor_position = self.document.clamp(&Position { x, y });
+        }
+        if let Some(top) = reply.viewport_top {
+            // カーソルが画面の外に出る場合は画面の中に戻す
+            let top = top.min(self.document.len().saturating_sub(1));
+            let folds = self.document.folds();
+            let bottom = folds.visible_below(top, self.window_height().saturating_sub(1));
+            self.offset.y = top;
+            self.cursor_position.y = self.cursor_position.y.clamp(top, bottom.max(top));
+            self.cursor_position = self.document.clamp(&self.cursor_position);
+        }
+        match reply.mode.as_deref() {
+            Some("normal") => self.enter_normal_mode(),
+            Some("insert") if self.document.is_editable() => {
+                self.selection = None;
+                self.vim_normal_mode = false;
+            }
+            _ => (),
+        }
+        // 選択範囲を指定された場合は最初の位置から最後の位置までを選択し、カーソルを最後の位置に置く
+        if let Some(((start_y, start_x), (end_y, end_x))) = reply.selection {
+            self.enter_normal_mode();
+            self.selection = Some(Selection {
+                anchor: self.document.clamp(&Position {
+                    x: start_x,
+                    y: start_y,
+                }),
+                linewise: false,
+            });
+            self.cursor_position = self.document.clamp(&Position { x: end_x, y: end_y });
+        }
+    }
+    // 指定した種類のバッファを表示しているウィンドウの画面上の順番
+    fn window_position(&self, kind: &BufferKind) -> Option<usize> {
+        if self.document.kind() == kind {
+            return Some(self.window_index);
+        }
+        let index = self
+            .windows
+            .iter()
+            .position(|window| window.document.kind() == kind)?;
+        // アクティブなウィンドウより後ろのウィンドウは画面上で1つ下にある
+        Some(if index < self.window_index {
+            index
+        } else {
+            index.saturating_add(1)
+        })
+    }
+    // 画面上で指定した順番のウィンドウの本文の表示幅と行数
+    fn text_size_at(&self, position: usize) -> (usize, usize) {
+        let height = self.window_heights().get(position).copied().unwrap_or(1);
+        let width = (self.terminal.size().width as usize)
+            .saturating_sub(self.options.line_numbers.width(height));
+        (width, height)
+    }
+    // :terminalでシェル(引数があればそのコマンド)を疑似端末で実行し、分割したウィンドウに画面を表示する
+    fn open_terminal(&mut self, args: &str) {
+        if self.shell.is_some() {
+            self.status_message = StatusMessage::from("Terminal already running.".to_string());
+            return;
+        }
+        let command = if args.is_empty() {
+            env::var("SHELL").unwrap_or_else(|_| "sh".to_string())
+        } else {
+            args.to_string()
+        };
+        let title = format!("{TERMINAL_TITLE}{command}");
+        let document = Document::from_lines(&[], BufferKind::Output(title.clone()));
+        if !self.split_window_with(document) {
+            return;
+        }
+        let (width, height) = self.text_size_at(self.window_index);
+        match Pty::spawn(&command, width, height) {
+            Ok(pty) => {
+                self.shell = Some((pty, vt::Screen::new(width, height), title));
+                self.show_shell_screen();
+            }
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not run: {command}: {error}"));
+            }
+        }
+    }
+    // :terminalのウィンドウがアクティブであれば、キーをプログラムに送ってtrueを返す
+    // (Ctrl-Wで始まるウィンドウ操作のみエディタで扱う)
+    fn forward_to_shell(&mut self, key: Key) -> bool {
+        let Some((pty, _, title)) = &mut self.shell else {
+            return false;
+        };
+        if !matches!(self.document.kind(), BufferKind::Output(name) if name == title)
+            || !self.pending_keys.is_empty()
+            || key == Key::Ctrl('w')
+        {
+            return false;
+        }
+        if let Err(error) = pty.write(&keys::to_bytes(key)) {
+            self.status_message = StatusMessage::from(format!("ERR: {error}"));
+        }
+        true
+    }
+    // :terminalで実行中のプログラムの出力を受け取って画面に反映する
+    // 画面の再描画が必要な場合はtrueを返す
+    fn receive_shell_output(&mut self) -> bool {
+        let Some((pty, screen, title)) = &mut self.shell else {
+            return false;
+        };
+        let mut output = Vec::new();
+        let status = pty.receive(&mut output);
+        screen.feed(&output);
+        let kind = BufferKind::Output(title.clone());
+        // ウィンドウを閉じたらプログラムも止める
+        let Some(position) = self.window_position(&kind) else {
+            self.shell = None;
+            return false;
+        };
+        let size = self.text_size_at(position);
+        let Some((pty, screen, _)) = &mut self.shell else {
+            return false;
+        };
+        // ウィンドウの大きさが変わったら疑似端末の大きさも合わせる
+        let resized = screen.size() != size;
+        if resized {
+            screen.resize(size.0, size.1);
+            if let Err(error) = pty.resize(size.0, size.1) {
+                self.status_message = StatusMessage::from(format!("ERR: {error}"));
+            }
+        }
+        let changed = resized || !output.is_empty();
+        if changed {
+            self.show_shell_screen();
+        }
+        if let Some(status) = status {
+            self.finish_shell(status);
+            return true;
+        }
+        changed
+    }
+    // :terminalで実行中のプログラムが終了するまで待つ
+    fn wait_shell(&mut self) {
+        let Some((pty, screen, _)) = &mut self.shell else {
+            return;
+        };
+        let mut output = Vec::new();
+        let status = pty.wait(&mut output);
+        screen.feed(&output);
+        self.show_shell_screen();
+        self.finish_shell(status);
+    }
+    fn finish_shell(&mut self, status: io::Result<ExitStatus>) {
+        let Some((_, _, title)) = self.shell.take() else {
+            return;
+        };
+        let command = title.strip_prefix(TERMINAL_TITLE).unwrap_or(&title);
+        self.status_message = StatusMessage::from(match status {
+            Ok(status) if status.success() => format!("Finished: {command}"),
+            Ok(status) => format!("ERR: {command}: {status}"),
+            Err(error) => format!("ERR: {command}: {error}"),
+        });
+    }
+    // :terminalの画面の内容を、表示しているウィンドウの出力バッファに反映する
+    fn show_shell_screen(&mut self) {
+        let Some((_, screen, title)) = &self.shell else {
+            return;
+        };
+        let lines = screen.lines();
+        let (x, y) = screen.cursor();
+        let kind = BufferKind::Output(title.clone());
+        let (document, cursor_position, offset) = if self.document.kind() == &kind {
+            (
+                &mut self.document,
+                &mut self.cursor_position,
+                &mut self.offset,
+            )
+        } else {
+            let Some(window) = self
+                .windows
+                .iter_mut()
+                .find(|window| window.document.kind() == &kind)
+            else {
+                return;
+            };
+            (
+                &mut window.document,
+                &mut window.cursor_position,
+                &mut window.offset,
+            )
+        };
+        document.set_colored_lines(lines);
+        *cursor_position = Position { x, y };
+        *offset = Position::default();
+    }
+    // !を使うコマンドであれば実行してtrueを返す
+    fn execute_bang_command(&mut self, command: &str) -> bool {
+        let Some((range, shell_command)) = command.split_once('!') else {
+            return false;
+        };
+        // 行の範囲に続く!で、その行をシェルコマンドに通した出力で置き換える
+        if let Some(range) = self.parse_range(range) {
+            match range {
+                Ok(range) => {
+                    if let Some(shell_command) = self.expand_alternate(shell_command.trim()) {
+                        self.filter_lines(range, &shell_command);
+                    }
+                }
+                Err(message) => self.status_message = StatusMessage::from(message),
+            }
+            return true;
+        }
+        // !に続くシェルコマンドを実行する
+        if range.trim().is_empty() {
+            if let Some(shell_command) = self.expand_alternate(shell_command.trim()) {
+                self.run_shell_command(&shell_command);
+            }
+            return true;
+        }
+        false
+    }
+    // 行の範囲を前に付けられるコマンド(s/pattern/replacement/flagsとd)であれば実行してtrueを返す
+    // 範囲を付けなければカーソル行が対象
+    fn execute_range_command(&mut self, command: &str) -> bool {
+        let (range, rest) = split_range(command.trim());
+        let substitute = parse_substitute(rest.trim_start());
+        if substitute.is_none() && !matches!(rest.trim(), "d" | "delete") {
+            return false;
+        }
+        let range = if range.trim().is_empty() {
+            let y = self.cursor_position.y;
+            y..y.saturating_add(1)
+        } else {
+            match self.parse_range(range) {
+                Some(Ok(range)) => range,
+                Some(Err(message)) => {
+                    self.status_message = StatusMessage::from(message);
+                    return true;
+                }
+                None => return false,
+            }
+        };
+        match substitute {
+            Some(substitute) if substitute.pattern.is_empty() => {
+                self.status_message =
+                    StatusMessage::from("Usage: :s/pattern/replacement/flags".to_string());
+            }
+            Some(substitute) => self.substitute(range, &substitute),
+            None if !self.document.is_editable() => {
+                self.status_message = StatusMessage::from("Cannot modify this buffer.".to_string());
+            }
+            None => {
+                let count = self.delete_lines(None, range);
+                self.status_message = StatusMessage::from(format!("{count} lines deleted."));
+            }
+        }
+        true
+    }
+    // 範囲の行をシェルコマンドの標準入力に渡し、標準出力の行で置き換える
+    fn filter_lines(&mut self, range: Range<usize>, command: &str) {
+        if !self.document.is_editable() {
+            self.status_message = StatusMessage::from("Cannot modify this buffer.".to_string());
+            return;
+        }
+        let lines = self.document.lines(range.start, range.end);
+        match filter::run(command, &[], &lines) {
+            Ok(output) => {
+                self.document.replace_lines(range.start, range.end, &output);
+                self.cursor_position = self.document.clamp(&Position {
+                    x: 0,
+                    y: range.start,
+                });
+                self.status_message =
+                    StatusMessage::from(format!("{} lines filtered.", lines.len()));
+            }
+            Err(message) => self.status_message = StatusMessage::from(format!("ERR: {message}")),
+        }
+    }
+    // %、または行の位置(1つか、カンマで区切った2つ)で書いた行の範囲を解釈する
+    // 範囲の書き方でなければNoneを、付いていない印を指定した場合はErrを返す
+    fn parse_range(&self, text: &str) -> Option<Result<Range<usize>, String>> {
+        let len = self.document.len();
+        let text = text.trim();
+        if text == "%" {
+            return Some(Ok(0..len));
+        }
+        let (first, second) = text.split_once(',').unwrap_or((text, text));
+        let (start, end) = (self.parse_address(first)?, self.parse_address(second)?);
+        Some(start.and_then(|start| {
+            let end = end?;
+            // 逆順に書いた範囲も受け付ける
+            Ok(start.min(end).min(len)..start.max(end).saturating_add(1).min(len))
+        }))
+    }
+    // 行番号、.(カーソル行)、$(最終行)、'{印}と、それに続く+N、-Nで書いた行の位置(0から始まる)
+    fn parse_address(&self, text: &str) -> Option<Result<usize, String>> {
+        let text = text.trim();
+        let digits = text.chars().take_while(char::is_ascii_digit).count();
+        let (base, mut rest) = if let Some(rest) = text.strip_prefix('.') {
+            (Ok(self.cursor_position.y), rest)
+        } else if let Some(rest) = text.strip_prefix('$') {
+            (Ok(self.document.len().saturating_sub(1)), rest)
+        } else if let Some(rest) = text.strip_prefix('\'') {
+            let mark = rest.chars().next()?;
+            let base = self
+                .document
+                .mark(mark)
+                .map(|position| position.y)
+                .ok_or_else(|| format!("Mark not set: {mark}"));
+            (base, rest.get(mark.len_utf8()..)?)
+        } else if digits > 0 {
+            let number: usize = text.get(..digits)?.parse().ok()?;
+            (Ok(number.saturating_sub(1)), text.get(digits..)?)
+        } else if text.starts_with(['+', '-']) {
+            (Ok(self.cursor_position.y), text)
+        } else {
+            return None;
+        };
+        let mut y = base;
+        while !rest.is_empty() {
+            let forward = rest.starts_with('+');
+            rest = rest.strip_prefix(['+', '-'])?;
+            let digits = rest.chars().take_while(char::is_ascii_digit).count();
+            // 数字を省略した場合は1行
+            let count = if digits == 0 {
+                1
+            } else {
+                rest.get(..digits)?.parse().ok()?
+            };
+            rest = rest.get(digits..)?;
+            y = y.map(|y| {
+                if forward {
+                    y.saturating_add(count)
+                } else {
+                    y.saturating_sub(count)
+                }
+            });
+        }
+        Some(y)
+    }
+    // 入力されたコマンドを実行する
+    fn execute_command(&mut self, command: &str) {
+        if self.execute_bang_command(command) {
+            return;
+        }
+        if self.execute_range_command(command) {
+            return;
+        }
+        let mut args = command.split_whitespace();
+        let Some(name) = args.next() else {
+            return;
+        };
+        // ファイル名を引数に取るコマンドでは#を直前に開いていたファイル名に置き換える
+        let file_argument = if FILE_COMMANDS.contains(&name) {
+            match self.expand_alternate(command_argument(command, name)) {
+                Some(argument) => argument,
+                None => return,
+            }
+        } else {
+            String::new()
+        };
+        match name {
+            "table-format" | "table-row" | "table-col" => self.table_command(name),
+            "grep" => {
+                let pattern = command_argument(command, name);
+                if pattern.is_empty() {
+                    self.status_message = StatusMessage::from("Usage: :grep pattern".to_string());
+                } else if self.can_abandon() {
+                    let document = grep::open(Path::new("."), pattern);
+                    if document.is_empty() {
+                        self.status_message =
+                            StatusMessage::from(format!("No matches for: {pattern}"));
+                    } else {
+                        self.open_document(document);
+                    }
+                }
+            }
+            "edit" | "e" => self.edit_file(&file_argument),
+            "split" | "sp" => {
+                self.split_window(Some(&file_argument[..]).filter(|arg| !arg.is_empty()));
+            }
+            "close" | "clo" => self.close_window(),
+            "quit" | "q" => self.quit(false),
+            "quit!" | "q!" => self.quit(true),
+            "write" | "w" => self.save(),
+            "wq" | "x" | "xit" => {
+                self.save();
+                if !self.document.is_dirty() {
+                    self.quit(false);
+                }
+            }
+            // 呼び出し元(gitなど)に編集を中止したことを伝えるため、失敗の終了ステータスで終了する
+            "cquit" | "cq" | "cq!" => {
+                self.exit_status = 1;
+                self.should_quit = true;
+            }
+            "only" | "on" => self.only_window(),
+            "set" | "se" => {
+                let argument = command_argument(command, name);
+                if WHOLE_ARGUMENT_OPTIONS
+                    .iter()
+                    .any(|prefix| argument.starts_with(prefix))
+                {
+                    self.set_option(argument);
+                } else {
+                    args.for_each(|option| self.set_option(option));
+                }
+            }
+            "count" => self.count(command_argument(command, name)),
+            "rename" => self.rename(&file_argument),
+            "macro-edit" => self.edit_macro(command_argument(command, name)),
+            "grep-edit" => self.edit_grep_results(),
+            "undo" | "u" => self.undo(),
+            "redo" | "red" => self.redo(),
+            "undojoin" | "undoj" => self.document.undo_join(),
+            "DiffOrig" => self.diff_original(),
+            "let" => self.let_register(command_argument(command, name)),
+            "abbreviate" | "ab" => self.abbreviate(command_argument(command, name)),
+            "unabbreviate" | "una" => self.unabbreviate(command_argument(command, name)),
+            "theme" => self.set_theme(command_argument(command, name)),
+            "reveal" => self.reveal(),
+            "substitute-register" => self.substitute_register(command_argument(command, name)),
+            "oldfiles" | "ol" | "recent" => self.show_recent_files(),
+            "messages" | "mes" => self.show_messages(),
+            "trim" => self.trim_trailing_whitespace(),
+            "format" | "fmt" => self.format(),
+            "make" | "mak" => self.make(command_argument(command, name)),
+            "terminal" | "ter" => self.open_terminal(command_argument(command, name)),
+            "pin" => self.pinned = true,
+            "unpin" => self.pinned = false,
+            "list-toggle" => {
+                if !self.document.toggle_checkbox(self.cursor_position.y) {
+                    self.status_message = StatusMessage::from("Not in a list.".to_string());
+                }
+            }
+            "list-renumber" => {
+                if !self.document.renumber_list(self.cursor_position.y) {
+                    self.status_message = StatusMessage::from("Not in a list.".to_string());
+                }
+            }
+            _ => self.run_plugin_command(name, command_argument(command, name)),
+        }
+    }
+    // 表を整形して、カーソルを次(前)のセルに移動する
+    fn move_table_cell(&mut self, forward: bool) {
+        if let Some(position) = self
+            .document
+            .move_table_cell(&self.cursor_position, forward)
+        {
+            self.cursor_position = position;
+        } else if self.cursor_position.x > 0 || forward {
+            // 移動先のセルが無い場合は行末(行頭)に移動
+            self.move_cursor(if forward { Key::End } else { Key::Home });
+        }
+    }
+    // カーソル位置からビジュアルモードの選択を始める
+    fn start_selection(&mut self, linewise: bool) {
+        self.selection = Some(Selection {
+            anchor: self.cursor_position.clone(),
+            linewise,
+        });
+    }
+    // ビジュアルモードで選択に対する操作のキーを処理し、処理したキーと選択中は使えないキーではtrueを返す
+    // 移動や検索のキーはfalseを返してノーマルモードと同じように処理し、カーソル側の選択の端を動かす
+    fn process_visual_key(&mut self, key: Key) -> bool {
+        let Some(selection) = self.selection.clone() else {
+            return false;
+        };
+        // 入力途中のコマンド(文字検索や印への移動など)の続きはノーマルモードと同じように処理する
+        // ただしgJとgiは選択中は使えず、"{レジスタ}に続くキーは選択に対する操作のみ使える
+        let editable = self.document.is_editable();
+        let name = match (&self.pending_keys[..], key) {
+            ("", _) => None,
+            ("g", Key::Char('J' | 'i')) => {
+                self.pending_keys.clear();
+                return true;
+            }
+            // gcで選択している行のコメントを切り替える
+            ("g", Key::Char('c')) if editable => {
+                self.pending_keys.clear();
+                self.toggle_selection_comment(&selection);
+                return true;
+            }
+            (pending, _) if pending.starts_with('"') && pending.chars().count() == 2 => {
+                pending.chars().nth(1)
+            }
+            _ => return false,
+        };
+        match key {
+            Key::Esc => self.selection = None,
+            // vとVで選択の単位を切り替え、同じ単位のキーならビジュアルモードを抜ける
+            Key::Char(c @ ('v' | 'V')) => {
+                let linewise = c == 'V';
+                self.selection = (selection.linewise != linewise).then_some(Selection {
+                    linewise,
+                    ..selection
+                });
+            }
+            // oでカーソルを選択の反対側の端に移動する
+            Key::Char('o') => {
+                self.selection = Some(Selection {
+                    anchor: self.cursor_position.clone(),
+                    ..selection
+                });
+                self.cursor_position = selection.anchor;
+            }
+            // コマンドの入力中は選択を残し、実行した後にビジュアルモードを抜ける
+            Key::Char(':') if name.is_none() => {
+                self.command();
+                self.selection = None;
+            }
+            // yでヤンク、dとxで削除、cで削除して挿入モードに移行する
+            Key::Char('y') => self.apply_selection(name, 'y', &selection),
+            Key::Char(operator @ ('d' | 'x' | 'c')) if editable => {
+                self.apply_selection(name, operator, &selection);
+            }
+            Key::Delete if editable => self.apply_selection(name, 'd', &selection),
+            // Ctrl-/(端末からはCtrl-7として届く)でも選択している行のコメントを切り替える
+            Key::Ctrl('7') if editable => self.toggle_selection_comment(&selection),
+            // Alt-kとAlt-jで選択している行をまとめて上下に移動し、Alt-dで複製する
+            Key::Alt(c @ ('k' | 'j')) if editable => self.move_selected_lines(&selection, c == 'k'),
+            Key::Alt('d') if editable => self.duplicate_selected_lines(&selection),
+            // >と<で選択している行のインデントを1段深く(浅く)する
+            Key::Char(c @ ('>' | '<')) if editable && name.is_none() => {
+                let lines = selection.lines(&self.cursor_position);
+                self.document
+                    .shift_indent(lines.start, lines.len(), c == '>');
+                self.leave_selection(lines.start);
+            }
+            // 選択中は挿入モードへの移行や、カーソル位置を編集するノーマルモードのコマンドは使えない
+            Key::Char(
+                'i' | 'x' | 'J' | 'p' | 'P' | 'u' | '.' | 'd' | 'c' | '<' | '>' | 'q' | '@' | '\n'
+                | '-' | 'K',
+            )
+            | Key::Ctrl('r' | 'w' | '6' | '7')
+            | Key::Alt(_)
+            | Key::Delete => (),
+            _ if name.is_some() => (),
+            _ => return false,
+        }
+        self.pending_keys.clear();
+        true
+    }
+    // 選択している行を上(upがfalseの場合は下)の行と入れ替え、選択も一緒に移動する
+    fn move_selected_lines(&mut self, selection: &Selection, up: bool) {
+        let lines = selection.lines(&self.cursor_position);
+        if !self.document.move_rows(lines.start, lines.end, up) {
+            return;
+        }
+        let moved = |y: usize| {
+            if up {
+                y.saturating_sub(1)
+            } else {
+                y.saturating_add(1)
+            }
+        };
+        self.move_selection(selection, moved);
+        self.renumber_list(lines.start.saturating_sub(usize::from(up)));
+    }
+    // 選択している行を複製し、複製した行を選択する
+    fn duplicate_selected_lines(&mut self, selection: &Selection) {
+        let lines = selection.lines(&self.cursor_position);
+        let count = self.document.duplicate_rows(lines.start, lines.end);
+        self.move_selection(selection, |y| y.saturating_add(count));
+        self.renumber_list(lines.start);
+    }
+    // 選択の両端とカーソルを、行を移動した先に移す
+    fn move_selection(&mut self, selection: &Selection, moved: impl Fn(usize) -> usize) {
+        self.selection = Some(Selection {
+            anchor: Position {
+                x: selection.anchor.x,
+                y: moved(selection.anchor.y),
+            },
+            linewise: selection.linewise,
+        });
+        self.cursor_position.y = moved(self.cursor_position.y);
+    }
+    // 選択している行のコメントを切り替えてビジュアルモードを抜ける
+    fn toggle_selection_comment(&mut self, selection: &Selection) {
+        let lines = selection.lines(&self.cursor_position);
+        if self.document.toggle_comment(lines.start, lines.len()) {
+            self.leave_selection(lines.start);
+        } else {
+            self.status_message =
+                StatusMessage::from("No line comment for this filetype".to_string());
+        }
+    }
+    // 選択している行を変更した後にビジュアルモードを抜け、y行目の最初の空白でない文字に移動する
+    fn leave_selection(&mut self, y: usize) {
+        self.selection = None;
+        self.cursor_position = Position {
+            x: self.document.first_non_blank(y),
+            y,
+        };
+    }
+    // 選択している範囲をヤンク(y)、削除(d、x)、または削除して挿入モードに移行(c)し、ビジュアルモードを抜ける
+    // 文字単位の選択は行をまたいでいても1回の変更として削除する
+    fn apply_selection(&mut self, name: Option<char>, operator: char, selection: &Selection) {
+        self.selection = None;
+        if selection.linewise {
+            let Range { start, end } = selection.lines(&self.cursor_position);
+            let lines = self.document.lines(start, end);
+            if lines.is_empty() {
+                return;
+            }
+            let register = Register {
+                lines,
+                linewise: true,
+            };
+            match operator {
+                'y' => {
+                    self.registers.yank(name, register);
+                    self.leave_selection(start);
+                }
+                // 行単位の選択を変更する場合は選択した行を1つの空行に置き換える
+                'c' => {
+                    self.registers.delete(name, register);
+                    self.document.replace_lines(start, end, &[String::new()]);
+                    self.cursor_position = Position { x: 0, y: start };
+                    self.vim_normal_mode = false;
+                }
+                _ => {
+                    self.delete_lines(name, start..end);
+                }
+            }
+            return;
+        }
+        let (start, end) = self.selected_range(selection);
+        self.cursor_position = start.clone();
+        if operator == 'y' {
+            let lines = self.document.text_range(&start, &end);
+            self.registers.yank(
+                name,
+                Register {
+                    lines,
+                    linewise: false,
+                },
+            );
+            return;
+        }
+        let deleted = self.document.delete_range(&start, &end);
+        self.registers.delete(
+            name,
+            Register {
+                lines: deleted.split('\n').map(str::to_string).collect(),
+                linewise: false,
+            },
+        );
+        self.renumber_list(start.y);
+        if operator == 'c' {
+            self.vim_normal_mode = false;
+        }
+    }
+    // ビジュアルモードで選択している文字列を行ごとに返す(ビジュアルモードでなければ空)
+    fn selected_lines(&self) -> Vec<String> {
+        match &self.selection {
+            Some(selection) if selection.linewise => {
+                let Range { start, end } = selection.lines(&self.cursor_position);
+                self.document.lines(start, end)
+            }
+            Some(selection) => {
+                let (start, end) = self.selected_range(selection);
+                self.document.text_range(&start, &end)
+            }
+            None => Vec::new(),
+        }
+    }
+    // 文字単位の選択の最初の位置と、最後の文字の次の位置を返す
+    // 行末の改行まで選択している場合は次の行の先頭までとする
+    fn selected_range(&self, selection: &Selection) -> (Position, Position) {
+        let (start, end) = selection.bounds(&self.cursor_position);
+        let (start, end) = (self.document.clamp(&start), self.document.clamp(&end));
+        let len = self.document.row(end.y).map_or(0, Row::len);
+        let end = if end.x < len {
+            Position {
+                x: end.x.saturating_add(1),
+                y: end.y,
+            }
+        } else if end.y.saturating_add(1) < self.document.len() {
+            Position {
+                x: 0,
+                y: end.y.saturating_add(1),
+            }
+        } else {
+            end
+        };
+        (start, end)
+    }
+    // 入力途中のノーマルモードのコマンドを解釈し、完結していれば実行する
+    fn process_pending_keys(&mut self) {
+        match &self.pending_keys[..] {
+            // 続くキーを待つ
+            "g" | "gc" | "z" | "q" | "m" | "<" | ">" | "@" | "'" | "`" | "f" | "t" | "F" | "T" => {
+                return
+            }
+            "q:" => self.open_command_window(),
+            // gccでカーソル行のコメントを切り替える
+            "gcc" if self.document.is_editable() => self.toggle_comment(),
+            // >>と<<でカーソル行のインデントを1段深く(浅く)する
+            ">>" | "<<" if self.document.is_editable() => {
+                let y = self.cursor_position.y;
+                self.document.shift_indent(y, 1, self.pending_keys == ">>");
+                self.cursor_position.x = self.document.first_non_blank(y);
+            }
+            // gJで空白を入れずにカーソル行と次の行を結合する
+            "gJ" if self.document.is_editable() => self.join_lines(false),
+            // "{レジスタ}に続けて、またはレジスタを指定せずにヤンク、削除、貼り付けをする
+            _ if self.pending_keys.starts_with(['"', 'y', 'd', 'c']) => {
+                if self.process_register_keys() {
+                    self.pending_keys.clear();
+                }
+                return;
+            }
+            "gf" | "gF" => self.goto_file(),
+            // z=でカーソル位置の単語の修正候補を表示し、zgで単語を辞書に加える
+            "z=" => self.suggest_spelling(),
+            "zg" => self.add_to_dictionary(),
+            "zz" | "zt" | "zb" => {
+                let place = self.pending_keys.chars().nth(1).unwrap_or_default();
+                self.scroll_cursor_line(place);
+            }
+            // gdで言語サーバにカーソル位置の定義を問い合わせて移動する
+            "gd" => self.request_language_server(lsp::Request::Definition),
+            // 最後に挿入モードを抜けた位置から挿入を再開する
+            "gi" if self.document.is_editable() => {
+                if let Some(position) = self.document.mark('^') {
+                    self.cursor_position = position;
+                }
+                self.vim_normal_mode = false;
+            }
+            // g;とg,で変更位置の履歴を古い方と新しい方に辿る
+            "g;" | "g," => {
+                let result = if self.pending_keys == "g;" {
+                    self.document.older_change()
+                } else {
+                    self.document.newer_change()
+                };
+                match result {
+                    Ok(position) => self.cursor_position = position,
+                    Err(message) => self.status_message = StatusMessage::from(message.to_string()),
+                }
+            }
+            "za" => {
+                if let Some(y) = self.document.toggle_fold(self.cursor_position.y) {
+                    // 折りたたんだ行の先頭にカーソルを移動
+                    if y != self.cursor_position.y {
+                        self.cursor_position = Position { x: 0, y };
+                    }
+                } else {
+                    self.status_message = StatusMessage::from("No fold found.".to_string());
+                }
+            }
+            // q{レジスタ}でマクロの記録を開始し、@{レジスタ}で実行する
+            _ if self.pending_keys.starts_with('q') => {
+                if let Some(register) = self.pending_keys.chars().nth(1) {
+                    if register.is_ascii_alphanumeric() {
+                        self.recording = Some((register, Vec::new()));
+                    }
+                }
+            }
+            _ if self.pending_keys.starts_with('@') => {
+                if let Some(register) = self.pending_keys.chars().nth(1) {
+                    self.play_macro(register);
+                }
+            }
+            // m{印}でカーソル位置に印を付ける(大文字の印はファイルをまたいで使える)
+            _ if self.pending_keys.starts_with('m') => {
+                let mark = self.pending_keys.chars().nth(1).unwrap_or_default();
+                self.set_mark(mark);
+            }
+            // '{印}で印の行の最初の空白でない文字に、`{印}で印の位置に移動する
+            _ if self.pending_keys.starts_with(['\'', '`']) => {
+                let mark = self.pending_keys.chars().nth(1).unwrap_or_default();
+                // 大文字の印は先に印を付けたファイルを開く
+                if mark.is_ascii_uppercase() && !self.open_file_mark(mark) {
+                    self.pending_keys.clear();
+                    return;
+                }
+                if let Some(mut position) = self.document.mark(mark) {
+                    if self.pending_keys.starts_with('\'') {
+                        position.x = self.document.first_non_blank(position.y);
+                    }
+                    self.cursor_position = position;
+                } else {
+                    self.status_message = StatusMessage::from("Mark not set.".to_string());
+                }
+            }
+            // f{文字}、t{文字}、F{文字}、T{文字}でカーソル行の中を文字検索して移動する
+            _ if self.pending_keys.starts_with(['f', 't', 'F', 'T']) => {
+                self.move_to_char(&self.pending_keys.clone());
+            }
+            _ if self.pending_keys.starts_with(WINDOW_PREFIX) => {
+                // Ctrl-Wに続くキーでウィンドウを操作する
+                match self.pending_keys.chars().nth(1) {
+                    Some('w' | 'j') => self.focus_next_window(true),
+                    Some('W' | 'k') => self.focus_next_window(false),
+                    Some('s') => self.split_window(None),
+                    Some('c') => self.close_window(),
+                    Some('o') => self.only_window(),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+        self.pending_keys.clear();
+    }
+    // レジスタを使うコマンドを解釈して実行する。続くキーを待つ場合はfalseを返す
+    fn process_register_keys(&mut self) -> bool {
+        let (name, command) = match self.pending_keys.strip_prefix('"') {
+            Some(rest) => {
+                let mut chars = rest.chars();
+                let Some(name) = chars.next() else {
+                    return false;
+                };
+                if !Registers::is_valid(name) {
+                    self.status_message =
+                        StatusMessage::from(format!("Invalid register name: {name}"));
+                    return true;
+                }
+                (Some(name), chars.as_str().to_string())
+            }
+            None => (None, self.pending_keys.clone()),
+        };
+        let editable = self.document.is_editable();
+        match &command[..] {
+            "" | "y" | "d" | "c" | "yi" | "ya" | "di" | "da" | "ci" | "ca" => return false,
+            "yy" => {
+                let lines = self.document.lines(
+                    self.cursor_position.y,
+                    self.cursor_position.y.saturating_add(1),
+                );
+                if !lines.is_empty() {
+                    self.registers.yank(
+                        name,
+                        Register {
+                            lines,
+                            linewise: true,
+                        },
+                    );
+                }
+            }
+            "dd" if editable => self.delete_line(name),
+            "x" if editable => self.delete_char(name),
+            "p" | "P" if editable => self.paste(name.unwrap_or('"'), command == "p"),
+            _ => {
+                let mut chars = command.chars();
+                let keys = (chars.next(), chars.next(), chars.next(), chars.next());
+                // 文字検索は続く文字を待つ
+                if let (Some(_), Some('f' | 't' | 'F' | 'T'), None, None) = keys {
+                    return false;
+                }
+                if !command.starts_with('y') && !editable {
+                    return true;
+                }
+                match keys {
+                    // {操作}{iまたはa}{テキストオブジェクト}
+                    (Some(operator), Some(kind @ ('i' | 'a')), Some(object), None) => {
+                        if let Some(object) = TextObject::from_char(object) {
+                            self.apply_text_object(name, operator, object, kind == 'a');
+                        }
+                    }
+                    // {操作}{f、t、F、T}{文字}、または{操作}{;または,}で文字検索した位置まで
+                    (Some(operator), Some(_), _, None) => {
+                        let keys = command.get(operator.len_utf8()..).unwrap_or_default();
+                        self.apply_char_search(name, operator, keys);
+                    }
+                    _ => (),
+                }
+            }
+        }
+        true
+    }
+    // テキストオブジェクトの範囲をヤンク(y)、削除(d)、または削除して挿入モードに移行(c)する
+    fn apply_text_object(
+        &mut self,
+        name: Option<char>,
+        operator: char,
+        object: TextObject,
+        around: bool,
+    ) {
+        let Some((start, end)) = self
+            .document
+            .row(self.cursor_position.y)
+            .and_then(|row| row.text_object(self.cursor_position.x, object, around))
+        else {
+            return;
+        };
+        self.apply_operator(name, operator, start, end);
+    }
+    // カーソル行から文字検索した位置までをヤンク、削除、または削除して挿入モードに移行する
+    fn apply_char_search(&mut self, name: Option<char>, operator: char, keys: &str) {
+        let at = self.cursor_position.x;
+        if let Some((search, found)) = self.char_search(keys) {
+            let (start, end) = search.range(at, found);
+            self.apply_operator(name, operator, start, end);
+        }
+    }
+    // カーソル行のstart番目からend番目の手前までをヤンク(y)、削除(d)、または削除して挿入モードに移行(c)する
+    fn apply_operator(&mut self, name: Option<char>, operator: char, start: usize, end: usize) {
+        let y = self.cursor_position.y;
+        let Some(row) = self.document.row(y) else {
+            return;
+        };
+        let register = Register {
+            lines: vec![row.substring(start, end)],
+            linewise: false,
+        };
+        self.cursor_position = Position { x: start, y };
+        if operator == 'y' {
+            self.registers.yank(name, register);
+            return;
+        }
+        self.registers.delete(name, register);
+        self.document
+            .delete_range(&self.cursor_position, &Position { x: end, y });
+        if operator == 'c' {
+            self.vim_normal_mode = false;
+        }
+    }
+    // 文字検索のキー(f{文字}、t{文字}、F{文字}、T{文字}、;、,)でカーソル行の中を検索し、検索と移動先を返す
+    // ;は最後の検索を繰り返し、,は逆向きに繰り返す
+    fn char_search(&mut self, keys: &str) -> Option<(CharSearch, usize)> {
+        let mut chars = keys.chars();
+        let (search, repeat) = match (chars.next()?, chars.next(), chars.next()) {
+            (';', None, _) => (self.last_char_search?, true),
+            (',', None, _) => (self.last_char_search?.reversed(), true),
+            (kind, Some(target), None) => {
+                let search = CharSearch::new(kind, target)?;
+                self.last_char_search = Some(search);
+                (search, false)
+            }
+            _ => return None,
+        };
+        let found = self.document.row(self.cursor_position.y)?.char_search(
+            self.cursor_position.x,
+            search,
+            repeat,
+        )?;
+        Some((search, found))
+    }
+    // 文字検索した位置にカーソルを移動する(見つからなければ移動しない)
+    fn move_to_char(&mut self, keys: &str) {
+        if let Some((_, x)) = self.char_search(keys) {
+            self.cursor_position.x = x;
+        }
+    }
+    // 対応する括弧に移動する
+    fn move_to_matching_bracket(&mut self) {
+        if let Some(position) = self.document.matching_bracket(&self.cursor_position) {
+            self.cursor_position = position;
+        }
+    }
+    // カーソル行を削除してレジスタに保存する
+    fn delete_line(&mut self, name: Option<char>) {
+        let y = self.cursor_position.y;
+        self.delete_lines(name, y..y.saturating_add(1));
+    }
+    // 範囲の行を削除してレジスタに保存し、削除した行数を返す
+    fn delete_lines(&mut self, name: Option<char>, range: Range<usize>) -> usize {
+        let lines = self.document.delete_lines(range.start, range.len());
+        let count = lines.len();
+        if lines.is_empty() {
+            return 0;
+        }
+        self.renumber_list(range.start);
+        self.registers.delete(
+            name,
+            Register {
+                lines,
+                linewise: true,
+            },
+        );
+        self.cursor_position.y = range.start.min(self.document.len().saturating_sub(1));
+        self.cursor_position.x = self.document.first_non_blank(self.cursor_position.y);
+        count
+    }
+    // カーソル位置の文字を削除してレジスタに保存する(行末では次の行と結合する)
+    fn delete_char(&mut self, name: Option<char>) {
+        let Some(row) = self.document.row(self.cursor_position.y) else {
+            return;
+        };
+        let lines = match row.grapheme(self.cursor_position.x) {
+            Some(grapheme) => vec![grapheme.to_string()],
+            None => vec![String::new(), String::new()],
+        };
+        self.registers.delete(
+            name,
+            Register {
+                lines,
+                linewise: false,
+            },
+        );
+        self.delete_at_cursor();
+    }
+    // レジスタの内容をカーソルの後ろ(afterがfalseの場合は前)に貼り付ける
+    fn paste(&mut self, name: char, after: bool) {
+        let Some(register) = self.registers.get(name).cloned() else {
+            self.status_message = StatusMessage::from(format!("Register \"{name} is empty."));
+            return;
+        };
+        if register.linewise {
+            let y = self
+                .cursor_position
+                .y
+                .saturating_add(usize::from(after && !self.document.is_empty()));
+            self.document.insert_lines(y, &register.lines);
+            self.cursor_position = Position {
+                x: self.document.first_non_blank(y),
+                y,
+            };
+            return;
+        }
+        // 空でない行ではカーソル位置の文字の後ろに貼り付ける
+        if after
+            && self
+                .document
+                .row(self.cursor_position.y)
+                .is_some_and(|row| row.len() > 0)
+        {
+            self.move_cursor(Key::Right);
+        }
+        let text = register.lines.join("\n");
+        self.cursor_position = self.document.insert_str(&self.cursor_position, &text);
+        // 貼り付けたテキストの最後の文字にカーソルを置く
+        if !text.ends_with('\n') {
+            self.move_cursor(Key::Left);
+        }
+    }
+    // プロンプトでCtrl-Rに続くキーを読み、挿入する文字列を返す
+    // Ctrl-Wの場合はカーソル位置の単語を返し、ビジュアルモードの"の場合は選択している文字列を返す
+    fn prompt_register_text(&mut self) -> Result<Option<String>, std::io::Error> {
+        let text = match self.read_key()? {
+            Key::Ctrl('w') => self
+                .document
+                .row(self.cursor_position.y)
+                .and_then(|row| row.word_at(self.cursor_position.x)),
+            Key::Char('"') if self.selection.is_some() => self.selected_lines().into_iter().next(),
+            // プロンプトは1行なので最初の行だけを挿入する
+            Key::Char(name) => self
+                .registers
+                .get(name)
+                .and_then(|register| register.lines.first().cloned()),
+            _ => None,
+        };
+        Ok(text)
+    }
+    // レジスタの内容を検索文字列にした置換コマンドを入力させる
+    // レジスタを省略した場合、ビジュアルモードでは選択している文字列、それ以外では無名レジスタの内容を使う
+    fn substitute_register(&mut self, argument: &str) {
+        let name = argument.chars().next();
+        let text = if name.is_none() && self.selection.is_some() {
+            self.selected_lines().into_iter().next()
+        } else {
+            self.registers
+                .get(name.unwrap_or('"'))
+                .and_then(|register| register.lines.first().cloned())
+        };
+        let Some(text) = text.filter(|text| !text.is_empty()) else {
+            let name = name.unwrap_or('"');
+            self.status_message = StatusMessage::from(format!("Register \"{name} is empty."));
+            return;
+        };
+        // 区切り文字の/はエスケープする
+        let pattern = text.replace('/', "\\/");
+        self.command_with(&format!("%s/{pattern}/"));
+    }
+    // マウス操作を処理する
+    fn process_mouse(&mut self, event: MouseEvent) {
+        let MouseEvent::Press(MouseButton::Left, x, y) = event else {
+            return;
+        };
+        // 画面上の位置(1から始まる)をドキュメント上の位置に変換する
+        let Some((index, window_y)) = self.window_at(usize::from(y).saturating_sub(1)) else {
+            return;
+        };
+        // 他のウィンドウがクリックされた場合はそのウィンドウをアクティブにする
+        self.focus_window(index);
+        let doc_y = self.document.folds().visible_below(self.offset.y, window_y);
+        let Some(row) = self.document.row(doc_y) else {
+            return;
+        };
+        let half_width_x = usize::from(x)
+            .saturating_sub(1)
+            .saturating_sub(self.gutter_width(&self.document));
+        self.cursor_position = Position {
+            x: row.half2full_position(self.offset.x, half_width_x),
+            y: doc_y,
+        };
+        // 同じ位置を続けてクリックしたらその位置のファイルを開く
+        let double_click = self.last_click.is_some_and(|(time, last_x, last_y)| {
+            time.elapsed() < DOUBLE_CLICK_INTERVAL && last_x == x && last_y == y
+        });
+        if double_click {
+            self.last_click = None;
+            self.goto_file();
+        } else {
+            self.last_click = Some((Instant::now(), x, y));
+        }
+    }
+    // マクロの実行中であれば溜められたキーを、そうでなければ端末からの入力を読み込む
+    // マクロの記録中は端末から入力されたキーを記録する
+    // 一定時間入力が無ければNoneを返す
+    fn read_event(&mut self) -> Result<Option<Event>, std::io::Error> {
+        if let Some(key) = self.input_queue.pop_front() {
+            return Ok(Some(Event::Key(key)));
+        }
+        if let Some(event) = self.pending_event.take() {
+            return Ok(Some(event));
+        }
+        let event = self.terminal.read_event(TICK_INTERVAL)?;
+        self.record_key(event.as_ref());
+        Ok(event)
+    }
+    // マクロの記録中であればキーを記録する
+    fn record_key(&mut self, event: Option<&Event>) {
+        if let (Some((_, keys)), Some(Event::Key(key))) = (&mut self.recording, event) {
+            keys.push(*key);
+        }
+    }
+    // 端末に既に届いている入力が文字であれば返す
+    // IMEで確定した文字列や貼り付けた文字列は一度に届くので、描画を挟まずにまとめて挿入するために使う
+    // 文字以外の入力は次にread_eventで読む
+    fn read_pending_char(&mut self) -> Option<Key> {
+        if !self.input_queue.is_empty() || self.pending_event.is_some() {
+            return None;
+        }
+        // 読めなければ次のread_eventで改めてエラーを返す
+        let event = self.terminal.read_event(Duration::ZERO).ok()??;
+        self.record_key(Some(&event));
+        match event {
+            Event::Key(key @ Key::Char(_)) => Some(key),
+            event => {
+                self.pending_event = Some(event);
+                None
+            }
+        }
+    }
+    fn read_key(&mut self) -> Result<Key, std::io::Error> {
+        loop {
+            if let Some(Event::Key(key)) = self.read_event()? {
+                return Ok(key);
+            }
+        }
+    }
+    // 入力を処理し、一定時間入力が無ければ時間経過による処理を行う
+    // 画面の再描画が必要な場合はtrueを返す
+    fn process_event(&mut self) -> Result<bool, std::io::Error> {
+        let received = self.receive_rows();
+        self.move_to_pending_location();
+        let received = self.receive_job_output() || received;
+        let received = self.receive_shell_output() || received;
+        let Some(event) = self.read_event()? else {
+            return Ok(self.tick()? || received);
+        };
+        self.last_input = Some(Instant::now());
+        self.last_key_time = Instant::now();
+        if let Event::Key(key) = event {
+            self.change_keys.push(key);
+        }
+        self.process_keypress(&event);
+        // 挿入モードで続けて届いている文字は、描画せずにまとめて挿入する
+        if matches!(event, Event::Key(Key::Char(_))) {
+            while !self.vim_normal_mode && self.prompt_cursor.is_none() {
+                let Some(key) = self.read_pending_char() else {
+                    break;
+                };
+                self.change_keys.push(key);
+                self.process_keypress(&Event::Key(key));
+            }
+        }
+        self.notify_plugins();
+        self.log_message();
+        self.sync_language_servers();
+        // 挿入モードの間の変更は1回で取り消せるようにまとめる
+        if self.vim_normal_mode && self.pending_keys.is_empty() {
+            // ドキュメントを変更したコマンドであれば.で繰り返せるように記録する
+            // :や/はプロンプトで入力した内容を記録できないので繰り返さない
+            let keys = mem::take(&mut self.change_keys);
+            if self.document.commit_undo()
+                && !keys
+                    .first()
+                    .is_some_and(|key| NOT_REPEATABLE_KEYS.contains(key))
+            {
+                self.last_change = keys;
+            }
+        }
+        Ok(true)
+    }
+    // 言語サーバが設定されているファイルタイプのファイルの内容を送り、届いた診断結果を受け取る
+    // 診断結果が変わって画面の再描画が必要な場合はtrueを返す
+    fn sync_language_servers(&mut self) -> bool {
+        let documents =
+            iter::once(&self.document).chain(self.windows.iter().map(|window| &window.document));
+        for document in documents {
+            let (Some(file_name), BufferKind::File, None) = (
+                &document.file_name,
+                document.kind(),
+                document.loading_progress(),
+            ) else {
+                continue;
+            };
+            let file_type = document.file_type();
+            let Some(command) =
+                lsp::server_command(self.options.language_servers.as_deref(), &file_type)
+            else {
+                continue;
+            };
+            let client = self
+                .language_servers
+                .entry(file_type.clone())
+                .or_insert_with(|| match lsp::Client::start(&command) {
+                    Ok(client) => Some(client),
+                    Err(error) => {
+                        self.status_message = StatusMessage::from(format!(
+                            "ERR: Could not start language server: {command}: {error}"
+                        ));
+                        None
+                    }
+                });
+            let (Some(client), Ok(path)) = (client, fs::canonicalize(file_name)) else {
+                continue;
+            };
+            let edit_count = document.edit_count();
+            let sent = if !client.is_open(&path) {
+                let language_id = file_type.to_lowercase().replace(' ', "");
+                client.did_open(&path, &language_id, &document.contents())
+            } else if self.lsp_synced.get(&path) != Some(&edit_count) {
+                client.did_change(&path, &document.contents())
+            } else {
+                Ok(())
+            };
+            if sent.is_ok() {
+                self.lsp_synced.insert(path, edit_count);
+            }
+        }
+        let mut changed = false;
+        for client in self.language_servers.values_mut() {
+            let Some(server) = client else {
+                continue;
+            };
+            match server.receive() {
+                Ok(received) => changed = changed || received,
+                Err(error) => {
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Language server: {error}"));
+                    *client = None;
+                    changed = true;
+                }
+            }
+        }
+        self.receive_response();
+        changed
+    }
+    // カーソル位置についての要求を言語サーバに送る(応答はsync_language_serversで受け取る)
+    fn request_language_server(&mut self, request: lsp::Request) {
+        let file_type = self.document.file_type();
+        let Some(Some(client)) = self.language_servers.get_mut(&file_type) else {
+            self.status_message =
+                StatusMessage::from("No language server for this file.".to_string());
+            return;
+        };
+        let Some(path) = self
+            .document
+            .file_name
+            .as_ref()
+            .and_then(|file_name| fs::canonicalize(file_name).ok())
+        else {
+            self.status_message = StatusMessage::from("No file name.".to_string());
+            return;
+        };
+        let Position { x, y } = self.cursor_position;
+        let column = self
+            .document
+            .row(y)
+            .map_or(0, |row| lsp::utf16_column(row.as_str(), x));
+        match client.request(request, &path, (y, column)) {
+            Ok(id) => self.lsp_request = Some((file_type, id, request)),
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: Language server: {error}"));
+            }
+        }
+    }
+    // 待っている要求への応答が届いていれば、定義へ移動するかホバーを表示する
+    fn receive_response(&mut self) {
+        let Some((file_type, id, request)) = &self.lsp_request else {
+            return;
+        };
+        let result = match self.language_servers.get_mut(file_type) {
+            Some(Some(client)) => match client.take_response(*id) {
+                Some(result) => result,
+                None => return,
+            },
+            // 応答を受け取る前にサーバが終了した場合
+            _ => serde_json::Value::Null,
+        };
+        let request = *request;
+        self.lsp_request = None;
+        match request {
+            lsp::Request::Definition => {
+                let Some((path, (y, column))) = lsp::parse_locations(&result).into_iter().next()
+                else {
+                    self.status_message = StatusMessage::from("No definition found.".to_string());
+                    return;
+                };
+                self.open_location(&Location {
+                    path: explorer::relative(&path),
+                    line: Some(y.saturating_add(1)),
+                    column: None,
+                });
+                // 開けなかった場合は別のファイルのままなので列を合わせない
+                let opened = self
+                    .document
+                    .file_name
+                    .as_ref()
+                    .and_then(|file_name| fs::canonicalize(file_name).ok());
+                if opened == Some(path) {
+                    if let Some(row) = self.document.row(self.cursor_position.y) {
+                        self.cursor_position.x = lsp::grapheme_index(row.as_str(), column);
+                    }
+                    self.scroll();
+                }
+            }
+            lsp::Request::Hover => {
+                self.hover = lsp::parse_hover(&result);
+                if self.hover.is_empty() {
+                    self.status_message = StatusMessage::from("No hover information.".to_string());
+                }
+            }
+        }
+    }
+    // ホバーの内容をカーソル位置(画面上の座標)の上、入りきらなければ下に枠で囲んで表示する
+    fn draw_hover(&self, cursor: &Position) {
+        if self.hover.is_empty() {
+            return;
+        }
+        let max_width = usize::from(self.terminal.size().width).saturating_sub(2);
+        let inner_width = self
+            .hover
+            .iter()
+            .map(|line| width::str_width(line))
+            .max()
+            .unwrap_or_default()
+            .min(max_width);
+        let (Position { x, y }, height) = self.popup_area(
+            cursor,
+            inner_width.saturating_add(2),
+            self.hover.len().saturating_add(2),
+            true,
+        );
+        let border = "─".repeat(inner_width);
+        Terminal::set_bg_color(self.theme.status_bg);
+        Terminal::set_fg_color(self.theme.status_fg);
+        for row in 0..height {
+            Terminal::cursor_position(&Position {
+                x,
+                y: y.saturating_add(row),
+            });
+            if row == 0 {
+                Terminal::print(format_args!("┌{border}┐"));
+            } else if row == height.saturating_sub(1) {
+                Terminal::print(format_args!("└{border}┘"));
+            } else {
+                let line = self
+                    .hover
+                    .get(row.saturating_sub(1))
+                    .map_or("", |line| width::truncate(line, inner_width));
+                let padding = " ".repeat(inner_width.saturating_sub(width::str_width(line)));
+                Terminal::print(format_args!("│{line}{padding}│"));
+            }
+        }
+        Terminal::reset_fg_color();
+        Terminal::reset_bg_color();
+    }
+    // 補完の候補をカーソル位置(画面上の座標)の下、入りきらなければ上に表示する
+    fn draw_completion(&self, cursor: &Position) {
+        let Some(completion) = self.completion.as_ref().filter(|_| !self.vim_normal_mode) else {
+            return;
+        };
+        // 入力中の単語の先頭に揃える
+        let start = Position {
+            x: cursor
+                .x
+                .saturating_sub(width::str_width(&completion.prefix).saturating_add(1)),
+            y: cursor.y,
+        };
+        self.draw_candidates(completion, &start);
+    }
+    // スペルの修正候補を単語の先頭にあるカーソル(画面上の座標)の下、入りきらなければ上に表示する
+    fn draw_spell_suggestions(&self, cursor: &Position) {
+        if let Some(suggestions) = &self.spell_suggestions {
+            let start = Position {
+                x: cursor.x.saturating_sub(1),
+                y: cursor.y,
+            };
+            self.draw_candidates(suggestions, &start);
+        }
+    }
+    // 候補の一覧を選択中の候補の色を反転して、startの位置(画面上の座標)の下、入りきらなければ上に表示する
+    fn draw_candidates(&self, completion: &Completion, start: &Position) {
+        let max_width = usize::from(self.terminal.size().width).saturating_sub(2);
+        let inner_width = completion
+            .candidates
+            .iter()
+            .map(|candidate| width::str_width(candidate))
+            .max()
+            .unwrap_or_default()
+            .min(max_width);
+        let (Position { x, y }, height) = self.popup_area(
+            start,
+            inner_width.saturating_add(2),
+            completion.candidates.len().min(MAX_COMPLETION_ROWS),
+            false,
+        );
+        // 選択中の候補が見えるようにスクロールする
+        let first = completion.selected.map_or(0, |selected| {
+            selected.saturating_add(1).saturating_sub(height)
+        });
+        for (row, candidate) in completion
+            .candidates
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(height)
+        {
+            let (bg, fg) = if completion.selected == Some(row) {
+                (self.theme.status_fg, self.theme.status_bg)
+            } else {
+                (self.theme.status_bg, self.theme.status_fg)
+            };
+            Terminal::cursor_position(&Position {
+                x,
+                y: y.saturating_add(row.saturating_sub(first)),
+            });
+            let text = width::truncate(candidate, inner_width);
+            let padding = " ".repeat(inner_width.saturating_sub(width::str_width(text)));
+            Terminal::set_bg_color(bg);
+            Terminal::set_fg_color(fg);
+            Terminal::print(format_args!(" {text}{padding} "));
+        }
+        Terminal::reset_fg_color();
+        Terminal::reset_bg_color();
+    }
+    // カーソル位置(画面上の座標)の上(または下)に重ねて表示する枠の左上の位置と高さ
+    // 文書を表示している行の範囲に収め、入りきらなければ広い方に表示する
+    fn popup_area(
+        &self,
+        cursor: &Position,
+        width: usize,
+        height: usize,
+        prefer_above: bool,
+    ) -> (Position, usize) {
+        let top = self.window_top();
+        let bottom = top.saturating_add(self.window_height());
+        let above = cursor.y.saturating_sub(top);
+        let below = bottom.saturating_sub(cursor.y.saturating_add(1));
+        let height = height.min(above.max(below));
+        let fits = if prefer_above { above } else { below };
+        let use_above = if height <= fits {
+            prefer_above
+        } else {
+            above >= below
+        };
+        let y = if use_above {
+            cursor.y.saturating_sub(height)
+        } else {
+            cursor.y.saturating_add(1)
+        };
+        let x = cursor
+            .x
+            .min(usize::from(self.terminal.size().width).saturating_sub(width));
+        // 枠を重ねる行は、枠を閉じた後の描画で文書の内容を出力し直す
+        self.forget_drawn_rows(y..y.saturating_add(height));
+        (Position { x, y }, height)
+    }
+    // ドキュメントに対して言語サーバから届いている診断結果
+    fn diagnostics(&self, document: &Document) -> &[lsp::Diagnostic] {
+        let Some(Some(client)) = self.language_servers.get(&document.file_type()) else {
+            return &[];
+        };
+        document
+            .file_name
+            .as_ref()
+            .and_then(|file_name| fs::canonicalize(file_name).ok())
+            .map_or(&[], |path| client.diagnostics(&path))
+    }
+    // カーソル行の最初の診断結果
+    fn cursor_diagnostic(&self) -> Option<&lsp::Diagnostic> {
+        let y = self.cursor_position.y;
+        self.diagnostics(&self.document)
+            .iter()
+            .find(|diagnostic| diagnostic.start.0 <= y && y <= diagnostic.end.0)
+    }
+    // 全てのウィンドウで別スレッドで読み込み中のファイルの行を受け取る
+    // 画面の再描画が必要な場合はtrueを返す
+    fn receive_rows(&mut self) -> bool {
+        let mut received = false;
+        let documents = iter::once((
+            &mut self.document,
+            &mut self.cursor_position,
+            &mut self.offset,
+        ))
+        .chain(self.windows.iter_mut().map(|window| {
+            (
+                &mut window.document,
+                &mut window.cursor_position,
+                &mut window.offset,
+            )
+        }));
+        for (document, cursor_position, offset) in documents {
+            match document.receive_rows() {
+                Ok(changed) => received = received || changed,
+                Err(error) => {
+                    // 16進数の表示に置き換わると行数が変わるので先頭に戻す
+                    *cursor_position = Position::default();
+                    *offset = Position::default();
+                    self.status_message =
+                        StatusMessage::from(format!("ERR: Could not read file: {error}"));
+                    received = true;
+                }
+            }
+        }
+        received
+    }
+    // 入力が無い間に一定間隔で呼ばれ、画面の再描画が必要な場合はtrueを返す
+    fn tick(&mut self) -> Result<bool, std::io::Error> {
+        let mut redraw = self.sync_language_servers();
+        if self.last_disk_check.elapsed() >= DISK_CHECK_INTERVAL {
+            self.last_disk_check = Instant::now();
+            redraw |= self.check_disk_change()?;
+        }
+        // 表示期間が過ぎたメッセージを消す
+        let message = &self.status_message;
+        if !message.text.is_empty() && message.time.elapsed() >= MESSAGE_DURATION {
+            self.status_message = StatusMessage::from(String::new());
+            redraw = true;
+        }
+        // 複数キーのコマンドの続きが一定時間入力されなければ、入力途中のキーを捨てる
+        // (単独で意味を持つ接頭キーは無いので、接頭キーだけのコマンドとして確定させることはない)
+        if self.options.timeout
+            && !self.pending_keys.is_empty()
+            && self.last_key_time.elapsed() >= self.options.timeout_len
+        {
+            self.pending_keys.clear();
+            redraw = true;
+        }
+        // 入力が止まってから一定時間経過したらカーソル位置の単語をハイライトする
+        if self
+            .last_input
+            .is_some_and(|time| time.elapsed() >= CURSOR_WORD_DELAY)
+        {
+            self.last_input = None;
+            redraw = redraw || self.options.cursor_word || self.deferred_rows;
+        }
+        Ok(redraw)
+    }
+    // 開いているファイルが他のプログラムによって更新されていれば読み込み直す
+    // 未保存の変更がある場合は読み込み直すか確認する。画面の再描画が必要な場合はtrueを返す
+    fn check_disk_change(&mut self) -> Result<bool, std::io::Error> {
+        if !self.document.changed_on_disk() {
+            return Ok(false);
+        }
+        let name = display_name(&self.document);
+        if self.document.is_dirty() {
+            let choice = self.choose(
+                &format!("WARNING! {name} changed on disk. [r]eload, [k]eep, [d]iff: "),
+                "rkd",
+            )?;
+            if choice != Some('r') {
+                self.document.ignore_disk_change();
+                // 変更を残したまま、ディスク上の内容との差分を表示する
+                if choice == Some('d') {
+                    self.diff_original();
+                }
+                return Ok(true);
+            }
+        }
+        match self.document.reload() {
+            Ok(()) => {
+                self.cursor_position = self.document.clamp(&self.cursor_position);
+                self.status_message =
+                    StatusMessage::from(format!("{name} changed on disk and was reloaded."));
+            }
+            Err(error) => {
+                self.document.ignore_disk_change();
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not reload {name}: {error}"));
+            }
+        }
+        Ok(true)
+    }
+    // 保存されているファイルとバッファの差分を分割したウィンドウに表示する
+    fn diff_original(&mut self) {
+        let Some(file_name) = self.document.file_name.clone() else {
+            self.status_message = StatusMessage::from("No file name.".to_string());
+            return;
+        };
+        let original: Vec<String> = match fs::read_to_string(&file_name) {
+            Ok(contents) => contents.lines().map(str::to_string).collect(),
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not read {file_name}: {error}"));
+                return;
+            }
+        };
+        let hunks = diff::unified(&original, &self.document.all_lines(), 3);
+        if hunks.is_empty() {
+            self.status_message = StatusMessage::from("No changes.".to_string());
+            return;
+        }
+        let mut lines = vec![
+            format!("--- {file_name} (on disk)"),
+            format!("+++ {file_name} (buffer)"),
+        ];
+        lines.extend(hunks);
+        let mut document =
+            Document::from_lines(&lines, BufferKind::Output(format!("diff {file_name}")));
+        // 変更した行の中で変わった文字を強調する
+        document.set_file_type(FileType::from(".diff"));
+        self.split_window_with(document);
+    }
+    // メッセージを表示して、選択肢のいずれかのキーが入力されるまで待つ(Escの場合はNoneを返す)
+    fn choose(&mut self, message: &str, choices: &str) -> Result<Option<char>, std::io::Error> {
+        self.status_message = StatusMessage::from(message.to_string());
+        self.refresh_screen()?;
+        let choice = loop {
+            match self.read_key()? {
+                Key::Esc => break None,
+                Key::Char(c) if choices.contains(c) => break Some(c),
+                _ => (),
+            }
+        };
+        self.status_message = StatusMessage::from(String::new());
+        Ok(choice)
+    }
+    // マクロを実行する(キーを入力の先頭に積む)
+    fn play_macro(&mut self, register: char) {
+        let register = if register == '@' {
+            let Some(last) = self.last_macro else {
+                self.status_message = StatusMessage::from("No previously used macro.".to_string());
+                return;
+            };
+            last
+        } else {
+            register
+        };
+        let Some(keys) = self.macros.get(&register) else {
+            self.status_message = StatusMessage::from(format!("Register @{register} is empty."));
+            return;
+        };
+        if self.input_queue.len().saturating_add(keys.len()) > MAX_QUEUED_KEYS {
+            self.input_queue.clear();
+            self.status_message = StatusMessage::from("Macro recursion is too deep.".to_string());
+            return;
+        }
+        for key in keys.iter().rev() {
+            self.input_queue.push_front(*key);
+        }
+        self.last_macro = Some(register);
+    }
+    // マクロの記録を終了してレジスタに保存する
+    fn stop_recording(&mut self) {
+        if let Some((register, mut keys)) = self.recording.take() {
+            // 記録を終了したqは含めない
+            keys.pop();
+            self.macros.insert(register, keys);
+        }
+    }
+    // マクロを文字列として編集するバッファを開く
+    fn edit_macro(&mut self, register: &str) {
+        let mut chars = register.chars();
+        let (Some(register), None) = (chars.next(), chars.next()) else {
+            self.status_message = StatusMessage::from("Usage: :macro-edit register".to_string());
+            return;
+        };
+        let text = keys::to_text(self.macros.get(&register).map_or(&[], |keys| &keys[..]));
+        self.split_window_with(Document::from_lines(&[text], BufferKind::Macro(register)));
+    }
+    // :let @q=keysでマクロを設定する
+    fn let_register(&mut self, argument: &str) {
+        let parsed = argument.strip_prefix('@').and_then(|rest| {
+            let mut chars = rest.chars();
+            let register = chars.next()?;
+            let value = chars.as_str().trim_start().strip_prefix('=')?.trim();
+            // 引用符で囲まれていれば外す
+            let value = ['"', '\'']
+                .iter()
+                .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+                .unwrap_or(value);
+            Some((register, value))
+        });
+        if let Some((register, value)) = parsed {
+            self.macros.insert(register, keys::parse(value));
+        } else {
+            self.status_message = StatusMessage::from("Usage: :let @register=keys".to_string());
+        }
+    }
+    // 入力をキーに変換する(マウス操作はここで処理してNoneを返す)
+    fn event_key(&mut self, event: &Event) -> Option<Key> {
+        match event {
+            Event::Key(key) => Some(*key),
+            Event::Mouse(event) => {
+                self.process_mouse(*event);
+                self.scroll();
+                None
+            }
+            Event::Unsupported(bytes) => keys::from_unsupported(bytes),
+        }
+    }
+    fn process_keypress(&mut self, event: &Event) {
+        let Some(pressed_key) = self.event_key(event) else {
+            return;
+        };
+        if self.forward_to_shell(pressed_key) || self.run_plugin_key(pressed_key) {
+            return;
+        }
+        if self.dismiss_for_key(pressed_key) {
+            self.scroll();
+            return;
+        }
+        match pressed_key {
+            // ビジュアルモードでは選択に対する操作を先に処理する
+            _ if self.selection.is_some() && self.process_visual_key(pressed_key) => (),
+            // マクロの記録中にqを入力したら記録を終了
+            Key::Char('q')
+                if self.vim_normal_mode
+                    && self.pending_keys.is_empty()
+                    && self.recording.is_some() =>
+            {
+                self.stop_recording();
+            }
+            // ページャではqですぐに終了する
+            Key::Char('q')
+                if self.pager && self.vim_normal_mode && self.pending_keys.is_empty() =>
+            {
+                self.should_quit = true;
+            }
+            // 複数キーからなるコマンドの入力途中、または最初のキーが入力されたとき
+            Key::Char(c)
+                if self.vim_normal_mode
+                    && (!self.pending_keys.is_empty() || PREFIX_KEYS.contains(c)) =>
+            {
+                self.pending_keys.push(c);
+                self.process_pending_keys();
+            }
+            Key::Ctrl('g') if self.vim_normal_mode && self.pending_keys == "g" => {
+                self.pending_keys.clear();
+                self.show_statistics();
+            }
+            // ウィンドウ操作のコマンドの入力開始
+            Key::Ctrl('w') if self.vim_normal_mode => self.pending_keys.push(WINDOW_PREFIX),
+            // Ctrl-^(端末からはCtrl-6として届く)で直前に開いていたファイルに切り替える
+            Key::Ctrl('6') if self.vim_normal_mode => self.edit_alternate_file(),
+            Key::Ctrl('q') => {
+                if !self.confirm_quit() {
+                    return;
+                }
+            }
+            Key::Ctrl('s') => self.save(),
+            // Ctrl-Lで画面を消して全ての行を描き直す
+            Key::Ctrl('l') if self.vim_normal_mode => self.redraw_all(),
+            // ノーマルモード時に.で最後にドキュメントを変更したコマンドを繰り返す
+            Key::Char('.') if self.vim_normal_mode => self.repeat_last_change(),
+            // ノーマルモード時にuで取り消し、Ctrl-Rでやり直す
+            Key::Char('u') if self.vim_normal_mode => self.undo(),
+            Key::Ctrl('r') if self.vim_normal_mode => self.redo(),
+            // ノーマルモード時に/で検索
+            Key::Char('/') if self.vim_normal_mode => self.search(),
+            // ノーマルモード時にn(N)で最後に検索した文字列の次(前)の位置に移動
+            Key::Char(c @ ('n' | 'N')) if self.vim_normal_mode => self.search_next(c == 'n'),
+            // Ctrl-/でカーソル行のコメントを切り替える(端末からはCtrl-7として届く)
+            Key::Ctrl('7') if self.document.is_editable() => self.toggle_comment(),
+            // ノーマルモード時に;で最後の文字検索を繰り返し、,で逆向きに繰り返す
+            Key::Char(c @ (';' | ',')) if self.vim_normal_mode => self.move_to_char(&c.to_string()),
+            // ノーマルモード時に%で対応する括弧に移動
+            Key::Char('%') if self.vim_normal_mode => self.move_to_matching_bracket(),
+            // ノーマルモード時に:でコマンド入力
+            Key::Char(':') if self.vim_normal_mode => self.command(),
+            // 挿入モード時に表の中でタブを押したら次のセルに移動
+            Key::Char('\t')
+                if !self.vim_normal_mode && self.document.is_table_row(self.cursor_position.y) =>
+            {
+                self.move_table_cell(true);
+            }
+            Key::BackTab if self.document.is_table_row(self.cursor_position.y) => {
+                self.move_table_cell(false);
+            }
+            // ファイル一覧でEnterキーが押されたらエントリを開く
+            Key::Char('\n') if !self.document.is_editable() => self.open_entry(),
+            Key::Char('\n') if *self.document.kind() == BufferKind::CommandLine => {
+                self.execute_command_line();
+            }
+            // ノーマルモード時に-でファイル一覧を開く
+            Key::Char('-') if self.vim_normal_mode => self.open_explorer(),
+            // Enterキーが押されたとき
+            Key::Char('\n') => self.insert_newline(),
+            // 挿入モード時に任意の文字が入力されたとき
+            Key::Char(c) if !self.vim_normal_mode => self.insert_char(c),
+            // ノーマルモード時にiを入力したら挿入モードに移行
+            // ファイル一覧などの編集できないバッファでは挿入モードに移行しない
+            Key::Char('i') if self.vim_normal_mode && self.document.is_editable() => {
+                self.vim_normal_mode = false;
+            }
+            // ノーマルモードに移行
+            Key::Esc => self.enter_normal_mode(),
+            // ノーマルモード時にvで文字単位、Vで行単位の選択を始める
+            Key::Char(c @ ('v' | 'V')) if self.vim_normal_mode => self.start_selection(c == 'V'),
+            // Deleteキー、またはノーマルモード時にxを押したらカーソル位置の文字を削除
+            //  挿入モードでxを押した時は、上のアームでマッチするのでここはマッチしない
+            Key::Delete | Key::Char('x') if self.document.is_editable() => {
+                // ノーマルモードで削除した文字はレジスタに保存する
+                if self.vim_normal_mode {
+                    self.delete_char(None);
+                } else {
+                    self.delete_at_cursor();
+                }
+            }
+            // Alt-k(Alt-Up)とAlt-j(Alt-Down)でカーソル行を上下に移動し、Alt-dで複製する
+            Key::Alt(c @ ('k' | 'j')) if self.document.is_editable() => self.move_line(c == 'k'),
+            Key::Alt('d') if self.document.is_editable() => self.duplicate_line(),
+            // ノーマルモード時にKでカーソル位置のホバーを言語サーバに問い合わせて表示する
+            Key::Char('K') if self.vim_normal_mode => {
+                self.request_language_server(lsp::Request::Hover);
+            }
+            // ノーマルモード時にJでカーソル行と次の行を空白1つを挟んで結合する
+            Key::Char('J') if self.vim_normal_mode && self.document.is_editable() => {
+                self.join_lines(true);
+            }
+            // ノーマルモード時にp(P)で直前にヤンクまたは削除したテキストを後ろ(前)に貼り付ける
+            Key::Char(c @ ('p' | 'P')) if self.vim_normal_mode && self.document.is_editable() => {
+                self.paste('"', c == 'p');
+            }
+            Key::Backspace => self.backspace(),
+            _ => self.move_cursor(pressed_key),
+        }
+        self.scroll();
+        // 終了コマンドを規定回数入力前に他の入力があったらカウントをリセット
+        if self.quit_times < QUIT_TIMES {
+            self.quit_times = QUIT_TIMES;
+            self.status_message = StatusMessage::from(String::new());
+        }
+    }
+    // キーが入力されるたびに入力途中のコマンドや一時的な表示を片付ける
+    // 補完の候補の操作としてキーを処理した場合はtrueを返す
+    fn dismiss_for_key(&mut self, pressed_key: Key) -> bool {
+        // 文字以外のキーが入力されたら入力途中のコマンドは破棄する(g Ctrl-Gのみ続けて解釈する)
+        let statistics_key = pressed_key == Key::Ctrl('g') && self.pending_keys == "g";
+        if !matches!(pressed_key, Key::Char(_)) && !statistics_key {
+            self.pending_keys.clear();
+            self.abbreviation_preview = None;
+        }
+        self.hover.clear();
+        if self.spell_suggestions.is_some() {
+            return self.spell_suggestion_key(pressed_key);
+        }
+        self.completion_key(pressed_key)
+    }
+    // 最後にドキュメントを変更したコマンドのキーを入力として与える
+    fn repeat_last_change(&mut self) {
+        if self.last_change.is_empty() {
+            return;
+        }
+        if self
+            .input_queue
+            .len()
+            .saturating_add(self.last_change.len())
+            > MAX_QUEUED_KEYS
+        {
+            self.input_queue.clear();
+            return;
+        }
+        for key in self.last_change.iter().rev() {
+            self.input_queue.push_front(*key);
+        }
+    }
+    // 改行を挿入する
+    fn insert_newline(&mut self) {
+        // 挿入モードでリストの項目を改行した場合は次の項目を続ける
+        let position = if self.vim_normal_mode {
+            None
+        } else {
+            self.document.insert_list_newline(&self.cursor_position)
+        };
+        if let Some(position) = position {
+            // 番号の桁数が変わることがあるので振り直した後の行頭部分の後ろに移動
+            self.renumber_list(position.y);
+            self.cursor_position = Position {
+                x: self
+                    .document
+                    .list_prefix_len(position.y)
+                    .unwrap_or(position.x),
+                y: position.y,
+            };
+        } else {
+            self.document.insert(&self.cursor_position, '\n');
+            // カーソルを下に移動
+            self.move_cursor(Key::Down);
+        }
+    }
+    fn undo(&mut self) {
+        if let Some(position) = self.document.undo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Already at oldest change.".to_string());
+        }
+    }
+    fn redo(&mut self) {
+        if let Some(position) = self.document.redo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Already at newest change.".to_string());
+        }
+    }
+    // プラグインに渡すカーソル位置、モード、選択範囲、画面に表示している範囲(行と列は0から数える)
+    fn plugin_state(&self) -> Value {
+        let selection = self.selection.as_ref().map(|selection| {
+            let (start, end) = selection.bounds(&self.cursor_position);
+            json!({
+                "start": { "line": start.y, "column": start.x },
+                "end": { "line": end.y, "column": end.x },
+                "linewise": selection.linewise,
+            })
+        });
+        json!({
+            "file_name": self.document.file_name,
+            "cursor": { "line": self.cursor_position.y, "column": self.cursor_position.x },
+            "mode": self.mode(),
+            "selection": selection,
+            "viewport": {
+                "top": self.offset.y,
+                "left": self.offset.x,
+                "height": self.window_height(),
+                "width": self.text_width(&self.document),
+            }
+        })
+    }
+    // カーソルが移動したりモードが変わったりしていれば、そのイベントを登録したプラグインに知らせる
+    // プラグインが応答でカーソル位置などを変えた場合、その変更は知らせない
+    fn notify_plugins(&mut self) {
+        let cursor = (self.cursor_position.y, self.cursor_position.x);
+        let mut notifications = Vec::new();
+        if cursor != self.notified_cursor {
+            notifications.push(Notification::CursorMoved);
+        }
+        if self.mode() != self.notified_mode {
+            notifications.push(Notification::ModeChanged);
+        }
+        if !notifications.is_empty() {
+            let state = self.plugin_state();
+            let mut replies = Vec::new();
+            for plugin in &mut self.plugins {
+                for notification in &notifications {
+                    if plugin.notifications.contains(notification) {
+                        replies.push(plugin.request(notification.method(), &state));
+                    }
+                }
+            }
+            for reply in replies {
+                match reply {
+                    Ok(reply) => self.apply_plugin_reply(reply),
+                    Err(error) => {
+                        self.status_message = StatusMessage::from(format!("ERR: {error}"));
+                    }
+                }
+            }
+            self.scroll();
+        }
+        self.notified_cursor = (self.cursor_position.y, self.cursor_position.x);
+        self.notified_mode = self.mode();
+    }
+    // プラグインに知らせるモードの名前
+    fn mode(&self) -> &'static str {
+        if !self.vim_normal_mode {
+            "insert"
+        } else if self.selection.is_some() {
+            "visual"
+        } else {
+            "normal"
+        }
+    }
+    // ノーマルモードに移行し、挿入モードから抜けた場合はその位置を記録する
+    fn enter_normal_mode(&mut self) {
+        if !self.vim_normal_mode {
+            self.document.set_mark('^', &self.cursor_position);
+        }
+        self.vim_normal_mode = true;
+        self.selection = None;
+    }
+    // カーソルの前後の文字を返す
+    fn chars_around_cursor(&self) -> (Option<char>, Option<char>) {
+        let Position { x, y } = self.cursor_position;
+        let Some(row) = self.document.row(y) else {
+            return (None, None);
+        };
+        let char_at = |x: usize| row.grapheme(x).and_then(|grapheme| grapheme.chars().next());
+        (x.checked_sub(1).and_then(char_at), char_at(x))
+    }
+    // カーソルが自動で閉じる括弧の組の間にあるか
+    fn in_empty_pair(&self) -> bool {
+        let (prev, next) = self.chars_around_cursor();
+        self.document
+            .editing_options()
+            .auto_pairs()
+            .iter()
+            .any(|(open, close)| prev == Some(*open) && next == Some(*close))
+    }
+    // 挿入モードで入力された文字を挿入する(括弧や引用符は閉じ記号も補う)
+    fn insert_char(&mut self, c: char) {
+        // 略語の展開を表示している間はTabで展開し、それ以外のキーでは展開しない
+        let preview = self.abbreviation_preview.take();
+        if let (Some(abbreviation), '\t') = (preview, c) {
+            self.expand_abbreviation(&abbreviation);
+            return;
+        }
+        self.insert_char_unexpanded(c);
+        self.abbreviation_preview = self.abbreviation_before_cursor();
+        self.update_completion(false);
+    }
+    fn insert_char_unexpanded(&mut self, c: char) {
+        // 空白でインデントする設定ではタブの代わりに次のインデント位置まで空白を挿入する
+        if c == '\t' && !self.document.indent().use_tabs {
+            let unit = self.document.indent().unit(self.cursor_position.x);
+            self.cursor_position = self.document.insert_str(&self.cursor_position, &unit);
+            return;
+        }
+        let (prev, next) = self.chars_around_cursor();
+        let pairs = self.document.editing_options().auto_pairs();
+        // 入力した閉じ記号が既にカーソル位置にあれば挿入せずに右に移動
+        let is_closer = pairs.iter().any(|(_, close)| *close == c);
+        let pair = pairs.iter().find(|(open, _)| *open == c).copied();
+        if is_closer && next == Some(c) {
+            self.move_cursor(Key::Right);
+            return;
+        }
+        // その文字を挿入してからカーソルを移動
+        if !self.insert_at_cursor(c) {
+            return;
+        }
+        if let Some((open, close)) = pair {
+            // 単語の直後の引用符はアポストロフィとみなして閉じない
+            let after_word = prev.is_some_and(char::is_alphanumeric);
+            if open != close || !after_word {
+                self.document.insert(&self.cursor_position, close);
+            }
+        }
+        if !c.is_whitespace() {
+            self.auto_wrap();
+        }
+    }
+    // カーソルの直前の単語(英数字と_の並び)が略語であれば返す
+    fn abbreviation_before_cursor(&self) -> Option<String> {
+        let word = self.word_before_cursor()?;
+        self.abbreviations.contains_key(&word).then_some(word)
+    }
+    // カーソルの直前にある単語(単語の途中でなければ空文字列)
+    fn word_before_cursor(&self) -> Option<String> {
+        let row = self.document.row(self.cursor_position.y)?;
+        let before: String = (0..self.cursor_position.x)
+            .filter_map(|x| row.grapheme(x))
+            .collect();
+        let word_start = before
+            .rfind(|c: char| !completion::is_word_char(c))
+            .map_or(0, |index| index.saturating_add(1));
+        before.get(word_start..).map(str::to_string)
+    }
+    // カーソルの直前の略語を展開後の文字列に置き換える
+    fn expand_abbreviation(&mut self, abbreviation: &str) {
+        let Some(expansion) = self.abbreviations.get(abbreviation).cloned() else {
+            return;
+        };
+        self.replace_before_cursor(abbreviation, &expansion);
+    }
+    // カーソルの直前のwordを削除してreplacementを挿入する
+    fn replace_before_cursor(&mut self, word: &str, replacement: &str) {
+        let end = self.cursor_position.clone();
+        self.cursor_position.x = end.x.saturating_sub(word.graphemes(true).count());
+        self.document.delete_range(&self.cursor_position, &end);
+        self.cursor_position = self.document.insert_str(&self.cursor_position, replacement);
+    }
+    // カーソル位置に1文字挿入し、新しい文字になった場合だけカーソルを右に移動してtrueを返す
+    // 結合文字などは前の文字とまとまるので、カーソルを書記素の途中に置かない
+    fn insert_at_cursor(&mut self, c: char) -> bool {
+        let len = |editor: &Self| {
+            editor
+                .document
+                .row(editor.cursor_position.y)
+                .map_or(0, Row::len)
+        };
+        let before = len(self);
+        self.document.insert(&self.cursor_position, c);
+        let grew = len(self) > before;
+        if grew {
+            self.move_cursor(Key::Right);
+        }
+        grew
+    }
+    // カーソルの直前の単語を補完する候補を求める
+    // 自動で表示する場合は短い単語では求めず、Ctrl-Nで表示する場合は最初の候補を選択する
+    fn update_completion(&mut self, force: bool) {
+        self.completion = None;
+        let prefix = self.word_before_cursor().unwrap_or_default();
+        let long_enough = prefix.graphemes(true).count() >= AUTO_COMPLETE_LENGTH;
+        if !(force || self.options.auto_complete && long_enough) {
+            return;
+        }
+        let documents =
+            iter::once(&self.document).chain(self.windows.iter().map(|window| &window.document));
+        let candidates = completion::candidates(&prefix, documents);
+        if candidates.is_empty() {
+            if force {
+                self.status_message = StatusMessage::from("No completions.".to_string());
+            }
+            return;
+        }
+        self.completion = Some(Completion {
+            prefix,
+            candidates,
+            selected: force.then_some(0),
+        });
+    }
+    // 挿入モードでCtrl-Nと、補完の候補を表示している間のキーを処理し、処理した場合はtrueを返す
+    fn completion_key(&mut self, key: Key) -> bool {
+        if self.vim_normal_mode || !self.document.is_editable() {
+            return false;
+        }
+        let Some(completion) = &mut self.completion else {
+            if key == Key::Ctrl('n') {
+                self.update_completion(true);
+                return true;
+            }
+            return false;
+        };
+        match key {
+            // 略語の展開を表示している間のTabは展開に使う
+            Key::Char('\t') if self.abbreviation_preview.is_some() => return false,
+            Key::Down | Key::Char('\t') | Key::Ctrl('n') => completion.select(true),
+            Key::Up | Key::BackTab | Key::Ctrl('p') => completion.select(false),
+            // Enterで選択中の候補を入力中の単語と置き換える(選択していなければ改行する)
+            Key::Char('\n') => {
+                let Some(candidate) = completion.selected_candidate().map(str::to_string) else {
+                    self.completion = None;
+                    return false;
+                };
+                let prefix = mem::take(&mut completion.prefix);
+                self.completion = None;
+                self.replace_before_cursor(&prefix, &candidate);
+            }
+            Key::Backspace => {
+                self.backspace();
+                self.update_completion(false);
+            }
+            // 文字を入力したら候補を求め直す
+            Key::Char(_) => return false,
+            _ => {
+                self.completion = None;
+                return false;
+            }
+        }
+        true
+    }
+    // :set spellのときに辞書を読み込む(設定が変わった場合は読み直し、読めなければスペルチェックを止める)
+    fn load_dictionary(&mut self) {
+        if !self.options.spell {
+            return;
+        }
+        let path = self.options.dictionary.as_deref();
+        let user_path = self.options.spell_file.as_deref();
+        if self
+            .dictionary
+            .as_ref()
+            .is_some_and(|dictionary| dictionary.is_loaded_from(path, user_path))
+        {
+            return;
+        }
+        match Dictionary::load(path, user_path) {
+            Ok(dictionary) => self.dictionary = Some(dictionary),
+            Err(message) => {
+                self.options.spell = false;
+                self.status_message = StatusMessage::from(format!("ERR: {message}"));
+            }
+        }
+    }
+    // カーソル位置にあるスペルチェックの対象の単語の先頭の位置と単語
+    fn spell_word_at_cursor(&self) -> Option<(Position, String)> {
+        let chars: Vec<char> = self
+            .document
+            .row(self.cursor_position.y)?
+            .as_str()
+            .chars()
+            .collect();
+        let range = spell::words(&chars)
+            .into_iter()
+            .find(|range| range.contains(&self.cursor_position.x))?;
+        let position = Position {
+            x: range.start,
+            y: self.cursor_position.y,
+        };
+        Some((position, chars.get(range)?.iter().collect()))
+    }
+    // カーソル位置の単語の修正候補を表示する
+    fn suggest_spelling(&mut self) {
+        let Some(dictionary) = self.dictionary.as_ref().filter(|_| self.options.spell) else {
+            self.status_message = StatusMessage::from("Spell checking is off.".to_string());
+            return;
+        };
+        let Some((position, word)) = self.spell_word_at_cursor() else {
+            self.status_message = StatusMessage::from("No word under cursor.".to_string());
+            return;
+        };
+        let candidates = dictionary.suggestions(&word);
+        if candidates.is_empty() {
+            self.status_message = StatusMessage::from("No suggestions.".to_string());
+            return;
+        }
+        self.cursor_position = position;
+        self.spell_suggestions = Some(Completion {
+            prefix: word,
+            candidates,
+            selected: Some(0),
+        });
+    }
+    // カーソル位置の単語を辞書に加える
+    fn add_to_dictionary(&mut self) {
+        let word = self.spell_word_at_cursor();
+        let Some(dictionary) = self.dictionary.as_mut().filter(|_| self.options.spell) else {
+            self.status_message = StatusMessage::from("Spell checking is off.".to_string());
+            return;
+        };
+        let Some((_, word)) = word else {
+            self.status_message = StatusMessage::from("No word under cursor.".to_string());
+            return;
+        };
+        self.status_message = StatusMessage::from(match dictionary.add(&word) {
+            Ok(()) => format!("Added \"{word}\" to dictionary."),
+            Err(message) => format!("ERR: Could not add word: {message}"),
+        });
+    }
+    // スペルの修正候補を表示している間のキーを処理し、処理した場合はtrueを返す
+    fn spell_suggestion_key(&mut self, key: Key) -> bool {
+        let Some(suggestions) = &mut self.spell_suggestions else {
+            return false;
+        };
+        match key {
+            Key::Down | Key::Char('j' | '\t') | Key::Ctrl('n') => suggestions.select(true),
+            Key::Up | Key::Char('k') | Key::BackTab | Key::Ctrl('p') => suggestions.select(false),
+            // Enterで選択中の候補、数字でその番号の候補とカーソル位置の単語を置き換える
+            Key::Char('\n' | '1'..='9') => {
+                if let Key::Char(c @ '1'..='9') = key {
+                    suggestions.selected = c
+                        .to_digit(10)
+                        .and_then(|number| usize::try_from(number).ok())
+                        .and_then(|number| number.checked_sub(1));
+                }
+                let suggestions = self.spell_suggestions.take();
+                if let Some((word, candidate)) = suggestions.as_ref().and_then(|suggestions| {
+                    Some((&suggestions.prefix, suggestions.selected_candidate()?))
+                }) {
+                    self.replace_word_at_cursor(word, candidate);
+                }
+            }
+            Key::Esc => self.spell_suggestions = None,
+            _ => {
+                self.spell_suggestions = None;
+                return false;
             }
-            self.document.file_name = new_name;
-        }
-
-        if self.document.save().is_ok() {
-            // 成功
-            self.status_message = StatusMessage::from("File saved successfully.".to_string());
-        } else {
-            // 失敗
-            self.status_message = StatusMessage::from("Error writing file!".to_string());
         }
+        true
     }
-    // 文字列検索
-    fn search(&mut self) {
-        // 検索開始前にカーソルの位置を保存
-        let old_position = self.cursor_position.clone();
-        let mut direction = SearchDirection::Forward;
-        // 検索文字列を取得
-        let query = self
-            .prompt(
-                "Search (ESC to cancel, Arrows to navigate): ",
-                |editor, key, query| {
-                    // このコールバック関数は改行またはEscが入力されるまでループ
-                    // 検索対象をずらすためにカーソルをずらしたか
-                    let mut moved = false;
-                    match key {
-                        Key::Right | Key::Down => {
-                            direction = SearchDirection::Forward;
-                            // 現在の位置から検索すると同じ場所でマッチするので1文字右にずらす
-                            editor.move_cursor(Key::Right);
-                            moved = true;
-                        }
-                        Key::Left | Key::Up => direction = SearchDirection::Backward,
-                        _ => direction = SearchDirection::Forward,
-                    }
-                    // 検索文字列が見つかった場合
-                    if let Some(position) =
-                        editor
-                            .document
-                            .find(query, &editor.cursor_position, direction)
-                    {
-                        // 文字が入力されるたびに検索文字列の位置にカーソルをジャンプ
-                        editor.cursor_position = position;
-                        editor.scroll();
-                    } else if moved {
-                        // 検索で見つからなかったらずらしたカーソルを元に戻す
-                        editor.move_cursor(Key::Left);
-                    }
-                    // ハイライトする検索文字列を保存
-                    editor.highlighted_word = Some(query.to_string());
-                },
-            )
-            .unwrap_or(None);
-        // 何も入力されない、またはEscでキャンセルされた場合
-        if query.is_none() {
-            // 検索開始前の位置にカーソルを戻す
-            self.cursor_position = old_position;
-            self.scroll();
+    // カーソル位置から始まるwordをreplacementに置き換える(カーソルは置き換えた単語の先頭に残す)
+    fn replace_word_at_cursor(&mut self, word: &str, replacement: &str) {
+        if !self.document.is_editable() {
+            return;
         }
-        // 検索が終わったら検索文字列のハイライトを解除
-        self.highlighted_word = None;
+        let end = Position {
+            x: self
+                .cursor_position
+                .x
+                .saturating_add(word.graphemes(true).count()),
+            y: self.cursor_position.y,
+        };
+        self.document.delete_range(&self.cursor_position, &end);
+        self.document.insert_str(&self.cursor_position, replacement);
     }
-    fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
-        match pressed_key {
-            Key::Ctrl('q') => {
-                // 更新有りで終了しようとしたときは入力を促すメッセージを表示するのみ
-                if self.quit_times > 0 && self.document.is_dirty() {
-                    self.status_message = StatusMessage::from(format!(
-                        "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
-                        self.quit_times
-                    ));
-                    self.quit_times = self.quit_times.saturating_sub(1);
-                    return Ok(());
-                }
-                // 更新無し、またはCtrl-Qを規定回数押されたときは終了
-                self.should_quit = true;
+    // :abbreviate 略語 展開後の文字列で略語を登録する(引数が無ければ一覧を表示する)
+    fn abbreviate(&mut self, argument: &str) {
+        match argument.split_once(char::is_whitespace) {
+            Some((abbreviation, expansion)) if !expansion.trim().is_empty() => {
+                self.abbreviations
+                    .insert(abbreviation.to_string(), expansion.trim().to_string());
             }
-            Key::Ctrl('s') => self.save(),
-            // ノーマルモード時に/で検索
-            Key::Char('/') if self.vim_normal_mode => self.search(),
-            // Enterキーが押されたとき
-            Key::Char('\n') => {
-                self.document.insert(&self.cursor_position, '\n');
-                // カーソルを下に移動
-                self.move_cursor(Key::Down);
+            _ if argument.is_empty() => {
+                let mut abbreviations: Vec<String> = self
+                    .abbreviations
+                    .iter()
+                    .map(|(abbreviation, expansion)| format!("{abbreviation} -> {expansion}"))
+                    .collect();
+                abbreviations.sort();
+                self.status_message = StatusMessage::from(if abbreviations.is_empty() {
+                    "No abbreviation found.".to_string()
+                } else {
+                    abbreviations.join(" | ")
+                });
             }
-            // 挿入モード時に任意の文字が入力されたとき
-            Key::Char(c) if !self.vim_normal_mode => {
-                // その文字を挿入してからカーソルを移動
-                self.document.insert(&self.cursor_position, c);
-                // カーソルを右に移動
-                self.move_cursor(Key::Right);
+            _ => {
+                self.status_message =
+                    StatusMessage::from("Usage: :abbreviate abbreviation expansion".to_string());
             }
-            // ノーマルモード時にiを入力したら挿入モードに移行
-            Key::Char('i') if self.vim_normal_mode => self.vim_normal_mode = false,
-            // ノーマルモードに移行
-            Key::Esc => self.vim_normal_mode = true,
-            // Deleteキー、またはノーマルモード時にxを押したらカーソル位置の文字を削除
-            //  挿入モードでxを押した時は、上のアームでマッチするのでここはマッチしない
-            Key::Delete | Key::Char('x') => {
-                self.document.delete(&self.cursor_position);
+        }
+    }
+    fn unabbreviate(&mut self, abbreviation: &str) {
+        if self.abbreviations.remove(abbreviation).is_none() {
+            self.status_message =
+                StatusMessage::from(format!("No such abbreviation: {abbreviation}"));
+        }
+    }
+    // textwidthを超えて入力したら、formatoptionsに従って文章やコメントを折り返す
+    fn auto_wrap(&mut self) {
+        if self.options.text_width == 0 {
+            return;
+        }
+        let editing_options = self.document.editing_options();
+        let comment = editing_options.line_comment().map(str::to_string);
+        let in_comment = comment.as_deref().is_some_and(|comment| {
+            self.document
+                .row(self.cursor_position.y)
+                .is_some_and(|row| row.as_str().trim_start().starts_with(comment))
+        });
+        let wrap = if in_comment {
+            self.options.wrap_comments
+        } else {
+            self.options.wrap_text && editing_options.is_prose()
+        };
+        if !wrap {
+            return;
+        }
+        if let Some(position) = self.document.wrap_line(
+            &self.cursor_position,
+            self.options.text_width,
+            comment.as_deref(),
+        ) {
+            self.cursor_position = position;
+        }
+    }
+    // 組み込みのテーマ名、またはテーマファイルのパスからテーマを切り替える
+    fn set_theme(&mut self, name: &str) {
+        if name.is_empty() {
+            self.status_message = StatusMessage::from(format!(
+                "Theme: {} (available: {})",
+                self.theme.name,
+                THEME_NAMES.join(", ")
+            ));
+            return;
+        }
+        match Theme::named(name).map_or_else(|| Theme::load(name), Ok) {
+            Ok(theme) => self.theme = theme,
+            Err(e) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not load theme: {name}: {e}"));
             }
-            Key::Backspace => {
-                // カーソルがドキュメントの先頭でなければ
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    // カーソルを一つ前に移動
-                    self.move_cursor(Key::Left);
-                    // 挿入モードの時のみ
-                    if !self.vim_normal_mode {
-                        // 文字を削除
-                        self.document.delete(&self.cursor_position);
-                    }
-                }
+        }
+    }
+    // 編集中のファイルの名前を変更する
+    fn rename(&mut self, new_name: &str) {
+        if new_name.is_empty() {
+            self.status_message = StatusMessage::from("Usage: :rename file".to_string());
+            return;
+        }
+        if !self.document.is_editable() {
+            self.status_message = StatusMessage::from("Cannot rename this buffer.".to_string());
+            return;
+        }
+        // 保存済みのファイルであればファイルも移動する
+        if let Some(old_name) = &self.document.file_name {
+            if Path::new(old_name).exists() && fs::rename(old_name, new_name).is_err() {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not rename file: {old_name}"));
+                return;
             }
-            _ => self.move_cursor(pressed_key),
         }
-        self.scroll();
-        // 終了コマンドを規定回数入力前に他の入力があったらカウントをリセット
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
-            self.status_message = StatusMessage::from(String::new());
+        self.document.set_file_name(new_name.to_string());
+    }
+    // :countコマンドでドキュメント全体の一致を数える
+    fn count(&mut self, argument: &str) {
+        // /pattern/の形式でも単にpatternでも良い
+        let pattern = split_pattern(argument)
+            .and_then(|parts| parts.into_iter().next())
+            .unwrap_or_else(|| argument.to_string());
+        if pattern.is_empty() {
+            self.status_message = StatusMessage::from("Usage: :count /pattern/".to_string());
+            return;
         }
-        Ok(())
+        let (lines, matches) =
+            self.document
+                .count_matches(0, self.document.len(), &pattern, self.search_options);
+        self.report_matches(&pattern, lines, matches, ("match", "matches"));
+    }
+    // :sコマンドを実行する
+    fn substitute(&mut self, Range { start, end }: Range<usize>, substitute: &Substitute) {
+        let mut options = self.search_options;
+        if substitute.flags.contains('i') {
+            options.case = CaseSensitivity::Insensitive;
+        } else if substitute.flags.contains('I') {
+            options.case = CaseSensitivity::Sensitive;
+        }
+        let global = substitute.flags.contains('g');
+        let pattern = &substitute.pattern;
+        if substitute.flags.contains('n') {
+            // nフラグは置換せずに数えるだけ
+            let (lines, matches) = if global {
+                self.document.count_matches(start, end, pattern, options)
+            } else {
+                // gフラグが無ければ各行の最初の一致のみ数える
+                let (lines, _) = self.document.count_matches(start, end, pattern, options);
+                (lines, lines)
+            };
+            self.report_matches(pattern, lines, matches, ("match", "matches"));
+        } else if !self.document.is_editable() {
+            self.status_message = StatusMessage::from("Cannot modify this buffer.".to_string());
+        } else {
+            let (lines, matches) = self.document.substitute(
+                start,
+                end,
+                pattern,
+                &substitute.replacement,
+                global,
+                options,
+            );
+            self.report_matches(pattern, lines, matches, ("substitution", "substitutions"));
+        }
+    }
+    // 一致した数をメッセージに表示する
+    fn report_matches(
+        &mut self,
+        pattern: &str,
+        lines: usize,
+        matches: usize,
+        (singular, plural): (&str, &str),
+    ) {
+        self.status_message = if matches == 0 {
+            StatusMessage::from(format!("Pattern not found: {pattern}"))
+        } else {
+            let noun = if matches == 1 { singular } else { plural };
+            let line = if lines == 1 { "line" } else { "lines" };
+            StatusMessage::from(format!("{matches} {noun} on {lines} {line}"))
+        };
     }
     // 入力したキーに応じてカーソル移動
     fn move_cursor(&mut self, key: Key) {
-        let terminal_height = self.terminal.size().height as usize;
+        let terminal_height = self.window_height();
         let Position { mut y, mut x } = self.cursor_position;
         let document_height = self.document.len();
         let width = if let Some(row) = self.document.row(y) {
@@ -291,13 +4630,21 @@ impl Editor {
         } else {
             0
         };
+        // Ctrl-DとCtrl-Uで半画面分、Ctrl-EとCtrl-Yで1行分スクロールする
+        #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+        let half = (terminal_height / 2).max(1);
         match key {
-            Key::Up | Key::Char('k') => y = y.saturating_sub(1),
-            Key::Down | Key::Char('j') => {
-                if y < document_height {
-                    y = y.saturating_add(1);
-                };
-            }
+            Key::Ctrl('d') => return self.scroll_view(true, half, true),
+            Key::Ctrl('u') => return self.scroll_view(false, half, true),
+            Key::Ctrl('e') => return self.scroll_view(true, 1, false),
+            Key::Ctrl('y') => return self.scroll_view(false, 1, false),
+            _ => (),
+        }
+        // 上下の移動では折りたたまれた行を飛ばす
+        let folds = self.document.folds();
+        match key {
+            Key::Up | Key::Char('k') => y = folds.visible_above(y, 1),
+            Key::Down | Key::Char('j') if y < document_height => y = folds.visible_below(y, 1),
             Key::Left | Key::Char('h') => {
                 if x > 0 {
                     // 行頭でなければ左に移動
@@ -305,7 +4652,7 @@ impl Editor {
                 } else if y > 0 {
                     // 行頭で、かつドキュメントの最初の行でない場合
                     // 1つ上の行に移動
-                    y = y.saturating_sub(1);
+                    y = folds.visible_above(y, 1);
                     // 行末に移動
                     if let Some(row) = self.document.row(y) {
                         x = row.len();
@@ -320,25 +4667,17 @@ impl Editor {
                 } else if y < document_height {
                     // 行末で、かつドキュメントの最後の行でない場合
                     // 下の行の行頭に移動
-                    y = y.saturating_add(1);
+                    y = folds.visible_below(y, 1);
                     x = 0;
                 }
             }
             Key::PageUp | Key::Ctrl('b') => {
                 // 1画面分上に移動
-                y = if y > terminal_height {
-                    y.saturating_sub(terminal_height)
-                } else {
-                    0
-                }
+                y = folds.visible_above(y, terminal_height);
             }
             Key::PageDown | Key::Ctrl('f') => {
                 // 1画面分下に移動
-                y = if y.saturating_add(terminal_height) < document_height {
-                    y.saturating_add(terminal_height)
-                } else {
-                    document_height
-                }
+                y = folds.visible_below(y, terminal_height).min(document_height);
             }
             Key::Home | Key::Char('0') => x = 0,
             Key::End | Key::Char('$') => x = width,
@@ -350,16 +4689,40 @@ impl Editor {
     fn scroll(&mut self) {
         // キー入力による移動後のカーソル位置を取得
         let Position { x, y } = self.cursor_position;
-        let terminal_width = self.terminal.size().width as usize;
-        let terminal_height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
-        // カーソルが画面より上
-        if y < offset.y {
-            // カーソルを画面の一番上に置く
-            offset.y = y;
-        } else if y >= offset.y.saturating_add(terminal_height) {
-            // カーソルが画面より下の時はカーソルを画面の一番下に置く
-            offset.y = y.saturating_sub(terminal_height).saturating_add(1);
+        // 検索などで隠れた行に移動した場合は折りたたみを開く
+        if self.document.folds().is_hidden(y) {
+            self.document.open_fold(y);
+        }
+        let terminal_width = self.text_width(&self.document);
+        let terminal_height = self.window_height();
+        let old_offset = self.offset.clone();
+        let offset = &mut self.offset;
+        // 縦方向は折りたたまれた行を除いた表示上の行数で比べる
+        let folds = self.document.folds();
+        let visible_y = folds.to_visible(y);
+        let visible_offset_y = folds.to_visible(offset.y);
+        // カーソルの上下に残す行数(下はドキュメントの末尾より後ろまでスクロールしない)
+        let (margin_above, margin_below) = scroll_margins(
+            self.options.scroll_off,
+            terminal_height,
+            folds,
+            y,
+            self.document.len(),
+        );
+        // カーソルが画面より上(または上の余白の中)
+        if visible_y < visible_offset_y.saturating_add(margin_above) {
+            // カーソルの上に余白を残して画面の一番上に置く
+            offset.y = folds.visible_above(y, margin_above);
+        } else if visible_y.saturating_add(margin_below)
+            >= visible_offset_y.saturating_add(terminal_height)
+        {
+            // カーソルが画面より下の時はカーソルの下に余白を残して画面の一番下に置く
+            offset.y = folds.visible_above(
+                y,
+                terminal_height
+                    .saturating_sub(1)
+                    .saturating_sub(margin_below),
+            );
         }
 
         if let Some(row) = self.document.row(y) {
@@ -370,11 +4733,98 @@ impl Editor {
             if x < offset.x {
                 // カーソルを画面の一番左に置く
                 offset.x = x;
-            } else if half_offset_x.saturating_add(terminal_width) <= half_cursor_x {
+            } else if half_offset_x.saturating_add(terminal_width.saturating_sub(1))
+                <= half_cursor_x
+            {
                 // カーソルが画面右端より右にある時はカーソルを画面の一番右に置く
-                offset.x = row.half2full_width(half_cursor_x.saturating_sub(terminal_width));
+                // 画面の一番右の列には文字を表示しないので、その1つ左に置く
+                offset.x = row.half2full_width(
+                    half_cursor_x
+                        .saturating_add(2)
+                        .saturating_sub(terminal_width),
+                );
             }
         }
+        self.sync_scroll(&old_offset);
+    }
+    // 画面をcount行下(上)にスクロールし、カーソルが画面の外(scrolloffの余白の中)に出たら画面の中に戻す
+    // with_cursorの場合はカーソルも同じ行数だけ移動する
+    fn scroll_view(&mut self, down: bool, count: usize, with_cursor: bool) {
+        let Some(last_y) = self.document.len().checked_sub(1) else {
+            return;
+        };
+        let height = self.window_height();
+        let old_offset = self.offset.clone();
+        let folds = self.document.folds();
+        // 折りたたまれた行を除いた表示上の行数で数える
+        let last = folds.to_visible(last_y);
+        let mut offset = folds.to_visible(self.offset.y);
+        let mut cursor = folds.to_visible(self.cursor_position.y);
+        if down {
+            // カーソルと一緒に動かす場合は最後の行が画面の一番下に来るまで、そうでなければ一番上に来るまで
+            let limit = if with_cursor {
+                last.saturating_sub(height.saturating_sub(1)).max(offset)
+            } else {
+                last
+            };
+            offset = offset.saturating_add(count).min(limit);
+        } else {
+            offset = offset.saturating_sub(count);
+        }
+        if with_cursor {
+            cursor = if down {
+                cursor.saturating_add(count).min(last)
+            } else {
+                cursor.saturating_sub(count)
+            };
+        }
+        let (margin, _) = scroll_margins(
+            self.options.scroll_off,
+            height,
+            folds,
+            self.cursor_position.y,
+            self.document.len(),
+        );
+        let top = if offset == 0 {
+            0
+        } else {
+            offset.saturating_add(margin)
+        };
+        let bottom = offset
+            .saturating_add(height.saturating_sub(1))
+            .saturating_sub(margin);
+        cursor = cursor.max(top).min(bottom).min(last);
+        self.offset.y = folds.to_row(offset);
+        self.cursor_position.y = folds.to_row(cursor);
+        self.sync_scroll(&old_offset);
+    }
+    // zzとztとzbでカーソル行が画面の中央、一番上、一番下に来るようにスクロールする
+    fn scroll_cursor_line(&mut self, place: char) {
+        let y = self.cursor_position.y;
+        let height = self.window_height();
+        let old_offset = self.offset.clone();
+        let folds = self.document.folds();
+        let (margin_above, margin_below) = scroll_margins(
+            self.options.scroll_off,
+            height,
+            folds,
+            y,
+            self.document.len(),
+        );
+        let above = match place {
+            't' => margin_above,
+            'b' => height.saturating_sub(1).saturating_sub(margin_below),
+            #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+            _ => height.saturating_sub(1) / 2,
+        };
+        self.offset.y = folds.visible_above(y, above);
+        self.sync_scroll(&old_offset);
+    }
+    // ドキュメントの末尾より後ろの行頭に表示する文字列
+    fn eob_fill(&self) -> String {
+        self.options
+            .eob
+            .map_or_else(|| " ".to_string(), |eob| eob.to_string())
     }
     fn draw_welcome_message(&self) {
         // バージョン情報を含めたメッセージ
@@ -383,93 +4833,353 @@ impl Editor {
         let width = self.terminal.size().width as usize;
         let len = welcome_message.len();
         // メッセージを中央に置いたときの空けるべき余白を計算
-        #[allow(clippy::integer_arithmetic, clippy::integer_division)]
+        #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
         let padding = width.saturating_sub(len) / 2;
         let spaces = " ".repeat(padding.saturating_sub(1));
         // 画面中央にメッセージを表示
-        welcome_message = format!("~{spaces}{welcome_message}");
-        welcome_message.truncate(width);
-        println!("{welcome_message}\r");
+        welcome_message = format!("{}{spaces}{welcome_message}", self.eob_fill());
+        let welcome_message = width::truncate(&welcome_message, width);
+        Terminal::print(format_args!("{welcome_message}\r\n"));
+    }
+    // 行番号の表示幅
+    fn gutter_width(&self, document: &Document) -> usize {
+        self.options.line_numbers.width(document.len())
     }
-    pub fn draw_row(&self, row: &Row) {
-        let half_width = self.terminal.size().width as usize;
+    // 行番号を除いた本文の表示幅
+    fn text_width(&self, document: &Document) -> usize {
+        (self.terminal.size().width as usize).saturating_sub(self.gutter_width(document))
+    }
+    fn draw_row(
+        &self,
+        row: &Row,
+        offset_x: usize,
+        gutter_width: usize,
+        half_width: usize,
+        overlay: &Overlay,
+        virtual_text: Option<&str>,
+    ) {
         // 表示する内容を指定した範囲で切り取る
         // offsetは全角文字単位、terminal_widthは半角文字単位
-        let row = row.trim_string(self.offset.x, half_width);
+        let text = row.render(offset_x, half_width, overlay, &self.theme);
+        Terminal::print(format_args!("{text}"));
+        // 行末の後ろに収まる分だけ仮の文字列を薄く表示する
+        if let Some(virtual_text) = virtual_text {
+            let rest = half_width
+                .saturating_sub(row.full2half_width(offset_x, row.len()))
+                .saturating_sub(1);
+            Terminal::set_fg_color(self.theme.nontext_fg);
+            Terminal::print(format_args!("{}", width::truncate(virtual_text, rest)));
+            Terminal::reset_fg_color();
+        }
+        // 画面の右端で切れている行は右端に印を表示する
+        if let Some(extends) = self.options.extends {
+            if row.full2half_width(offset_x, row.len()) >= half_width {
+                Terminal::cursor_to_column(
+                    gutter_width.saturating_add(half_width).saturating_sub(1),
+                );
+                Terminal::set_fg_color(self.theme.nontext_fg);
+                Terminal::print(format_args!("{extends}"));
+                Terminal::reset_fg_color();
+            }
+        }
         // カーソルのある行を描画して改行する
-        println!("{row}\r");
+        Terminal::print(format_args!("\r\n"));
     }
-    #[allow(clippy::integer_division, clippy::integer_arithmetic)]
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            let line_number = terminal_row as usize + self.offset.y;
-            // 表示すべきファイルの行があれば表示する
-            if let Some(row) = self.document.row(line_number) {
-                // 表示する行番号が5桁以上の場合は下4桁だけ表示する
-                draw_line_number((line_number + 1) % 10000);
-                self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                // ドキュメントが空であれば、1/3の高さの行にウェルカムメッセージを表示する
-                self.draw_welcome_message();
+    // 折りたたまれた行をまとめて1行で描画する
+    fn draw_fold(&self, row: &Row, lines: usize, width: usize) {
+        let text = format!("+--{lines:>4} lines: {}", row.as_str().trim());
+        let row = Row::from(&text[..]);
+        let text = row.render(0, width, &Overlay::default(), &self.theme);
+        let padding = width.saturating_sub(row.full2half_width(0, row.len()));
+        Terminal::set_bg_color(self.theme.fold_bg);
+        Terminal::print(format_args!("{text}{}", " ".repeat(padding)));
+        Terminal::reset_bg_color();
+        Terminal::print(format_args!("\r\n"));
+    }
+    // 全てのウィンドウを上から順に描画する
+    // 全てのウィンドウを描画し、描画の期限を過ぎて残した行があればtrueを返す(期限は入力中のウィンドウのみ)
+    fn draw_rows(&self, deadline: Option<Instant>) -> bool {
+        let heights = self.window_heights();
+        let mut top: usize = 0;
+        let mut deferred = false;
+        for (index, height) in heights.iter().enumerate() {
+            let rows = top..top.saturating_add(*height);
+            // 区切り行の分も進める
+            top = rows.end.saturating_add(1);
+            let (document, offset, cursor) = if index == self.window_index {
+                (&self.document, &self.offset, &self.cursor_position)
+            } else if let Some(window) = self
+                .windows
+                .get(index.saturating_sub(usize::from(index > self.window_index)))
+            {
+                (&window.document, &window.offset, &window.cursor_position)
+            } else {
+                continue;
+            };
+            // 仮の文字列は入力中のウィンドウにのみ表示する
+            let virtual_text = (index == self.window_index)
+                .then(|| self.virtual_text())
+                .flatten();
+            let deadline = deadline.filter(|_| index == self.window_index);
+            // 選択範囲も入力中のウィンドウにのみ表示する
+            let selection = self
+                .selection
+                .as_ref()
+                .filter(|_| index == self.window_index);
+            deferred |= self.draw_window(
+                document,
+                (offset, cursor),
+                selection,
+                rows,
+                virtual_text.as_deref(),
+                deadline,
+            );
+            // 最後のウィンドウ以外は下にファイル名を表示した区切り行を入れる
+            if index.saturating_add(1) < heights.len() {
+                self.draw_window_separator(document);
+            }
+        }
+        deferred
+    }
+    fn draw_window_separator(&self, document: &Document) {
+        let width = self.terminal.size().width as usize;
+        let mut separator = display_name(document);
+        separator.push_str(&" ".repeat(width.saturating_sub(width::str_width(&separator))));
+        let separator = width::truncate(&separator, width);
+        Terminal::clear_current_line();
+        Terminal::set_bg_color(self.theme.status_bg);
+        Terminal::set_fg_color(self.theme.status_fg);
+        Terminal::print(format_args!("{separator}\r\n"));
+        Terminal::reset_fg_color();
+        Terminal::reset_bg_color();
+    }
+    // ドキュメントをウィンドウの画面上の行の範囲に描画する
+    // 期限がある場合はカーソル行に近い行から描画し、期限を過ぎたら離れた行は前の画面のまま残してtrueを返す
+    #[allow(clippy::integer_division, clippy::arithmetic_side_effects)]
+    fn draw_window(
+        &self,
+        document: &Document,
+        (offset, cursor): (&Position, &Position),
+        selection: Option<&Selection>,
+        rows: Range<usize>,
+        virtual_text: Option<&str>,
+        deadline: Option<Instant>,
+    ) -> bool {
+        let height = rows.len();
+        let gutter_width = self.gutter_width(document);
+        let text_width = self.text_width(document);
+        let folds = document.folds();
+        let diagnostics = self.diagnostics(document);
+        let cursor_y = folds.to_visible(cursor.y);
+        // カーソル列は画面左端からの半角文字単位の位置で揃える
+        let column = self
+            .options
+            .cursor_column
+            .then(|| {
+                document
+                    .row(cursor.y)
+                    .map_or(0, |row| row.full2half_width(offset.x, cursor.x))
+            })
+            .map(|column| (column, self.theme.cursorline_bg));
+        // ウィンドウの各行に表示するドキュメントの行(折りたたまれた行は先頭の行)
+        let mut lines = Vec::with_capacity(height);
+        let mut line_number = offset.y;
+        for _ in 0..height {
+            if line_number < document.len() {
+                lines.push(Some(line_number));
+                line_number = folds
+                    .end_of(line_number)
+                    .unwrap_or(line_number.saturating_add(1));
             } else {
-                // 行頭にチルダを表示
-                println!("~\r");
+                lines.push(None);
             }
         }
+        let cursor_row = cursor_y.saturating_sub(folds.to_visible(offset.y));
+        let mut order: Vec<usize> = (0..height).collect();
+        if deadline.is_some() {
+            order.sort_by_key(|terminal_row| terminal_row.abs_diff(cursor_row));
+        }
+        let mut deferred = false;
+        for terminal_row in order {
+            if terminal_row.abs_diff(cursor_row) > NEARBY_ROWS
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                deferred = true;
+                continue;
+            }
+            let y = rows.start + terminal_row;
+            let output = Terminal::record(|| {
+                Terminal::cursor_position(&Position { x: 0, y });
+                Terminal::clear_current_line();
+                let line = lines
+                    .get(terminal_row)
+                    .copied()
+                    .flatten()
+                    .and_then(|line_number| Some((line_number, document.row(line_number)?)));
+                // 表示すべきファイルの行があれば表示する
+                if let Some((line_number, row)) = line {
+                    let distance = folds.to_visible(line_number).abs_diff(cursor_y);
+                    self.options.line_numbers.draw(
+                        line_number,
+                        distance,
+                        gutter_width,
+                        &self.theme,
+                    );
+                    if let Some(end) = folds.end_of(line_number) {
+                        // 折りたたまれた行は行数と先頭行の内容のみ表示する
+                        self.draw_fold(row, end.saturating_sub(line_number), text_width);
+                    } else {
+                        let mut columns: Vec<(usize, Rgb)> = column.iter().copied().collect();
+                        columns.extend(self.color_columns(row, offset.x));
+                        let overlay = Overlay {
+                            line: (self.options.cursor_line && line_number == cursor.y)
+                                .then_some(self.theme.cursorline_bg),
+                            columns,
+                            underlines: diagnostic_underlines(
+                                diagnostics,
+                                row,
+                                line_number,
+                                offset.x,
+                            ),
+                            selection: selected_span(selection, cursor, row, line_number, offset.x)
+                                .map(|(start, end)| (start, end, self.theme.visual_bg)),
+                        };
+                        self.draw_row(
+                            row,
+                            offset.x,
+                            gutter_width,
+                            text_width,
+                            &overlay,
+                            virtual_text.filter(|_| line_number == cursor.y),
+                        );
+                    }
+                } else if self.options.welcome
+                    && document.is_empty()
+                    && document.file_name.is_none()
+                    && terminal_row == height / 3
+                {
+                    // 名前の無いドキュメントが空であれば、1/3の高さの行にウェルカムメッセージを表示する
+                    self.draw_welcome_message();
+                } else {
+                    // 行頭に末尾より後ろであることを示す文字を表示
+                    Terminal::print(format_args!("{}\r\n", self.eob_fill()));
+                }
+            });
+            self.draw_row_output(y, output);
+        }
+        Terminal::cursor_position(&Position { x: 0, y: rows.end });
+        deferred
     }
-    fn draw_status_bar(&self) {
-        let mut status;
-        // 更新されていた場合
-        let modified_indicator = if self.document.is_dirty() {
-            " (modified)"
+    // 画面上のy行目に前回と違う内容を出力する場合だけ出力する
+    fn draw_row_output(&self, y: usize, output: String) {
+        let mut drawn_rows = self.drawn_rows.borrow_mut();
+        if drawn_rows.len() <= y {
+            drawn_rows.resize(y.saturating_add(1), None);
+        }
+        if let Some(drawn) = drawn_rows
+            .get_mut(y)
+            .filter(|drawn| **drawn != Some(output.clone()))
+        {
+            Terminal::print(format_args!("{output}"));
+            *drawn = Some(output);
+        }
+    }
+    fn redraw_all(&self) {
+        Terminal::clear_screen();
+        self.forget_drawn_rows(0..usize::MAX);
+    }
+    // 画面上の行の範囲を、次の描画で内容が変わらなくても出力し直すようにする
+    // (ポップアップなどを重ねて表示した行や、画面全体を描き直すときに使う)
+    fn forget_drawn_rows(&self, rows: Range<usize>) {
+        for drawn in self
+            .drawn_rows
+            .borrow_mut()
+            .iter_mut()
+            .take(rows.end)
+            .skip(rows.start)
+        {
+            *drawn = None;
+        }
+    }
+    // カーソル行の行末に表示する仮の文字列(表示中の略語の展開)
+    fn virtual_text(&self) -> Option<String> {
+        let abbreviation = self.abbreviation_preview.as_ref()?;
+        let expansion = self.abbreviations.get(abbreviation)?;
+        Some(format!(" \u{21e5} {expansion}"))
+    }
+    // 縦の目安線の画面左端からの位置(半角文字単位)と色
+    // 横にスクロールしている場合は、行ごとに画面左端より左の文字の表示幅だけずらす
+    fn color_columns(&self, row: &Row, offset_x: usize) -> Vec<(usize, Rgb)> {
+        // 行末より右までスクロールしている分は半角文字とみなす
+        let scrolled = row
+            .full2half_width(0, offset_x)
+            .saturating_add(offset_x.saturating_sub(row.len()));
+        self.options
+            .color_columns
+            .iter()
+            .filter_map(|column| column.checked_sub(1)?.checked_sub(scrolled))
+            .map(|column| (column, self.theme.colorcolumn_bg))
+            .collect()
+    }
+    fn draw_status_bar(&mut self) {
+        // ブランチ名は書式で使う場合のみ読む
+        let branch = if self.options.status_line.contains("%b") {
+            self.git_branch
+                .get(self.document.file_name.as_deref())
+                .map(str::to_string)
         } else {
-            ""
+            None
         };
-        // ファイル名が指定されなかった場合のデフォルトの表示名
-        let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            // ファイル名で20文字を超えていた分は表示しない
-            file_name.truncate(60);
+        let mut flags = String::new();
+        // ウィンドウがバッファに固定されている場合
+        if self.pinned {
+            flags.push_str(" [pinned]");
         }
-        // ファイル名
-        status = format!("{file_name}  ");
-        // カーソルのある行/総行数 (最初を1とする)
-        let line_indicator = format!(
-            "{} | line: {}/{}  ",
-            self.document.file_type(),
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
-        );
-        let char_width = if let Some(row) = self.document.row(self.cursor_position.y) {
-            row.len()
-        } else {
-            0
+        // 書き込みが許可されていないファイルの場合
+        if self.document.is_read_only() {
+            flags.push_str(" [RO]");
+        }
+        // 別スレッドで読み込み中の場合は進み具合
+        if let Some(progress) = self.document.loading_progress() {
+            flags = format!("{flags} [loading {progress}%]");
+        }
+        let fields = statusline::Fields {
+            file_name: display_name(&self.document),
+            file_type: self.document.file_type(),
+            mode: match self.selection.as_ref() {
+                _ if !self.vim_normal_mode => "INSERT",
+                Some(selection) if selection.linewise => "V-LINE",
+                Some(_) => "VISUAL",
+                None => "NORMAL",
+            },
+            // カーソルのある行/総行数、カーソルの行頭からの文字数/総文字数 (最初を1とする)
+            line: self.cursor_position.y.saturating_add(1),
+            lines: self.document.len(),
+            column: self.cursor_position.x.saturating_add(1),
+            columns: self
+                .document
+                .row(self.cursor_position.y)
+                .map_or(0, Row::len),
+            character: self
+                .document
+                .row(self.cursor_position.y)
+                .and_then(|row| row.grapheme(self.cursor_position.x))
+                .and_then(|grapheme| grapheme.chars().next()),
+            modified: self.document.is_dirty(),
+            flags,
+            branch,
         };
-        // カーソルの行頭からの文字数/総文字数
-        let column_indicator = format!(
-            "col: {}/{char_width}",
-            self.cursor_position.x.saturating_add(1),
+        // 画面に収まりきらない部分は表示幅で削る
+        let status = statusline::render(
+            &self.options.status_line,
+            &fields,
+            self.terminal.size().width as usize,
         );
-        #[allow(clippy::integer_arithmetic)]
-        let show_len =
-            status.len() + line_indicator.len() + column_indicator.len() + modified_indicator.len();
-        // 行番号表示スペースも考慮する
-        let terminal_width =
-            (self.terminal.size().width as usize).saturating_add(LINE_NUMBER_SPACES);
-        // 左端のファイル名と右端の行数表示の間は半角空白で埋める
-        status.push_str(&" ".repeat(terminal_width.saturating_sub(show_len)));
-
-        status = format!("{status}{line_indicator}{column_indicator}{modified_indicator}");
-        // 画面に収まりきらない部分は削る
-        status.truncate(terminal_width);
         // 背景色、文字色を設定
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
+        Terminal::set_bg_color(self.theme.status_bg);
+        Terminal::set_fg_color(self.theme.status_fg);
         // ステータスバー上の文字を表示
-        println!("{status}\r");
+        Terminal::print(format_args!("{status}\r\n"));
         Terminal::reset_fg_color();
         Terminal::reset_bg_color();
     }
@@ -477,56 +5187,200 @@ impl Editor {
         // メッセージバーをクリア
         Terminal::clear_current_line();
         let message = &self.status_message;
+        let width = self.terminal.size().width as usize;
         // メッセージが表示開始から一定時間経過するまで表示
-        if message.time.elapsed() < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            // 画面からはみ出すメッセージ部分は削除
-            text.truncate((self.terminal.size().width as usize).saturating_add(LINE_NUMBER_SPACES));
-            print!("{text}");
+        if !message.text.is_empty() && message.time.elapsed() < MESSAGE_DURATION {
+            // 最後の行の右端に書くと端末がスクロールすることがあるので、右端の列は空けておく
+            let width = width.saturating_sub(SHOWCMD_WIDTH.saturating_add(1));
+            // エラーと複数行のメッセージは折り返して全て表示し、それ以外ははみ出す部分を削る
+            if message.text.contains('\n')
+                || COMMAND_ERRORS
+                    .iter()
+                    .any(|prefix| message.text.starts_with(prefix))
+            {
+                self.draw_message_lines(&width::wrap(&message.text, width));
+            } else {
+                let text = width::truncate(&message.text, width);
+                Terminal::print(format_args!("{text}"));
+            }
+        } else if !self.vim_normal_mode {
+            // メッセージが無ければ挿入モードであることを表示
+            Terminal::print(format_args!("-- INSERT --"));
+        } else if let Some(selection) = &self.selection {
+            // メッセージが無ければビジュアルモードであることを表示
+            let line = if selection.linewise { " LINE" } else { "" };
+            Terminal::print(format_args!("-- VISUAL{line} --"));
+        } else if let Some((register, _)) = &self.recording {
+            // メッセージが無ければマクロの記録中であることを表示
+            Terminal::print(format_args!("recording @{register}"));
+        } else if let Some(diagnostic) = self.cursor_diagnostic() {
+            // カーソル行に診断結果があればそのメッセージを表示
+            let message = format!(
+                "{}: {}",
+                diagnostic.severity.label(),
+                diagnostic.message.lines().next().unwrap_or_default()
+            );
+            let text = width::truncate(
+                &message,
+                width.saturating_sub(SHOWCMD_WIDTH.saturating_add(1)),
+            );
+            Terminal::print(format_args!("{text}"));
+        }
+        // 入力途中のコマンドは右端に表示する(最後の列には表示しない)
+        let pending = self.pending_indicator();
+        if !pending.is_empty() {
+            Terminal::cursor_to_column(width.saturating_sub(SHOWCMD_WIDTH.saturating_add(1)));
+            Terminal::print(format_args!("{pending}"));
+        }
+    }
+    // メッセージバーに収まらない行は、最後の行がメッセージバーになるように上の行に重ねて表示する
+    // 多すぎる行は省略する(:messagesで全て見られる)
+    // プロンプトで補完中の候補をメッセージバーの上の行に並べ、選択中の候補の色を反転して表示する
+    // 入りきらなければ選択中の候補が見えるように先頭の候補を省く
+    fn draw_prompt_completion(&self) {
+        let Some(completion) = &self.prompt_completion else {
+            return;
+        };
+        let width = usize::from(self.terminal.size().width);
+        let selected = completion.selected.unwrap_or_default();
+        let mut first = 0;
+        while first < selected
+            && completion
+                .candidates
+                .iter()
+                .take(selected.saturating_add(1))
+                .skip(first)
+                .map(|candidate| width::str_width(candidate).saturating_add(2))
+                .sum::<usize>()
+                > width
+        {
+            first = first.saturating_add(1);
+        }
+        Terminal::cursor_position(&Position {
+            x: 0,
+            y: usize::from(self.terminal.size().height),
+        });
+        Terminal::set_bg_color(self.theme.status_bg);
+        Terminal::set_fg_color(self.theme.status_fg);
+        Terminal::clear_current_line();
+        let mut rest = width;
+        for (index, candidate) in completion.candidates.iter().enumerate().skip(first) {
+            let text = width::truncate(candidate, rest.saturating_sub(2));
+            if text.is_empty() {
+                break;
+            }
+            if Some(index) == completion.selected {
+                Terminal::set_bg_color(self.theme.status_fg);
+                Terminal::set_fg_color(self.theme.status_bg);
+            }
+            Terminal::print(format_args!(" {text}"));
+            Terminal::set_bg_color(self.theme.status_bg);
+            Terminal::set_fg_color(self.theme.status_fg);
+            Terminal::print(format_args!(" "));
+            rest = rest.saturating_sub(width::str_width(text).saturating_add(2));
+        }
+        Terminal::reset_fg_color();
+        Terminal::reset_bg_color();
+    }
+    fn draw_message_lines(&self, lines: &[&str]) {
+        let bar_y = usize::from(self.terminal.size().height).saturating_add(1);
+        let count = lines
+            .len()
+            .min(MAX_MESSAGE_LINES)
+            .min(bar_y.saturating_add(1));
+        self.forget_drawn_rows(bar_y.saturating_add(1).saturating_sub(count)..bar_y);
+        for (index, line) in lines.iter().take(count).enumerate() {
+            let y = bar_y
+                .saturating_add(index)
+                .saturating_sub(count.saturating_sub(1));
+            Terminal::cursor_position(&Position { x: 0, y });
+            Terminal::clear_current_line();
+            if index.saturating_add(1) == count && lines.len() > count {
+                Terminal::print(format_args!("... (see :messages)"));
+            } else {
+                Terminal::print(format_args!("{line}"));
+            }
+        }
+    }
+    // 入力途中のノーマルモードのコマンドを表示用の文字列で返す(制御文字は^Wのように表す)
+    fn pending_indicator(&self) -> String {
+        let mut indicator: String = self
+            .pending_keys
+            .chars()
+            .map(|c| match u8::try_from(c) {
+                Ok(byte) if byte < b' ' => format!("^{}", char::from(byte.saturating_add(b'@'))),
+                _ => c.to_string(),
+            })
+            .collect();
+        // 表示しきれない場合は最後の方を表示する
+        let overflow = indicator.chars().count().saturating_sub(SHOWCMD_WIDTH);
+        if overflow > 0 {
+            indicator = indicator.chars().skip(overflow).collect();
         }
+        indicator
     }
-    // 引数の文字列を表示してから文字入力を受け付け、入力された文字を返す
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    // 入力済みの文字列、上下キーでたどる履歴、Tabで補完する候補の種類を持つ入力欄でプロンプトを表示し、
+    // 入力された文字列を返す
+    fn prompt_with<C>(
+        &mut self,
+        prompt: &str,
+        mut input: prompt::Input,
+        mut callback: C,
+    ) -> Result<Option<String>, std::io::Error>
     where
         C: FnMut(&mut Self, Key, &String),
     {
-        let mut result = String::new();
         // 改行またはEscが入力されるまでループ
         loop {
-            // プロンプト表示
-            self.status_message = StatusMessage::from(format!("{prompt}{result}"));
+            // プロンプト表示(入力途中の内容はメッセージの履歴に残さない)
+            let head = format!("{}{prompt}", self.prompt_indicator);
+            self.prompt_cursor = Some(width::str_width(&head).saturating_add(input.cursor_width()));
+            self.prompt_completion = input.completion().cloned();
+            self.status_message = StatusMessage {
+                logged: true,
+                ..StatusMessage::from(format!("{head}{}", input.text()))
+            };
             self.refresh_screen()?;
 
             // 1文字ずつ読み込む
-            let key = Terminal::read_key()?;
+            let key = self.read_key()?;
             match key {
-                Key::Backspace => {
-                    // 最後の1文字を削除
-                    result = result[..]
-                        .graphemes(true)
-                        .take(result[..].graphemes(true).count().saturating_sub(1))
-                        .collect();
-                }
                 // 改行が入力されたら入力終了
                 Key::Char('\n') => break,
-                Key::Char(c) => {
-                    // 入力文字が制御文字でなければ追加
-                    if !c.is_control() {
-                        result.push(c);
+                // Ctrl-Rに続けてレジスタ名を入力したらレジスタの内容を挿入する
+                Key::Ctrl('r') => {
+                    if let Some(text) = self.prompt_register_text()? {
+                        input.insert(&text);
+                    }
+                }
+                // Ctrl-Vで無名レジスタの内容を貼り付ける
+                Key::Ctrl('v') => {
+                    if let Some(text) = self
+                        .registers
+                        .get('"')
+                        .and_then(|register| register.lines.first().cloned())
+                    {
+                        input.insert(&text);
                     }
                 }
                 Key::Esc => {
                     // それまでの入力内容を破棄して終了
-                    result = String::new();
+                    input.clear();
                     break;
                 }
-                _ => (),
+                key => {
+                    input.edit(key);
+                }
             }
             // 入力されるたびに実行される
-            callback(self, key, &result);
+            callback(self, key, &input.text().to_string());
         }
+        self.prompt_cursor = None;
+        self.prompt_completion = None;
+        let result = input.text().to_string();
         // ステータスメッセージを初期化
         self.status_message = StatusMessage::from(String::new());
+        self.prompt_indicator = String::new();
         if result.is_empty() {
             return Ok(None);
         }
@@ -535,19 +5389,286 @@ impl Editor {
     }
 }
 
-// 右揃え空白詰めで行番号表示
-fn draw_line_number(line_number: usize) {
-    Terminal::set_bg_color(LINE_NUMBER_BG_COLOR);
-    // 行番号表示の後に半角スペースを1つ入れる
-    print!(
-        "{line_number:>digits_width$} ",
-        digits_width = LINE_NUMBER_SPACES.saturating_sub(1)
-    );
-    Terminal::reset_bg_color();
+// 診断結果の範囲に引く下線の、画面左端からの半角文字単位の範囲
+fn diagnostic_underlines(
+    diagnostics: &[lsp::Diagnostic],
+    row: &Row,
+    line_number: usize,
+    offset_x: usize,
+) -> Vec<(usize, usize)> {
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| lsp::range_in_line(diagnostic, line_number, row.as_str()))
+        .map(|(start, end)| {
+            (
+                row.full2half_width(offset_x, start),
+                row.full2half_width(offset_x, end),
+            )
+        })
+        .collect()
+}
+
+// line_number行目で選択されている範囲の、画面左端からの半角文字単位の範囲(行末の改行は1文字分とする)
+fn selected_span(
+    selection: Option<&Selection>,
+    cursor: &Position,
+    row: &Row,
+    line_number: usize,
+    offset_x: usize,
+) -> Option<(usize, usize)> {
+    let len = row.len();
+    let Range { start, end } = selection?.columns(cursor, line_number, len)?;
+    let newline = usize::from(end > len && offset_x <= len);
+    Some((
+        row.full2half_width(offset_x, start.min(len)),
+        row.full2half_width(offset_x, end.min(len))
+            .saturating_add(newline),
+    ))
+}
+
+// scrolloffに従ってy行目の上下に残す表示上の行数
+// 画面の半分を超えないようにし、下はドキュメントの最後の行より後ろを残さない
+fn scroll_margins(
+    scroll_off: usize,
+    height: usize,
+    folds: &Folds,
+    y: usize,
+    document_len: usize,
+) -> (usize, usize) {
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+    let margin = scroll_off.min(height.saturating_sub(1) / 2);
+    let below = folds
+        .to_visible(document_len.saturating_sub(1))
+        .saturating_sub(folds.to_visible(y));
+    (margin, margin.min(below))
+}
+
+// ステータスバーなどに表示するバッファ名を返す
+fn display_name(document: &Document) -> String {
+    // ファイル名が指定されなかった場合のデフォルトの表示名
+    let mut file_name = "[No Name]".to_string();
+    if let BufferKind::Explorer(dir) = document.kind() {
+        // ファイル一覧では表示中のディレクトリを表示
+        file_name = format!("{}/", explorer::relative(dir));
+    } else if let BufferKind::QuickFix(title)
+    | BufferKind::Output(title)
+    | BufferKind::GrepEdit(title, _) = document.kind()
+    {
+        file_name = format!("[{title}]");
+    } else if let BufferKind::Macro(register) = document.kind() {
+        file_name = format!("[macro @{register}]");
+    } else if *document.kind() == BufferKind::CommandLine {
+        file_name = "[Command Line]".to_string();
+    } else if let Some(name) = &document.file_name {
+        file_name.clone_from(name);
+        // ファイル名で20文字を超えていた分は表示しない
+        file_name.truncate(60);
+    }
+    file_name
+}
+
+// ファイル(ディレクトリの場合はファイル一覧)を開き、開いたときに表示するメッセージがあれば一緒に返す
+// 開けなかった場合は原因に応じて代わりのドキュメントを返し、代わりも無ければエラーメッセージを返す
+fn open_path(file_name: &str) -> Result<(Document, Option<String>), String> {
+    let path = Path::new(file_name);
+    if path.is_dir() {
+        return explorer::open(path)
+            .map(|document| (document, None))
+            .map_err(|error| format!("ERR: Could not open directory: {file_name}: {error}"));
+    }
+    match Document::open(file_name) {
+        Ok(document) => {
+            let message = document
+                .is_read_only()
+                .then(|| format!("\"{file_name}\" [readonly]"));
+            Ok((document, message))
+        }
+        Err(error) => match error.kind() {
+            // 存在しないファイルは空のバッファで開き、保存したときに作る
+            io::ErrorKind::NotFound => Ok((
+                Document::new_file(file_name),
+                Some(format!("\"{file_name}\" [New File]")),
+            )),
+            io::ErrorKind::PermissionDenied => Err(format!("ERR: Permission denied: {file_name}")),
+            // UTF-8として読めないファイルは16進数で表示する
+            io::ErrorKind::InvalidData => {
+                let bytes = fs::read(file_name)
+                    .map_err(|error| format!("ERR: Could not open file: {file_name}: {error}"))?;
+                Ok((
+                    Document::hex_view(file_name, &bytes),
+                    Some(format!("\"{file_name}\" is not valid UTF-8 (hex dump)")),
+                ))
+            }
+            _ => Err(format!("ERR: Could not open file: {file_name}: {error}")),
+        },
+    }
+}
+
+// ファイルを開き直したときに戻す表示の状態(保存しないファイルタイプの場合はNone)
+fn view_of(document: &Document, offset: &Position, file_types: Option<&str>) -> Option<View> {
+    view::is_enabled(file_types, &document.file_type()).then(|| View {
+        folds: document.folds().ranges().to_vec(),
+        offset_x: offset.x,
+        options: document.local_options(),
+    })
+}
+
+// ページャで表示するドキュメントを作る(ファイル名が無ければ標準入力を読む)
+// エスケープシーケンスの色を再現し、ファイル名からシンタックスハイライトも行う
+fn pager_document(file_name: Option<&String>) -> Result<Document, std::io::Error> {
+    let (bytes, title) = if let Some(file_name) = file_name {
+        (fs::read(file_name)?, file_name.clone())
+    } else {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        (bytes, "stdin".to_string())
+    };
+    let text = String::from_utf8_lossy(&bytes);
+    let mut document = Document::from_ansi(&text, BufferKind::Output(title.clone()));
+    document.set_file_type(FileType::from(&title));
+    Ok(document)
+}
+
+// プラグインに渡すバッファの内容とカーソル位置(行と列は0から数える)
+fn plugin_params(document: &Document, at: &Position) -> Value {
+    json!({
+        "file_name": document.file_name,
+        "file_type": document.file_type(),
+        "lines": document.all_lines(),
+        "cursor": { "line": at.y, "column": at.x }
+    })
+}
+
+// フックを登録したプラグインに順に内容を渡し、応答の内容で置き換える
+// 置き換えた後のカーソル位置と、表示するメッセージ(エラーの場合はERR: で始まる)を返す
+fn run_hook(
+    plugins: &mut [Plugin],
+    hook: Hook,
+    document: &mut Document,
+    at: &Position,
+) -> (Position, Option<String>) {
+    let mut position = at.clone();
+    let mut message = None;
+    // 読み込み中の大きなファイルや、ファイル以外のバッファには適用しない
+    if *document.kind() != BufferKind::File || document.loading_progress().is_some() {
+        return (position, message);
+    }
+    for plugin in plugins
+        .iter_mut()
+        .filter(|plugin| plugin.hooks.contains(&hook))
+    {
+        match plugin.request(hook.method(), &plugin_params(document, &position)) {
+            Ok(reply) => {
+                if let Some(lines) = reply.lines.filter(|_| document.is_editable()) {
+                    position = document.replace_all_lines(&lines, &position);
+                }
+                message = reply.message.or(message);
+            }
+            Err(error) => message = Some(format!("ERR: {error}")),
+        }
+    }
+    (position, message)
+}
+
+// :makeの出力を追記する出力バッファか
+fn is_make_output(document: &Document) -> bool {
+    matches!(document.kind(), BufferKind::Output(title) if title.starts_with(MAKE_TITLE))
+}
+
+// 本人だけが読み書きできるファイルに書き出す
+// 一時ディレクトリでは他のユーザが先に作ったファイルやリンクに書き込まないように、新しく作る場合のみ書き出す
+fn write_private_file(path: &Path, contents: &[u8], create_new: bool) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).mode(0o600);
+    if create_new {
+        options.create_new(true);
+    } else {
+        options.create(true).truncate(true);
+    }
+    let mut file = options.open(path)?;
+    // 既にあったファイルは作成時のモードが使われないので変更する
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+// 標準入力の内容を編集できるファイル名の無いドキュメントにする
+fn stdin_document() -> Result<Document, std::io::Error> {
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes)?;
+    Ok(unnamed_document(&bytes))
+}
+
+// 読み込んだ内容をファイル名の無いバッファにする(保存時にファイル名を尋ねる)
+fn unnamed_document(bytes: &[u8]) -> Document {
+    let lines: Vec<String> = String::from_utf8_lossy(bytes)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    Document::from_lines(&lines, BufferKind::File)
+}
+
+// コマンド名の後ろの引数部分を返す
+fn command_argument<'a>(command: &'a str, name: &str) -> &'a str {
+    command
+        .trim_start()
+        .strip_prefix(name)
+        .unwrap_or_default()
+        .trim()
+}
+
+// /pattern/replacement/のように先頭の文字で区切られた文字列を分割する
+// 区切り文字は\でエスケープでき、末尾の区切り文字は省略できる
+fn split_pattern(text: &str) -> Option<Vec<String>> {
+    let mut chars = text.chars();
+    let delimiter = chars
+        .next()
+        .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && *c != '\\')?;
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut()?;
+        if c == delimiter {
+            parts.push(String::new());
+        } else if c == '\\' {
+            match chars.next() {
+                Some(next) if next == delimiter => part.push(next),
+                Some(next) => {
+                    part.push(c);
+                    part.push(next);
+                }
+                None => part.push(c),
+            }
+        } else {
+            part.push(c);
+        }
+    }
+    Some(parts)
+}
+
+// コマンドを先頭の行の範囲(%、行番号、.、$、'{印}、+N、-Nとカンマ)と残りに分ける
+fn split_range(command: &str) -> (&str, &str) {
+    let mut after_quote = false;
+    let end = command
+        .char_indices()
+        .find(|&(_, c)| {
+            // 'に続く1文字は印の名前
+            if after_quote {
+                after_quote = false;
+                return false;
+            }
+            after_quote = c == '\'';
+            !(after_quote || c.is_ascii_digit() || "%.$+-, ".contains(c))
+        })
+        .map_or(command.len(), |(index, _)| index);
+    command.split_at(end)
 }
 
-fn die(e: &std::io::Error) {
-    // エラーで終了前に画面をクリア
-    Terminal::clear_screen();
-    panic!("{}", e);
+// s/pattern/replacement/flagsの形式のコマンドを解析する
+fn parse_substitute(command: &str) -> Option<Substitute> {
+    let mut parts = split_pattern(command.strip_prefix('s')?)?.into_iter();
+    let pattern = parts.next()?;
+    Some(Substitute {
+        pattern,
+        replacement: parts.next().unwrap_or_default(),
+        flags: parts.next().unwrap_or_default(),
+    })
 }
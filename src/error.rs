@@ -0,0 +1,42 @@
+// エディタを続けられなくなったエラー
+use std::fmt;
+use std::io;
+
+/// エディタを続けられなくなったエラー
+#[derive(Debug)]
+pub enum Error {
+    /// 端末を使う準備に失敗した
+    Init(io::Error),
+    /// 状態のファイルか復元するファイルを読み込めなかった(パスフレーズが違うなど)
+    State(io::Error),
+    /// キー入力の読み込みか画面の描画に失敗した
+    Terminal(io::Error),
+    /// 処理中にパニックした(メッセージはパニック時のフックが表示する)
+    Panic,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Init(error) => write!(f, "failed to initialize the terminal: {error}"),
+            Self::Terminal(error) => write!(f, "terminal I/O failed: {error}"),
+            Self::State(error) => write!(f, "could not read the state or recovery file: {error}"),
+            Self::Panic => write!(f, "internal error"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Init(error) | Self::State(error) | Self::Terminal(error) => Some(error),
+            Self::Panic => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Terminal(error)
+    }
+}
@@ -0,0 +1,52 @@
+use crate::document::BufferKind;
+use crate::Document;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 親ディレクトリを表すエントリ
+const PARENT_ENTRY: &str = "../";
+
+// 指定したディレクトリの一覧をドキュメントとして開く
+pub fn open(dir: &Path) -> Result<Document, std::io::Error> {
+    let dir = dir.canonicalize()?;
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // ディレクトリは末尾に/を付けて区別する
+        if entry.path().is_dir() {
+            dirs.push(format!("{name}/"));
+        } else {
+            files.push(name);
+        }
+    }
+    dirs.sort();
+    files.sort();
+    // 親ディレクトリ、ディレクトリ、ファイルの順に並べる
+    let mut lines = vec![PARENT_ENTRY.to_string()];
+    lines.append(&mut dirs);
+    lines.append(&mut files);
+    Ok(Document::from_lines(&lines, BufferKind::Explorer(dir)))
+}
+
+// 一覧の行に対応するパスを返す
+pub fn entry(dir: &Path, line: &str) -> PathBuf {
+    if line == PARENT_ENTRY {
+        return dir.parent().unwrap_or(dir).to_path_buf();
+    }
+    dir.join(line.trim_end_matches('/'))
+}
+
+// カレントディレクトリ以下のパスであれば相対パスに変換する
+pub fn relative(path: &Path) -> String {
+    let relative = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf());
+    // カレントディレクトリ自身は.で表す
+    if relative.as_os_str().is_empty() {
+        return ".".to_string();
+    }
+    relative.to_string_lossy().to_string()
+}
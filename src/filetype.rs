@@ -1,8 +1,235 @@
+use crate::syntax::{Definition, Definitions};
+
+// gitがメッセージを編集させるときに使うファイル名
+const GIT_MESSAGE_FILES: [&str; 5] = [
+    "COMMIT_EDITMSG",
+    "MERGE_MSG",
+    "TAG_EDITMSG",
+    "EDIT_DESCRIPTION",
+    "git-rebase-todo",
+];
+
+// 拡張子(小文字)とファイルタイプ名
+const EXTENSIONS: [(&str, &str); 29] = [
+    ("rs", "rust"),
+    ("md", "markdown"),
+    ("markdown", "markdown"),
+    ("diff", "diff"),
+    ("patch", "diff"),
+    ("py", "python"),
+    ("pyw", "python"),
+    ("sh", "sh"),
+    ("bash", "sh"),
+    ("zsh", "sh"),
+    ("rb", "ruby"),
+    ("pl", "perl"),
+    ("pm", "perl"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("cxx", "cpp"),
+    ("hh", "cpp"),
+    ("hpp", "cpp"),
+    ("go", "go"),
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("cjs", "javascript"),
+    ("ts", "typescript"),
+    ("toml", "toml"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("json", "json"),
+];
+
+// シバンで指定されるインタプリタ(バージョンの数字を除く)とファイルタイプ名
+const INTERPRETERS: [(&str, &str); 9] = [
+    ("python", "python"),
+    ("sh", "sh"),
+    ("bash", "sh"),
+    ("zsh", "sh"),
+    ("dash", "sh"),
+    ("ksh", "sh"),
+    ("ruby", "ruby"),
+    ("perl", "perl"),
+    ("node", "javascript"),
+];
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+const SHELL_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+    "local", "return", "then", "until", "while",
+];
+const RUBY_KEYWORDS: &[&str] = &[
+    "begin", "break", "case", "class", "def", "do", "else", "elsif", "end", "ensure", "false",
+    "for", "if", "in", "module", "next", "nil", "require", "rescue", "return", "self", "then",
+    "true", "unless", "until", "when", "while", "yield",
+];
+const PERL_KEYWORDS: &[&str] = &[
+    "else", "elsif", "for", "foreach", "if", "last", "local", "my", "next", "our", "package",
+    "return", "sub", "unless", "until", "use", "while",
+];
+const C_KEYWORDS: &[&str] = &[
+    "break", "case", "const", "continue", "default", "do", "else", "enum", "extern", "for", "goto",
+    "if", "inline", "register", "return", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "volatile", "while",
+];
+const CPP_KEYWORDS: &[&str] = &[
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "constexpr",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "explicit",
+    "extern",
+    "false",
+    "for",
+    "friend",
+    "if",
+    "inline",
+    "namespace",
+    "new",
+    "nullptr",
+    "operator",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "typename",
+    "union",
+    "using",
+    "virtual",
+    "while",
+];
+const C_TYPES: &[&str] = &[
+    "bool", "char", "double", "float", "int", "long", "short", "signed", "size_t", "unsigned",
+    "void",
+];
+const GO_KEYWORDS: &[&str] = &[
+    "break",
+    "case",
+    "chan",
+    "const",
+    "continue",
+    "default",
+    "defer",
+    "else",
+    "fallthrough",
+    "false",
+    "for",
+    "func",
+    "go",
+    "goto",
+    "if",
+    "import",
+    "interface",
+    "map",
+    "nil",
+    "package",
+    "range",
+    "return",
+    "select",
+    "struct",
+    "switch",
+    "true",
+    "type",
+    "var",
+];
+const GO_TYPES: &[&str] = &[
+    "bool", "byte", "error", "float32", "float64", "int", "int8", "int16", "int32", "int64",
+    "rune", "string", "uint", "uint8", "uint16", "uint32", "uint64", "uintptr",
+];
+const JS_KEYWORDS: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "null",
+    "return",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "yield",
+];
+const TS_TYPES: &[&str] = &[
+    "any",
+    "boolean",
+    "interface",
+    "never",
+    "number",
+    "string",
+    "type",
+    "unknown",
+];
+
+/// ファイル名や最初の行から判定した言語と、その言語のハイライトと編集の設定
 pub struct FileType {
     name: String,
     hl_opts: HighlightingOptions,
+    editing_opts: EditingOptions,
+}
+
+/// ファイルタイプごとの編集時の動作
+pub struct EditingOptions {
+    // 挿入モードで自動的に閉じる括弧や引用符の組
+    auto_pairs: Vec<(char, char)>,
+    // 行コメントの記号(無い場合はNone)
+    line_comment: Option<String>,
+    // 文章を書くファイルタイプか(textwidthでの自動的な折り返しの対象にする)
+    prose: bool,
 }
 
+/// ファイルタイプごとにハイライトする対象
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Default)]
 pub struct HighlightingOptions {
@@ -11,6 +238,12 @@ pub struct HighlightingOptions {
     strings: bool,
     characters: bool,
     comments: bool,
+    // 行頭の#から行末までをコメントとするか
+    hash_comments: bool,
+    // unified形式の差分として行頭の+、-、@で行全体をハイライトし、変更した行の組の中で変わった文字を強調するか
+    diff: bool,
+    // 行の途中からでも行末までをコメントとする記号(ユーザが定義したファイルタイプのみ)
+    line_comment: Option<String>,
     primary_keywords: Vec<String>,
     secondary_keywords: Vec<String>,
 }
@@ -20,120 +253,368 @@ impl Default for FileType {
         Self {
             name: String::from("No filetype"),
             hl_opts: HighlightingOptions::default(),
+            editing_opts: EditingOptions {
+                auto_pairs: vec![('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')],
+                line_comment: None,
+                prose: true,
+            },
         }
     }
 }
 impl FileType {
+    /// ファイルタイプ名
     pub fn name(&self) -> String {
         self.name.clone()
     }
+    /// ハイライトする対象
     pub fn highlighting_options(&self) -> &HighlightingOptions {
         &self.hl_opts
     }
-    // ファイル名からファイルタイプを判断し、設定する
+    /// 編集時の動作
+    pub fn editing_options(&self) -> &EditingOptions {
+        &self.editing_opts
+    }
+    /// ファイル名から組み込みのファイルタイプを判断する
     pub fn from(file_name: &str) -> Self {
-        #[allow(clippy::case_sensitive_file_extension_comparisons)]
-        if file_name.ends_with(".rs") {
-            return Self {
-                name: String::from("Rust"),
-                hl_opts: HighlightingOptions {
-                    numbers: true,
-                    strings: true,
-                    characters: true,
-                    comments: true,
-                    primary_keywords: vec![
-                        "as".to_string(),
-                        "break".to_string(),
-                        "const".to_string(),
-                        "continue".to_string(),
-                        "crate".to_string(),
-                        "else".to_string(),
-                        "enum".to_string(),
-                        "extern".to_string(),
-                        "false".to_string(),
-                        "fn".to_string(),
-                        "for".to_string(),
-                        "if".to_string(),
-                        "impl".to_string(),
-                        "in".to_string(),
-                        "let".to_string(),
-                        "loop".to_string(),
-                        "match".to_string(),
-                        "mod".to_string(),
-                        "move".to_string(),
-                        "mut".to_string(),
-                        "pub".to_string(),
-                        "ref".to_string(),
-                        "return".to_string(),
-                        "self".to_string(),
-                        "Self".to_string(),
-                        "static".to_string(),
-                        "struct".to_string(),
-                        "super".to_string(),
-                        "trait".to_string(),
-                        "true".to_string(),
-                        "type".to_string(),
-                        "unsafe".to_string(),
-                        "use".to_string(),
-                        "where".to_string(),
-                        "while".to_string(),
-                        "dyn".to_string(),
-                        "abstract".to_string(),
-                        "become".to_string(),
-                        "box".to_string(),
-                        "do".to_string(),
-                        "final".to_string(),
-                        "macro".to_string(),
-                        "override".to_string(),
-                        "priv".to_string(),
-                        "typeof".to_string(),
-                        "unsized".to_string(),
-                        "virtual".to_string(),
-                        "yield".to_string(),
-                        "async".to_string(),
-                        "await".to_string(),
-                        "try".to_string(),
-                    ],
-                    secondary_keywords: vec![
-                        "bool".to_string(),
-                        "char".to_string(),
-                        "i8".to_string(),
-                        "i16".to_string(),
-                        "i32".to_string(),
-                        "i64".to_string(),
-                        "isize".to_string(),
-                        "u8".to_string(),
-                        "u16".to_string(),
-                        "u32".to_string(),
-                        "u64".to_string(),
-                        "usize".to_string(),
-                        "f32".to_string(),
-                        "f64".to_string(),
-                    ],
-                },
-            };
+        Self::detect(file_name, "", &Definitions::default())
+    }
+    /// ファイル名と最初の行(シバンやモードライン)からファイルタイプを判断する
+    /// definitionsはユーザが定義したファイルタイプで、組み込みのものより優先する
+    pub fn detect(file_name: &str, first_line: &str, definitions: &Definitions) -> Self {
+        // モードラインで明示されたファイルタイプを最優先する
+        if let Some(file_type) =
+            modeline(first_line).and_then(|name| Self::from_name(&name, definitions))
+        {
+            return file_type;
+        }
+        if let Some(definition) = definitions.find_by_file_name(file_name) {
+            return Self::from_definition(definition);
+        }
+        // gitがコミットメッセージなどを編集させるときの一時ファイル
+        let base_name = file_name.rsplit('/').next().unwrap_or(file_name);
+        if GIT_MESSAGE_FILES.contains(&base_name) {
+            return Self::git_commit();
+        }
+        // 拡張子は大文字と小文字を区別しない
+        let extension = base_name
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase());
+        let name = EXTENSIONS
+            .iter()
+            .find(|(known, _)| extension.as_deref() == Some(*known))
+            .or_else(|| {
+                let interpreter = shebang(first_line)?;
+                INTERPRETERS.iter().find(|(known, _)| *known == interpreter)
+            })
+            .map(|(_, name)| *name);
+        name.and_then(|name| Self::from_name(name, definitions))
+            .unwrap_or_default()
+    }
+    // gitのコミットメッセージのファイルタイプ
+    fn git_commit() -> Self {
+        Self {
+            name: String::from("Git commit"),
+            hl_opts: HighlightingOptions {
+                hash_comments: true,
+                ..HighlightingOptions::default()
+            },
+            // 文章中のアポストロフィや引用符は閉じない
+            editing_opts: EditingOptions {
+                auto_pairs: vec![('(', ')'), ('[', ']'), ('{', '}')],
+                line_comment: Some("#".to_string()),
+                prose: true,
+            },
+        }
+    }
+    // Markdownのファイルタイプ
+    fn markdown() -> Self {
+        Self {
+            name: String::from("Markdown"),
+            hl_opts: HighlightingOptions::default(),
+            // 文章中のアポストロフィは閉じない
+            editing_opts: EditingOptions {
+                auto_pairs: vec![('(', ')'), ('[', ']'), ('{', '}'), ('`', '`')],
+                line_comment: None,
+                prose: true,
+            },
+        }
+    }
+    // unified形式の差分のファイルタイプ
+    fn diff() -> Self {
+        Self {
+            name: String::from("Diff"),
+            hl_opts: HighlightingOptions {
+                diff: true,
+                ..HighlightingOptions::default()
+            },
+            editing_opts: EditingOptions {
+                auto_pairs: Vec::new(),
+                line_comment: None,
+                prose: false,
+            },
+        }
+    }
+    // プログラミング言語や設定ファイルのファイルタイプ
+    // //で始まる行コメントは/* */のコメントと一緒にハイライトし、それ以外の記号は行の途中からでもコメントとする
+    fn code(name: &str, line_comment: Option<&str>, primary: &[&str], secondary: &[&str]) -> Self {
+        let c_comments = line_comment == Some("//");
+        // Cの系統の言語では'を文字リテラル、それ以外では文字列にも使う
+        let characters = c_comments && !matches!(name, "JavaScript" | "TypeScript");
+        let mut auto_pairs = vec![('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')];
+        if !characters {
+            auto_pairs.push(('\'', '\''));
+        }
+        Self {
+            name: name.to_string(),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: true,
+                characters,
+                comments: c_comments,
+                hash_comments: false,
+                diff: false,
+                line_comment: line_comment.filter(|_| !c_comments).map(str::to_string),
+                primary_keywords: words(primary),
+                secondary_keywords: words(secondary),
+            },
+            editing_opts: EditingOptions {
+                auto_pairs,
+                line_comment: line_comment.map(str::to_string),
+                prose: false,
+            },
         }
-        Self::default()
+    }
+    // Rustのファイルタイプ
+    fn rust() -> Self {
+        Self {
+            name: String::from("Rust"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: true,
+                characters: true,
+                comments: true,
+                hash_comments: false,
+                diff: false,
+                line_comment: None,
+                primary_keywords: vec![
+                    "as".to_string(),
+                    "break".to_string(),
+                    "const".to_string(),
+                    "continue".to_string(),
+                    "crate".to_string(),
+                    "else".to_string(),
+                    "enum".to_string(),
+                    "extern".to_string(),
+                    "false".to_string(),
+                    "fn".to_string(),
+                    "for".to_string(),
+                    "if".to_string(),
+                    "impl".to_string(),
+                    "in".to_string(),
+                    "let".to_string(),
+                    "loop".to_string(),
+                    "match".to_string(),
+                    "mod".to_string(),
+                    "move".to_string(),
+                    "mut".to_string(),
+                    "pub".to_string(),
+                    "ref".to_string(),
+                    "return".to_string(),
+                    "self".to_string(),
+                    "Self".to_string(),
+                    "static".to_string(),
+                    "struct".to_string(),
+                    "super".to_string(),
+                    "trait".to_string(),
+                    "true".to_string(),
+                    "type".to_string(),
+                    "unsafe".to_string(),
+                    "use".to_string(),
+                    "where".to_string(),
+                    "while".to_string(),
+                    "dyn".to_string(),
+                    "abstract".to_string(),
+                    "become".to_string(),
+                    "box".to_string(),
+                    "do".to_string(),
+                    "final".to_string(),
+                    "macro".to_string(),
+                    "override".to_string(),
+                    "priv".to_string(),
+                    "typeof".to_string(),
+                    "unsized".to_string(),
+                    "virtual".to_string(),
+                    "yield".to_string(),
+                    "async".to_string(),
+                    "await".to_string(),
+                    "try".to_string(),
+                ],
+                secondary_keywords: vec![
+                    "bool".to_string(),
+                    "char".to_string(),
+                    "i8".to_string(),
+                    "i16".to_string(),
+                    "i32".to_string(),
+                    "i64".to_string(),
+                    "isize".to_string(),
+                    "u8".to_string(),
+                    "u16".to_string(),
+                    "u32".to_string(),
+                    "u64".to_string(),
+                    "usize".to_string(),
+                    "f32".to_string(),
+                    "f64".to_string(),
+                ],
+            },
+            // ライフタイムと衝突するのでシングルクオートは閉じない
+            editing_opts: EditingOptions {
+                auto_pairs: vec![('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')],
+                line_comment: Some("//".to_string()),
+                prose: false,
+            },
+        }
+    }
+    // 設定ファイルで定義されたファイルタイプ
+    fn from_definition(definition: &Definition) -> Self {
+        Self {
+            name: definition.name.clone(),
+            hl_opts: HighlightingOptions {
+                numbers: definition.numbers,
+                strings: definition.strings,
+                characters: definition.characters,
+                comments: definition.block_comments,
+                hash_comments: false,
+                diff: false,
+                line_comment: definition.line_comment.clone(),
+                primary_keywords: definition.primary_keywords.clone(),
+                secondary_keywords: definition.secondary_keywords.clone(),
+            },
+            editing_opts: EditingOptions {
+                auto_pairs: definition.auto_pairs.clone(),
+                line_comment: definition.line_comment.clone(),
+                prose: definition.prose,
+            },
+        }
+    }
+    /// :set filetype=で指定された名前からファイルタイプを返す(知らない名前の場合はNone)
+    pub fn from_name(name: &str, definitions: &Definitions) -> Option<Self> {
+        if let Some(definition) = definitions.find_by_name(name) {
+            return Some(Self::from_definition(definition));
+        }
+        // モードラインではEmacsのモード名も使われる
+        let file_type = match &name.to_lowercase()[..] {
+            "rust" | "rs" => Self::rust(),
+            "gitcommit" | "git" => Self::git_commit(),
+            "diff" | "patch" => Self::diff(),
+            "markdown" | "md" => Self::markdown(),
+            "python" | "py" => Self::code("Python", Some("#"), PYTHON_KEYWORDS, &[]),
+            "sh" | "shell" | "shell-script" | "bash" | "zsh" => {
+                Self::code("Shell", Some("#"), SHELL_KEYWORDS, &[])
+            }
+            "ruby" | "rb" => Self::code("Ruby", Some("#"), RUBY_KEYWORDS, &[]),
+            "perl" | "pl" => Self::code("Perl", Some("#"), PERL_KEYWORDS, &[]),
+            "c" => Self::code("C", Some("//"), C_KEYWORDS, C_TYPES),
+            "cpp" | "c++" => Self::code("C++", Some("//"), CPP_KEYWORDS, C_TYPES),
+            "go" | "golang" => Self::code("Go", Some("//"), GO_KEYWORDS, GO_TYPES),
+            "javascript" | "js" => Self::code("JavaScript", Some("//"), JS_KEYWORDS, &[]),
+            "typescript" | "ts" => Self::code("TypeScript", Some("//"), JS_KEYWORDS, TS_TYPES),
+            "toml" => Self::code("TOML", Some("#"), &["true", "false"], &[]),
+            "yaml" | "yml" => Self::code("YAML", Some("#"), &["true", "false", "null"], &[]),
+            "json" => Self::code("JSON", None, &["true", "false", "null"], &[]),
+            "" | "none" | "text" => Self::default(),
+            _ => return None,
+        };
+        Some(file_type)
     }
 }
 
 impl HighlightingOptions {
+    /// 数値をハイライトするか
     pub fn numbers(&self) -> bool {
         self.numbers
     }
+    /// 二重引用符で囲んだ文字列をハイライトするか
     pub fn strings(&self) -> bool {
         self.strings
     }
+    /// 一重引用符で囲んだ文字をハイライトするか
     pub fn characters(&self) -> bool {
         self.characters
     }
+    /// //と/* */のコメントをハイライトするか
     pub fn comments(&self) -> bool {
         self.comments
     }
+    /// 行頭の#から行末までをコメントとするか
+    pub fn hash_comments(&self) -> bool {
+        self.hash_comments
+    }
+    /// unified形式の差分としてハイライトするか
+    pub fn diff(&self) -> bool {
+        self.diff
+    }
+    /// 行の途中からでも行末までをコメントとする記号
+    pub fn line_comment(&self) -> Option<&str> {
+        self.line_comment.as_deref()
+    }
+    /// 制御構文などの1つ目の種類のキーワード
     pub fn primary_keywords(&self) -> &Vec<String> {
         &self.primary_keywords
     }
+    /// 型名などの2つ目の種類のキーワード
     pub fn secondary_keywords(&self) -> &Vec<String> {
         &self.secondary_keywords
     }
 }
+
+impl EditingOptions {
+    pub fn auto_pairs(&self) -> &Vec<(char, char)> {
+        &self.auto_pairs
+    }
+    pub fn line_comment(&self) -> Option<&str> {
+        self.line_comment.as_deref()
+    }
+    pub fn is_prose(&self) -> bool {
+        self.prose
+    }
+}
+
+fn words(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| (*word).to_string()).collect()
+}
+
+// シバンで指定されたインタプリタの名前(envを通す場合はその引数、バージョンの数字は除く)
+fn shebang(first_line: &str) -> Option<&str> {
+    let mut args = first_line.strip_prefix("#!")?.split_whitespace();
+    let mut program = args.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = args.find(|arg| !arg.starts_with('-') && !arg.contains('='))?;
+    }
+    Some(program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+}
+
+// vim(vim: set ft=python:)またはEmacs(-*- mode: python -*-)のモードラインで指定されたファイルタイプ名
+fn modeline(line: &str) -> Option<String> {
+    if let Some((_, rest)) = line.split_once("-*-") {
+        let (inner, _) = rest.split_once("-*-")?;
+        // 変数を並べる形式ではmodeを探し、それ以外はモード名のみとみなす
+        if !inner.contains(':') {
+            return Some(inner.trim().to_string());
+        }
+        return inner.split(';').find_map(|variable| {
+            let (key, value) = variable.split_once(':')?;
+            (key.trim().eq_ignore_ascii_case("mode")).then(|| value.trim().to_string())
+        });
+    }
+    let options = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        let (before, after) = line.split_once(marker)?;
+        // 単語の途中(eval:など)は除く
+        (before.is_empty() || before.ends_with(char::is_whitespace)).then_some(after)
+    })?;
+    options
+        .split(|c: char| c == ':' || c.is_whitespace())
+        .find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            matches!(key, "ft" | "filetype" | "syn" | "syntax").then(|| value.to_string())
+        })
+}
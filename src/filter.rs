@@ -0,0 +1,48 @@
+// 行を外部コマンドに通して置き換えるためのパイプ
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+use std::thread;
+
+// 行をシェルコマンドの標準入力に渡し、標準出力の行を返す(argsはコマンドから$1、$2…で参照できる)
+// 失敗した場合は標準エラー出力の最初の行(無ければ終了ステータス)を返す
+pub fn run(command: &str, args: &[&str], lines: &[String]) -> Result<Vec<String>, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("sh")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("Could not run {command}: {error}"))?;
+    let mut input = lines.join("\n");
+    if !lines.is_empty() {
+        input.push('\n');
+    }
+    // 出力を読みながら書き込まないと、大きなファイルではパイプが詰まる
+    let writer = child.stdin.take().map(|mut stdin| {
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        })
+    });
+    let output = child
+        .wait_with_output()
+        .map_err(|error| format!("{command}: {error}"))?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map_or_else(|| output.status.to_string(), str::to_string);
+        let program = command.split_whitespace().next().unwrap_or(command);
+        return Err(format!("{program}: {message}"));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| format!("{command}: output is not valid UTF-8"))?;
+    Ok(stdout.lines().map(str::to_string).collect())
+}
@@ -0,0 +1,89 @@
+// 折りたたまれた行の範囲を管理し、ドキュメントの行と画面に表示される行を対応させる
+// 表示される行は折りたたみの先頭行のみで、それ以降の行は隠される
+#[derive(Default)]
+pub struct Folds {
+    // 閉じている折りたたみの(開始行, 終了行の次の行)を開始行の順に並べたもの(重ならない)
+    ranges: Vec<(usize, usize)>,
+}
+
+impl Folds {
+    pub fn ranges(&self) -> &[(usize, usize)] {
+        &self.ranges
+    }
+    // 指定行から始まる閉じている折りたたみがあれば、終了行の次の行を返す
+    pub fn end_of(&self, y: usize) -> Option<usize> {
+        self.ranges
+            .iter()
+            .find(|(start, _)| *start == y)
+            .map(|(_, end)| *end)
+    }
+    // 指定行が折りたたみによって隠されているか
+    pub fn is_hidden(&self, y: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|(start, end)| *start < y && y < *end)
+    }
+    // ドキュメントの行を画面に表示される行の番号に変換する
+    // 隠された行は折りたたみの先頭行の位置とする
+    pub fn to_visible(&self, y: usize) -> usize {
+        let hidden: usize = self
+            .ranges
+            .iter()
+            .take_while(|(start, _)| *start < y)
+            .map(|(start, end)| {
+                (*end)
+                    .min(y.saturating_add(1))
+                    .saturating_sub(start.saturating_add(1))
+            })
+            .sum();
+        y.saturating_sub(hidden)
+    }
+    // 画面に表示される行の番号をドキュメントの行に変換する
+    pub fn to_row(&self, visible: usize) -> usize {
+        let mut y = visible;
+        for (start, end) in &self.ranges {
+            if *start >= y {
+                break;
+            }
+            y = y.saturating_add(end.saturating_sub(start.saturating_add(1)));
+        }
+        y
+    }
+    // 指定行から表示される行でcount行下の行を返す
+    pub fn visible_below(&self, y: usize, count: usize) -> usize {
+        self.to_row(self.to_visible(y).saturating_add(count))
+    }
+    // 指定行から表示される行でcount行上の行を返す
+    pub fn visible_above(&self, y: usize, count: usize) -> usize {
+        self.to_row(self.to_visible(y).saturating_sub(count))
+    }
+    // 範囲を折りたたむ(範囲内の折りたたみはまとめる)
+    pub fn close(&mut self, start: usize, end: usize) {
+        if end <= start.saturating_add(1) {
+            return;
+        }
+        self.ranges.retain(|(s, e)| *e <= start || end <= *s);
+        let index = self.ranges.partition_point(|(s, _)| *s < start);
+        self.ranges.insert(index, (start, end));
+    }
+    // 指定行を含む折りたたみを開く。開いた場合はtrueを返す
+    pub fn open(&mut self, y: usize) -> bool {
+        let count = self.ranges.len();
+        self.ranges.retain(|(start, end)| y < *start || *end <= y);
+        count != self.ranges.len()
+    }
+    // y行目を編集してその後ろにdelta行挿入(負の場合は削除)したときに折りたたみを更新する
+    // 編集した行と削除した行を含む折りたたみは開く
+    pub fn edit(&mut self, y: usize, delta: isize) {
+        self.open(y);
+        if delta < 0 {
+            self.open(y.saturating_add(delta.unsigned_abs()));
+        }
+        for (start, end) in &mut self.ranges {
+            if *start > y {
+                *start = start.saturating_add_signed(delta);
+                *end = end.saturating_add_signed(delta);
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// ファイルタイプごとに設定された外部の整形コマンド
+use crate::filter;
+use std::env;
+
+// rust=rustfmt,python=black -qのように、ファイルタイプと整形コマンドの組をカンマで区切って並べる
+// コマンドを空にすると組み込みの整形コマンドも使わない
+const FORMATTERS_VAR: &str = "DECI_FORMAT";
+
+// 設定が無い場合に使う整形コマンド({file}はファイル名に置き換える)
+const DEFAULT_FORMATTERS: [(&str, &str); 10] = [
+    ("Rust", "rustfmt --emit stdout --edition 2021"),
+    ("Python", "black --quiet -"),
+    ("Go", "gofmt"),
+    ("C", "clang-format --assume-filename={file}"),
+    ("C++", "clang-format --assume-filename={file}"),
+    ("JavaScript", "prettier --stdin-filepath {file}"),
+    ("TypeScript", "prettier --stdin-filepath {file}"),
+    ("Markdown", "prettier --stdin-filepath {file}"),
+    ("JSON", "prettier --stdin-filepath {file}"),
+    ("YAML", "prettier --stdin-filepath {file}"),
+];
+
+// ファイルタイプに対して使う整形コマンド
+// formattersは:set formatprgで設定した値(設定されていなければ環境変数の値を使う)
+pub fn command(formatters: Option<&str>, file_type: &str) -> Option<String> {
+    let formatters = formatters
+        .map(str::to_string)
+        .or_else(|| env::var(FORMATTERS_VAR).ok());
+    let configured = formatters.and_then(|formatters| {
+        formatters
+            .split(',')
+            .filter_map(|entry| entry.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case(file_type))
+            .map(|(_, command)| command.trim().to_string())
+    });
+    configured
+        .or_else(|| {
+            DEFAULT_FORMATTERS
+                .iter()
+                .find(|(name, _)| *name == file_type)
+                .map(|(_, command)| (*command).to_string())
+        })
+        .filter(|command| !command.is_empty())
+}
+
+// 行を整形コマンドの標準入力に渡し、標準出力の行を返す
+pub fn run(command: &str, file_name: &str, lines: &[String]) -> Result<Vec<String>, String> {
+    // ファイル名は引数として渡し、シェルに解釈させない
+    filter::run(&command.replace("{file}", "\"$1\""), &[file_name], lines)
+}
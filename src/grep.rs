@@ -0,0 +1,188 @@
+use crate::document::BufferKind;
+use crate::explorer;
+use crate::Document;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use unicode_segmentation::UnicodeSegmentation;
+
+// 検索結果の最大件数
+const MAX_RESULTS: usize = 1000;
+// 検索しないディレクトリ
+const IGNORED_DIRS: [&str; 2] = ["target", "node_modules"];
+
+// 指定したディレクトリ以下の全てのファイルから検索し、結果の一覧をドキュメントとして返す
+pub fn open(root: &Path, pattern: &str) -> Document {
+    let mut results = Vec::new();
+    search_dir(root, pattern, &mut results);
+    Document::from_lines(&results, BufferKind::QuickFix(format!("grep {pattern}")))
+}
+
+fn search_dir(dir: &Path, pattern: &str, results: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        if results.len() >= MAX_RESULTS {
+            return;
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // 隠しファイルとビルド成果物は検索しない
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            if !IGNORED_DIRS.contains(&&name[..]) {
+                search_dir(&path, pattern, results);
+            }
+        } else {
+            search_file(&path, pattern, results);
+        }
+    }
+}
+
+// ファイル内で見つかった行をpath:line:col: textの形式で追加する
+fn search_file(path: &Path, pattern: &str, results: &mut Vec<String>) {
+    // UTF-8として読めないファイルはバイナリとみなして飛ばす
+    let Ok(contents) = fs::read_to_string(path) else {
+        return;
+    };
+    let file_name = explorer::relative(path);
+    for (index, line) in contents.lines().enumerate() {
+        if results.len() >= MAX_RESULTS {
+            return;
+        }
+        if let Some(byte_index) = line.find(pattern) {
+            // 列番号は全角文字単位で数える
+            let column = line
+                .get(..byte_index)
+                .map_or(0, |prefix| prefix.graphemes(true).count());
+            results.push(format!(
+                "{file_name}:{}:{}: {}",
+                index.saturating_add(1),
+                column.saturating_add(1),
+                line.trim()
+            ));
+        }
+    }
+}
+
+// 検索結果の行をファイル名、行番号、行の内容に分ける
+// ファイル名にも行の内容にも:が含まれることがあるので、:行:列: の前が存在するファイルになる位置で分ける
+fn split_result(line: &str) -> Option<(&str, usize, &str)> {
+    line.match_indices(':').find_map(|(index, _)| {
+        let path = line.get(..index)?;
+        let mut parts = line.get(index.saturating_add(1)..)?.splitn(3, ':');
+        let number = parts.next()?.parse().ok()?;
+        parts.next()?.parse::<usize>().ok()?;
+        let text = parts.next()?.strip_prefix(' ')?;
+        Path::new(path).is_file().then_some((path, number, text))
+    })
+}
+
+// 編集した検索結果の行を元のファイルに書き戻し、(ファイル数, 行数)を返す
+// 行の追加や削除、ファイル名や行番号の変更はできない
+// 検索後に変更されたファイルや、dirtyに含まれる(開いているバッファに保存していない変更がある)ファイルがある場合は、
+// 変更を失わないようにどのファイルにも書き込まない
+pub fn write_back(
+    original: &[String],
+    edited: &[String],
+    dirty: &[PathBuf],
+) -> Result<(usize, usize), String> {
+    if original.len() != edited.len() {
+        return Err("Cannot add or remove lines in grep results.".to_string());
+    }
+    // ファイルごとに(行番号, 元の内容, 新しい内容)をまとめる
+    let mut changes: BTreeMap<&str, Vec<(usize, &str, &str)>> = BTreeMap::new();
+    for (old, new) in original.iter().zip(edited).filter(|(old, new)| old != new) {
+        let (Some((path, number, old_text)), Some((new_path, new_number, new_text))) =
+            (split_result(old), split_result(new))
+        else {
+            return Err(format!("Invalid grep result: {new}"));
+        };
+        if path != new_path || number != new_number {
+            return Err("Cannot change file names or line numbers in grep results.".to_string());
+        }
+        changes
+            .entry(path)
+            .or_default()
+            .push((number, old_text, new_text));
+    }
+    // 全てのファイルの変更後の内容を作ってから書き込む
+    let mut contents = Vec::new();
+    let mut lines = 0_usize;
+    for (path, edits) in &changes {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        if dirty.contains(&canonical) {
+            return Err(format!("{path} has unsaved changes in an open buffer."));
+        }
+        // 改行(CRLFを含む)は行に含めたまま置き換え、元の改行を変えない
+        let mut rows: Vec<String> = fs::read_to_string(path)
+            .map_err(|_| format!("Could not read {path}"))?
+            .split_inclusive('\n')
+            .map(str::to_string)
+            .collect();
+        for (number, old_text, new_text) in edits {
+            // 検索結果は前後の空白を除いて表示しているので、元の空白は残す
+            let row = number
+                .checked_sub(1)
+                .and_then(|index| rows.get_mut(index))
+                .filter(|row| row.trim() == *old_text)
+                .ok_or_else(|| format!("{path}:{number} has changed since the search."))?;
+            let start = row.len().saturating_sub(row.trim_start().len());
+            let end = row.trim_end().len().max(start);
+            *row = format!(
+                "{}{new_text}{}",
+                row.get(..start).unwrap_or_default(),
+                row.get(end..).unwrap_or_default()
+            );
+            lines = lines.saturating_add(1);
+        }
+        contents.push((path, rows.concat()));
+    }
+    for (path, text) in &contents {
+        fs::write(path, text).map_err(|_| format!("Could not write {path}"))?;
+    }
+    Ok((contents.len(), lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn writes_back_to_files_with_colons_keeping_crlf() {
+        let path = env::temp_dir().join(format!("deci-grep-{}:1:2: x.txt", process::id()));
+        let file_name = path.to_string_lossy().to_string();
+        fs::write(&path, "  one: a\r\ntwo\r\nlast").expect("file should be writable");
+        let original = [
+            format!("{file_name}:1:3: one: a"),
+            format!("{file_name}:3:1: last"),
+        ];
+        let edited = [
+            format!("{file_name}:1:3: one: b"),
+            format!("{file_name}:3:1: end"),
+        ];
+        // 開いているバッファに保存していない変更があれば書き込まない
+        let dirty = [fs::canonicalize(&path).expect("file should exist")];
+        let refused = write_back(&original, &edited, &dirty);
+        let unchanged = fs::read_to_string(&path);
+        let written = write_back(&original, &edited, &[]);
+        let contents = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+        assert!(refused.is_err());
+        assert!(unchanged.is_ok_and(|text| text == "  one: a\r\ntwo\r\nlast"));
+        assert!(written == Ok((1, 2)));
+        assert!(contents.is_ok_and(|text| text == "  one: b\r\ntwo\r\nend"));
+    }
+}
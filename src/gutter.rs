@@ -0,0 +1,64 @@
+use crate::theme::Theme;
+use crate::Terminal;
+
+// 行番号の最小表示桁数
+const MIN_DIGITS: usize = 3;
+
+// 行頭に表示する行番号の表示方法(vimのnumberとrelativenumberに対応)
+// 両方有効な場合はカーソル行のみ行番号、それ以外の行は相対的な行番号を表示する
+#[derive(Clone, Copy)]
+pub struct LineNumbers {
+    // 行番号を表示する
+    pub absolute: bool,
+    // カーソル行からの相対的な行番号を表示する
+    pub relative: bool,
+}
+
+impl Default for LineNumbers {
+    fn default() -> Self {
+        Self {
+            absolute: true,
+            relative: false,
+        }
+    }
+}
+
+impl LineNumbers {
+    pub fn is_visible(self) -> bool {
+        self.absolute || self.relative
+    }
+    // ドキュメントの行数から、行番号の後ろの半角スペース1つを含む表示幅を返す
+    pub fn width(self, line_count: usize) -> usize {
+        if !self.is_visible() {
+            return 0;
+        }
+        line_count
+            .to_string()
+            .len()
+            .max(MIN_DIGITS)
+            .saturating_add(1)
+    }
+    // y行目(0から始まる)に表示する番号を返す
+    // distanceはカーソル行から表示上何行離れているか
+    pub fn number(self, y: usize, distance: usize) -> usize {
+        if self.relative && !(self.absolute && distance == 0) {
+            distance
+        } else {
+            y.saturating_add(1)
+        }
+    }
+    // 右揃え空白詰めで行番号表示
+    pub fn draw(self, y: usize, distance: usize, width: usize, theme: &Theme) {
+        if !self.is_visible() {
+            return;
+        }
+        Terminal::set_bg_color(theme.line_number_bg);
+        // 行番号表示の後に半角スペースを1つ入れる
+        Terminal::print(format_args!(
+            "{:>digits_width$} ",
+            self.number(y, distance),
+            digits_width = width.saturating_sub(1)
+        ));
+        Terminal::reset_bg_color();
+    }
+}
@@ -0,0 +1,526 @@
+// 端末の代わりにメモリ上の画面に描画して、決められたキー入力の後の画面をゴールデンファイルと比べる
+// DECI_UPDATE_GOLDEN=1を設定して実行するとゴールデンファイルを書き換える
+use crate::document::BufferKind;
+use crate::keys;
+use crate::screen::Screen;
+use crate::syntax;
+use crate::{Document, Editor, Terminal};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use termion::event::Event;
+
+// ゴールデンファイルとテストで開くファイルを置くディレクトリ(パッケージのルートからの相対パス)
+const GOLDEN_DIR: &str = "tests/golden";
+const FIXTURE_DIR: &str = "tests/fixtures";
+const UPDATE_VAR: &str = "DECI_UPDATE_GOLDEN";
+
+// fixtureのファイル(Noneの場合は空のドキュメント)を開き、<CR>などの表記を含むキーを入力した後の画面を返す
+fn run(fixture: Option<&str>, width: u16, height: u16, keys: &str) -> Screen {
+    let document = fixture.map_or_else(Document::default, open_fixture);
+    run_document(document, &[], width, height, keys)
+}
+
+// pluginsのコマンドでプラグインを起動してから、ドキュメントを表示してキーを入力した後の画面を返す
+fn run_document(
+    document: Document,
+    plugins: &[String],
+    width: u16,
+    height: u16,
+    keys: &str,
+) -> Screen {
+    let events = keys::parse(keys).into_iter().map(Event::Key).collect();
+    let mut editor = Editor::headless(Terminal::headless(width, height, events), document);
+    if !plugins.is_empty() {
+        editor.start_plugins(plugins);
+    }
+    editor.run_headless().expect("rendering should not fail");
+    let mut screen = Screen::new(usize::from(width), usize::from(height));
+    screen.feed(&Terminal::take_output());
+    screen
+}
+
+fn open_fixture(name: &str) -> Document {
+    Document::open(&format!("{FIXTURE_DIR}/{name}")).expect("fixture should be readable")
+}
+
+fn assert_golden(name: &str, screen: &Screen) {
+    let path = PathBuf::from(GOLDEN_DIR).join(format!("{name}.txt"));
+    let actual = screen.to_golden();
+    if env::var_os(UPDATE_VAR).is_some() {
+        fs::write(&path, actual).expect("golden file should be writable");
+        return;
+    }
+    let Ok(expected) = fs::read_to_string(&path) else {
+        panic!(
+            "missing golden file {}; run with {UPDATE_VAR}=1 to create it\n{actual}",
+            path.display()
+        );
+    };
+    assert!(
+        expected == actual,
+        "screen differs from {}; run with {UPDATE_VAR}=1 to update it\nexpected:\n{expected}\nactual:\n{actual}",
+        path.display()
+    );
+}
+
+#[test]
+fn highlights_rust_syntax() {
+    assert_golden("highlight_rust", &run(Some("hello.rs"), 40, 8, ""));
+}
+
+#[test]
+fn draws_line_numbers_in_gutter() {
+    let screen = run(Some("hello.rs"), 40, 8, ":set nu<CR>:set rnu<CR>j");
+    assert_golden("gutter_numbers", &screen);
+}
+
+#[test]
+fn truncates_long_and_wide_lines() {
+    assert_golden("long_lines", &run(Some("wide.txt"), 30, 6, ""));
+}
+
+#[test]
+fn highlights_cursor_line_and_column() {
+    let screen = run(Some("hello.rs"), 40, 8, ":set cul<CR>:set cuc<CR>jjlll");
+    assert_golden("cursor_line", &screen);
+}
+
+#[test]
+fn shows_insert_mode_and_modified_file() {
+    let screen = run(Some("hello.rs"), 70, 8, "iHi<Esc>i");
+    assert_golden("insert_mode", &screen);
+}
+
+#[test]
+fn highlights_trailing_whitespace() {
+    assert_golden("trailing_whitespace", &run(Some("trailing.txt"), 30, 7, ""));
+}
+
+#[test]
+fn draws_color_columns_across_wide_characters() {
+    let screen = run(Some("wide.txt"), 30, 6, ":set cc=12,25<CR>");
+    assert_golden("color_column", &screen);
+}
+
+#[test]
+fn formats_status_bar_with_statusline() {
+    let keys = ":set stl=%M %f%m %=%y %e %l:%c %p%% <CR>jjiX<Esc>";
+    assert_golden("status_line", &run(Some("hello.rs"), 60, 6, keys));
+}
+
+#[test]
+fn truncates_status_and_message_by_display_width() {
+    let screen = run(Some("wide.txt"), 40, 6, ":日本語の命令です<CR>");
+    assert_golden("narrow_message", &screen);
+}
+
+#[test]
+fn shows_message_history() {
+    let screen = run(
+        Some("hello.rs"),
+        40,
+        8,
+        ":bogus<CR>:s/x/y/<CR>:messages<CR>",
+    );
+    assert_golden("message_history", &screen);
+}
+
+#[test]
+fn edits_prompt_and_recalls_history() {
+    // 入力途中ではメッセージバーのカーソル位置に表示する
+    let screen = run(Some("hello.rs"), 40, 8, ":et nux<Home>s<End><Left>");
+    assert_golden("prompt_cursor", &screen);
+    // 上キーは入力済みの文字列で始まる履歴だけをたどる
+    let screen = run(
+        Some("hello.rs"),
+        40,
+        8,
+        ":et nux<Home>s<End><Left><Del><CR>:set cul<CR>:set n<Up><C-w>nonu<CR>",
+    );
+    assert_golden("prompt_history", &screen);
+}
+
+#[test]
+fn completes_prompt_with_tab() {
+    // コマンド名の後はコマンドに応じた引数を補完し、候補はメッセージバーの上に並べる
+    let screen = run(Some("hello.rs"), 40, 8, ":the<Tab> <Tab><Tab>");
+    assert_golden("prompt_completion", &screen);
+    let screen = run(None, 40, 8, ":e tests/fix<Tab>hel<Tab><CR>");
+    assert_golden("prompt_file_completion", &screen);
+}
+
+#[test]
+fn moves_over_grapheme_clusters() {
+    // 結合文字やZWJで繋いだ絵文字は1文字として、表示幅の分だけカーソルを進める
+    let screen = run(Some("clusters.txt"), 40, 8, "jll");
+    assert_golden("grapheme_clusters", &screen);
+}
+
+#[test]
+fn inserts_composed_text_on_grapheme_boundaries() {
+    // 結合文字は前の文字とまとめ、続けて届いた漢字の後にカーソルを置く
+    let screen = run(None, 40, 8, "ie\u{301}\u{6f22}\u{5b57}x<Esc>");
+    assert_golden("composed_text", &screen);
+}
+
+#[test]
+fn opens_command_history_window() {
+    let screen = run(Some("hello.rs"), 40, 10, ":set nu<CR>:set cul<CR>q:k");
+    assert_golden("command_window", &screen);
+}
+
+#[test]
+fn shows_document_statistics() {
+    let screen = run(Some("wide.txt"), 60, 6, "jg<C-g>");
+    assert_golden("statistics", &screen);
+    // ビジュアルモードでは選択している範囲を数える
+    let screen = run(Some("wide.txt"), 90, 6, "jlvjg<C-g>");
+    assert_golden("statistics_selection", &screen);
+}
+
+#[test]
+fn shows_unsaved_changes_as_diff() {
+    let screen = run(Some("hello.rs"), 40, 20, "jjddiX<Esc>:DiffOrig<CR>");
+    assert_golden("diff_original", &screen);
+}
+
+#[test]
+fn wraps_inserted_text_at_text_width() {
+    let keys = ":set tw=20<CR>i- the quick brown fox jumps over the lazy dog<Esc>";
+    assert_golden("text_width", &run(None, 30, 7, keys));
+}
+
+#[test]
+fn renders_ansi_colors_of_pager_input() {
+    let text = fs::read_to_string(format!("{FIXTURE_DIR}/colored.log"))
+        .expect("fixture should be readable");
+    let document = Document::from_ansi(&text, BufferKind::Output("stdin".to_string()));
+    assert_golden("pager_colors", &run_document(document, &[], 40, 7, "ix"));
+}
+
+#[test]
+fn previews_and_expands_abbreviations() {
+    let define = ":ab brb be right back<CR>";
+    let screen = run(None, 40, 6, &format!("{define}iI will brb"));
+    assert_golden("abbreviation_preview", &screen);
+    let screen = run(None, 40, 6, &format!("{define}iI will brb<Tab>, ok brb!"));
+    assert_golden("abbreviation_expanded", &screen);
+}
+
+#[test]
+fn opens_missing_file_as_new_buffer() {
+    let screen = run(None, 50, 6, ":e tests/fixtures/missing.txt<CR>");
+    assert_golden("open_missing", &screen);
+}
+
+#[test]
+fn opens_directory_in_explorer() {
+    let screen = run(None, 50, 6, ":e tests/fixtures/dir<CR>");
+    assert_golden("open_directory", &screen);
+}
+
+#[test]
+fn opens_invalid_utf8_as_hex_dump() {
+    let screen = run(None, 80, 6, ":e tests/fixtures/invalid_utf8.bin<CR>");
+    assert_golden("open_invalid_utf8", &screen);
+}
+
+#[test]
+fn opens_unwritable_file_read_only() {
+    // 書き込みを許可しないファイルはテストのたびに作り直す
+    let path = "target/read_only.txt";
+    let _ = fs::remove_file(path);
+    fs::write(path, "read only\n").expect("file should be writable");
+    let mut permissions = fs::metadata(path).expect("file should exist").permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions).expect("permissions should be settable");
+    let document = Document::open(path).expect("file should be readable");
+    let screen = run_document(document, &[], 70, 6, "ix<Esc><C-s>");
+    assert_golden("open_read_only", &screen);
+}
+
+#[test]
+fn highlights_user_defined_filetype() {
+    let text = fs::read_to_string(format!("{FIXTURE_DIR}/syntax/ini.toml"))
+        .expect("syntax definition should be readable");
+    let definition = syntax::parse(&text).expect("syntax definition should be valid");
+    // 拡張子の大文字と小文字は区別しない
+    let mut editor = Editor::headless(
+        Terminal::headless(40, 8, Vec::new()),
+        open_fixture("settings.INI"),
+    );
+    editor.register_syntax(definition);
+    editor.run_headless().expect("rendering should not fail");
+    let mut screen = Screen::new(40, 8);
+    screen.feed(&Terminal::take_output());
+    assert_golden("user_syntax", &screen);
+}
+
+#[test]
+fn detects_filetype_from_first_line() {
+    // 拡張子の無いファイルはシバンから判定する
+    let screen = run(Some("greet"), 40, 8, "");
+    assert_golden("shebang_filetype", &screen);
+    // モードラインは拡張子より優先する
+    let screen = run(Some("main.txt"), 40, 8, "");
+    assert_golden("modeline_filetype", &screen);
+}
+
+#[test]
+fn underlines_and_corrects_misspelled_words() {
+    let setup = ":set dictionary=tests/fixtures/words.txt<CR>:set spellfile=target/spell.txt<CR>:set spell<CR>";
+    let screen = run(Some("spelling.txt"), 40, 8, setup);
+    assert_golden("spell_errors", &screen);
+    let screen = run(Some("spelling.txt"), 40, 8, &format!("{setup}llllllllllz="));
+    assert_golden("spell_suggestions", &screen);
+    let screen = run(
+        Some("spelling.txt"),
+        40,
+        8,
+        &format!("{setup}llllllllllz=<Down><CR>"),
+    );
+    assert_golden("spell_corrected", &screen);
+}
+
+#[test]
+fn formats_buffer_with_external_command() {
+    // カーソルはappleの行に付いて移る
+    let screen = run(
+        Some("fruits.md"),
+        40,
+        8,
+        ":set formatprg=markdown=sort<CR>jll:fmt<CR>",
+    );
+    assert_golden("format_sorted", &screen);
+    let keys = ":set formatprg=markdown=echo broken input >&2; exit 1<CR>:fmt<CR>";
+    let screen = run(Some("fruits.md"), 40, 8, keys);
+    assert_golden("format_error", &screen);
+}
+
+#[test]
+fn inserts_table_column_keeping_indent() {
+    // リストの項目の中の表でも、インデントを保ったまま列を挿入して整形する
+    let screen = run(Some("table.md"), 40, 8, "jjjjw:table-col<CR>");
+    assert_golden("table_column", &screen);
+}
+
+#[test]
+fn filters_range_through_shell_command() {
+    // 印を付けた2行目からカーソル行の4行目までを並べ替える
+    let screen = run(Some("fruits.md"), 40, 8, "jmajj:'a,.!sort<CR>");
+    assert_golden("filter_range", &screen);
+}
+
+#[test]
+fn streams_make_output_into_split() {
+    let keys = r":set makeprg=printf 'error: oops\n --> tests/fixtures/hello.rs:2:5\n'; exit 1<CR>:make<CR>";
+    let screen = run(Some("hello.rs"), 40, 12, keys);
+    assert_golden("make_output", &screen);
+    // 最後以外のコマンドの標準エラー出力もまとめて受け取る
+    let keys = ":set makeprg=echo first >&2; echo second | cat; echo third >&2<CR>:make<CR>";
+    let screen = run(Some("hello.rs"), 40, 12, keys);
+    assert_golden("make_stderr", &screen);
+}
+
+#[test]
+fn streams_shell_command_output_into_output_buffer() {
+    // 最後以外のコマンドの標準エラー出力もまとめて受け取る
+    let keys = ":!echo a >&2; echo b | cat; exit 3<CR>";
+    let screen = run(Some("hello.rs"), 40, 8, keys);
+    assert_golden("shell_output", &screen);
+}
+
+#[test]
+fn runs_command_in_terminal_pane() {
+    // 色を付けた出力を書き、カーソルを戻して上書きする
+    let keys = r":terminal printf '\033[31mred\033[0m text\r\nabcdef\033[3D\033[KXY'<CR>";
+    let screen = run(Some("hello.rs"), 40, 12, keys);
+    assert_golden("terminal_pane", &screen);
+}
+
+#[test]
+fn runs_plugin_hooks_and_commands() {
+    let plugins = [format!("{FIXTURE_DIR}/plugin.sh")];
+    let screen = run_document(open_fixture("hello.rs"), &plugins, 40, 8, "");
+    assert_golden("plugin_open", &screen);
+    let screen = run_document(open_fixture("hello.rs"), &plugins, 40, 8, "<F5>");
+    assert_golden("plugin_command", &screen);
+}
+
+#[test]
+fn notifies_plugins_of_cursor_and_mode_and_applies_their_changes() {
+    let plugins = [format!("{FIXTURE_DIR}/plugin.sh")];
+    // カーソルの移動とモードの変更はプラグインに知らされる
+    let screen = run_document(
+        open_fixture("hello.rs"),
+        &plugins,
+        50,
+        8,
+        "jjlli<Esc>:where<CR>",
+    );
+    assert_golden("plugin_notifications", &screen);
+    // プラグインはカーソル位置、画面の一番上の行、モードを変更できる
+    let screen = run_document(open_fixture("hello.rs"), &plugins, 50, 8, ":jump<CR>X");
+    assert_golden("plugin_set_state", &screen);
+}
+
+#[test]
+fn passes_and_sets_the_visual_selection_for_plugins() {
+    let plugins = [format!("{FIXTURE_DIR}/plugin.sh")];
+    // ビジュアルモードの選択範囲はモードと一緒にプラグインに渡される
+    let screen = run_document(
+        open_fixture("hello.rs"),
+        &plugins,
+        50,
+        8,
+        "Vjl:selected<CR>",
+    );
+    assert_golden("plugin_selection", &screen);
+    // プラグインは選択範囲を変更できる
+    let screen = run_document(open_fixture("hello.rs"), &plugins, 50, 8, ":select<CR>");
+    assert_golden("plugin_select", &screen);
+}
+
+#[test]
+fn highlights_the_visual_selection() {
+    // vで文字単位に選択し、カーソルと一緒に選択の端を動かす
+    let screen = run(Some("hello.rs"), 40, 8, "jlvjl");
+    assert_golden("visual_selection", &screen);
+    // Vで行単位の選択に切り替え、oで反対側の端に移動する
+    let screen = run(Some("hello.rs"), 40, 8, "jlvjlVo");
+    assert_golden("visual_line_selection", &screen);
+}
+
+#[test]
+fn deletes_and_yanks_the_visual_selection() {
+    // 行をまたいだ文字単位の選択を削除し、削除した文字列を次の行の前に貼り付ける
+    let screen = run(Some("hello.rs"), 40, 8, "jlvjld0jP");
+    assert_golden("visual_delete", &screen);
+    // 行単位の選択をレジスタにヤンクして、下の行の後ろに貼り付ける
+    let screen = run(Some("hello.rs"), 40, 8, "jVj\"ayjj\"ap");
+    assert_golden("visual_line_yank", &screen);
+}
+
+#[test]
+fn inserts_the_visual_selection_into_prompts() {
+    // Ctrl-R "で選択している文字列をコマンドの入力に挿入する
+    let screen = run(Some("hello.rs"), 40, 8, "jjllllvll:<C-r>\"");
+    assert_golden("visual_prompt", &screen);
+    // 選択している文字列を検索文字列にした置換コマンドを入力する
+    let keys = "jjllllvll:substitute-register<CR>var<CR>";
+    let screen = run(Some("hello.rs"), 40, 8, keys);
+    assert_golden("visual_substitute", &screen);
+}
+
+#[test]
+fn shifts_the_indent_of_selected_lines() {
+    // 選択している行のインデントを深くし、続けて1行目以外を浅くする
+    let screen = run(Some("hello.rs"), 40, 8, "jVjj>jvj<");
+    assert_golden("visual_shift", &screen);
+}
+
+#[test]
+fn toggles_comments_on_selected_lines() {
+    // gcで選択している行をコメントにし、Ctrl-/で2行目以降を戻す
+    let screen = run(Some("hello.rs"), 40, 8, "jVjjgcjVj<C-7>");
+    assert_golden("visual_comment", &screen);
+}
+
+#[test]
+fn moves_and_duplicates_selected_lines() {
+    // 選択している2行を下の行と入れ替え、選択したまま元に戻してから複製する
+    let screen = run(Some("hello.rs"), 40, 8, "jVj<M-j>");
+    assert_golden("visual_move_lines", &screen);
+    let screen = run(Some("hello.rs"), 40, 8, "jVj<M-j><M-k><M-d>");
+    assert_golden("visual_duplicate_lines", &screen);
+}
+
+#[test]
+fn completes_words_from_open_buffers() {
+    let screen = run(Some("hello.rs"), 40, 10, "iprintln and pri");
+    assert_golden("completion_popup", &screen);
+    let screen = run(Some("hello.rs"), 40, 10, "iprintln and pri<Tab><CR>");
+    assert_golden("completion_accepted", &screen);
+}
+
+#[test]
+fn completes_deferred_rows_when_input_stops() {
+    // 期限を0にすると入力の直後はカーソル行の付近しか描画しない
+    let screen = run(
+        Some("hello.rs"),
+        40,
+        10,
+        ":set renderbudget=0<CR>:set nonu<CR>",
+    );
+    assert_golden("render_budget", &screen);
+}
+
+#[test]
+fn redraws_only_changed_rows() {
+    // 文書を変えない移動では行を出力し直さず、Ctrl-Lでは全ての行を出力し直す
+    let output = |keys: &str| {
+        let events = keys::parse(keys).into_iter().map(Event::Key).collect();
+        let mut editor =
+            Editor::headless(Terminal::headless(40, 8, events), open_fixture("hello.rs"));
+        editor.run_headless().expect("rendering should not fail");
+        Terminal::take_output()
+    };
+    assert_eq!(output("ll").matches("deci").count(), 1);
+    assert_eq!(output("ll<C-l>").matches("deci").count(), 2);
+    let screen = run(Some("hello.rs"), 40, 8, "jjx");
+    assert_golden("redraw_changed_rows", &screen);
+}
+
+#[test]
+fn keeps_scrolloff_lines_and_repositions_cursor_line() {
+    // カーソルの下に2行残してスクロールし、最後の行ではドキュメントの後ろまではスクロールしない
+    let screen = run(Some("numbers.txt"), 30, 8, ":set so=2<CR>jjjjjj");
+    assert_golden("scroll_off", &screen);
+    let keys = format!(":set so=2<CR>{}", "j".repeat(29));
+    assert_golden("scroll_off_end", &run(Some("numbers.txt"), 30, 8, &keys));
+    // ztでカーソル行の上に2行残して一番上に、zzで中央に、zbで下に2行残して一番下に置く
+    let down = format!(":set so=2<CR>{}", "j".repeat(15));
+    let screen = run(Some("numbers.txt"), 30, 10, &format!("{down}zt"));
+    assert_golden("scroll_top", &screen);
+    let screen = run(Some("numbers.txt"), 30, 10, &format!("{down}zz"));
+    assert_golden("scroll_center", &screen);
+    let screen = run(Some("numbers.txt"), 30, 10, &format!("{down}ztzb"));
+    assert_golden("scroll_bottom", &screen);
+}
+
+#[test]
+fn scrolls_half_pages_and_viewport_lines() {
+    // Ctrl-DとCtrl-Uは画面とカーソルを半画面分動かす
+    let screen = run(Some("numbers.txt"), 30, 10, "j<C-d><C-d>");
+    assert_golden("half_page_down", &screen);
+    let keys = format!("{}<C-u>", "j".repeat(29));
+    assert_golden("half_page_up", &run(Some("numbers.txt"), 30, 10, &keys));
+    // Ctrl-EとCtrl-Yは画面だけを動かし、カーソルはscrolloffの余白の外に押し出す
+    let screen = run(Some("numbers.txt"), 30, 10, ":set so=2<CR><C-e><C-e><C-e>");
+    assert_golden("scroll_view_down", &screen);
+    let screen = run(Some("numbers.txt"), 30, 10, "<C-d><C-d><C-y><C-y><C-y>");
+    assert_golden("scroll_view_up", &screen);
+}
+
+#[test]
+fn finds_characters_in_line() {
+    // fで次のeに移動し、;で繰り返し、,で逆向きに繰り返す
+    let screen = run(Some("hello.rs"), 40, 8, "jjjfe;,;");
+    assert_golden("char_search", &screen);
+    // 操作に続けると、前方への検索は移動先の文字まで、後方への検索はカーソルの手前までが対象
+    let screen = run(Some("hello.rs"), 40, 8, "jjjf(dt,f}dF{");
+    assert_golden("char_search_operator", &screen);
+    // 結合文字の付いた文字も基底の文字で見つかり、書記素単位で移動と削除をする
+    let screen = run(Some("clusters.txt"), 30, 6, "$Fe;xjdfa");
+    assert_golden("char_search_clusters", &screen);
+}
+
+#[test]
+fn renumbers_lists_only_in_prose_filetypes() {
+    // Markdownでは項目を削除すると後ろの番号を振り直し、ソースコードでは変えない
+    let screen = run(Some("numbered.md"), 30, 6, "jdd");
+    assert_golden("renumber_markdown", &screen);
+    let screen = run(Some("numbered.rs"), 30, 6, "jdd");
+    assert_golden("renumber_source", &screen);
+}
@@ -0,0 +1,31 @@
+// UTF-8として読めないファイルを表示するための16進数のダンプ
+use std::fmt::Write as _;
+
+// 1行に表示するバイト数
+const BYTES_PER_LINE: usize = 16;
+
+// オフセット、16進数のバイト、印字可能なASCII文字(それ以外は.)を並べた行に変換する
+pub fn lines(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(BYTES_PER_LINE)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut line = format!("{:08x} ", index.saturating_mul(BYTES_PER_LINE));
+            for byte in chunk {
+                let _ = write!(line, " {byte:02x}");
+            }
+            // 最後の行も文字の列の位置を揃える
+            let missing = BYTES_PER_LINE.saturating_sub(chunk.len());
+            line.push_str(&" ".repeat(missing.saturating_mul(3)));
+            line.push_str("  ");
+            line.extend(chunk.iter().map(|byte| {
+                if byte.is_ascii_graphic() || *byte == b' ' {
+                    char::from(*byte)
+                } else {
+                    '.'
+                }
+            }));
+            line
+        })
+        .collect()
+}
@@ -1,28 +1,126 @@
+use crate::theme::Theme;
 use termion::color;
+/// 書記素ごとのハイライトの種類
 #[derive(PartialEq, Clone, Copy)]
 pub enum Type {
+    /// ハイライトしない
     None,
+    /// 数値
     Number,
+    /// 検索語句に一致した部分
     Match,
+    /// 二重引用符で囲んだ文字列
     String,
+    /// 一重引用符で囲んだ文字
     Character,
+    /// 行コメント
     Comment,
+    /// 複数行コメント
     MultilineComment,
+    /// 1つ目の種類のキーワード
     PrimaryKeywords,
+    /// 2つ目の種類のキーワード
     SecondaryKeywords,
+    /// カーソル位置の括弧と対応する括弧
+    MatchingBracket,
+    /// カーソル位置の単語と同じ単語
+    CursorWord,
+    /// 差分の追加した行
+    DiffAdd,
+    /// 差分の削除した行
+    DiffDelete,
+    /// 変更した行の中で変わった文字
+    DiffText,
+    /// 行末の空白とタブ
+    Whitespace,
+    /// 辞書に無い単語(下線を引く)
+    SpellError,
+    /// ページャで表示するテキストのエスケープシーケンスで指定された文字色
+    Ansi(u8, u8, u8),
 }
 impl Type {
-    // 返り値の型はimpl traitで指定
-    pub fn to_color(self) -> impl color::Color {
+    /// テーマから文字色を返す(デフォルトの文字色の場合はNone)
+    pub fn to_color(self, theme: &Theme) -> Option<color::Rgb> {
         match self {
-            Type::Number => color::Rgb(220, 163, 163),
-            Type::Match => color::Rgb(38, 139, 210),
-            Type::String => color::Rgb(211, 54, 130),
-            Type::Character => color::Rgb(108, 113, 196),
-            Type::Comment | Type::MultilineComment => color::Rgb(133, 153, 0),
-            Type::PrimaryKeywords => color::Rgb(181, 137, 0),
-            Type::SecondaryKeywords => color::Rgb(42, 161, 152),
-            Type::None => color::Rgb(255, 255, 255),
+            Type::Number => Some(theme.number),
+            Type::Match => Some(theme.search_match),
+            Type::String => Some(theme.string),
+            Type::Character => Some(theme.character),
+            Type::Comment | Type::MultilineComment => Some(theme.comment),
+            Type::PrimaryKeywords => Some(theme.primary_keywords),
+            Type::SecondaryKeywords => Some(theme.secondary_keywords),
+            Type::MatchingBracket => Some(theme.matching_bracket),
+            Type::DiffAdd => Some(theme.diff_add),
+            Type::DiffDelete => Some(theme.diff_delete),
+            Type::Ansi(r, g, b) => Some(color::Rgb(r, g, b)),
+            Type::None
+            | Type::CursorWord
+            | Type::DiffText
+            | Type::Whitespace
+            | Type::SpellError => None,
         }
     }
+    /// 背景色を付ける種類の場合はその色を返す
+    pub fn to_bg_color(self, theme: &Theme) -> Option<color::Rgb> {
+        match self {
+            Type::CursorWord => Some(theme.cursor_word_bg),
+            Type::DiffText => Some(theme.diff_text_bg),
+            Type::Whitespace => Some(theme.trailing_whitespace_bg),
+            _ => None,
+        }
+    }
+}
+
+/// 描画時にシンタックスハイライトの上に重ねる背景色
+#[derive(Default)]
+pub struct Overlay {
+    /// 行全体の背景色
+    pub line: Option<color::Rgb>,
+    /// 画面左端から指定した位置(半角文字単位)の列の背景色(前にあるものを優先する)
+    pub columns: Vec<(usize, color::Rgb)>,
+    /// 下線を引く画面左端からの範囲[開始, 終了)(半角文字単位)
+    pub underlines: Vec<(usize, usize)>,
+    /// ビジュアルモードで選択している範囲[開始, 終了)(半角文字単位)と背景色(他の背景色より優先する)
+    pub selection: Option<(usize, usize, color::Rgb)>,
+}
+
+impl Overlay {
+    pub fn is_empty(&self) -> bool {
+        self.line.is_none()
+            && self.columns.is_empty()
+            && self.underlines.is_empty()
+            && self.selection.is_none()
+    }
+    /// 画面左端からの位置と幅(半角文字単位)の文字に下線を引くか
+    pub fn is_underlined(&self, position: usize, width: usize) -> bool {
+        self.underlines
+            .iter()
+            .any(|(start, end)| *start < position.saturating_add(width) && position < *end)
+    }
+    /// 画面左端からの位置と幅(半角文字単位)の文字に重ねる背景色を返す
+    pub fn bg_at(&self, position: usize, width: usize) -> Option<color::Rgb> {
+        self.columns
+            .iter()
+            .find(|(column, _)| position <= *column && *column < position.saturating_add(width))
+            .map(|(_, bg)| *bg)
+            .or(self.line)
+    }
+    /// 画面左端からの位置と幅(半角文字単位)の文字が選択範囲にあれば選択の背景色を返す
+    pub fn selection_bg(&self, position: usize, width: usize) -> Option<color::Rgb> {
+        self.selection
+            .filter(|(start, end, _)| *start < position.saturating_add(width) && position < *end)
+            .map(|(_, _, bg)| bg)
+    }
+    /// 背景色を付ける一番右の列
+    pub fn last_column(&self) -> Option<usize> {
+        let selection = self
+            .selection
+            .filter(|(start, end, _)| start < end)
+            .map(|(_, end, _)| end.saturating_sub(1));
+        self.columns
+            .iter()
+            .map(|(column, _)| *column)
+            .chain(selection)
+            .max()
+    }
 }
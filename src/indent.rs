@@ -0,0 +1,140 @@
+use std::cmp::Reverse;
+
+// インデントの推測に使う最大行数
+const MAX_GUESS_LINES: usize = 1000;
+// 推測できない場合のインデント幅
+const DEFAULT_WIDTH: usize = 4;
+
+// インデント設定がどこから来たか
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Source {
+    #[default]
+    Default,
+    // ファイルの内容から推測した
+    Detected,
+    // :setなどで明示的に指定した(推測で上書きしない)
+    Explicit,
+}
+
+// バッファごとのインデント設定
+#[derive(Clone, Copy)]
+pub struct Indent {
+    // trueの場合はタブ、falseの場合は空白でインデントする
+    pub use_tabs: bool,
+    // 1段のインデントの幅
+    pub width: usize,
+    pub source: Source,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Self {
+            use_tabs: true,
+            width: DEFAULT_WIDTH,
+            source: Source::Default,
+        }
+    }
+}
+
+impl Indent {
+    // :set indent?で表示する説明
+    pub fn describe(&self) -> String {
+        let kind = if self.use_tabs { "tabs" } else { "spaces" };
+        let source = match self.source {
+            Source::Default => "default",
+            Source::Detected => "detected",
+            Source::Explicit => "set",
+        };
+        format!("indent: {kind}, width {} ({source})", self.width)
+    }
+    // カーソルの列から次のインデント位置までの、インデント1段分の文字列
+    pub fn unit(&self, x: usize) -> String {
+        if self.use_tabs {
+            return "\t".to_string();
+        }
+        let width = self.width.max(1);
+        #[allow(clippy::arithmetic_side_effects)]
+        let count = width - x % width;
+        " ".repeat(count)
+    }
+    // 行頭の空白の幅(タブは次のインデント位置まで進む)
+    #[allow(clippy::arithmetic_side_effects)]
+    pub fn columns(&self, leading: &str) -> usize {
+        let width = self.width.max(1);
+        leading.chars().fold(0, |columns, c| {
+            if c == '\t' {
+                columns + width - columns % width
+            } else {
+                columns + 1
+            }
+        })
+    }
+    // 指定した幅の行頭の空白(タブでインデントする場合は余りを空白で埋める)
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+    pub fn whitespace(&self, columns: usize) -> String {
+        if !self.use_tabs {
+            return " ".repeat(columns);
+        }
+        let width = self.width.max(1);
+        format!(
+            "{}{}",
+            "\t".repeat(columns / width),
+            " ".repeat(columns % width)
+        )
+    }
+}
+
+// 行頭の空白からインデントにタブと空白のどちらを使っているかと幅を推測する
+// インデントされた行が無ければNoneを返す
+pub fn guess<'a>(lines: impl Iterator<Item = &'a str>) -> Option<Indent> {
+    let mut tab_lines = 0_usize;
+    let mut space_lines = 0_usize;
+    // 前の行とのインデントの差ごとの出現回数
+    let mut deltas = [0_usize; 9];
+    let mut previous = 0;
+    for line in lines.take(MAX_GUESS_LINES) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with('\t') {
+            tab_lines = tab_lines.saturating_add(1);
+            continue;
+        }
+        let indent = line
+            .len()
+            .saturating_sub(line.trim_start_matches(' ').len());
+        if indent > 0 {
+            space_lines = space_lines.saturating_add(1);
+        }
+        if let Some(count) = deltas.get_mut(indent.abs_diff(previous)) {
+            *count = count.saturating_add(1);
+        }
+        previous = indent;
+    }
+    if tab_lines == 0 && space_lines == 0 {
+        return None;
+    }
+    if tab_lines > space_lines {
+        return Some(Indent {
+            use_tabs: true,
+            source: Source::Detected,
+            ..Indent::default()
+        });
+    }
+    // 最も多く現れた差を幅とし、同数の場合は狭い方を選ぶ
+    // 差が1のものはブロックコメントの続きの行などなので幅の候補にしない
+    let width = (2..deltas.len())
+        .max_by_key(|width| {
+            (
+                deltas.get(*width).copied().unwrap_or_default(),
+                Reverse(*width),
+            )
+        })
+        .filter(|width| deltas.get(*width).is_some_and(|count| *count > 0))
+        .unwrap_or(DEFAULT_WIDTH);
+    Some(Indent {
+        use_tabs: false,
+        width,
+        source: Source::Detected,
+    })
+}
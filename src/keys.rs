@@ -0,0 +1,129 @@
+use termion::event::Key;
+
+// キーの並びを<CR>や<C-w>のような表記を使った文字列にする
+pub fn to_text(keys: &[Key]) -> String {
+    keys.iter().map(|key| key_name(*key)).collect()
+}
+
+fn key_name(key: Key) -> String {
+    let name = match key {
+        Key::Char('\n') => "CR".to_string(),
+        Key::Char('\t') => "Tab".to_string(),
+        Key::Char('<') => "lt".to_string(),
+        Key::Char(c) => return c.to_string(),
+        Key::Esc => "Esc".to_string(),
+        Key::Backspace => "BS".to_string(),
+        Key::Delete => "Del".to_string(),
+        Key::Insert => "Insert".to_string(),
+        Key::Up => "Up".to_string(),
+        Key::Down => "Down".to_string(),
+        Key::Left => "Left".to_string(),
+        Key::Right => "Right".to_string(),
+        Key::Home => "Home".to_string(),
+        Key::End => "End".to_string(),
+        Key::PageUp => "PageUp".to_string(),
+        Key::PageDown => "PageDown".to_string(),
+        Key::BackTab => "S-Tab".to_string(),
+        Key::Ctrl(c) => format!("C-{c}"),
+        Key::Alt(c) => format!("M-{c}"),
+        Key::F(n) => format!("F{n}"),
+        // 表記できないキーは記録しない
+        _ => return String::new(),
+    };
+    format!("<{name}>")
+}
+
+// :terminalで実行中のプログラムに送るキーのバイト列(送れないキーは空)
+pub fn to_bytes(key: Key) -> Vec<u8> {
+    let sequence = match key {
+        // 端末からはEnterがCRとして届く
+        Key::Char('\n') => "\r",
+        Key::Char(c) => return c.to_string().into_bytes(),
+        Key::Ctrl(c) if c.is_ascii() => return vec![(c as u8) & 0x1f],
+        Key::Alt(c) => return format!("\x1b{c}").into_bytes(),
+        Key::Esc => "\x1b",
+        Key::Backspace => "\x7f",
+        Key::Delete => "\x1b[3~",
+        Key::Insert => "\x1b[2~",
+        Key::Up => "\x1b[A",
+        Key::Down => "\x1b[B",
+        Key::Right => "\x1b[C",
+        Key::Left => "\x1b[D",
+        Key::Home => "\x1b[H",
+        Key::End => "\x1b[F",
+        Key::PageUp => "\x1b[5~",
+        Key::PageDown => "\x1b[6~",
+        Key::BackTab => "\x1b[Z",
+        _ => "",
+    };
+    sequence.as_bytes().to_vec()
+}
+
+// termionが解釈しないキーの入力のうち、扱えるものをキーに変換する
+// Alt+上下の矢印キーはAlt-kとAlt-jとして扱う
+pub fn from_unsupported(bytes: &[u8]) -> Option<Key> {
+    match bytes {
+        b"\x1b[1;3A" => Some(Key::Alt('k')),
+        b"\x1b[1;3B" => Some(Key::Alt('j')),
+        _ => None,
+    }
+}
+
+// to_textの表記の文字列をキーの並びに戻す
+// 知らない<...>の表記はそのままの文字として扱う
+pub fn parse(text: &str) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let special = rest
+            .strip_prefix('<')
+            .and_then(|inner| inner.split_once('>'))
+            .and_then(|(name, after)| Some((named_key(name)?, after)));
+        if let Some((key, after)) = special {
+            keys.push(key);
+            rest = after;
+        } else {
+            keys.push(Key::Char(c));
+            rest = rest.get(c.len_utf8()..).unwrap_or_default();
+        }
+    }
+    keys
+}
+
+fn named_key(name: &str) -> Option<Key> {
+    let key = match &name.to_lowercase()[..] {
+        "cr" | "enter" | "return" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "lt" => Key::Char('<'),
+        "space" => Key::Char(' '),
+        "esc" => Key::Esc,
+        "bs" => Key::Backspace,
+        "del" => Key::Delete,
+        "insert" => Key::Insert,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "s-tab" => Key::BackTab,
+        lower => {
+            // 修飾キーの後ろの文字は大文字と小文字を区別する
+            let modified = |prefix: &str| {
+                let mut chars = name.get(prefix.len()..)?.chars();
+                let c = chars.next()?;
+                chars.next().is_none().then_some(c)
+            };
+            if lower.starts_with("c-") {
+                Key::Ctrl(modified("c-")?)
+            } else if lower.starts_with("m-") {
+                Key::Alt(modified("m-")?)
+            } else {
+                Key::F(lower.strip_prefix('f')?.parse().ok()?)
+            }
+        }
+    };
+    Some(key)
+}
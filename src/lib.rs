@@ -0,0 +1,83 @@
+//! エディタの本体(ベンチマークや他のツールから端末無しで編集とハイライトの処理を使えるようにライブラリにしている)
+//! 公開するのはDocument、Row、FileTypeとハイライトの種類などで、それ以外のモジュールは内部の実装とする
+#![warn(missing_docs)]
+#![warn(clippy::all, clippy::pedantic)]
+// crates.ioで配布するライブラリではないので、公開する関数の書き方に対する指摘はしない
+#![allow(
+    clippy::must_use_candidate,
+    clippy::return_self_not_must_use,
+    clippy::len_without_is_empty,
+    clippy::should_implement_trait
+)]
+#![warn(
+    clippy::indexing_slicing,
+    clippy::arithmetic_side_effects,
+    clippy::cast_possible_truncation,
+    clippy::integer_division
+)]
+mod ansi;
+mod changelist;
+mod charsearch;
+mod cli;
+mod comment;
+mod completion;
+mod crypt;
+mod diff;
+mod document;
+mod editor;
+mod error;
+mod explorer;
+mod filetype;
+mod filter;
+mod fold;
+mod format;
+mod grep;
+mod gutter;
+#[cfg(test)]
+mod harness;
+mod hexdump;
+mod highlighting;
+mod indent;
+mod keys;
+mod list;
+mod loader;
+mod location;
+mod lsp;
+mod make;
+mod marks;
+mod options;
+mod plugin;
+mod prompt;
+mod pty;
+mod recent;
+mod registers;
+mod row;
+#[cfg(test)]
+mod screen;
+mod selection;
+mod spell;
+mod state;
+mod statusline;
+mod syntax;
+mod table;
+mod terminal;
+mod textobject;
+mod theme;
+mod undo;
+mod view;
+mod vt;
+mod width;
+
+pub use cli::Cli;
+pub use document::BufferKind;
+pub use document::Document;
+pub use editor::Editor;
+pub use editor::Position;
+pub use editor::SearchDirection;
+pub use editor::SearchOptions;
+pub use error::Error;
+pub use filetype::FileType;
+pub use filetype::HighlightingOptions;
+pub use highlighting::Type as HighlightType;
+pub use row::Row;
+pub use terminal::Terminal;
@@ -0,0 +1,135 @@
+// Markdownのリストの項目
+pub struct Item {
+    indent: String,
+    // 番号付きリストの番号(箇条書きの場合はNone)
+    number: Option<usize>,
+    // 箇条書きの記号(-*+)、または番号の後ろの記号(.か))
+    marker: char,
+    // チェックボックスの状態(チェックボックスが無い場合はNone)
+    checked: Option<bool>,
+    // 行頭部分の後ろの本文
+    text: String,
+}
+
+impl Item {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    pub fn is_ordered(&self) -> bool {
+        self.number.is_some()
+    }
+    // インデント、記号、チェックボックスからなる行頭部分を返す
+    pub fn prefix(&self) -> String {
+        let marker = if let Some(number) = self.number {
+            format!("{number}{}", self.marker)
+        } else {
+            self.marker.to_string()
+        };
+        let checkbox = match self.checked {
+            Some(true) => "[x] ",
+            Some(false) => "[ ] ",
+            None => "",
+        };
+        format!("{}{marker} {checkbox}", self.indent)
+    }
+    pub fn to_line(&self) -> String {
+        format!("{}{}", self.prefix(), self.text)
+    }
+    // 改行したときに続ける次の項目を返す
+    pub fn next(&self) -> Self {
+        Self {
+            indent: self.indent.clone(),
+            number: self.number.map(|number| number.saturating_add(1)),
+            marker: self.marker,
+            checked: self.checked.map(|_| false),
+            text: String::new(),
+        }
+    }
+    // チェックボックスの状態を切り替える(無い場合は未チェックのチェックボックスを付ける)
+    pub fn toggle(&mut self) {
+        self.checked = Some(self.checked == Some(false));
+    }
+}
+
+// 行がリストの項目であれば解析して返す
+pub fn parse(line: &str) -> Option<Item> {
+    let rest = line.trim_start();
+    let indent = line
+        .get(..line.len().saturating_sub(rest.len()))
+        .unwrap_or_default()
+        .to_string();
+    let mut chars = rest.chars();
+    let first = chars.next()?;
+    let (number, marker, rest) = if "-*+".contains(first) {
+        (None, first, chars.as_str())
+    } else {
+        // 数字の後ろに.か)が続けば番号付きリスト
+        let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        let number = digits.parse().ok()?;
+        let rest = rest.get(digits.len()..)?;
+        let mut chars = rest.chars();
+        let marker = chars.next().filter(|c| *c == '.' || *c == ')')?;
+        (Some(number), marker, chars.as_str())
+    };
+    // 記号の後ろには空白が必要(空の項目は記号のみでも良い)
+    let rest = if rest.is_empty() {
+        rest
+    } else {
+        rest.strip_prefix(' ')?
+    };
+    let (checked, text) = if let Some(text) = rest.strip_prefix("[ ]") {
+        (Some(false), text)
+    } else if let Some(text) = rest
+        .strip_prefix("[x]")
+        .or_else(|| rest.strip_prefix("[X]"))
+    {
+        (Some(true), text)
+    } else {
+        (None, rest)
+    };
+    Some(Item {
+        indent,
+        number,
+        marker,
+        checked,
+        text: text.strip_prefix(' ').unwrap_or(text).to_string(),
+    })
+}
+
+// 番号付きリストの番号を、インデントの深さごとに振り直す
+pub fn renumber(lines: &[String]) -> Vec<String> {
+    // インデントの深さと次に振る番号のスタック
+    let mut counters: Vec<(usize, usize)> = Vec::new();
+    lines
+        .iter()
+        .map(|line| {
+            let Some(mut item) = parse(line) else {
+                return line.clone();
+            };
+            let depth = item.indent.len();
+            // より深いインデントの番号は数え直す
+            while counters.last().is_some_and(|(d, _)| *d > depth) {
+                counters.pop();
+            }
+            if !item.is_ordered() {
+                // 同じ深さの箇条書きが挟まったら番号付きリストは終わり
+                if counters.last().is_some_and(|(d, _)| *d == depth) {
+                    counters.pop();
+                }
+                return line.clone();
+            }
+            match counters.last_mut() {
+                Some((d, next)) if *d == depth => {
+                    item.number = Some(*next);
+                    *next = next.saturating_add(1);
+                }
+                _ => {
+                    // リストの最初の番号はそのまま使う
+                    let first = item.number.unwrap_or(1);
+                    counters.push((depth, first.saturating_add(1)));
+                }
+            }
+            item.to_line()
+        })
+        .collect()
+}
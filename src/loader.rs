@@ -0,0 +1,89 @@
+use crate::Row;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// 一度に送る行数
+const CHUNK_LINES: usize = 1000;
+
+// 別スレッドでファイルを読み込み、読み込んだ行を少しずつ受け取る
+pub struct Loader {
+    chunks: Receiver<Result<(Vec<Row>, u64), Error>>,
+    // ファイルのバイト数と読み込み済みのバイト数
+    total: u64,
+    loaded: u64,
+}
+
+impl Loader {
+    pub fn start(file: File, total: u64) -> Self {
+        let (sender, chunks) = mpsc::channel();
+        thread::spawn(move || {
+            let mut rows = Vec::new();
+            let mut bytes = 0_u64;
+            let mut reader = BufReader::new(file);
+            let mut line = String::new();
+            loop {
+                line.clear();
+                let read = match reader.read_line(&mut line) {
+                    // ファイルの終わり
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(error) => {
+                        let _ = sender.send(Err(error));
+                        return;
+                    }
+                };
+                bytes = bytes.saturating_add(read as u64);
+                let text = line.strip_suffix('\n').unwrap_or(&line);
+                rows.push(Row::from(text.strip_suffix('\r').unwrap_or(text)));
+                if rows.len() >= CHUNK_LINES {
+                    if sender.send(Ok((mem::take(&mut rows), bytes))).is_err() {
+                        // ドキュメントが閉じられた
+                        return;
+                    }
+                    bytes = 0;
+                }
+            }
+            let _ = sender.send(Ok((rows, bytes)));
+        });
+        Self {
+            chunks,
+            total,
+            loaded: 0,
+        }
+    }
+    // 読み込み済みの行を受け取る
+    // 全て読み込んだ場合はtrueを返す
+    pub fn receive(&mut self, rows: &mut Vec<Row>) -> Result<bool, Error> {
+        loop {
+            match self.chunks.try_recv() {
+                Ok(chunk) => {
+                    let (chunk, bytes) = chunk?;
+                    rows.extend(chunk);
+                    self.loaded = self.loaded.saturating_add(bytes);
+                }
+                Err(TryRecvError::Empty) => return Ok(false),
+                Err(TryRecvError::Disconnected) => return Ok(true),
+            }
+        }
+    }
+    // 全て読み込むまで待って行を受け取る
+    pub fn wait(&mut self, rows: &mut Vec<Row>) -> Result<(), Error> {
+        for chunk in &self.chunks {
+            let (chunk, bytes) = chunk?;
+            rows.extend(chunk);
+            self.loaded = self.loaded.saturating_add(bytes);
+        }
+        Ok(())
+    }
+    // 読み込んだ割合(%)
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+    pub fn progress(&self) -> u64 {
+        if self.total == 0 {
+            return 100;
+        }
+        self.loaded.saturating_mul(100) / self.total
+    }
+}
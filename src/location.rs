@@ -0,0 +1,40 @@
+// ファイル内の位置 (path:line:col)
+pub struct Location {
+    pub path: String,
+    // 1から始まる行番号
+    pub line: Option<usize>,
+    // 1から始まる列番号
+    pub column: Option<usize>,
+}
+
+// ファイル以外を指すURLか判定する
+pub fn is_url(text: &str) -> bool {
+    text.contains("://") && !text.contains("file://")
+}
+
+// path:line:col, path:line, pathの形式の文字列を解析する
+// コンパイラの出力やスタックトレース中の文字列を想定して、前後の括弧や引用符は無視する
+pub fn parse(text: &str) -> Option<Location> {
+    let text = text.trim_matches(|c: char| "\"'`()[]<>{},;".contains(c));
+    let text = text.strip_prefix("file://").unwrap_or(text);
+    let mut parts = text.split(':');
+    let path = parts.next().filter(|path| !path.is_empty())?;
+    let line = parts.next().and_then(|line| line.parse().ok());
+    // 行番号が無い場合は列番号も無視する
+    let column = line.and(parts.next().and_then(|column| column.parse().ok()));
+    Some(Location {
+        path: path.to_string(),
+        line,
+        column,
+    })
+}
+
+// コンパイラの出力などの行の中から、行番号の付いたpath:line:colを探す
+// (rustcの"--> src/main.rs:3:5"やgccの"main.c:3:5: error: ..."など)
+pub fn find(text: &str) -> Option<Location> {
+    text.split_whitespace()
+        .filter_map(|word| parse(word.trim_end_matches(':')))
+        .find(|location| {
+            location.line.is_some() && !location.path.chars().all(|c| c.is_ascii_digit())
+        })
+}
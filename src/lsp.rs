@@ -0,0 +1,555 @@
+// Language Server Protocolのクライアント(診断結果、定義へのジャンプ、ホバーのみ)
+// ファイルタイプごとにサーバを起動し、開いたファイルの内容を送って、送られてくる診断結果や応答を受け取る
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use unicode_segmentation::UnicodeSegmentation;
+
+// ファイルタイプ名=サーバのコマンドを,で区切って指定する(Rust=rust-analyzerなど)
+const SERVERS_VAR: &str = "DECI_LSP";
+// initializeの要求のID(他の要求にはこれより大きいIDを順に振る)
+const INITIALIZE_ID: u64 = 1;
+// 受け取るメッセージの最大バイト数(Content-Lengthがこれを超えたら接続を切る)
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+// 終了するときにサーバがshutdownに応答してexitで終了するのを待つ時間
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+// 診断結果の重要度
+#[derive(Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    // メッセージバーに表示するときの接頭辞
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Error => "E",
+            Self::Warning => "W",
+            Self::Information => "I",
+            Self::Hint => "H",
+        }
+    }
+}
+
+// エディタから送る要求の種類
+#[derive(Clone, Copy, PartialEq)]
+pub enum Request {
+    Definition,
+    Hover,
+}
+
+impl Request {
+    fn method(self) -> &'static str {
+        match self {
+            Self::Definition => "textDocument/definition",
+            Self::Hover => "textDocument/hover",
+        }
+    }
+}
+
+pub struct Diagnostic {
+    // 開始位置と終了位置の(行, 列)(列はUTF-16のコード単位で数える)
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub severity: Severity,
+    pub message: String,
+}
+
+// 起動したサーバとの接続
+pub struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    // 別スレッドで読み込んだサーバからのメッセージ(読めなかった場合はそのエラーを送って読むのをやめる)
+    messages: Receiver<Result<Value, Error>>,
+    // initializeの応答を受け取るまで送らずに溜めておく通知と要求(初期化が終わったらNone)
+    queued: Option<Vec<Value>>,
+    // 最後に送った要求のID
+    last_id: u64,
+    // 要求のIDごとに受け取った応答の結果
+    responses: HashMap<u64, Value>,
+    // ファイルごとに送った内容の版
+    versions: HashMap<PathBuf, i64>,
+    // ファイルごとの診断結果
+    diagnostics: HashMap<PathBuf, Vec<Diagnostic>>,
+}
+
+// ファイルタイプに対して設定されているサーバのコマンド
+// serversは:set lspで設定した値(設定されていなければ環境変数の値を使う)
+pub fn server_command(servers: Option<&str>, file_type: &str) -> Option<String> {
+    servers
+        .map(str::to_string)
+        .or_else(|| env::var(SERVERS_VAR).ok())?
+        .split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(file_type))
+        .map(|(_, command)| command.trim().to_string())
+        .filter(|command| !command.is_empty())
+}
+
+impl Client {
+    // サーバを起動してinitializeを要求する(応答は待たない)
+    pub fn start(command: &str) -> Result<Self, Error> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Empty command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(Error::new(ErrorKind::BrokenPipe, "No pipe to the server"));
+        };
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            loop {
+                let message = match read_message(&mut reader) {
+                    Ok(Some(message)) => Ok(message),
+                    Ok(None) => break,
+                    Err(error) => Err(error),
+                };
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let mut client = Self {
+            child,
+            stdin,
+            messages,
+            queued: Some(Vec::new()),
+            last_id: INITIALIZE_ID,
+            responses: HashMap::new(),
+            versions: HashMap::new(),
+            diagnostics: HashMap::new(),
+        };
+        let root = env::current_dir()?;
+        client.write(&json!({
+            "jsonrpc": "2.0",
+            "id": INITIALIZE_ID,
+            "method": "initialize",
+            "params": {
+                "processId": process::id(),
+                "rootUri": to_uri(&root),
+                "capabilities": {
+                    "textDocument": {
+                        "publishDiagnostics": {},
+                        "definition": {},
+                        "hover": { "contentFormat": ["plaintext", "markdown"] }
+                    }
+                }
+            }
+        }))?;
+        Ok(client)
+    }
+    // Content-Lengthのヘッダを付けてメッセージを送る
+    fn write(&mut self, message: &Value) -> Result<(), Error> {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.stdin.flush()
+    }
+    // 初期化が終わっていれば送り、終わっていなければ溜めておく
+    fn send(&mut self, message: Value) -> Result<(), Error> {
+        if let Some(queued) = &mut self.queued {
+            queued.push(message);
+            return Ok(());
+        }
+        self.write(&message)
+    }
+    fn notify(&mut self, method: &str, params: &Value) -> Result<(), Error> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+    // ファイルの(行, UTF-16の列)の位置について要求を送り、応答を受け取るためのIDを返す
+    pub fn request(
+        &mut self,
+        request: Request,
+        path: &Path,
+        position: (usize, usize),
+    ) -> Result<u64, Error> {
+        self.last_id = self.last_id.saturating_add(1);
+        let id = self.last_id;
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": request.method(),
+            "params": {
+                "textDocument": { "uri": to_uri(path) },
+                "position": { "line": position.0, "character": position.1 }
+            }
+        }))?;
+        Ok(id)
+    }
+    // 要求に対する応答を受け取っていれば、その結果を返す
+    pub fn take_response(&mut self, id: u64) -> Option<Value> {
+        self.responses.remove(&id)
+    }
+    // 送ったことのあるファイルか
+    pub fn is_open(&self, path: &Path) -> bool {
+        self.versions.contains_key(path)
+    }
+    pub fn did_open(&mut self, path: &Path, language_id: &str, text: &str) -> Result<(), Error> {
+        self.versions.insert(path.to_path_buf(), 0);
+        self.notify(
+            "textDocument/didOpen",
+            &json!({
+                "textDocument": {
+                    "uri": to_uri(path),
+                    "languageId": language_id,
+                    "version": 0,
+                    "text": text
+                }
+            }),
+        )
+    }
+    // 変更後の内容全体を送る
+    pub fn did_change(&mut self, path: &Path, text: &str) -> Result<(), Error> {
+        let version = self.versions.entry(path.to_path_buf()).or_default();
+        *version = version.saturating_add(1);
+        let version = *version;
+        self.notify(
+            "textDocument/didChange",
+            &json!({
+                "textDocument": { "uri": to_uri(path), "version": version },
+                "contentChanges": [{ "text": text }]
+            }),
+        )
+    }
+    // 届いたメッセージを処理し、診断結果が変わったか要求への応答を受け取った場合はtrueを返す
+    // サーバが終了していたらErrを返す
+    pub fn receive(&mut self) -> Result<bool, Error> {
+        let mut changed = false;
+        loop {
+            let message = match self.messages.try_recv() {
+                Ok(message) => message?,
+                Err(TryRecvError::Empty) => return Ok(changed),
+                Err(TryRecvError::Disconnected) => {
+                    return Err(Error::new(ErrorKind::BrokenPipe, "Language server exited"));
+                }
+            };
+            let method = message.get("method").and_then(Value::as_str);
+            match (method, message.get("id")) {
+                // initializeの応答を受け取ったら溜めていた通知を送る
+                (None, Some(id)) if *id == json!(INITIALIZE_ID) => {
+                    self.write(
+                        &json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+                    )?;
+                    for message in self.queued.take().unwrap_or_default() {
+                        self.write(&message)?;
+                    }
+                }
+                // 要求への応答(エラーの場合は結果を空とする)
+                (None, Some(id)) => {
+                    if let Some(id) = id.as_u64() {
+                        let result = message.get("result").cloned().unwrap_or_default();
+                        self.responses.insert(id, result);
+                        changed = true;
+                    }
+                }
+                (Some("textDocument/publishDiagnostics"), None) => {
+                    if let Some((path, diagnostics)) =
+                        message.get("params").and_then(parse_diagnostics)
+                    {
+                        self.diagnostics.insert(path, diagnostics);
+                        changed = true;
+                    }
+                }
+                // サーバからの要求には対応していないので空の結果を返す
+                (Some(_), Some(id)) => {
+                    let id = id.clone();
+                    self.write(&json!({ "jsonrpc": "2.0", "id": id, "result": null }))?;
+                }
+                _ => (),
+            }
+        }
+    }
+    pub fn diagnostics(&self, path: &Path) -> &[Diagnostic] {
+        self.diagnostics.get(path).map_or(&[], Vec::as_slice)
+    }
+    // shutdownを要求し、応答を受け取ったらexitを通知してサーバが終了するのを待つ(待つ時間には上限を設ける)
+    fn shutdown(&mut self) {
+        let start = Instant::now();
+        self.last_id = self.last_id.saturating_add(1);
+        let id = self.last_id;
+        if self
+            .write(&json!({ "jsonrpc": "2.0", "id": id, "method": "shutdown" }))
+            .is_err()
+        {
+            return;
+        }
+        loop {
+            let timeout = SHUTDOWN_TIMEOUT.saturating_sub(start.elapsed());
+            match self.messages.recv_timeout(timeout) {
+                Ok(Ok(message)) if message.get("id") == Some(&json!(id)) => break,
+                Ok(Ok(_)) => (),
+                _ => return,
+            }
+        }
+        if self
+            .write(&json!({ "jsonrpc": "2.0", "method": "exit" }))
+            .is_err()
+        {
+            return;
+        }
+        while start.elapsed() < SHUTDOWN_TIMEOUT {
+            if !matches!(self.child.try_wait(), Ok(None)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+// 初期化が終わっていればサーバに終了を求め、それでも終了しなければ止める
+impl Drop for Client {
+    fn drop(&mut self) {
+        if self.queued.is_none() {
+            self.shutdown();
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// ヘッダと本文からなるメッセージを1つ読む(サーバの出力が終わったらNoneを返す)
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>, Error> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| Error::new(ErrorKind::InvalidData, "No Content-Length"))?;
+    // 壊れたサーバが巨大な長さを送ってきても、その分のメモリを確保しない
+    if length > MAX_MESSAGE_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Message too large: {length} bytes"),
+        ));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+// publishDiagnosticsの引数からファイルと診断結果を読む
+fn parse_diagnostics(params: &Value) -> Option<(PathBuf, Vec<Diagnostic>)> {
+    let path = from_uri(params.get("uri")?.as_str()?)?;
+    let position = |value: &Value| -> Option<(usize, usize)> {
+        let line = usize::try_from(value.get("line")?.as_u64()?).ok()?;
+        let character = usize::try_from(value.get("character")?.as_u64()?).ok()?;
+        Some((line, character))
+    };
+    let diagnostics = params
+        .get("diagnostics")?
+        .as_array()?
+        .iter()
+        .filter_map(|diagnostic| {
+            let range = diagnostic.get("range")?;
+            Some(Diagnostic {
+                start: position(range.get("start")?)?,
+                end: position(range.get("end")?)?,
+                severity: match diagnostic.get("severity").and_then(Value::as_u64) {
+                    Some(2) => Severity::Warning,
+                    Some(3) => Severity::Information,
+                    Some(4) => Severity::Hint,
+                    _ => Severity::Error,
+                },
+                message: diagnostic.get("message")?.as_str()?.to_string(),
+            })
+        })
+        .collect();
+    Some((path, diagnostics))
+}
+
+// definitionの応答(Location、Locationの配列、LocationLinkの配列)から移動先のファイルと(行, UTF-16の列)を読む
+pub fn parse_locations(result: &Value) -> Vec<(PathBuf, (usize, usize))> {
+    let locations = match result {
+        Value::Array(locations) => locations.as_slice(),
+        Value::Null => &[],
+        location => std::slice::from_ref(location),
+    };
+    locations
+        .iter()
+        .filter_map(|location| {
+            let (uri, range) = match location.get("targetUri") {
+                Some(uri) => (uri, location.get("targetSelectionRange")?),
+                None => (location.get("uri")?, location.get("range")?),
+            };
+            let start = range.get("start")?;
+            Some((
+                from_uri(uri.as_str()?)?,
+                (
+                    usize::try_from(start.get("line")?.as_u64()?).ok()?,
+                    usize::try_from(start.get("character")?.as_u64()?).ok()?,
+                ),
+            ))
+        })
+        .collect()
+}
+
+// hoverの応答の内容(MarkupContent、MarkedString、MarkedStringの配列)を行に分けて読む
+// Markdownのコードブロックの区切りの行と前後の空行は除く
+pub fn parse_hover(result: &Value) -> Vec<String> {
+    let Some(contents) = result.get("contents") else {
+        return Vec::new();
+    };
+    let contents = match contents {
+        Value::Array(contents) => contents.as_slice(),
+        contents => std::slice::from_ref(contents),
+    };
+    let text = contents
+        .iter()
+        .filter_map(|content| match content {
+            Value::String(text) => Some(text.as_str()),
+            content => content.get("value")?.as_str(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let lines: Vec<String> = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .map(str::to_string)
+        .collect();
+    let start = lines
+        .iter()
+        .position(|line| !line.trim().is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty())
+        .map_or(start, |end| end.saturating_add(1));
+    lines.get(start..end).unwrap_or_default().to_vec()
+}
+
+// 英数字と一部の記号以外をパーセントエンコードしたfile:のURI
+fn to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            let _ = write!(uri, "%{byte:02X}");
+        }
+    }
+    uri
+}
+
+fn from_uri(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut index = 0;
+    while let Some(byte) = encoded.get(index) {
+        let decoded = (*byte == b'%')
+            .then(|| encoded.get(index.saturating_add(1)..index.saturating_add(3)))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        if let Some(decoded) = decoded {
+            bytes.push(decoded);
+            index = index.saturating_add(3);
+        } else {
+            bytes.push(*byte);
+            index = index.saturating_add(1);
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+// UTF-16のコード単位で数えた列を、書記素単位の位置に変換する
+pub fn grapheme_index(line: &str, utf16_column: usize) -> usize {
+    let mut units: usize = 0;
+    for (index, grapheme) in line.graphemes(true).enumerate() {
+        if units >= utf16_column {
+            return index;
+        }
+        units = units.saturating_add(grapheme.encode_utf16().count());
+    }
+    line.graphemes(true).count()
+}
+
+// 書記素単位の位置を、UTF-16のコード単位で数えた列に変換する
+pub fn utf16_column(line: &str, index: usize) -> usize {
+    line.graphemes(true)
+        .take(index)
+        .map(|grapheme| grapheme.encode_utf16().count())
+        .fold(0, usize::saturating_add)
+}
+
+// 診断結果の範囲のうち、y行目にかかる部分の書記素単位の[開始, 終了)
+pub fn range_in_line(diagnostic: &Diagnostic, y: usize, line: &str) -> Option<(usize, usize)> {
+    if y < diagnostic.start.0 || diagnostic.end.0 < y {
+        return None;
+    }
+    let start = if y == diagnostic.start.0 {
+        grapheme_index(line, diagnostic.start.1)
+    } else {
+        0
+    };
+    let end = if y == diagnostic.end.0 {
+        grapheme_index(line, diagnostic.end.1)
+    } else {
+        line.graphemes(true).count()
+    };
+    // 幅の無い範囲は1文字分とする
+    Some((start, end.max(start.saturating_add(1))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_server_for_the_file_type_in_the_option() {
+        let servers = Some("rust = rust-analyzer,Python=");
+        assert!(server_command(servers, "Rust").as_deref() == Some("rust-analyzer"));
+        // コマンドが空の場合は環境変数の設定も使わない
+        assert!(server_command(servers, "Python").is_none());
+        assert!(server_command(Some(""), "Rust").is_none());
+    }
+
+    #[test]
+    fn reads_messages_with_content_length() {
+        // ヘッダの名前は大文字と小文字を区別せず、知らないヘッダは無視する
+        let mut input = concat!(
+            "Content-Length: 8\r\n\r\n{\"id\":1}",
+            "content-length: 2\r\nContent-Type: x\r\n\r\n{}"
+        )
+        .as_bytes();
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "id": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({})));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_messages_over_the_size_limit() {
+        let mut input = "Content-Length: 18446744073709551615\r\n\r\n{}".as_bytes();
+        let error = read_message(&mut input).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let mut input = "Content-Type: x\r\n\r\n{}".as_bytes();
+        assert!(read_message(&mut input).is_err());
+    }
+}
@@ -1,26 +1,93 @@
 #![warn(clippy::all, clippy::pedantic)]
 #![warn(
     clippy::indexing_slicing,
-    clippy::integer_arithmetic,
+    clippy::arithmetic_side_effects,
     clippy::cast_possible_truncation,
     clippy::integer_division
 )]
-mod document;
-mod editor;
-mod filetype;
-mod highlighting;
-mod row;
-mod terminal;
+use deci::{Cli, Editor};
+use std::env;
+use std::io;
+use std::process;
 
-use document::Document;
-use editor::Editor;
-use editor::Position;
-use editor::SearchDirection;
-use filetype::FileType;
-use filetype::HighlightingOptions;
-use row::Row;
-use terminal::Terminal;
+// 使い方の説明
+const USAGE: &str = "\
+Usage: deci [OPTIONS] [+LINE] [--] [FILE[:LINE[:COLUMN]]|DIRECTORY|-]
+       deci --batch COMMANDS FILE
+
+With -, standard input is read into an unnamed buffer (named when saved).
+
+Options:
+  -p, --pager           View the file or standard input read-only (q quits)
+  -R, --readonly        Open the file without allowing changes
+  -x, --encrypt         Ask for a passphrase to encrypt the state file
+                        (asked automatically once the file is encrypted)
+      --recover PATH    Open a file written after a crash as an unnamed buffer
+      --config PATH     Run the ex commands in PATH (one per line) at startup
+      --batch COMMANDS  Run ex commands (one per line, e.g. '%s/a/b/g' and 'w')
+                        on FILE without the screen, then exit
+      --no-color        Do not use colors
+  -h, --help            Print this help and exit
+  -V, --version         Print the version and exit
+";
 
 fn main() {
-    Editor::default().run();
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(error) => usage_error(&error),
+    };
+    if cli.help {
+        print!("{USAGE}");
+        return;
+    }
+    if cli.version {
+        println!("deci {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    // --batchでは端末を使わずにコマンドを実行して終了する
+    if let Some(commands) = &cli.batch {
+        let Some(file_name) = &cli.file else {
+            usage_error("--batch requires a file");
+        };
+        process::exit(Editor::batch(file_name, commands));
+    }
+    // 端末でなければ画面を描画できないので、エラーと使い方を表示して終了する
+    // ページャとして使う場合と-を指定した場合は標準入力からテキストを読む
+    let stdin = cli.pager || cli.file.as_deref() == Some("-");
+    if !(stdin || termion::is_tty(&io::stdin())) || !termion::is_tty(&io::stdout()) {
+        usage_error("standard input and output must be a terminal");
+    }
+    let mut editor = match Editor::new(&cli) {
+        Ok(editor) => editor,
+        Err(error) => {
+            eprintln!("deci: {error}");
+            process::exit(1);
+        }
+    };
+    let result = editor.run();
+    // 続けられないエラーで終了する場合は、保存していない変更を失わないように書き出す
+    let recovered = if result.is_err() {
+        editor.write_recovery_files()
+    } else {
+        Vec::new()
+    };
+    // 端末の状態を元に戻してから終了する
+    drop(editor);
+    match result {
+        Ok(status) => process::exit(status),
+        Err(error) => {
+            eprintln!("deci: {error}");
+            for path in recovered {
+                eprintln!("deci: unsaved changes were written to {path} (open with --recover)");
+            }
+            process::exit(1);
+        }
+    }
+}
+
+// エラーと使い方を表示して終了する
+fn usage_error(message: &str) -> ! {
+    eprintln!("deci: {message}\n");
+    eprint!("{USAGE}");
+    process::exit(2);
 }
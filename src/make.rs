@@ -0,0 +1,145 @@
+// :makeと:!で別スレッドで実行するコマンド
+use std::env;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// :makeで実行するコマンド(:makeの引数は後ろに付け足す)
+const MAKE_VAR: &str = "DECI_MAKE";
+
+// programは:set makeprgで設定した値(設定されていなければ環境変数の値を使う)
+// どちらも無い場合はCargoのプロジェクトであればcargo build、それ以外はmakeを実行する
+pub fn command(program: Option<&str>, args: &str) -> String {
+    let program = program
+        .map(str::to_string)
+        .or_else(|| env::var(MAKE_VAR).ok())
+        .map(|command| command.trim().to_string())
+        .filter(|command| !command.is_empty())
+        .unwrap_or_else(|| {
+            if Path::new("Cargo.toml").exists() {
+                "cargo build".to_string()
+            } else {
+                "make".to_string()
+            }
+        });
+    if args.is_empty() {
+        program
+    } else {
+        format!("{program} {args}")
+    }
+}
+
+// 実行中のコマンド(標準エラー出力も標準出力とまとめて1行ずつ受け取る)
+pub struct Job {
+    child: Child,
+    lines: Receiver<String>,
+}
+
+impl Job {
+    pub fn start(command: &str) -> io::Result<Self> {
+        // 後ろに2>&1を付けると、;や|で繋いだコマンドでは最後のコマンドの標準エラー出力しかまとめられない
+        // 先にシェル自体の標準エラー出力を標準出力に向けてから実行する
+        // 止めるときにシェルが起動したコマンドもまとめて止められるように、新しいプロセスグループで実行する
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(format!("exec 2>&1; {command}"))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+        let (sender, lines) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            thread::spawn(move || {
+                // UTF-8でない出力で読むのをやめると、書き込んだコマンドがSIGPIPEで終了してしまう
+                // 1行ずつバイト列で読み、UTF-8でない部分は置き換えて最後まで読む
+                let mut reader = BufReader::new(stdout);
+                let mut bytes = Vec::new();
+                while matches!(reader.read_until(b'\n', &mut bytes), Ok(read) if read > 0) {
+                    let line = bytes.strip_suffix(b"\n").unwrap_or(&bytes);
+                    let line = line.strip_suffix(b"\r").unwrap_or(line);
+                    if sender
+                        .send(String::from_utf8_lossy(line).into_owned())
+                        .is_err()
+                    {
+                        // コマンドを止めた
+                        return;
+                    }
+                    bytes.clear();
+                }
+            });
+        }
+        Ok(Self { child, lines })
+    }
+    // 出力された行を受け取る
+    // 出力が終わった場合はコマンドの終了を待って終了状態を返す
+    pub fn receive(&mut self, lines: &mut Vec<String>) -> Option<io::Result<ExitStatus>> {
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => lines.push(line),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(self.child.wait()),
+            }
+        }
+    }
+    // コマンドが終了するまで出力を受け取り、終了状態を返す
+    pub fn wait(&mut self, lines: &mut Vec<String>) -> io::Result<ExitStatus> {
+        lines.extend(self.lines.iter());
+        self.child.wait()
+    }
+}
+
+// 終了前に破棄した場合(:makeをやり直した場合など)はシェルが起動したコマンドも含めて止める
+impl Drop for Job {
+    fn drop(&mut self) {
+        if matches!(self.child.try_wait(), Ok(None)) {
+            // プロセスグループのIDはシェルのプロセスIDと同じ
+            if let Ok(group) = libc::pid_t::try_from(self.child.id()) {
+                // SAFETY: まだ終了を待っていない子プロセスが作ったプロセスグループにシグナルを送るだけ
+                unsafe {
+                    libc::kill(group.saturating_neg(), libc::SIGKILL);
+                }
+            }
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn reads_output_that_is_not_utf8_to_the_end() {
+        let mut job = Job::start("printf 'before\\377\\r\\nafter\\n'; exit 3").unwrap();
+        let mut lines = Vec::new();
+        let status = job.wait(&mut lines).unwrap();
+        assert_eq!(lines, ["before\u{fffd}", "after"]);
+        // SIGPIPEで終了せずに最後まで実行される
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn stops_commands_started_by_the_shell() {
+        let mut job = Job::start("sleep 30 & echo $!; wait").unwrap();
+        let mut lines = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while lines.is_empty() && Instant::now() < deadline {
+            assert!(job.receive(&mut lines).is_none());
+            thread::sleep(Duration::from_millis(10));
+        }
+        let sleep = PathBuf::from(format!("/proc/{}", lines.first().unwrap()));
+        assert!(sleep.exists());
+        drop(job);
+        // 親のシェルが止まった後に残ったsleepはinitが回収する
+        while sleep.exists() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!sleep.exists());
+    }
+}
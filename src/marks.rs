@@ -0,0 +1,34 @@
+use crate::Position;
+use std::collections::HashMap;
+
+// ドキュメント内の位置に付けた印('^は最後に挿入モードを抜けた位置)
+#[derive(Default)]
+pub struct Marks {
+    positions: HashMap<char, Position>,
+}
+
+impl Marks {
+    pub fn set(&mut self, mark: char, at: &Position) {
+        self.positions.insert(mark, at.clone());
+    }
+    pub fn get(&self, mark: char) -> Option<&Position> {
+        self.positions.get(&mark)
+    }
+    // y行目の後ろにdelta行挿入(負の場合は削除)したときに印の位置を更新する
+    pub fn edit(&mut self, y: usize, delta: isize) {
+        for position in self.positions.values_mut() {
+            shift(position, y, delta);
+        }
+    }
+}
+
+// y行目の後ろにdelta行挿入(負の場合は削除)したときの位置に移す
+// 削除した行にあった位置はy行目に移す
+pub fn shift(position: &mut Position, y: usize, delta: isize) {
+    let removed_end = y.saturating_add(delta.min(0).unsigned_abs());
+    if position.y > removed_end {
+        position.y = position.y.saturating_add_signed(delta);
+    } else if position.y > y {
+        position.y = y;
+    }
+}
@@ -0,0 +1,234 @@
+use crate::gutter::LineNumbers;
+use crate::statusline;
+use crate::width;
+use std::time::Duration;
+
+// :setで変更できる、ウィンドウ全体に共通する表示の設定
+#[allow(clippy::struct_excessive_bools)]
+pub struct Options {
+    pub line_numbers: LineNumbers,
+    // カーソル位置の単語と同じ単語をハイライトするか
+    pub cursor_word: bool,
+    // カーソル行とカーソル列に背景色を付けるか
+    pub cursor_line: bool,
+    pub cursor_column: bool,
+    // 縦の目安線を引く列(行頭からの半角文字単位で、1から始まる)
+    pub color_columns: Vec<usize>,
+    // 画面の右端で切れている行の右端に表示する文字
+    pub extends: Option<char>,
+    // ドキュメントの末尾より後ろの行の行頭に表示する文字(Noneの場合は何も表示しない)
+    pub eob: Option<char>,
+    // 空のドキュメントでウェルカムメッセージを表示するか
+    pub welcome: bool,
+    // scrollbindで横方向にもスクロールを合わせるか
+    pub scrollbind_horizontal: bool,
+    // 複数キーのコマンドの続きのキーを待つ時間に上限を設けるか、とその時間
+    pub timeout: bool,
+    pub timeout_len: Duration,
+    // 保存する前に行末の空白を削除するか
+    pub trim_on_save: bool,
+    // 保存する前にファイルタイプの整形コマンドで整形するか
+    pub format_on_save: bool,
+    // ファイルタイプごとの整形コマンド(rust=rustfmt,python=black -qの形式で、Noneの場合は環境変数の設定を使う)
+    pub formatters: Option<String>,
+    // :makeで実行するコマンド(Noneの場合は環境変数の設定を使う)
+    pub make_program: Option<String>,
+    // 表示の状態を保存するファイルタイプ(Rust,Markdownの形式で、Noneの場合は環境変数の設定を使う)
+    pub view_file_types: Option<String>,
+    // ファイルタイプごとの言語サーバのコマンド(Rust=rust-analyzerの形式で、Noneの場合は環境変数の設定を使う)
+    pub language_servers: Option<String>,
+    // 起動時に起動するプラグインのコマンド(,で区切り、Noneの場合は環境変数の設定を使う)
+    // 起動した後に変更しても、既に起動したプラグインはそのまま使う
+    pub plugins: Option<String>,
+    // スクロールするときにカーソルの上下に残す行数
+    pub scroll_off: usize,
+    // 挿入モードで行を折り返す表示幅(0の場合は折り返さない)
+    pub text_width: usize,
+    // textwidthを超えたときに文章(t)とコメント(c)を折り返すか
+    pub wrap_text: bool,
+    pub wrap_comments: bool,
+    // 挿入モードで単語を入力している間に補完の候補を自動で表示するか
+    pub auto_complete: bool,
+    // 文章を書くファイルタイプで辞書に無い単語に下線を引くか
+    pub spell: bool,
+    // スペルチェックに使う辞書ファイル(Noneの場合はシステムの辞書)と、zgで単語を追加するファイル
+    pub dictionary: Option<String>,
+    pub spell_file: Option<String>,
+    // キー入力の直後に画面の描画にかける時間(超えたらカーソルから離れた行は入力が止まってから描画する)
+    pub render_budget: Duration,
+    // ステータスバーの書式
+    pub status_line: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            line_numbers: LineNumbers::default(),
+            cursor_word: true,
+            cursor_line: false,
+            cursor_column: false,
+            color_columns: Vec::new(),
+            extends: Some('»'),
+            eob: Some('~'),
+            welcome: true,
+            scrollbind_horizontal: false,
+            timeout: true,
+            timeout_len: Duration::from_secs(1),
+            trim_on_save: false,
+            format_on_save: false,
+            formatters: None,
+            make_program: None,
+            view_file_types: None,
+            language_servers: None,
+            plugins: None,
+            scroll_off: 0,
+            text_width: 0,
+            wrap_text: true,
+            wrap_comments: true,
+            auto_complete: true,
+            spell: false,
+            dictionary: None,
+            spell_file: None,
+            render_budget: Duration::from_millis(16),
+            status_line: statusline::DEFAULT_FORMAT.to_string(),
+        }
+    }
+}
+
+impl Options {
+    // :setの引数を1つ解釈して設定する。知らない設定の場合はErrを返す
+    pub fn set(&mut self, option: &str) -> Result<(), String> {
+        match option {
+            "number" | "nu" => self.line_numbers.absolute = true,
+            "nonumber" | "nonu" => self.line_numbers.absolute = false,
+            "relativenumber" | "rnu" => self.line_numbers.relative = true,
+            "norelativenumber" | "nornu" => self.line_numbers.relative = false,
+            "cursorline" | "cul" => self.cursor_line = true,
+            "nocursorline" | "nocul" => self.cursor_line = false,
+            "cursorcolumn" | "cuc" => self.cursor_column = true,
+            "nocursorcolumn" | "nocuc" => self.cursor_column = false,
+            "cursorword" => self.cursor_word = true,
+            "nocursorword" => self.cursor_word = false,
+            "welcome" => self.welcome = true,
+            "nowelcome" => self.welcome = false,
+            "timeout" | "to" => self.timeout = true,
+            "notimeout" | "noto" => self.timeout = false,
+            "autocomplete" | "ac" => self.auto_complete = true,
+            "noautocomplete" | "noac" => self.auto_complete = false,
+            "spell" => self.spell = true,
+            "nospell" => self.spell = false,
+            "trimonsave" => self.trim_on_save = true,
+            "notrimonsave" => self.trim_on_save = false,
+            "formatonsave" => self.format_on_save = true,
+            "noformatonsave" => self.format_on_save = false,
+            "scrollopt=ver" => self.scrollbind_horizontal = false,
+            "scrollopt=hor" | "scrollopt=ver,hor" | "scrollopt=hor,ver" => {
+                self.scrollbind_horizontal = true;
+            }
+            _ => {
+                let (name, value) = option
+                    .split_once('=')
+                    .ok_or_else(|| format!("Unknown option: {option}"))?;
+                // 文字の表示幅の設定はドキュメントの全ての行の描画とカーソル位置の計算に使う
+                match name {
+                    "width" => return width::set_backend(value),
+                    "widthoverride" => return width::set_overrides(value),
+                    _ => (),
+                }
+                // 80,100のようにカンマで区切って複数指定できる(空の場合は消す)
+                if name == "colorcolumn" || name == "cc" {
+                    self.color_columns = value
+                        .split(',')
+                        .filter(|column| !column.is_empty())
+                        .map(|column| {
+                            column
+                                .parse()
+                                .map_err(|_| format!("Invalid colorcolumn: {value}"))
+                        })
+                        .collect::<Result<_, _>>()?;
+                    return Ok(());
+                }
+                if let Some(result) = self.set_number(name, value) {
+                    return result;
+                }
+                // tcのように折り返す対象を表す文字を並べる
+                if name == "formatoptions" || name == "fo" {
+                    if let Some(flag) = value.chars().find(|flag| !['t', 'c'].contains(flag)) {
+                        return Err(format!("Invalid formatoptions flag: {flag}"));
+                    }
+                    self.wrap_text = value.contains('t');
+                    self.wrap_comments = value.contains('c');
+                    return Ok(());
+                }
+                if self.set_text(name, value) {
+                    return Ok(());
+                }
+                if name == "statusline" || name == "stl" {
+                    return self.set_status_line(value);
+                }
+                if name == "renderbudget" {
+                    let millis = value
+                        .parse()
+                        .map_err(|_| format!("Invalid renderbudget: {value}"))?;
+                    self.render_budget = Duration::from_millis(millis);
+                    return Ok(());
+                }
+                if name == "timeoutlen" || name == "tm" {
+                    let millis = value
+                        .parse()
+                        .map_err(|_| format!("Invalid timeoutlen: {value}"))?;
+                    self.timeout_len = Duration::from_millis(millis);
+                    return Ok(());
+                }
+                // 文字を指定する設定は空の場合は表示しない
+                let value = value.chars().next();
+                match name {
+                    "extends" => self.extends = value,
+                    "eob" => self.eob = value,
+                    _ => return Err(format!("Unknown option: {option}")),
+                }
+            }
+        }
+        Ok(())
+    }
+    // 数値を指定する設定であれば設定する
+    fn set_number(&mut self, name: &str, value: &str) -> Option<Result<(), String>> {
+        let (name, number) = match name {
+            "scrolloff" | "so" => ("scrolloff", &mut self.scroll_off),
+            "textwidth" | "tw" => ("textwidth", &mut self.text_width),
+            _ => return None,
+        };
+        Some(
+            value
+                .parse()
+                .map(|value| *number = value)
+                .map_err(|_| format!("Invalid {name}: {value}")),
+        )
+    }
+    // ファイルのパスやコマンドを指定する設定であれば設定する(空の場合は既定のものを使う)
+    fn set_text(&mut self, name: &str, value: &str) -> bool {
+        let text = match name {
+            "dictionary" | "dict" => &mut self.dictionary,
+            "spellfile" | "spf" => &mut self.spell_file,
+            "formatprg" | "fp" => &mut self.formatters,
+            "makeprg" | "mp" => &mut self.make_program,
+            "viewfiletypes" => &mut self.view_file_types,
+            "lsp" => &mut self.language_servers,
+            "plugins" => &mut self.plugins,
+            _ => return false,
+        };
+        *text = (!value.is_empty()).then(|| value.to_string());
+        true
+    }
+    // ステータスバーの書式を設定する(空の場合は既定の書式に戻す)
+    fn set_status_line(&mut self, format: &str) -> Result<(), String> {
+        statusline::validate(format)?;
+        self.status_line = if format.is_empty() {
+            statusline::DEFAULT_FORMAT
+        } else {
+            format
+        }
+        .to_string();
+        Ok(())
+    }
+}
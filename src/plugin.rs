@@ -0,0 +1,272 @@
+// 外部のプロセスとして動くプラグイン
+// 標準入出力で1行に1つのJSON-RPCのメッセージをやり取りする
+// 起動するとinitializeを要求し、その結果でコマンド、キー、フック、受け取るイベントを登録する
+//   {"commands": ["lint"], "keys": {"<F5>": "lint"}, "hooks": ["open", "pre_save"],
+//    "events": ["cursor_moved", "mode_changed"]}
+// コマンドの実行やフックではcommand、open、pre_saveを要求し、結果の内容で置き換えたりメッセージを表示したりする
+//   {"lines": ["..."], "message": "..."}(どちらも省略できる)
+// カーソルが移動したりモードが変わったりすると、登録したプラグインにcursor_movedとmode_changedを要求する
+// commandとイベントのパラメータにはファイル名、カーソル位置、モード、選択範囲、画面に表示している範囲が含まれる
+// モードはnormal、insert、visualのいずれかで、選択範囲はビジュアルモードでなければnullになる
+//   {"file_name": "...", "cursor": {"line": 0, "column": 0}, "mode": "visual",
+//    "selection": {"start": {"line": 0, "column": 0}, "end": {"line": 1, "column": 3}, "linewise": false},
+//    "viewport": {"top": 0, "left": 0, "height": 20, "width": 80}}
+// commandとイベントの結果ではカーソル位置、画面の一番上の行、モード、選択範囲も変更できる
+//   {"cursor": {"line": 0, "column": 0}, "viewport": {"top": 0}, "mode": "insert"}
+//   {"selection": {"start": {"line": 0, "column": 0}, "end": {"line": 1, "column": 3}}}
+use serde_json::{json, Value};
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 起動するプラグインのコマンドを,で区切って指定する
+const PLUGINS_VAR: &str = "DECI_PLUGINS";
+// 要求への応答を待つ時間
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// 内容を変換するために呼ばれる時点
+#[derive(Clone, Copy, PartialEq)]
+pub enum Hook {
+    // ファイルを開いたとき
+    Open,
+    // ファイルを保存する直前(整形より前)
+    PreSave,
+}
+
+impl Hook {
+    pub fn method(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::PreSave => "pre_save",
+        }
+    }
+}
+
+// プラグインが受け取れる編集中の出来事
+#[derive(Clone, Copy, PartialEq)]
+pub enum Notification {
+    CursorMoved,
+    // ノーマルモードと挿入モードを切り替えたとき
+    ModeChanged,
+}
+
+impl Notification {
+    pub fn method(self) -> &'static str {
+        match self {
+            Self::CursorMoved => "cursor_moved",
+            Self::ModeChanged => "mode_changed",
+        }
+    }
+}
+
+// 要求に対するプラグインの応答
+pub struct Reply {
+    // バッファの全ての行を置き換える内容
+    pub lines: Option<Vec<String>>,
+    // メッセージバーに表示するメッセージ
+    pub message: Option<String>,
+    // 移動先のカーソル位置(行, 列)と画面の一番上の行(0から数える)
+    pub cursor: Option<(usize, usize)>,
+    pub viewport_top: Option<usize>,
+    // 切り替えるモード(normalまたはinsert)
+    pub mode: Option<String>,
+    // 文字単位で選択する範囲の最初と最後の位置(行, 列)
+    pub selection: Option<((usize, usize), (usize, usize))>,
+}
+
+pub struct Plugin {
+    // 起動したコマンド(エラーメッセージに使う)
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    // 別スレッドで読み込んだプラグインからのメッセージ
+    messages: Receiver<Value>,
+    last_id: u64,
+    // 登録されたコマンド、キー(<F5>のような表記)とそのキーで実行するコマンド、フック
+    pub commands: Vec<String>,
+    pub keys: Vec<(String, String)>,
+    pub hooks: Vec<Hook>,
+    pub notifications: Vec<Notification>,
+}
+
+// 設定されているプラグインのコマンド
+// pluginsは:set pluginsで設定した値(設定されていなければ環境変数の値を使う)
+pub fn commands(plugins: Option<&str>) -> Vec<String> {
+    plugins
+        .map(str::to_string)
+        .or_else(|| env::var(PLUGINS_VAR).ok())
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl Plugin {
+    // プラグインを起動してinitializeの応答で登録内容を受け取る
+    pub fn start(command: &str) -> Result<Self, String> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|error| format!("{command}: {error}"))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(format!("{command}: No pipe to the plugin"));
+        };
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                // JSONでない行は無視する
+                let Ok(message) = serde_json::from_str(&line) else {
+                    continue;
+                };
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut plugin = Self {
+            name: command.to_string(),
+            child,
+            stdin,
+            messages,
+            last_id: 0,
+            commands: Vec::new(),
+            keys: Vec::new(),
+            hooks: Vec::new(),
+            notifications: Vec::new(),
+        };
+        let result = plugin.call(
+            "initialize",
+            &json!({ "version": env!("CARGO_PKG_VERSION") }),
+        )?;
+        plugin.commands = strings(result.get("commands"));
+        plugin.keys = result
+            .get("keys")
+            .and_then(Value::as_object)
+            .map(|keys| {
+                keys.iter()
+                    .filter_map(|(key, command)| Some((key.clone(), command.as_str()?.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        plugin.hooks = strings(result.get("hooks"))
+            .iter()
+            .filter_map(|hook| {
+                [Hook::Open, Hook::PreSave]
+                    .into_iter()
+                    .find(|known| known.method() == hook)
+            })
+            .collect();
+        plugin.notifications = strings(result.get("events"))
+            .iter()
+            .filter_map(|event| {
+                [Notification::CursorMoved, Notification::ModeChanged]
+                    .into_iter()
+                    .find(|known| known.method() == event)
+            })
+            .collect();
+        Ok(plugin)
+    }
+    // 要求を送り、応答の結果を待って返す
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, String> {
+        self.last_id = self.last_id.saturating_add(1);
+        let id = self.last_id;
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        writeln!(self.stdin, "{message}")
+            .and_then(|()| self.stdin.flush())
+            .map_err(|error| format!("{}: {error}", self.name))?;
+        let deadline = Instant::now().checked_add(RESPONSE_TIMEOUT);
+        loop {
+            let timeout = deadline.map_or(RESPONSE_TIMEOUT, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            let message = match self.messages.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(format!("{}: No response to {method}", self.name))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(format!("{}: Plugin exited", self.name))
+                }
+            };
+            // 以前に待つのをやめた要求への応答などは捨てる
+            if message.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                let text = error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .map_or_else(|| error.to_string(), str::to_string);
+                return Err(format!("{}: {text}", self.name));
+            }
+            return Ok(message.get("result").cloned().unwrap_or_default());
+        }
+    }
+    // 要求を送り、応答を置き換える内容やカーソル位置などの変更として受け取る
+    pub fn request(&mut self, method: &str, params: &Value) -> Result<Reply, String> {
+        let result = self.call(method, params)?;
+        Ok(Reply {
+            lines: result
+                .get("lines")
+                .and_then(Value::as_array)
+                .map(|_| strings(result.get("lines"))),
+            message: result
+                .get("message")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            cursor: result.get("cursor").and_then(position),
+            viewport_top: result
+                .pointer("/viewport/top")
+                .and_then(Value::as_u64)
+                .and_then(|top| usize::try_from(top).ok()),
+            mode: result
+                .get("mode")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            selection: result.get("selection").and_then(|selection| {
+                Some((
+                    position(selection.get("start")?)?,
+                    position(selection.get("end")?)?,
+                ))
+            }),
+        })
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// {"line": 0, "column": 0}の形式の位置(行, 列)
+fn position(value: &Value) -> Option<(usize, usize)> {
+    Some((
+        usize::try_from(value.get("line")?.as_u64()?).ok()?,
+        usize::try_from(value.get("column")?.as_u64()?).ok()?,
+    ))
+}
+
+// 文字列の配列(文字列でない要素は無視する)
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,317 @@
+// プロンプトで入力中の1行
+// カーソルは書記素の位置で持ち、上下キーでは入力済みの文字列で始まる履歴だけをたどる
+// Tab(Shift-Tab)ではカーソルの前の単語を補完の候補に順に置き換える
+use crate::completion::Completion;
+use crate::editor::FILE_COMMANDS;
+use crate::theme::THEME_NAMES;
+use crate::width;
+use std::fs;
+use std::path::MAIN_SEPARATOR;
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+
+// 補完するコマンド名(省略形は候補に出さない)
+const COMMAND_NAMES: [&str; 41] = [
+    "DiffOrig",
+    "abbreviate",
+    "close",
+    "count",
+    "cquit",
+    "delete",
+    "edit",
+    "format",
+    "grep",
+    "grep-edit",
+    "let",
+    "list-renumber",
+    "list-toggle",
+    "macro-edit",
+    "make",
+    "messages",
+    "oldfiles",
+    "only",
+    "pin",
+    "quit",
+    "quit!",
+    "recent",
+    "redo",
+    "rename",
+    "reveal",
+    "set",
+    "split",
+    "substitute-register",
+    "table-col",
+    "table-format",
+    "table-row",
+    "terminal",
+    "theme",
+    "trim",
+    "unabbreviate",
+    "undo",
+    "undojoin",
+    "unpin",
+    "wq",
+    "write",
+    "xit",
+];
+
+// Tabで補完する候補の種類
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    None,
+    // :のコマンド(コマンド名と、コマンドに応じた引数)
+    Command,
+    File,
+    Theme,
+}
+
+pub struct Input {
+    text: String,
+    // カーソルの前にある書記素の数
+    cursor: usize,
+    // 古い順に並べた履歴
+    history: Vec<String>,
+    // 表示している履歴の位置(Noneなら入力中の文字列)
+    history_index: Option<usize>,
+    // 履歴をたどり始めたときの入力中の文字列
+    typed: String,
+    source: Source,
+    // 補完中の単語の先頭のバイト位置と候補
+    completion: Option<(usize, Completion)>,
+}
+
+impl Input {
+    pub fn new(initial: &str, history: Vec<String>, source: Source) -> Self {
+        Self {
+            text: initial.to_string(),
+            cursor: initial.graphemes(true).count(),
+            history,
+            history_index: None,
+            typed: String::new(),
+            source,
+            completion: None,
+        }
+    }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    // カーソルより前の表示幅
+    pub fn cursor_width(&self) -> usize {
+        width::str_width(&self.text[..self.byte_index(self.cursor)])
+    }
+    // 補完中の候補
+    pub fn completion(&self) -> Option<&Completion> {
+        self.completion.as_ref().map(|(_, completion)| completion)
+    }
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.completion = None;
+    }
+    // カーソル位置に文字列を挿入する(改行は空白にする)
+    pub fn insert(&mut self, text: &str) {
+        self.completion = None;
+        let text = text.replace(['\r', '\n'], " ");
+        let index = self.byte_index(self.cursor);
+        self.text.insert_str(index, &text);
+        self.cursor = self.cursor.saturating_add(text.graphemes(true).count());
+    }
+    // 編集のキーを処理する(処理しなかったキーならfalseを返す)
+    pub fn edit(&mut self, key: Key) -> bool {
+        match key {
+            Key::Char('\t') => return self.complete(true),
+            Key::BackTab => return self.complete(false),
+            _ => self.completion = None,
+        }
+        let len = self.text.graphemes(true).count();
+        match key {
+            Key::Char(c) if !c.is_control() => self.insert(&c.to_string()),
+            Key::Backspace | Key::Ctrl('h') => {
+                if self.cursor > 0 {
+                    self.remove(self.cursor.saturating_sub(1), self.cursor);
+                }
+            }
+            Key::Delete => {
+                if self.cursor < len {
+                    self.remove(self.cursor, self.cursor.saturating_add(1));
+                }
+            }
+            Key::Left | Key::Ctrl('b') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => self.cursor = self.cursor.saturating_add(1).min(len),
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = len,
+            // カーソルの前の単語(と続く空白)を削除
+            Key::Ctrl('w') => self.remove(self.word_start(), self.cursor),
+            // カーソルより前を削除
+            Key::Ctrl('u') => self.remove(0, self.cursor),
+            Key::Up => self.recall(true),
+            Key::Down => self.recall(false),
+            _ => return false,
+        }
+        true
+    }
+    // 入力を始めたときの文字列で始まる、1つ古いまたは新しい履歴を表示する
+    fn recall(&mut self, older: bool) {
+        if self.history_index.is_none() {
+            self.typed = self.text.clone();
+        }
+        let matches = |entry: &&String| entry.starts_with(&self.typed);
+        let index = if older {
+            let end = self.history_index.unwrap_or(self.history.len());
+            self.history
+                .get(..end)
+                .and_then(|entries| entries.iter().rposition(|entry| matches(&entry)))
+        } else {
+            let Some(start) = self.history_index.map(|index| index.saturating_add(1)) else {
+                return;
+            };
+            self.history
+                .get(start..)
+                .and_then(|entries| entries.iter().position(|entry| matches(&entry)))
+                .map(|index| index.saturating_add(start))
+        };
+        // 古い方に無ければそのまま、新しい方に無ければ入力中の文字列に戻る
+        let text = match index {
+            Some(index) => self.history.get(index).cloned(),
+            None if older => return,
+            None => Some(self.typed.clone()),
+        };
+        self.history_index = index;
+        self.text = text.unwrap_or_default();
+        self.cursor = self.text.graphemes(true).count();
+    }
+    // 補完中なら次(前)の候補に、そうでなければカーソルの前の単語を最初(最後)の候補に置き換える
+    // 候補が1つだけなら補完を終え、続けてTabを押すとその続き(ディレクトリの中など)を補完する
+    fn complete(&mut self, forward: bool) -> bool {
+        let cursor = self.byte_index(self.cursor);
+        if self.completion.is_none() {
+            let before = &self.text[..cursor];
+            let (start, candidates) = candidates(self.source, before);
+            if candidates.is_empty() {
+                return false;
+            }
+            let prefix = before.get(start..).unwrap_or_default().to_string();
+            self.completion = Some((
+                start,
+                Completion {
+                    prefix,
+                    candidates,
+                    selected: None,
+                },
+            ));
+        }
+        let Some((start, completion)) = &mut self.completion else {
+            return false;
+        };
+        completion.select(forward);
+        let start = *start;
+        let candidate = completion
+            .selected_candidate()
+            .unwrap_or_default()
+            .to_string();
+        if completion.candidates.len() == 1 {
+            self.completion = None;
+        }
+        self.text.replace_range(start..cursor, &candidate);
+        self.cursor = self.text[..start.saturating_add(candidate.len())]
+            .graphemes(true)
+            .count();
+        true
+    }
+    fn word_start(&self) -> usize {
+        let graphemes: Vec<&str> = self.text.graphemes(true).take(self.cursor).collect();
+        let is_blank = |g: &&str| g.chars().all(char::is_whitespace);
+        let blanks = graphemes.iter().rev().take_while(|g| is_blank(g)).count();
+        let before = graphemes.len().saturating_sub(blanks);
+        let word = graphemes
+            .iter()
+            .take(before)
+            .rev()
+            .take_while(|g| !is_blank(g))
+            .count();
+        before.saturating_sub(word)
+    }
+    // 書記素の位置の範囲を削除してカーソルを範囲の先頭に置く
+    fn remove(&mut self, start: usize, end: usize) {
+        let range = self.byte_index(start)..self.byte_index(end);
+        self.text.replace_range(range, "");
+        self.cursor = start;
+    }
+    fn byte_index(&self, grapheme: usize) -> usize {
+        self.text
+            .grapheme_indices(true)
+            .nth(grapheme)
+            .map_or(self.text.len(), |(index, _)| index)
+    }
+}
+
+// カーソルより前の文字列に対する補完の候補と、置き換える部分の先頭のバイト位置
+fn candidates(source: Source, before: &str) -> (usize, Vec<String>) {
+    match source {
+        Source::None => (0, Vec::new()),
+        Source::File => (0, file_candidates(before)),
+        Source::Theme => (0, matching(&THEME_NAMES, before)),
+        Source::Command => {
+            let start = before.len().saturating_sub(before.trim_start().len());
+            let Some((name, argument)) = before.get(start..).and_then(|command| {
+                command
+                    .split_once(char::is_whitespace)
+                    .map(|(name, argument)| (name, argument.trim_start()))
+            }) else {
+                // 空白が無ければコマンド名を補完する
+                let name = before.get(start..).unwrap_or_default();
+                return (start, matching(&COMMAND_NAMES, name));
+            };
+            let argument_start = before.len().saturating_sub(argument.len());
+            let source = match name {
+                "theme" => Source::Theme,
+                _ if FILE_COMMANDS.contains(&name) => Source::File,
+                _ => Source::None,
+            };
+            let (start, candidates) = candidates(source, argument);
+            (argument_start.saturating_add(start), candidates)
+        }
+    }
+}
+
+// prefixで始まる名前を並べ替えて重複無く返す
+fn matching(names: &[&str], prefix: &str) -> Vec<String> {
+    let mut names: Vec<String> = names
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| (*name).to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// pathで始まるファイルとディレクトリ(末尾に区切り文字を付ける)
+// .で始まる名前は、.から入力した場合だけ候補にする
+fn file_candidates(path: &str) -> Vec<String> {
+    let (dir, name) = path
+        .rfind(MAIN_SEPARATOR)
+        .map_or(("", path), |index| path.split_at(index.saturating_add(1)));
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            if !file_name.starts_with(name)
+                || (file_name.starts_with('.') && !name.starts_with('.'))
+            {
+                return None;
+            }
+            let separator = if entry.path().is_dir() {
+                MAIN_SEPARATOR.to_string()
+            } else {
+                String::new()
+            };
+            Some(format!("{dir}{file_name}{separator}"))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
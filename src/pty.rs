@@ -0,0 +1,148 @@
+// :terminalでプログラムを実行する疑似端末
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// 一度に読み込む出力のバイト数
+const READ_SIZE: usize = 4096;
+// 実行するプログラムに知らせる端末の種類
+const TERM: &str = "xterm-256color";
+
+pub struct Pty {
+    master: File,
+    child: Child,
+    output: Receiver<Vec<u8>>,
+}
+
+impl Pty {
+    // 指定した大きさの疑似端末でシェルのコマンドを実行する
+    pub fn spawn(command: &str, width: usize, height: usize) -> io::Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+        let size = window_size(width, height);
+        // SAFETY: 受け取ったファイルディスクリプタはこの後OwnedFdが所有して閉じる
+        let result = unsafe {
+            libc::openpty(
+                &raw mut master,
+                &raw mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                &raw const size,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openptyが開いたファイルディスクリプタで、他に所有者はいない
+        let (master, slave) =
+            unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        // 実行するプログラムには疑似端末の親側を引き継がない
+        close_on_exec(&master)?;
+        close_on_exec(&slave)?;
+        let mut process = Command::new("sh");
+        process
+            .arg("-c")
+            .arg(command)
+            .env("TERM", TERM)
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // SAFETY: fork後の子プロセスではシグナル安全な関数のみ呼ぶ
+        unsafe {
+            process.pre_exec(|| {
+                // 新しいセッションを作り、疑似端末を制御端末にする
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = process.spawn()?;
+        // 子プロセスが持つ子側を全て閉じる(全て閉じると親側の読み込みが終わる)
+        drop(process);
+        let master = File::from(master);
+        let mut reader = master.try_clone()?;
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; READ_SIZE];
+            // プログラムが終了して子側が閉じられると、Linuxではエラーになる
+            while let Ok(read) = reader.read(&mut buffer) {
+                let Some(bytes) = buffer.get(..read).filter(|bytes| !bytes.is_empty()) else {
+                    return;
+                };
+                if sender.send(bytes.to_vec()).is_err() {
+                    // 疑似端末を閉じた
+                    return;
+                }
+            }
+        });
+        Ok(Self {
+            master,
+            child,
+            output,
+        })
+    }
+    // キー入力などをプログラムに送る
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.master.write_all(bytes)
+    }
+    // 疑似端末の大きさを変える(プログラムにはSIGWINCHで通知される)
+    pub fn resize(&self, width: usize, height: usize) -> io::Result<()> {
+        let size = window_size(width, height);
+        // SAFETY: 開いている疑似端末に対して、有効なwinsizeへのポインタを渡す
+        let result = unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    // 出力を受け取る
+    // 出力が終わった場合はプログラムの終了を待って終了状態を返す
+    pub fn receive(&mut self, output: &mut Vec<u8>) -> Option<io::Result<ExitStatus>> {
+        loop {
+            match self.output.try_recv() {
+                Ok(bytes) => output.extend(bytes),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(self.child.wait()),
+            }
+        }
+    }
+    // プログラムが終了するまで出力を受け取り、終了状態を返す
+    pub fn wait(&mut self, output: &mut Vec<u8>) -> io::Result<ExitStatus> {
+        output.extend(self.output.iter().flatten());
+        self.child.wait()
+    }
+}
+
+// 終了前に破棄した場合はプログラムを止める
+impl Drop for Pty {
+    fn drop(&mut self) {
+        if matches!(self.child.try_wait(), Ok(None)) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+fn window_size(width: usize, height: usize) -> libc::winsize {
+    libc::winsize {
+        ws_row: u16::try_from(height).unwrap_or(u16::MAX),
+        ws_col: u16::try_from(width).unwrap_or(u16::MAX),
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn close_on_exec(fd: &OwnedFd) -> io::Result<()> {
+    // SAFETY: 開いているファイルディスクリプタのフラグのみ変更する
+    let result = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use crate::explorer;
+use crate::Position;
+use std::fs;
+use std::path::PathBuf;
+
+// 記録するファイルの最大数
+const MAX_FILES: usize = 100;
+
+// 最近開いたファイルと、それぞれのファイルで最後にカーソルがあった位置
+#[derive(Default)]
+pub struct RecentFiles {
+    // 新しい順に並べた、ファイルの絶対パスとカーソル位置
+    entries: Vec<(PathBuf, Position)>,
+}
+
+impl RecentFiles {
+    // 保存されていた項目を古い方の末尾に加える
+    pub fn push(&mut self, file: PathBuf, position: Position) {
+        if self.entries.len() < MAX_FILES {
+            self.entries.push((file, position));
+        }
+    }
+    pub fn entries(&self) -> &[(PathBuf, Position)] {
+        &self.entries
+    }
+    pub fn retain(&mut self, keep: impl Fn(&PathBuf) -> bool) {
+        self.entries.retain(|(file, _)| keep(file));
+    }
+    // この一覧を新しいものとして、古い一覧に無いファイルを後ろに加える
+    pub fn merge(mut self, older: Self) -> Self {
+        for (file, position) in older.entries {
+            if !self.entries.iter().any(|(newer, _)| *newer == file) {
+                self.push(file, position);
+            }
+        }
+        self
+    }
+    // ファイルを開いていたことと、そのときのカーソル位置を記録する
+    pub fn record(&mut self, file_name: &str, position: &Position) {
+        let Ok(path) = fs::canonicalize(file_name) else {
+            return;
+        };
+        self.entries.retain(|(file, _)| *file != path);
+        self.entries.insert(0, (path, position.clone()));
+        self.entries.truncate(MAX_FILES);
+    }
+    // ファイルで最後にカーソルがあった位置
+    pub fn position(&self, file_name: &str) -> Option<Position> {
+        let path = fs::canonicalize(file_name).ok()?;
+        self.entries
+            .iter()
+            .find(|(file, _)| *file == path)
+            .map(|(_, position)| position.clone())
+    }
+    // 存在するファイルの一覧をpath:line:colの形式で返す
+    pub fn lines(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|(file, _)| file.is_file())
+            .map(|(file, position)| {
+                format!(
+                    "{}:{}:{}",
+                    explorer::relative(file),
+                    position.y.saturating_add(1),
+                    position.x.saturating_add(1)
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+// 削除した行を順に残す番号付きレジスタの数("1から"9)
+const NUMBERED: u32 = 9;
+
+// ヤンクや削除したテキスト
+#[derive(Clone)]
+pub struct Register {
+    pub lines: Vec<String>,
+    // trueの場合は行単位で貼り付ける
+    pub linewise: bool,
+}
+
+// ヤンク、削除、貼り付けで使うレジスタ
+// ""は最後にヤンクまたは削除したテキスト、"0は最後にヤンクしたテキスト、
+// "1から"9は行単位で削除したテキスト(新しい順)、"-は行内で削除したテキスト
+#[derive(Default)]
+pub struct Registers {
+    registers: HashMap<char, Register>,
+}
+
+impl Registers {
+    // "に続けて指定できるレジスタ名か
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || name == '"' || name == '-'
+    }
+    pub fn get(&self, name: char) -> Option<&Register> {
+        self.registers.get(&name.to_ascii_lowercase())
+    }
+    // ヤンクしたテキストを保存する(名前が無ければ"0に保存する)
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(name) if name != '"' => self.store(name, register.clone()),
+            _ => {
+                self.registers.insert('0', register.clone());
+            }
+        }
+        self.registers.insert('"', register);
+    }
+    // 削除したテキストを保存する(名前が無ければ"1から"9または"-に保存する)
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        match name {
+            Some(name) if name != '"' => self.store(name, register.clone()),
+            _ if register.linewise || register.lines.len() > 1 => {
+                // 古いものから1つずつ後ろにずらす
+                for number in (1..NUMBERED).rev() {
+                    if let Some(older) = self.registers.remove(&digit(number)) {
+                        self.registers
+                            .insert(digit(number.saturating_add(1)), older);
+                    }
+                }
+                self.registers.insert('1', register.clone());
+            }
+            _ => {
+                self.registers.insert('-', register.clone());
+            }
+        }
+        self.registers.insert('"', register);
+    }
+    // 名前付きのレジスタに保存する(大文字の場合は小文字のレジスタに追加する)
+    fn store(&mut self, name: char, register: Register) {
+        let lower = name.to_ascii_lowercase();
+        match self.registers.get_mut(&lower) {
+            Some(existing) if name.is_ascii_uppercase() => {
+                if existing.linewise || register.linewise {
+                    existing.lines.extend(register.lines);
+                    existing.linewise = true;
+                } else {
+                    let mut lines = register.lines.into_iter();
+                    if let (Some(last), Some(first)) = (existing.lines.last_mut(), lines.next()) {
+                        last.push_str(&first);
+                    }
+                    existing.lines.extend(lines);
+                }
+            }
+            _ => {
+                self.registers.insert(lower, register);
+            }
+        }
+    }
+}
+
+fn digit(number: u32) -> char {
+    char::from_digit(number, 10).unwrap_or('9')
+}
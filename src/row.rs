@@ -1,19 +1,136 @@
+use std::cell::RefCell;
 use std::cmp;
+use std::ops::Range;
 use termion::color;
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::editor::SearchDirection;
+use crate::ansi;
+use crate::charsearch::CharSearch;
+use crate::editor::{SearchDirection, SearchOptions};
 use crate::highlighting;
+use crate::indent::Indent;
+use crate::spell::{self, Dictionary};
+use crate::textobject::TextObject;
+use crate::theme::Theme;
+use crate::width;
 use crate::HighlightingOptions;
+use crate::Terminal;
 
+/// ドキュメントの1行の文字列と、そのハイライトや表示幅のキャッシュ
 #[derive(Default)]
 pub struct Row {
     string: String,
     highlighting: Vec<highlighting::Type>,
+    /// ハイライトが最新であればtrue(falseにすると次の描画でハイライトし直す)
     pub is_highlighted: bool,
+    // ハイライトしたときに行頭と行末が複数行コメントの中だったか
+    starts_in_comment: bool,
+    ends_in_comment: bool,
     // 全角文字にも対応した行の文字数
     len_full_width: usize,
+    // 検索語句に一致した位置のキャッシュと、それを求めたときのドキュメントの検索語句の世代
+    // 行を変更したら捨てる
+    match_cache: Option<(u64, Vec<usize>)>,
+    // ページャで表示するテキストに含まれていた各文字の色(ページャ以外では空)
+    ansi_colors: Vec<ansi::Color>,
+    // 書記素の境界と表示幅の索引(行を変更したら捨て、カーソルの位置の計算などで必要になったら作る)
+    index: RefCell<Option<GraphemeIndex>>,
+}
+
+// 行の各書記素の位置と、そこまでの表示幅
+struct GraphemeIndex {
+    // 作ったときの表示幅の求め方の世代
+    generation: u64,
+    // 各書記素の先頭のバイト位置と、最後に行のバイト数
+    bytes: Vec<usize>,
+    // 各書記素より前の半角文字単位の表示幅と、最後に行全体の表示幅
+    widths: Vec<usize>,
+}
+
+impl GraphemeIndex {
+    fn new(string: &str, generation: u64) -> Self {
+        let mut bytes = Vec::new();
+        let mut widths = Vec::new();
+        let mut total: usize = 0;
+        for (byte, grapheme) in string.grapheme_indices(true) {
+            bytes.push(byte);
+            widths.push(total);
+            total = total.saturating_add(width::str_width(grapheme));
+        }
+        bytes.push(string.len());
+        widths.push(total);
+        Self {
+            generation,
+            bytes,
+            widths,
+        }
+    }
+    // 変更後の文字列で、first番目の書記素から変更した範囲の後ろまでの索引を作り直す
+    // editedは変更後の文字列で挿入した範囲、removedは削除したバイト数で、
+    // 変更した範囲より後ろで変更前と同じ位置に境界が見つかったら、そこから後ろはバイト位置と表示幅をずらすだけにする
+    fn splice(&mut self, string: &str, first: usize, edited: Range<usize>, removed: usize) {
+        let start = self.byte(first);
+        let mut total = self.width(first);
+        let mut bytes = Vec::new();
+        let mut widths = Vec::new();
+        let mut last = self.len();
+        let old_byte = |byte: usize| byte.saturating_add(removed).saturating_sub(edited.len());
+        for (byte, grapheme) in string
+            .get(start..)
+            .unwrap_or_default()
+            .grapheme_indices(true)
+        {
+            let byte = start.saturating_add(byte);
+            if byte >= edited.end {
+                if let Ok(at) = self.bytes.binary_search(&old_byte(byte)) {
+                    last = at;
+                    break;
+                }
+            }
+            bytes.push(byte);
+            widths.push(total);
+            total = total.saturating_add(width::str_width(grapheme));
+        }
+        let old_total = self.width(last);
+        for byte in self.bytes.get_mut(last..).unwrap_or_default() {
+            *byte = byte.saturating_add(edited.len()).saturating_sub(removed);
+        }
+        for width in self.widths.get_mut(last..).unwrap_or_default() {
+            *width = width.saturating_sub(old_total).saturating_add(total);
+        }
+        self.bytes.splice(first..last, bytes);
+        self.widths.splice(first..last, widths);
+    }
+    // at番目までの書記素だけを残す
+    fn truncate(&mut self, at: usize) {
+        self.bytes.truncate(at.saturating_add(1));
+        self.widths.truncate(at.saturating_add(1));
+    }
+    // バイト位置を含む書記素の番号
+    fn containing(&self, byte: usize) -> usize {
+        self.bytes
+            .partition_point(|start| *start <= byte)
+            .saturating_sub(1)
+            .min(self.len())
+    }
+    // 書記素の数
+    fn len(&self) -> usize {
+        self.bytes.len().saturating_sub(1)
+    }
+    // at番目の書記素の先頭のバイト位置(行末より後ろは行のバイト数)
+    fn byte(&self, at: usize) -> usize {
+        self.bytes
+            .get(at.min(self.len()))
+            .copied()
+            .unwrap_or_default()
+    }
+    // at番目の書記素より前の表示幅
+    fn width(&self, at: usize) -> usize {
+        self.widths
+            .get(at.min(self.len()))
+            .copied()
+            .unwrap_or_default()
+    }
 }
 // 文字列スライスからRowへの変換
 impl From<&str> for Row {
@@ -22,166 +139,352 @@ impl From<&str> for Row {
             string: String::from(slice),
             highlighting: Vec::new(),
             is_highlighted: false,
+            starts_in_comment: false,
+            ends_in_comment: false,
             len_full_width: slice.graphemes(true).count(),
+            match_cache: None,
+            ansi_colors: Vec::new(),
+            index: RefCell::new(None),
         }
     }
 }
 
 impl Row {
+    /// エスケープシーケンスを取り除いた文字列と、各文字の色から行を作る
+    pub fn with_ansi_colors(slice: &str, colors: Vec<ansi::Color>) -> Self {
+        Self {
+            ansi_colors: colors,
+            ..Self::from(slice)
+        }
+    }
+    /// 書記素の数
     pub fn len(&self) -> usize {
         self.len_full_width
     }
-    // 指定した位置の後ろに1文字挿入する
+    /// 指定した位置の後ろに1文字挿入する
+    /// 結合文字のように前後の文字とまとまって1文字になる場合は文字数を増やさない
     pub fn insert(&mut self, at: usize, c: char) {
-        // 挿入位置が文字列の最後のとき
-        if at >= self.len() {
+        let at = at.min(self.len());
+        // 索引がまだ無い行の行末にASCIIの文字を続ける場合は、前の文字とまとまらないので索引を作らずに済ませる
+        if at == self.len()
+            && self.index.get_mut().is_none()
+            && c.is_ascii()
+            && self
+                .string
+                .bytes()
+                .last()
+                .is_none_or(|b| b.is_ascii() && b != b'\r')
+        {
             self.string.push(c);
-            // 文字列数を更新
             self.len_full_width = self.len_full_width.saturating_add(1);
+            self.changed();
             return;
         }
-        let mut result: String = String::new();
-        let mut length: usize = 0;
-        // 1文字ずつ処理
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            length = length.saturating_add(1);
-            // 指定位置では文字を挿入
-            if index == at {
-                length = length.saturating_add(1);
-                result.push(c);
-            }
-            result.push_str(grapheme);
-        }
-        self.len_full_width = length;
-        self.string = result;
+        let byte = self.byte_index(at);
+        self.replace_bytes(byte..byte, c.encode_utf8(&mut [0; 4]));
     }
+    /// 指定した位置の後ろに改行を含まない文字列を挿入する
+    pub fn insert_str(&mut self, at: usize, text: &str) {
+        let byte = self.byte_index(at);
+        self.replace_bytes(byte..byte, text);
+    }
+    /// at番目の書記素を削除する
     pub fn delete(&mut self, at: usize) {
         // カーソルが行の最後にある時
         if at >= self.len() {
             // 何もしない
             return;
         }
-        let mut result: String = String::new();
-        let mut length: usize = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            // 指定された位置の文字のみスキップ
-            if index != at {
-                length = length.saturating_add(1);
-                result.push_str(grapheme);
-            }
-        }
-        self.len_full_width = length;
-        self.string = result;
+        let range = self.byte_index(at)..self.byte_index(at.saturating_add(1));
+        self.replace_bytes(range, "");
     }
-    // 自身の後ろに指定された行を結合する
+    /// 自身の後ろに指定された行を結合する
     pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.len_full_width = self.len_full_width.saturating_add(new.len_full_width);
+        let end = self.string.len();
+        self.replace_bytes(end..end, &new.string);
     }
-    // 指定位置で行を分割し、後半の行を返す
-    pub fn split(&mut self, at: usize) -> Self {
-        // 前半行
-        let mut row: String = String::new();
-        let mut length: usize = 0;
-        // 後半行
-        let mut splitted_row: String = String::new();
-        let mut splitted_length: usize = 0;
-        // 1文字ずつ処理
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            // 指定された位置の前後で割り振る
-            if index < at {
-                length = length.saturating_add(1);
-                row.push_str(grapheme);
-            } else {
-                splitted_length = splitted_length.saturating_add(1);
-                splitted_row.push_str(grapheme);
-            }
-        }
-        // 前半行
-        self.string = row;
-        self.len_full_width = length;
+    // バイト位置の範囲をtextで置き換え、書記素の索引と文字数は変更した範囲の付近だけ数え直す
+    // 結合文字やZWJのように前の文字とまとまる場合があるので、変更した範囲の前の書記素から数え直す
+    fn replace_bytes(&mut self, range: Range<usize>, text: &str) {
+        let generation = width::generation();
+        let mut index = (self.index.take())
+            .filter(|index| index.generation == generation)
+            .unwrap_or_else(|| GraphemeIndex::new(&self.string, generation));
+        let first = index.containing(range.start).saturating_sub(1);
+        let edited = range.start..range.start.saturating_add(text.len());
+        let removed = range.len();
+        self.string.replace_range(range, text);
+        index.splice(&self.string, first, edited, removed);
+        self.len_full_width = index.len();
+        self.index = RefCell::new(Some(index));
+        self.changed();
+    }
+    // 文字列を変更したら、ハイライトと文字列から求めたキャッシュをやり直す(書記素の索引は変更した側で直す)
+    fn changed(&mut self) {
         self.is_highlighted = false;
+        self.match_cache = None;
+    }
+    // 書記素の索引を(無いか古ければ作ってから)使う
+    fn with_index<T>(&self, f: impl FnOnce(&GraphemeIndex) -> T) -> T {
+        let generation = width::generation();
+        let mut index = self.index.borrow_mut();
+        if index
+            .as_ref()
+            .is_some_and(|index| index.generation != generation)
+        {
+            *index = None;
+        }
+        f(index.get_or_insert_with(|| GraphemeIndex::new(&self.string, generation)))
+    }
+    // at番目の書記素の先頭のバイト位置(行末より後ろは行のバイト数)
+    fn byte_index(&self, at: usize) -> usize {
+        // 行末に追加する場合は索引を作らない
+        if at >= self.len() {
+            return self.string.len();
+        }
+        self.with_index(|index| index.byte(at))
+    }
+    /// 自身の後ろに指定された行の先頭の空白を除いて結合し、結合した位置を返す
+    /// `with_space`がtrueの場合は間に空白を1つ入れる(自身が空白で終わる場合と、結合する行が空か閉じ括弧で始まる場合は入れない)
+    /// `with_space`がfalseの場合は空白を除かずにそのまま結合する
+    pub fn join(&mut self, next: &Self, with_space: bool) -> usize {
+        let at = self.len();
+        if !with_space {
+            self.append(next);
+            return at;
+        }
+        let rest = next.string.trim_start();
+        if !rest.is_empty()
+            && !rest.starts_with(')')
+            && !self.string.is_empty()
+            && !self.string.ends_with(char::is_whitespace)
+        {
+            self.append(&Self::from(" "));
+        }
+        self.append(&Self::from(rest));
+        at
+    }
+    /// 指定位置で行を分割し、後半の行を返す
+    pub fn split(&mut self, at: usize) -> Self {
+        let at = at.min(self.len());
+        let splitted_row = self.string.split_off(self.byte_index(at));
         // 後半行
-        Self {
+        let splitted = Self {
+            len_full_width: self.len_full_width.saturating_sub(at),
             string: splitted_row,
-            len_full_width: splitted_length,
-            highlighting: Vec::new(),
-            is_highlighted: false,
+            ..Self::default()
+        };
+        // 前半行(書記素の境界で分けるので、索引は分けた位置より後ろを除くだけでよい)
+        self.len_full_width = at;
+        if let Some(index) = self.index.get_mut() {
+            index.truncate(at);
+        }
+        self.changed();
+        splitted
+    }
+    /// 行頭の空白をインデント1段分深くする
+    pub fn indent(&mut self, indent: &Indent) {
+        self.shift_indent(indent, true);
+    }
+    /// 行頭の空白をインデント1段分浅くする
+    pub fn dedent(&mut self, indent: &Indent) {
+        self.shift_indent(indent, false);
+    }
+    // インデントの幅を1段分増減し、行頭の空白をタブと空白のどちらを使うかの設定に合わせて作り直す
+    // 段の途中にある場合は段の境界に揃える。空白だけの行は変更しない
+    #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+    fn shift_indent(&mut self, indent: &Indent, deeper: bool) {
+        let body = self.string.trim_start_matches([' ', '\t']);
+        if body.is_empty() {
+            return;
         }
+        let leading = &self.string[..self.string.len() - body.len()];
+        let columns = indent.columns(leading);
+        let width = indent.width.max(1);
+        let columns = if deeper {
+            (columns / width + 1) * width
+        } else {
+            columns.saturating_sub(1) / width * width
+        };
+        let string = format!("{}{body}", indent.whitespace(columns));
+        *self = Self::from(&string[..]);
     }
+    /// 行の文字列のバイト列
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
     }
-    // 自身のat文字目以降(以前)で引数の文字列が見つかったら、行頭からの全角文字単位での位置を返す
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
+    /// 行の文字列
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+    /// 空白(全角スペースなども含む)で区切られた単語の数
+    pub fn word_count(&self) -> usize {
+        self.string.split_whitespace().count()
+    }
+    /// 指定位置の文字を返す
+    pub fn grapheme(&self, at: usize) -> Option<&str> {
+        if at >= self.len() {
+            return None;
+        }
+        let (start, end) =
+            self.with_index(|index| (index.byte(at), index.byte(at.saturating_add(1))));
+        self.string.get(start..end)
+    }
+    /// 最後にハイライトした結果(文字ごとの種類)
+    pub fn highlighting(&self) -> &[highlighting::Type] {
+        &self.highlighting
+    }
+    /// 指定位置の文字のハイライトを上書きする(次に描画するときにハイライトをやり直す)
+    pub fn set_highlighting(&mut self, at: usize, hl_type: highlighting::Type) {
+        if let Some(highlighting) = self.highlighting.get_mut(at) {
+            *highlighting = hl_type;
+            self.is_highlighted = false;
+        }
+    }
+    /// 自身のat文字目以降(以前)で引数の文字列が見つかったら、行頭からの全角文字単位での位置を返す
+    pub fn find(
+        &self,
+        query: &str,
+        at: usize,
+        direction: SearchDirection,
+        options: SearchOptions,
+    ) -> Option<usize> {
         // 指定位置が行末の時は検索結果無し
         if at > self.len() || query.is_empty() {
             return None;
         }
-        // 検索方向により検索範囲を決める
-        let start = if direction == SearchDirection::Forward {
-            at
-        } else {
-            0
-        };
-        let end = if direction == SearchDirection::Forward {
-            self.len_full_width
-        } else {
-            at
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let query: Vec<&str> = query.graphemes(true).collect();
+        let ignore_case = options.ignore_case(&query);
+        // 指定位置から検索文字列が始まっているか
+        let matches_at = |start: &usize| {
+            let end = start.saturating_add(query.len());
+            let Some(candidate) = graphemes.get(*start..end) else {
+                return false;
+            };
+            if !candidate
+                .iter()
+                .zip(&query)
+                .all(|(a, b)| a == b || (ignore_case && a.to_lowercase() == b.to_lowercase()))
+            {
+                return false;
+            }
+            // 単語単位で検索する場合は前後が単語の文字でないこと
+            !options.whole_word
+                || !(start
+                    .checked_sub(1)
+                    .and_then(|before| graphemes.get(before))
+                    .is_some_and(|g| is_word_grapheme(g))
+                    || graphemes.get(end).is_some_and(|g| is_word_grapheme(g)))
         };
-        // 指定範囲の文字列を取得
-        #[allow(clippy::integer_arithmetic)]
-        let substring: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
-        let matching_byte_index = if direction == SearchDirection::Forward {
-            substring.find(query)
+        // 検索方向により検索範囲を決める
+        if direction == SearchDirection::Forward {
+            (at..graphemes.len()).find(matches_at)
         } else {
-            substring.rfind(query)
-        };
-        // 検索文字列が見つかった場合
-        if let Some(matching_byte_index) = matching_byte_index {
-            // 文字の半角単位の位置と全角単位の位置を比較
-            for (grapheme_index, (byte_index, _)) in
-                substring[..].grapheme_indices(true).enumerate()
-            {
-                if matching_byte_index == byte_index {
-                    #[allow(clippy::integer_arithmetic)]
-                    return Some(start + grapheme_index);
-                }
+            // 指定位置より前で終わる文字列のみ対象とする
+            (0..at.saturating_sub(query.len()).saturating_add(1))
+                .rev()
+                .find(|start| start.saturating_add(query.len()) <= at && matches_at(start))
+        }
+    }
+    /// 重ならない全ての一致位置を返す
+    pub fn find_all(&self, query: &str, options: SearchOptions) -> Vec<usize> {
+        let query_len = query.graphemes(true).count();
+        let mut matches = Vec::new();
+        let mut index = 0;
+        while let Some(found) = self.find(query, index, SearchDirection::Forward, options) {
+            matches.push(found);
+            index = found.saturating_add(query_len);
+        }
+        matches
+    }
+    /// 検索語句の世代に対応するキャッシュがあればそれを、無ければ求めた全ての一致位置を返す
+    pub fn cached_matches(
+        &mut self,
+        generation: u64,
+        query: &str,
+        options: SearchOptions,
+    ) -> &[usize] {
+        if self
+            .match_cache
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != generation)
+        {
+            self.match_cache = Some((generation, self.find_all(query, options)));
+        }
+        self.match_cache
+            .as_ref()
+            .map_or(&[], |(_, matches)| &matches[..])
+    }
+    /// 一致した文字列を置換した文字列を返す(globalでなければ最初の一致のみ)
+    pub fn replace(
+        &self,
+        query: &str,
+        replacement: &str,
+        global: bool,
+        options: SearchOptions,
+    ) -> (String, usize) {
+        let mut matches = self.find_all(query, options);
+        if !global {
+            matches.truncate(1);
+        }
+        let query_len = query.graphemes(true).count();
+        let mut result = String::new();
+        let mut next_match = matches.iter().peekable();
+        let mut skip_until = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            if next_match.peek().is_some_and(|start| **start == index) {
+                next_match.next();
+                result.push_str(replacement);
+                skip_until = index.saturating_add(query_len);
+            }
+            if index >= skip_until {
+                result.push_str(grapheme);
             }
         }
-        None
+        (result, matches.len())
     }
-    fn highlight_match(&mut self, word: &Option<String>) {
+    // 検索語句に一致した文字をハイライトする(一致位置はドキュメントが管理する世代のキャッシュを使う)
+    // 行末の空白とタブを他のハイライトの上から目立たせる
+    // シンタックスハイライトの無い文字にエスケープシーケンスで指定された色を付ける
+    fn highlight_ansi_colors(&mut self) {
+        for (highlighting, color) in self.highlighting.iter_mut().zip(&self.ansi_colors) {
+            if let (highlighting::Type::None, Some((r, g, b))) = (*highlighting, color) {
+                *highlighting = highlighting::Type::Ansi(*r, *g, *b);
+            }
+        }
+    }
+    fn highlight_trailing_whitespace(&mut self, chars: &[char]) {
+        let trailing = chars
+            .iter()
+            .rev()
+            .take_while(|c| **c == ' ' || **c == '\t')
+            .count();
+        let start = chars.len().saturating_sub(trailing);
+        for highlighting in self.highlighting.iter_mut().skip(start) {
+            *highlighting = highlighting::Type::Whitespace;
+        }
+    }
+    fn highlight_match(&mut self, word: Option<(u64, &String)>, options: SearchOptions) {
         // 検索文字列が指定されていた場合のみハイライト追加
-        if let Some(word) = word {
+        if let Some((generation, word)) = word {
             // 検索文字列が空文字列の場合はハイライトなし
             if word.is_empty() {
                 return;
             }
-            let mut index = 0;
-            // 見つかった検索文字列の位置
-            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
-                // 見つかった検索文字列の末尾の位置
-                if let Some(next_index) = search_match.checked_add(word[..].graphemes(true).count())
-                {
-                    // 見つかった検索文字列をハイライト
-                    #[allow(clippy::indexing_slicing)]
-                    for i in search_match..next_index {
-                        self.highlighting[i] = highlighting::Type::Match;
+            let word_len = word[..].graphemes(true).count();
+            let matches = self.cached_matches(generation, word, options).to_vec();
+            for search_match in matches {
+                // 見つかった検索文字列をハイライト
+                for i in search_match..search_match.saturating_add(word_len) {
+                    if let Some(highlighting) = self.highlighting.get_mut(i) {
+                        *highlighting = highlighting::Type::Match;
                     }
-                    // 次の検索開始地点を更新
-                    index = next_index;
-                } else {
-                    break;
                 }
             }
         }
     }
-    #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+    #[allow(clippy::indexing_slicing, clippy::arithmetic_side_effects)]
     fn highlight_multiline_comment(
         &mut self,
         index: &mut usize,
@@ -194,9 +497,12 @@ impl Row {
                 // /*という文字列を発見
                 if *next_char == '*' {
                     // 閉じ記号の場所か、行末までハイライト(同行に複数のコメントにも対応)
-                    let closing_index = if let Some(closing_index) =
-                        self.find("*/", *index + 2, SearchDirection::Forward)
-                    {
+                    let closing_index = if let Some(closing_index) = self.find(
+                        "*/",
+                        *index + 2,
+                        SearchDirection::Forward,
+                        SearchOptions::default(),
+                    ) {
                         closing_index + 2
                     } else {
                         chars.len()
@@ -208,7 +514,7 @@ impl Row {
                     // コメントハイライト完了
                     return true;
                 }
-            };
+            }
         }
         // ハイライトしなかった
         false
@@ -256,7 +562,7 @@ impl Row {
     ) -> bool {
         // 前の文字を取得
         if *index > 0 {
-            #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+            #[allow(clippy::indexing_slicing, clippy::arithmetic_side_effects)]
             let prev_char = chars[*index - 1];
             // 前の文字がセパレータでなかったら
             if !is_separator(prev_char) {
@@ -267,7 +573,7 @@ impl Row {
         // ハイライトする単語を取得
         for word in keywords {
             if *index < chars.len().saturating_sub(word.len()) {
-                #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+                #[allow(clippy::indexing_slicing, clippy::arithmetic_side_effects)]
                 let next_char = chars[*index + word.len()];
                 // 現在位置にキーワードがあると仮定して、キーワードの後にセパレータが無い場合
                 if !is_separator(next_char) {
@@ -346,6 +652,29 @@ impl Row {
         false
     }
 
+    // 差分の行頭の記号から行全体をハイライトする
+    fn highlight_diff_line(
+        &mut self,
+        index: &mut usize,
+        opts: &HighlightingOptions,
+        c: char,
+        chars: &[char],
+    ) -> bool {
+        if !opts.diff() || *index != 0 {
+            return false;
+        }
+        let hl_type = match c {
+            '+' => highlighting::Type::DiffAdd,
+            '-' => highlighting::Type::DiffDelete,
+            '@' => highlighting::Type::Comment,
+            _ => return false,
+        };
+        for _ in 0..chars.len() {
+            self.highlighting.push(hl_type);
+        }
+        *index = chars.len();
+        true
+    }
     fn highlight_comment(
         &mut self,
         index: &mut usize,
@@ -353,6 +682,28 @@ impl Row {
         c: char,
         chars: &[char],
     ) -> bool {
+        // 行頭の#から行末までをコメントとするファイルタイプの場合
+        if opts.hash_comments() && c == '#' && *index == 0 {
+            for _ in 0..chars.len() {
+                self.highlighting.push(highlighting::Type::Comment);
+            }
+            *index = chars.len();
+            return true;
+        }
+        // ファイルタイプで定義された行コメントの記号から行末まで
+        if let Some(marker) = opts.line_comment() {
+            let at_marker = marker
+                .chars()
+                .enumerate()
+                .all(|(i, m)| chars.get(index.saturating_add(i)) == Some(&m));
+            if at_marker {
+                for _ in *index..chars.len() {
+                    self.highlighting.push(highlighting::Type::Comment);
+                }
+                *index = chars.len();
+                return true;
+            }
+        }
         // スラッシュが見つかった場合
         if opts.comments() && c == '/' && *index < chars.len() {
             if let Some(next_char) = chars.get(index.saturating_add(1)) {
@@ -366,7 +717,7 @@ impl Row {
                     // ハイライトした
                     return true;
                 }
-            };
+            }
         }
         // ハイライトしなかった
         false
@@ -411,7 +762,7 @@ impl Row {
     ) -> bool {
         if opts.numbers() && c.is_ascii_digit() {
             if *index > 0 {
-                #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+                #[allow(clippy::indexing_slicing, clippy::arithmetic_side_effects)]
                 let prev_char = chars[*index - 1];
                 // 一個前の文字がセパレータ
                 if !is_separator(prev_char) {
@@ -438,39 +789,21 @@ impl Row {
         // 数字でなかった
         false
     }
-    // 次の行がコメントで始まる場合はtrueを返す
-    #[allow(clippy::indexing_slicing, clippy::integer_arithmetic)]
+    /// 行をハイライトし、次の行が複数行コメントの中から始まる場合はtrueを返す
+    /// `start_with_comment`には前の行の`highlight`が返した値を渡す(`Document::highlight`が行の順に渡す)
+    #[allow(clippy::indexing_slicing, clippy::arithmetic_side_effects)]
     pub fn highlight(
         &mut self,
         opts: &HighlightingOptions,
-        word: &Option<String>,
+        word: Option<(u64, &String)>,
+        search_options: SearchOptions,
         start_with_comment: bool,
     ) -> bool {
-        let chars: Vec<char> = self.string.chars().collect();
-        // ハイライトが更新済みかつ検索中でない場合
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                // 行末が複数行コメントで終わっている場合
-                if *hl_type == highlighting::Type::MultilineComment {
-                    if let Some(closing_index) =
-                        self.find("*/", self.len(), SearchDirection::Backward)
-                    {
-                        // 行の最後が複数行コメントで、閉じ記号で終わっている場合
-                        if closing_index + 2 == self.len() {
-                            // 次の行はコメントで始まらない
-                            return false;
-                        }
-                    }
-                    // コメント閉じ記号が無い、または行末以外の場所にある場合は次の行はコメントで始まる
-                    return true;
-                }
-            } else if start_with_comment {
-                // 空行で、かつコメントから始まっていた場合は次の行もコメントで始まる
-                return true;
-            }
-            // 次の行はコメントで始まらない
-            return false;
+        // ハイライトが更新済みで、前の行から続くコメントの状態が変わっておらず、検索中でない場合
+        if self.is_highlighted && self.starts_in_comment == start_with_comment && word.is_none() {
+            return self.ends_in_comment;
         }
+        let chars: Vec<char> = self.string.chars().collect();
         // ハイライトが未更新、または検索中の場合はハイライトを更新する
         self.highlighting = Vec::new();
         let mut index = 0;
@@ -478,17 +811,18 @@ impl Row {
         // 現在行がコメントから始まっている場合
         if in_ml_comment {
             // 閉じ記号を探す
-            let closing_index =
-                if let Some(closing_index) = self.find("*/", 0, SearchDirection::Forward) {
-                    // 一番行末に閉じ記号が見つかった場合
-                    if closing_index + 2 == self.len() {
-                        // コメントフラグを下ろす
-                        in_ml_comment = false;
-                    }
-                    closing_index + 2
-                } else {
-                    chars.len()
-                };
+            let closing_index = if let Some(closing_index) =
+                self.find("*/", 0, SearchDirection::Forward, SearchOptions::default())
+            {
+                // 一番行末に閉じ記号が見つかった場合
+                if closing_index + 2 == self.len() {
+                    // コメントフラグを下ろす
+                    in_ml_comment = false;
+                }
+                closing_index + 2
+            } else {
+                chars.len()
+            };
 
             // 閉じ記号または行末までハイライト
             for _ in 0..closing_index {
@@ -507,7 +841,8 @@ impl Row {
             }
             in_ml_comment = false;
             // この記述順次第でハイライトが変わるので注意
-            if self.highlight_char(&mut index, opts, *c, &chars)
+            if self.highlight_diff_line(&mut index, opts, *c, &chars)
+                || self.highlight_char(&mut index, opts, *c, &chars)
                 || self.highlight_comment(&mut index, opts, *c, &chars)
                 || self.highlight_primary_keywords(&mut index, opts, &chars)
                 || self.highlight_secondary_keywords(&mut index, opts, &chars)
@@ -525,43 +860,74 @@ impl Row {
             self.highlighting.push(highlighting::Type::None);
             index = index.saturating_add(1);
         }
+        self.highlight_trailing_whitespace(&chars);
+        self.highlight_ansi_colors();
         // 検索結果のハイライトのみ、他のハイライトを上書きする
-        self.highlight_match(word);
-        // 行末のハイライトが複数行コメント
-        if in_ml_comment {
-            if let Some(closing_index) = self.find("*/", self.len(), SearchDirection::Backward) {
-                // 行末に閉じ記号が無い場合
-                if closing_index + 2 != self.len() {
-                    // 次の行はコメントから始まる
-                    return true;
-                }
-            } else {
-                // コメントが閉じていなければ次の行はコメントから始まる
-                return true;
+        self.highlight_match(word, search_options);
+        // 行末が閉じていない複数行コメントの場合は次の行はコメントから始まる
+        let ends_in_comment = in_ml_comment
+            && self
+                .find(
+                    "*/",
+                    self.len(),
+                    SearchDirection::Backward,
+                    SearchOptions::default(),
+                )
+                .is_none_or(|closing_index| closing_index + 2 != self.len());
+        self.is_highlighted = true;
+        self.starts_in_comment = start_with_comment;
+        self.ends_in_comment = ends_in_comment;
+        ends_in_comment
+    }
+    /// 辞書に無い単語をハイライトする(Noneの場合は消す)
+    /// ハイライトのたびに呼ばれるので、他のハイライトが付いていない単語のみ付け直す
+    pub fn highlight_spelling(&mut self, dictionary: Option<&Dictionary>) {
+        let chars: Vec<char> = self.string.chars().collect();
+        for range in spell::words(&chars) {
+            let Some(types) = self.highlighting.get_mut(range.clone()) else {
+                continue;
+            };
+            if !types
+                .iter()
+                .all(|t| matches!(t, highlighting::Type::None | highlighting::Type::SpellError))
+            {
+                continue;
             }
+            let word: String = chars.get(range).unwrap_or_default().iter().collect();
+            let hl_type = if dictionary.is_some_and(|dictionary| !dictionary.contains(&word)) {
+                highlighting::Type::SpellError
+            } else {
+                highlighting::Type::None
+            };
+            types.fill(hl_type);
         }
-        self.is_highlighted = true;
-        // 次の行はコメントで始まらない
-        false
+    }
+    /// 次の行が複数行コメントから始まるか(ハイライト済みの場合のみ正しい)
+    pub fn ends_in_comment(&self) -> bool {
+        self.ends_in_comment
     }
 
-    // 全角文字にも対応した、画面に収まる文字列を返す
-    pub fn trim_string(&self, full_width_offset: usize, half_width_area: usize) -> String {
+    /// 全角文字にも対応した、画面に収まる文字列をテーマの色でハイライトして返す
+    /// overlayの背景色はシンタックスハイライトの上から重ねる
+    pub fn render(
+        &self,
+        full_width_offset: usize,
+        half_width_area: usize,
+        overlay: &highlighting::Overlay,
+        theme: &Theme,
+    ) -> String {
         let mut current_width = 0;
         let mut end_idx: usize = 0;
         // 画面左側に映らない文字を削除
-        let string = self.string[..]
-            .graphemes(true)
-            .skip(full_width_offset)
-            .collect::<String>();
+        let string = &self.string[self.byte_index(full_width_offset)..];
 
         // 画面左端より左で行の文字列が終わっていた場合
-        if string.is_empty() {
+        if string.is_empty() && overlay.is_empty() {
             return String::new();
         }
-        for c in string.chars() {
+        for grapheme in string.graphemes(true) {
             // 次の一文字の幅を取得
-            let char_width = UnicodeWidthChar::width(c).unwrap_or(1);
+            let char_width = width::str_width(grapheme).max(1);
             // 画面右端に到達したら
             if current_width <= half_width_area
                 && half_width_area <= current_width.saturating_add(char_width)
@@ -573,55 +939,195 @@ impl Row {
         }
         let mut result = String::new();
         let mut current_highlighting = &highlighting::Type::None;
+        let mut current_bg = None;
+        // 背景色が変わった場合のみ色情報を付与する
+        let mut push_bg = |result: &mut String, bg: Option<color::Rgb>| {
+            if bg != current_bg {
+                current_bg = bg;
+                let bg = if let Some(bg_color) = bg {
+                    Terminal::bg(bg_color)
+                } else {
+                    Terminal::bg_reset()
+                };
+                result.push_str(&bg);
+            }
+        };
+        let mut current_underline = false;
+        let mut width: usize = 0;
         for (index, grapheme) in string[..].graphemes(true).enumerate().take(end_idx) {
             if let Some(c) = grapheme.chars().next() {
                 // 1文字の色を取得
                 let highlighting_type = self
                     .highlighting
-                    .get(index)
+                    .get(index.saturating_add(full_width_offset))
                     .unwrap_or(&highlighting::Type::None);
                 // 前の文字と色が違う場合
                 if highlighting_type != current_highlighting {
                     current_highlighting = highlighting_type;
                     // 色情報を付与
-                    let start_highlight = if highlighting_type == &highlighting::Type::None {
-                        // 属性無しの場合はデフォルトの色に戻す
-                        format!("{}", termion::color::Fg(color::Reset))
+                    let start_highlight = if let Some(fg_color) = highlighting_type.to_color(theme)
+                    {
+                        Terminal::fg(fg_color)
                     } else {
-                        format!("{}", termion::color::Fg(highlighting_type.to_color()))
+                        // 属性無しの場合はデフォルトの色に戻す
+                        Terminal::fg_reset()
                     };
                     result.push_str(&start_highlight[..]);
                 }
+                let char_width = width::str_width(grapheme).max(1);
+                // 選択範囲、ハイライトの背景色の順に優先し、無ければ重ねる背景色を使う
+                let bg = overlay
+                    .selection_bg(width, char_width)
+                    .or_else(|| highlighting_type.to_bg_color(theme))
+                    .or_else(|| overlay.bg_at(width, char_width));
+                push_bg(&mut result, bg);
+                let underline = overlay.is_underlined(width, char_width)
+                    || *highlighting_type == highlighting::Type::SpellError;
+                if underline != current_underline {
+                    current_underline = underline;
+                    result.push_str(&Terminal::underline(underline));
+                }
+                width = width.saturating_add(char_width);
                 if c == '\t' {
                     // タブは半角空白に変換
                     result.push(' ');
-                } else {
+                } else if grapheme.chars().any(char::is_control) {
                     result.push(c);
+                } else {
+                    // 結合文字やZWJで繋いだ絵文字も含めて1文字として出力する
+                    result.push_str(grapheme);
                 }
             }
         }
+        // 行末より右も背景色を重ねる範囲は空白で埋める
+        let fill_end = if overlay.line.is_some() {
+            half_width_area.saturating_sub(1)
+        } else {
+            overlay.last_column().map_or(0, |column| {
+                column
+                    .saturating_add(1)
+                    .min(half_width_area.saturating_sub(1))
+            })
+        };
+        for position in width..fill_end {
+            let bg = overlay
+                .selection_bg(position, 1)
+                .or_else(|| overlay.bg_at(position, 1));
+            push_bg(&mut result, bg);
+            result.push(' ');
+        }
+        if current_underline {
+            result.push_str(&Terminal::underline(false));
+        }
         // 最後に色情報をリセット
-        let end_highlight = format!("{}", termion::color::Fg(color::Reset));
+        let end_highlight = format!("{}{}", Terminal::fg_reset(), Terminal::bg_reset());
         result.push_str(&end_highlight[..]);
         result
     }
-    // 指定した範囲[start..end] (全角文字単位)の文字列を半角文字単位で何個分かを返す
+    /// 指定した範囲[start..end] (全角文字単位)の文字列を半角文字単位で何個分かを返す
     pub fn full2half_width(&self, full_width_start: usize, full_width_end: usize) -> usize {
-        let end = cmp::min(full_width_end, self.len());
         let start = cmp::min(full_width_start, full_width_end);
-        #[allow(clippy::integer_arithmetic)]
-        let string = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect::<String>();
-        UnicodeWidthStr::width(&string[..])
-    }
-    // 指定した範囲[..end] (半角文字単位)の文字列を全角文字単位で何個分かを返す
+        self.with_index(|index| {
+            index
+                .width(full_width_end)
+                .saturating_sub(index.width(start))
+        })
+    }
+    /// 指定した範囲[..end] (半角文字単位)の文字列を全角文字単位で何個分かを返す
     pub fn half2full_width(&self, half_width_end: usize) -> usize {
-        let string = self.trim_string(0, half_width_end);
-        UnicodeWidthStr::width(&string[..])
+        self.with_index(|index| {
+            let widths = index.widths.get(..index.len()).unwrap_or_default();
+            widths.partition_point(|width| *width < half_width_end)
+        })
+    }
+    /// 全角文字単位のオフセットから数えて、半角文字単位で`half_width`番目にある文字の全角文字単位の位置を返す
+    pub fn half2full_position(&self, full_width_offset: usize, half_width: usize) -> usize {
+        self.with_index(|index| {
+            if full_width_offset >= index.len() {
+                return index.len();
+            }
+            let end = index.width(full_width_offset).saturating_add(half_width);
+            // 書記素の終わりの表示幅がendを超える最初の書記素
+            let ends = index
+                .widths
+                .get(full_width_offset.saturating_add(1)..)
+                .unwrap_or_default();
+            full_width_offset.saturating_add(ends.partition_point(|width| *width <= end))
+        })
     }
+    /// 指定位置を含む、空白を含まない文字列を返す
+    pub fn word_at(&self, at: usize) -> Option<String> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        if !graphemes
+            .get(at)
+            .is_some_and(|grapheme| is_word_grapheme(grapheme))
+        {
+            return None;
+        }
+        let start = graphemes
+            .iter()
+            .take(at)
+            .rposition(|grapheme| !is_word_grapheme(grapheme))
+            .map_or(0, |index| index.saturating_add(1));
+        Some(
+            graphemes
+                .iter()
+                .skip(start)
+                .take_while(|grapheme| is_word_grapheme(grapheme))
+                .copied()
+                .collect(),
+        )
+    }
+    /// at番目の文字を含むテキストオブジェクトの範囲を書記素の位置で返す
+    pub fn text_object(
+        &self,
+        at: usize,
+        object: TextObject,
+        around: bool,
+    ) -> Option<(usize, usize)> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        object.range(&graphemes, at, around)
+    }
+    /// at番目から文字検索(f、t、F、T)した移動先の位置を書記素の位置で返す
+    pub fn char_search(&self, at: usize, search: CharSearch, repeat: bool) -> Option<usize> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        search.find(&graphemes, at, repeat)
+    }
+    /// start番目からend番目の手前までの文字列
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        let start = self.byte_index(start);
+        let end = self.byte_index(end).max(start);
+        self.string[start..end].to_string()
+    }
+    /// at番目の書記素を含む空白以外の文字の並び(書記素が空白の場合はNone)
+    pub fn big_word_at(&self, at: usize) -> Option<String> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let is_word = |grapheme: &&str| !grapheme.chars().all(char::is_whitespace);
+        if !graphemes.get(at).is_some_and(is_word) {
+            return None;
+        }
+        let start = graphemes
+            .iter()
+            .take(at)
+            .rposition(|grapheme| !is_word(grapheme))
+            .map_or(0, |index| index.saturating_add(1));
+        Some(
+            graphemes
+                .iter()
+                .skip(start)
+                .take_while(|grapheme| is_word(grapheme))
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+// 単語を構成する文字か判定する
+fn is_word_grapheme(grapheme: &str) -> bool {
+    grapheme
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_')
 }
 
 fn is_separator(c: char) -> bool {
@@ -0,0 +1,246 @@
+use crate::Position;
+use std::fmt::Write;
+use unicode_width::UnicodeWidthChar;
+
+// 画面の文字の色
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    // 端末のデフォルトの色
+    #[default]
+    Default,
+    Rgb(u8, u8, u8),
+    // 256色のパレットの番号
+    Ansi(u8),
+}
+
+impl Color {
+    fn name(self) -> String {
+        match self {
+            Self::Default => "default".to_string(),
+            Self::Rgb(r, g, b) => format!("#{r:02x}{g:02x}{b:02x}"),
+            Self::Ansi(index) => format!("ansi{index}"),
+        }
+    }
+}
+
+// 画面の1マス(全角文字の右半分はtextが空)
+#[derive(Clone, PartialEq, Eq)]
+struct Cell {
+    text: String,
+    fg: Color,
+    bg: Color,
+    underline: bool,
+}
+
+impl Cell {
+    fn blank(bg: Color) -> Self {
+        Self {
+            text: " ".to_string(),
+            fg: Color::Default,
+            bg,
+            underline: false,
+        }
+    }
+}
+
+// エディタが端末に出力した制御文字列を解釈して、描画された画面を再現する
+// 画面の右端を超えた文字は折り返さずに捨てる
+pub struct Screen {
+    width: usize,
+    cells: Vec<Vec<Cell>>,
+    cursor: Position,
+    cursor_visible: bool,
+    fg: Color,
+    bg: Color,
+    underline: bool,
+    // 直前に書いたマス(幅0の結合文字と、ZWJに続く文字はこのマスに加える)
+    last_cell: Option<Position>,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            cells: vec![vec![Cell::blank(Color::Default); width]; height],
+            cursor: Position::default(),
+            cursor_visible: true,
+            fg: Color::Default,
+            bg: Color::Default,
+            underline: false,
+            last_cell: None,
+        }
+    }
+    // 出力された文字列を順に解釈する
+    pub fn feed(&mut self, output: &str) {
+        let mut chars = output.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\u{1b}' => {
+                    if chars.next_if_eq(&'[').is_none() {
+                        continue;
+                    }
+                    // 引数とそれに続く終端の文字を読む
+                    let mut params = String::new();
+                    while let Some(c) = chars.next_if(|c| !c.is_ascii_alphabetic()) {
+                        params.push(c);
+                    }
+                    if let Some(command) = chars.next() {
+                        self.control(&params, command);
+                    }
+                }
+                '\r' => {
+                    self.cursor.x = 0;
+                    self.last_cell = None;
+                }
+                '\n' => {
+                    self.cursor.y = self.cursor.y.saturating_add(1);
+                    self.last_cell = None;
+                }
+                c => self.put(c),
+            }
+        }
+    }
+    fn control(&mut self, params: &str, command: char) {
+        let numbers: Vec<usize> = params
+            .trim_start_matches('?')
+            .split(';')
+            .map(|param| param.parse().unwrap_or_default())
+            .collect();
+        let number = |index: usize| numbers.get(index).copied().unwrap_or_default();
+        // 色の指定以外ではカーソルが動くか画面が変わるので、続く結合文字は新しいマスとする
+        if command != 'm' {
+            self.last_cell = None;
+        }
+        match command {
+            'H' => {
+                self.cursor = Position {
+                    x: number(1).saturating_sub(1),
+                    y: number(0).saturating_sub(1),
+                };
+            }
+            'C' => self.cursor.x = self.cursor.x.saturating_add(number(0).max(1)),
+            'J' if number(0) == 2 => {
+                for row in &mut self.cells {
+                    row.fill(Cell::blank(self.bg));
+                }
+            }
+            'K' => {
+                // 2は行全体、それ以外はカーソルから行末まで消す
+                let start = if number(0) == 2 { 0 } else { self.cursor.x };
+                if let Some(row) = self.cells.get_mut(self.cursor.y) {
+                    for cell in row.iter_mut().skip(start) {
+                        *cell = Cell::blank(self.bg);
+                    }
+                }
+            }
+            'h' | 'l' if params == "?25" => self.cursor_visible = command == 'h',
+            'm' => self.select_graphic_rendition(&numbers),
+            _ => (),
+        }
+    }
+    // 文字色と背景色を設定する
+    fn select_graphic_rendition(&mut self, numbers: &[usize]) {
+        let byte = |index: usize| {
+            numbers
+                .get(index)
+                .and_then(|number| u8::try_from(*number).ok())
+                .unwrap_or_default()
+        };
+        let color = match numbers.get(1) {
+            Some(2) => Color::Rgb(byte(2), byte(3), byte(4)),
+            Some(5) => Color::Ansi(byte(2)),
+            _ => Color::Default,
+        };
+        match numbers.first() {
+            Some(38) => self.fg = color,
+            Some(48) => self.bg = color,
+            Some(39) => self.fg = Color::Default,
+            Some(49) => self.bg = Color::Default,
+            Some(4) => self.underline = true,
+            Some(24) => self.underline = false,
+            Some(0) | None => {
+                self.fg = Color::Default;
+                self.bg = Color::Default;
+                self.underline = false;
+            }
+            _ => (),
+        }
+    }
+    fn put(&mut self, c: char) {
+        let width = c.width().unwrap_or_default();
+        // 書記素の続きの文字は前のマスの文字に加える
+        if let Some(Position { x, y }) = self.last_cell {
+            let joined = self
+                .cells
+                .get(y)
+                .and_then(|row| row.get(x))
+                .is_some_and(|cell| cell.text.ends_with('\u{200D}'));
+            if (width == 0 && !c.is_control()) || joined {
+                if let Some(cell) = self.cells.get_mut(y).and_then(|row| row.get_mut(x)) {
+                    cell.text.push(c);
+                }
+                return;
+            }
+        }
+        if width == 0 || self.cursor.x.saturating_add(width) > self.width {
+            return;
+        }
+        self.last_cell = Some(self.cursor.clone());
+        let Some(row) = self.cells.get_mut(self.cursor.y) else {
+            return;
+        };
+        for (index, cell) in row.iter_mut().skip(self.cursor.x).take(width).enumerate() {
+            *cell = Cell {
+                text: if index == 0 {
+                    c.to_string()
+                } else {
+                    String::new()
+                },
+                fg: self.fg,
+                bg: self.bg,
+                underline: self.underline,
+            };
+        }
+        self.cursor.x = self.cursor.x.saturating_add(width);
+    }
+    // 画面の内容をゴールデンファイルの形式にする
+    // 各行の文字、各マスの色を表す記号、記号と色の対応の順に並べる
+    pub fn to_golden(&self) -> String {
+        const SYMBOLS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let mut styles: Vec<(Color, Color, bool)> = Vec::new();
+        let mut text = String::new();
+        let mut colors = String::new();
+        for row in &self.cells {
+            text.push('|');
+            colors.push('|');
+            for cell in row {
+                text.push_str(&cell.text);
+                let style = (cell.fg, cell.bg, cell.underline);
+                let index = styles.iter().position(|s| *s == style).unwrap_or_else(|| {
+                    styles.push(style);
+                    styles.len().saturating_sub(1)
+                });
+                colors.push(SYMBOLS.chars().nth(index).unwrap_or('?'));
+            }
+            text.push_str("|\n");
+            colors.push_str("|\n");
+        }
+        let mut golden = format!(
+            "cursor {},{}{}\n\n{text}\n{colors}\n",
+            self.cursor.y,
+            self.cursor.x,
+            if self.cursor_visible { "" } else { " hidden" }
+        );
+        for (index, (fg, bg, underline)) in styles.iter().enumerate() {
+            let symbol = SYMBOLS.chars().nth(index).unwrap_or('?');
+            let underline = if *underline { " underline" } else { "" };
+            let _ = writeln!(
+                golden,
+                "{symbol} fg={} bg={}{underline}",
+                fg.name(),
+                bg.name()
+            );
+        }
+        golden
+    }
+}
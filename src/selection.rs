@@ -0,0 +1,45 @@
+// ビジュアルモードの選択(vで文字単位、Vで行単位に選択する)
+// 選択はビジュアルモードを始めた位置からカーソル位置までで、両端の文字も含む
+use crate::Position;
+use std::ops::Range;
+
+#[derive(Clone)]
+pub struct Selection {
+    // 選択を始めた位置(カーソルと反対側の端)
+    pub anchor: Position,
+    pub linewise: bool,
+}
+
+impl Selection {
+    // 選択の最初と最後の位置をドキュメントの順に返す
+    pub fn bounds(&self, cursor: &Position) -> (Position, Position) {
+        if (cursor.y, cursor.x) < (self.anchor.y, self.anchor.x) {
+            (cursor.clone(), self.anchor.clone())
+        } else {
+            (self.anchor.clone(), cursor.clone())
+        }
+    }
+    // 選択がかかっている行の範囲
+    pub fn lines(&self, cursor: &Position) -> Range<usize> {
+        let (start, end) = self.bounds(cursor);
+        start.y..end.y.saturating_add(1)
+    }
+    // 長さlenのy行目で選択されている書記素の範囲[開始, 終了)
+    // 行末の改行も選択されている場合は行の長さより1つ後ろまでとする
+    pub fn columns(&self, cursor: &Position, y: usize, len: usize) -> Option<Range<usize>> {
+        let (start, end) = self.bounds(cursor);
+        if y < start.y || end.y < y {
+            return None;
+        }
+        if self.linewise {
+            return Some(0..len.saturating_add(1));
+        }
+        let first = if y == start.y { start.x } else { 0 };
+        let last = if y == end.y {
+            end.x.saturating_add(1).min(len.saturating_add(1))
+        } else {
+            len.saturating_add(1)
+        };
+        Some(first..last)
+    }
+}
@@ -0,0 +1,186 @@
+// 文章を書くファイルタイプのスペルチェックに使う辞書
+use crate::syntax;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write as _;
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// :set dictionary=で指定しなかった場合に探すシステムの単語の一覧(1行に1単語)
+const SYSTEM_DICTIONARIES: [&str; 2] = ["/usr/share/dict/words", "/usr/dict/words"];
+// zgで追加した単語を保存するファイル(設定ディレクトリからの相対パス)
+const USER_DICTIONARY: &str = "spell.txt";
+// 候補とする編集距離の上限
+const MAX_DISTANCE: usize = 2;
+// z=で表示する候補の最大数
+const MAX_SUGGESTIONS: usize = 10;
+
+pub struct Dictionary {
+    // 読み込んだ辞書ファイル(設定が変わったら読み直す)
+    path: PathBuf,
+    user_path: Option<PathBuf>,
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    // 辞書ファイル(Noneの場合はシステムの辞書)とzgで追加した単語を読み込む
+    pub fn load(path: Option<&str>, user_path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => SYSTEM_DICTIONARIES
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.is_file())
+                .ok_or_else(|| "No dictionary found (set dictionary=)".to_string())?,
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|error| format!("Could not read {}: {error}", path.display()))?;
+        let mut words: HashSet<String> = dictionary_words(&text).collect();
+        let user_path = user_path
+            .map(PathBuf::from)
+            .or_else(|| syntax::config_dir().map(|dir| dir.join(USER_DICTIONARY)));
+        // 追加した単語が無ければファイルも無い
+        if let Some(text) = user_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+        {
+            words.extend(dictionary_words(&text));
+        }
+        Ok(Self {
+            path,
+            user_path,
+            words,
+        })
+    }
+    // 指定した設定で読み込んだ辞書か
+    pub fn is_loaded_from(&self, path: Option<&str>, user_path: Option<&str>) -> bool {
+        path.is_none_or(|path| self.path == Path::new(path))
+            && user_path
+                .is_none_or(|user_path| self.user_path.as_deref() == Some(Path::new(user_path)))
+    }
+    // 辞書にある単語か(文頭などで大文字になった単語は小文字でも探す)
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(word) || self.words.contains(&word.to_lowercase())
+    }
+    // 辞書の単語のうち編集距離の近いものを近い順に返す
+    pub fn suggestions(&self, word: &str) -> Vec<String> {
+        let lowercase: Vec<char> = word.to_lowercase().chars().collect();
+        let mut candidates: Vec<(usize, String)> = self
+            .words
+            .iter()
+            .filter_map(|candidate| {
+                let chars: Vec<char> = candidate.to_lowercase().chars().collect();
+                if chars.len().abs_diff(lowercase.len()) > MAX_DISTANCE {
+                    return None;
+                }
+                let distance = distance(&lowercase, &chars);
+                (distance <= MAX_DISTANCE).then(|| (distance, candidate.clone()))
+            })
+            .collect();
+        candidates.sort();
+        // 先頭が大文字の単語は候補も大文字で始める
+        let capitalized = word.chars().next().is_some_and(char::is_uppercase);
+        let mut suggestions: Vec<String> = Vec::new();
+        for (_, candidate) in candidates {
+            let candidate = if capitalized {
+                capitalize(&candidate)
+            } else {
+                candidate
+            };
+            if candidate != word && !suggestions.contains(&candidate) {
+                suggestions.push(candidate);
+            }
+            if suggestions.len() >= MAX_SUGGESTIONS {
+                break;
+            }
+        }
+        suggestions
+    }
+    // 単語を辞書に加え、zgで追加した単語のファイルに保存する
+    pub fn add(&mut self, word: &str) -> Result<(), String> {
+        let path = self
+            .user_path
+            .as_ref()
+            .ok_or_else(|| "No spellfile set".to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|error| error.to_string())?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| error.to_string())?;
+        writeln!(file, "{word}").map_err(|error| error.to_string())?;
+        self.words.insert(word.to_string());
+        Ok(())
+    }
+}
+
+// 辞書ファイルの単語(hunspellの辞書のように/以降に付いている情報は無視する)
+fn dictionary_words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.lines()
+        .filter_map(|line| line.split('/').next())
+        .map(str::trim)
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+}
+
+// 行の中でスペルチェックする単語の範囲(文字単位)
+// 英字の並び(間のアポストロフィを含む)のうち、数字や_の付いた識別子らしいものと1文字のものは除く
+pub fn words(chars: &[char]) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for index in 0..=chars.len() {
+        let c = chars.get(index).copied();
+        let next = chars.get(index.saturating_add(1)).copied();
+        let in_word = match c {
+            Some(c) if c.is_alphanumeric() || c == '_' => true,
+            Some('\'') => start.is_some() && next.is_some_and(char::is_alphabetic),
+            _ => false,
+        };
+        match (in_word, start) {
+            (true, None) => start = Some(index),
+            (false, Some(word_start)) => {
+                start = None;
+                let word = chars.get(word_start..index).unwrap_or_default();
+                if word.len() > 1 && word.iter().all(|c| c.is_alphabetic() || *c == '\'') {
+                    words.push(word_start..index);
+                }
+            }
+            _ => (),
+        }
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
+
+// 隣り合う2文字の入れ替えも1回の編集と数える編集距離
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i.saturating_add(1)];
+        for (j, cb) in b.iter().enumerate() {
+            let at = |row: &[usize], index: usize| row.get(index).copied().unwrap_or(usize::MAX);
+            let deletion = at(&previous, j.saturating_add(1)).saturating_add(1);
+            let insertion = at(&current, j).saturating_add(1);
+            let substitution = at(&previous, j).saturating_add(usize::from(ca != cb));
+            let mut best = deletion.min(insertion).min(substitution);
+            if let (Some(pi), Some(pj)) = (i.checked_sub(1), j.checked_sub(1)) {
+                if a.get(pi) == Some(cb) && b.get(pj) == Some(ca) {
+                    best = best.min(at(&before, pj).saturating_add(1));
+                }
+            }
+            current.push(best);
+        }
+        before = mem::replace(&mut previous, current);
+    }
+    previous.last().copied().unwrap_or_default()
+}
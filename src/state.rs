@@ -0,0 +1,411 @@
+use crate::crypt;
+use crate::recent::RecentFiles;
+use crate::view::View;
+use crate::Position;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use termion::input::TermRead;
+
+// 状態を保存するファイル(ホームディレクトリからの相対パス)
+const STATE_FILE: &str = ".deci_state";
+// 状態のファイルの1行目(形式を変えたら番号を上げる)
+const HEADER: &str = "deci-state 1";
+// 保存する検索とコマンドの履歴の最大数
+const MAX_HISTORY: usize = 100;
+// 状態のファイルを暗号化するパスフレーズを尋ねる端末
+// 環境変数や引数で受け取ると/proc/<pid>/environやシェルの履歴に残るので、端末からしか読まない
+const TTY: &str = "/dev/tty";
+// :で区切ったパターンに一致するファイルは状態に一切残さない(*.env:~/.password-store/*など)
+const EXCLUDE_VAR: &str = "DECI_NO_PERSIST";
+
+// 終了後も引き継ぐ状態(起動時に読み込み、終了時にファイルの内容とまとめて保存する)
+#[derive(Default)]
+pub struct State {
+    pub recent: RecentFiles,
+    // 大文字の印(ファイルをまたいで移動できる)
+    pub file_marks: HashMap<char, (PathBuf, Position)>,
+    // レジスタの内容(キーの並びを<CR>などの表記で表した文字列)
+    pub registers: HashMap<char, String>,
+    // 古い順に並べた検索文字列とコマンドの履歴
+    pub search_history: Vec<String>,
+    pub command_history: Vec<String>,
+    // ファイルごとの表示の状態(ファイルの絶対パスごと)
+    pub views: HashMap<PathBuf, View>,
+    // 除外するファイルを開いた場合はtrue(内容が残っているかもしれないので、このプロセスのレジスタと履歴も保存しない)
+    pub private: bool,
+    // 状態のファイルを暗号化する鍵(パスフレーズが設定されていなければNone)
+    keyring: Option<crypt::Keyring>,
+}
+
+impl State {
+    // 状態のファイルを読み込む(形式が違う場合は空とする)
+    // passphraseはask_passphraseで尋ねたもので、保存するときも同じ鍵を使う
+    // 暗号化されたファイルを復号できない場合などはErrを返す
+    pub fn load(passphrase: Option<String>) -> Result<Self, Error> {
+        let mut keyring = passphrase.map(crypt::Keyring::new);
+        let mut state = match state_path() {
+            Some(path) => Self::read(&path, keyring.as_mut())?,
+            None => Self::default(),
+        };
+        state.keyring = keyring;
+        Ok(state)
+    }
+    // 状態のファイルと同じ鍵で暗号化する(パスフレーズが無ければそのまま返す)
+    pub fn encrypt(&mut self, plain: &[u8]) -> Result<Vec<u8>, Error> {
+        match &mut self.keyring {
+            Some(keyring) => keyring.encrypt(plain),
+            None => Ok(plain.to_vec()),
+        }
+    }
+    // encryptで暗号化したデータを復号する(暗号化されていなければそのまま返す)
+    pub fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if !crypt::is_encrypted(data) {
+            return Ok(data.to_vec());
+        }
+        let keyring = self
+            .keyring
+            .as_mut()
+            .ok_or_else(|| Error::new(ErrorKind::PermissionDenied, "File is encrypted"))?;
+        keyring.decrypt(data)
+    }
+    // ファイルが無ければ空とし、暗号化されていて復号できない場合はErrを返す
+    fn read(path: &Path, keyring: Option<&mut crypt::Keyring>) -> Result<Self, Error> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+        let data = if crypt::is_encrypted(&data) {
+            let keyring = keyring.ok_or_else(|| {
+                Error::new(ErrorKind::PermissionDenied, "State file is encrypted")
+            })?;
+            keyring.decrypt(&data)?
+        } else {
+            data
+        };
+        Ok(parse(&String::from_utf8_lossy(&data)))
+    }
+    // 他のプロセスが保存した内容にこのプロセスの内容を上書きして保存する
+    // 保存されている内容を復号できない場合は、上書きして失わないように保存しない
+    pub fn save(mut self) -> Result<(), Error> {
+        let Some(path) = state_path() else {
+            return Ok(());
+        };
+        let mut merged = Self::read(&path, self.keyring.as_mut())?;
+        merged.recent = self.recent.merge(merged.recent);
+        merged.file_marks.extend(self.file_marks);
+        merged.views.extend(self.views);
+        if !self.private {
+            merged.registers.extend(self.registers);
+            merge_history(&mut merged.search_history, self.search_history);
+            merge_history(&mut merged.command_history, self.command_history);
+        }
+        // 後から除外したファイルも取り除く
+        merged.recent.retain(|file| !is_excluded(file));
+        merged.file_marks.retain(|_, (file, _)| !is_excluded(file));
+        // 最近開いたファイルの履歴から消えたファイルの表示の状態は捨てる
+        let recent = &merged.recent;
+        merged.views.retain(|file, view| {
+            !view.is_empty()
+                && !is_excluded(file)
+                && recent.entries().iter().any(|(path, _)| path == file)
+        });
+        let text = merged.to_text();
+        let data = match &mut self.keyring {
+            Some(keyring) => keyring.encrypt(text.as_bytes())?,
+            None => text.into_bytes(),
+        };
+        fs::write(path, data)
+    }
+    fn to_text(&self) -> String {
+        let mut lines = vec![HEADER.to_string()];
+        for (file, position) in self.recent.entries() {
+            lines.push(format!(
+                "recent\t{}\t{}\t{}",
+                position.y,
+                position.x,
+                file.display()
+            ));
+        }
+        let mut marks: Vec<_> = self.file_marks.iter().collect();
+        marks.sort_by_key(|(mark, _)| **mark);
+        for (mark, (file, position)) in marks {
+            lines.push(format!(
+                "mark\t{mark}\t{}\t{}\t{}",
+                position.y,
+                position.x,
+                file.display()
+            ));
+        }
+        let mut views: Vec<_> = self.views.iter().collect();
+        views.sort_by_key(|(file, _)| *file);
+        for (file, view) in views {
+            lines.push(format!("view\t{}\t{}", view.to_text(), file.display()));
+        }
+        let mut registers: Vec<_> = self.registers.iter().collect();
+        registers.sort_by_key(|(register, _)| **register);
+        for (register, text) in registers {
+            lines.push(format!("register\t{register}\t{text}"));
+        }
+        for query in &self.search_history {
+            lines.push(format!("search\t{query}"));
+        }
+        for command in &self.command_history {
+            lines.push(format!("command\t{command}"));
+        }
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+}
+
+impl State {
+    // ファイルの表示の状態を記録する(戻すものが無ければ消す)
+    pub fn record_view(&mut self, file_name: &str, view: View) {
+        let Ok(path) = fs::canonicalize(file_name) else {
+            return;
+        };
+        if view.is_empty() {
+            self.views.remove(&path);
+        } else {
+            self.views.insert(path, view);
+        }
+    }
+    pub fn view(&self, file_name: &str) -> Option<&View> {
+        self.views.get(&fs::canonicalize(file_name).ok()?)
+    }
+}
+
+// 履歴に追加する(既にあれば最新に移す)
+pub fn add_history(history: &mut Vec<String>, entry: &str) {
+    history.retain(|old| old != entry);
+    history.push(entry.to_string());
+    if history.len() > MAX_HISTORY {
+        history.remove(0);
+    }
+}
+
+// 保存されていた履歴の後ろにこのプロセスの履歴を新しいものとして加える
+fn merge_history(history: &mut Vec<String>, newer: Vec<String>) {
+    for entry in newer {
+        add_history(history, &entry);
+    }
+}
+
+// 状態に残さないファイルか
+pub fn is_excluded(file: &Path) -> bool {
+    let Some(patterns) = env::var_os(EXCLUDE_VAR) else {
+        return false;
+    };
+    let path = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    matches_patterns(&patterns.to_string_lossy(), &path.to_string_lossy())
+}
+
+// :で区切ったパターンのどれかに絶対パスが一致するか
+fn matches_patterns(patterns: &str, path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    patterns
+        .split(':')
+        .filter(|pattern| !pattern.is_empty())
+        .any(|pattern| {
+            // /を含まないパターンはファイル名と比べる
+            if pattern.contains('/') {
+                glob_match(&expand_home(pattern), path)
+            } else {
+                glob_match(pattern, name)
+            }
+        })
+}
+
+fn expand_home(pattern: &str) -> String {
+    match (pattern.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{home}/{rest}"),
+        _ => pattern.to_string(),
+    }
+}
+
+// *は/を含む任意の文字列、?は任意の1文字に一致する
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // 最後に見た*の位置と、そのときの文字列の位置
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p = p.saturating_add(1);
+            }
+            Some(c) if *c == '?' || Some(c) == text.get(t) => {
+                p = p.saturating_add(1);
+                t = t.saturating_add(1);
+            }
+            // 一致しなければ*が吸収する文字を1つ増やしてやり直す
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p.saturating_add(1);
+                    t = star_t.saturating_add(1);
+                    star = Some((star_p, t));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern.iter().skip(p).all(|c| *c == '*')
+}
+
+// 状態のファイルが暗号化されているか
+pub fn is_encrypted() -> bool {
+    state_path().is_some_and(|path| is_encrypted_file(&path))
+}
+
+// ファイルの先頭が暗号化したデータのヘッダか(読めない場合はfalse)
+pub fn is_encrypted_file(path: &Path) -> bool {
+    let mut head = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(64).read_to_end(&mut head))
+        .is_ok_and(|_| crypt::is_encrypted(&head))
+}
+
+// 状態のファイルを暗号化するパスフレーズを、入力を表示せずに端末で尋ねる
+// confirmがtrueの場合(新しく暗号化する場合)は、打ち間違えないようにもう一度入力させる
+// 空のパスフレーズが入力された場合やCtrl-Cなどで中止した場合はNoneを返す
+pub fn ask_passphrase(confirm: bool) -> Result<Option<String>, Error> {
+    let mut tty = OpenOptions::new().read(true).write(true).open(TTY)?;
+    let mut output = tty.try_clone()?;
+    let mut ask = |prompt: &str| -> Result<Option<String>, Error> {
+        write!(output, "{prompt}")?;
+        output.flush()?;
+        let passphrase = tty.read_passwd(&mut output)?;
+        // 入力した改行は表示されないので改行する
+        writeln!(output)?;
+        Ok(passphrase.filter(|passphrase| !passphrase.is_empty()))
+    };
+    let Some(passphrase) = ask(&format!("Passphrase for ~/{STATE_FILE}: "))? else {
+        return Ok(None);
+    };
+    if confirm && ask("Confirm passphrase: ")?.as_ref() != Some(&passphrase) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Passphrases do not match",
+        ));
+    }
+    Ok(Some(passphrase))
+}
+
+fn state_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| Path::new(&home).join(STATE_FILE))
+}
+
+// 種類<TAB>値...の形式の行を読む(知らない種類の行は無視する)
+fn parse(contents: &str) -> State {
+    let mut state = State::default();
+    let mut lines = contents.lines();
+    if lines.next() != Some(HEADER) {
+        return state;
+    }
+    for line in lines {
+        let Some((kind, rest)) = line.split_once('\t') else {
+            continue;
+        };
+        match kind {
+            "recent" => {
+                if let Some((position, file)) = parse_position(rest) {
+                    state.recent.push(file, position);
+                }
+            }
+            "mark" => {
+                let mut chars = rest.chars();
+                if let (Some(mark), Some('\t')) = (chars.next(), chars.next()) {
+                    if let Some((position, file)) = parse_position(chars.as_str()) {
+                        state.file_marks.insert(mark, (file, position));
+                    }
+                }
+            }
+            "view" => {
+                if let [offset_x, folds, options, file] =
+                    rest.splitn(4, '\t').collect::<Vec<_>>()[..]
+                {
+                    if let Some(view) = View::parse(offset_x, folds, options) {
+                        state.views.insert(PathBuf::from(file), view);
+                    }
+                }
+            }
+            "register" => {
+                let mut chars = rest.chars();
+                if let (Some(register), Some('\t')) = (chars.next(), chars.next()) {
+                    state.registers.insert(register, chars.as_str().to_string());
+                }
+            }
+            "search" => state.search_history.push(rest.to_string()),
+            "command" => state.command_history.push(rest.to_string()),
+            _ => (),
+        }
+    }
+    state
+}
+
+// y<TAB>x<TAB>pathの形式の値を読む
+fn parse_position(text: &str) -> Option<(Position, PathBuf)> {
+    let mut fields = text.splitn(3, '\t');
+    let y = fields.next()?.parse().ok()?;
+    let x = fields.next()?.parse().ok()?;
+    let file = PathBuf::from(fields.next()?);
+    Some((Position { x, y }, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_any_characters_with_star_and_one_with_question_mark() {
+        assert!(glob_match("*.env", ".env"));
+        assert!(glob_match("*.env", "prod.env"));
+        assert!(!glob_match("*.env", "prod.env.bak"));
+        assert!(glob_match("secret?.txt", "secret1.txt"));
+        assert!(!glob_match("secret?.txt", "secret.txt"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("", "a"));
+        // *は/も含めて一致する
+        assert!(glob_match("/home/*/notes", "/home/user/private/notes"));
+    }
+
+    #[test]
+    fn patterns_without_slash_match_the_file_name() {
+        let patterns = "*.env::/home/user/.password-store/*";
+        assert!(matches_patterns(patterns, "/work/app/.env"));
+        assert!(!matches_patterns(patterns, "/work/app.env/main.rs"));
+        assert!(matches_patterns(
+            patterns,
+            "/home/user/.password-store/mail/a.gpg"
+        ));
+        assert!(!matches_patterns(patterns, "/home/user/.password-store"));
+        assert!(!matches_patterns("", "/work/main.rs"));
+    }
+
+    #[test]
+    fn encrypts_other_data_with_the_state_file_key() {
+        let mut state = State {
+            keyring: Some(crypt::Keyring::new("secret".to_string())),
+            ..State::default()
+        };
+        let encrypted = state
+            .encrypt(b"unsaved text")
+            .expect("data should be encrypted");
+        assert!(crypt::is_encrypted(&encrypted));
+        let decrypted = state.decrypt(&encrypted).expect("data should be decrypted");
+        assert!(decrypted == b"unsaved text");
+        // パスフレーズが無ければ暗号化せず、暗号化されたデータは復号できない
+        let mut plain = State::default();
+        assert!(plain.encrypt(b"text").is_ok_and(|data| data == b"text"));
+        assert!(plain.decrypt(&encrypted).is_err());
+        assert!(plain.decrypt(b"text").is_ok_and(|data| data == b"text"));
+    }
+}
@@ -0,0 +1,159 @@
+// ステータスバーの書式(:set statusline=)
+// %に続く1文字を項目の内容に置き換え、%=より後ろは右端に寄せる
+//   %f ファイル名      %y ファイルタイプ  %M モード(NORMAL/INSERT)  %e 文字コード
+//   %l カーソル行      %L 総行数          %c カーソル列             %C 行の文字数
+//   %p 行の位置(%)     %u カーソル位置の文字のコードポイント(U+0041、行末ではEOL)
+//   %m 更新されていれば" (modified)"
+//   %r " [pinned]"などの状態              %b gitのブランチ          %% %
+use crate::width;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FORMAT: &str = "%f  %=%y | line: %l/%L %p  col: %c/%C %u%m%r";
+// ブランチ名を読み直す間隔
+const BRANCH_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+// 書式の項目に入れる内容
+pub struct Fields {
+    pub file_name: String,
+    pub file_type: String,
+    pub mode: &'static str,
+    // 行と列は1から始まる
+    pub line: usize,
+    pub lines: usize,
+    pub column: usize,
+    pub columns: usize,
+    // カーソル位置の文字(行末ではNone)
+    pub character: Option<char>,
+    pub modified: bool,
+    // 先頭に空白を付けた状態の表示
+    pub flags: String,
+    pub branch: Option<String>,
+}
+
+// 書式に使えない項目があればErrを返す
+pub fn validate(format: &str) -> Result<(), String> {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        match chars.next() {
+            Some(
+                'f' | 'y' | 'M' | 'e' | 'l' | 'L' | 'c' | 'C' | 'p' | 'u' | 'm' | 'r' | 'b' | '%'
+                | '=',
+            ) => {}
+            Some(item) => return Err(format!("Unknown statusline item: %{item}")),
+            None => return Err("Unknown statusline item: %".to_string()),
+        }
+    }
+    Ok(())
+}
+
+// 書式に従って、指定した表示幅のステータスバーの文字列を作る
+pub fn render(format: &str, fields: &Fields, width: usize) -> String {
+    // %=の前と後ろ
+    let mut parts = vec![String::new()];
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        let text = match c {
+            '%' => match chars.next() {
+                Some('=') if parts.len() == 1 => {
+                    parts.push(String::new());
+                    continue;
+                }
+                Some(item) => expand(item, fields),
+                None => String::new(),
+            },
+            c => c.to_string(),
+        };
+        if let Some(part) = parts.last_mut() {
+            part.push_str(&text);
+        }
+    }
+    let left = parts.first().map_or("", String::as_str);
+    let right = parts.get(1).map_or("", String::as_str);
+    // 左寄せと右寄せの部分の間は半角空白で埋める
+    let padding = width
+        .saturating_sub(width::str_width(left))
+        .saturating_sub(width::str_width(right));
+    let status = format!("{left}{}{right}", " ".repeat(padding));
+    width::truncate(&status, width).to_string()
+}
+
+fn expand(item: char, fields: &Fields) -> String {
+    match item {
+        'f' => fields.file_name.clone(),
+        'y' => fields.file_type.clone(),
+        'M' => fields.mode.to_string(),
+        'e' => "utf-8".to_string(),
+        'l' => fields.line.to_string(),
+        'L' => fields.lines.to_string(),
+        'c' => fields.column.to_string(),
+        'C' => fields.columns.to_string(),
+        'p' => {
+            #[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+            let percent = fields.line.saturating_mul(100) / fields.lines.max(1);
+            format!("{}%", percent.min(100))
+        }
+        'u' => fields
+            .character
+            .map_or_else(|| "EOL".to_string(), |c| format!("U+{:04X}", u32::from(c))),
+        'm' if fields.modified => " (modified)".to_string(),
+        'r' => fields.flags.clone(),
+        'b' => fields.branch.clone().unwrap_or_default(),
+        '%' => "%".to_string(),
+        _ => String::new(),
+    }
+}
+
+// ファイルのあるgitのリポジトリのブランチ名を、一定間隔で読み直して覚えておく
+#[derive(Default)]
+pub struct GitBranch {
+    file_name: Option<String>,
+    checked: Option<Instant>,
+    branch: Option<String>,
+}
+
+impl GitBranch {
+    pub fn get(&mut self, file_name: Option<&str>) -> Option<&str> {
+        let stale = self
+            .checked
+            .is_none_or(|checked| checked.elapsed() >= BRANCH_CHECK_INTERVAL);
+        if stale || self.file_name.as_deref() != file_name {
+            self.file_name = file_name.map(str::to_string);
+            self.checked = Some(Instant::now());
+            self.branch = git_branch(file_name);
+        }
+        self.branch.as_deref()
+    }
+}
+
+// ファイル(ファイル名が無ければカレントディレクトリ)のあるリポジトリでチェックアウトしているブランチ名
+// ブランチでなければコミットハッシュの先頭7文字を返す
+fn git_branch(file_name: Option<&str>) -> Option<String> {
+    let dir = file_name
+        .and_then(|file_name| Path::new(file_name).parent())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map_or_else(env::current_dir, fs::canonicalize)
+        .ok()?;
+    let (dir, git) = dir
+        .ancestors()
+        .map(|dir| (dir, dir.join(".git")))
+        .find(|(_, git)| git.exists())?;
+    // worktreeやサブモジュールでは.gitはgitdir: pathを書いたファイル
+    let git_dir = if git.is_file() {
+        let contents = fs::read_to_string(&git).ok()?;
+        dir.join(contents.trim().strip_prefix("gitdir: ")?)
+    } else {
+        git
+    };
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    Some(
+        head.strip_prefix("ref: refs/heads/")
+            .map_or_else(|| head.chars().take(7).collect(), str::to_string),
+    )
+}
@@ -0,0 +1,225 @@
+// 設定ファイルでユーザが定義するファイルタイプ
+// ~/.config/deci/syntax/*.tomlを読み、エディタが持つ定義の一覧に登録する
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 設定ファイルを置くディレクトリ($XDG_CONFIG_HOMEまたは$HOME/.configからの相対パス)
+const CONFIG_DIR: &str = "deci";
+// 定義ファイルを置くディレクトリ(設定ディレクトリからの相対パス)
+const SYNTAX_DIR: &str = "syntax";
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone)]
+pub struct Definition {
+    pub name: String,
+    // 対象とするファイルの拡張子(.を含まず、大文字と小文字を区別しない)
+    pub extensions: Vec<String>,
+    pub primary_keywords: Vec<String>,
+    pub secondary_keywords: Vec<String>,
+    // 行コメントの記号
+    pub line_comment: Option<String>,
+    // /* */のコメントをハイライトするか
+    pub block_comments: bool,
+    pub numbers: bool,
+    pub strings: bool,
+    pub characters: bool,
+    // 挿入モードで自動的に閉じる括弧や引用符の組
+    pub auto_pairs: Vec<(char, char)>,
+    // 文章を書くファイルタイプか
+    pub prose: bool,
+}
+
+impl Default for Definition {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            extensions: Vec::new(),
+            primary_keywords: Vec::new(),
+            secondary_keywords: Vec::new(),
+            line_comment: None,
+            block_comments: false,
+            numbers: true,
+            strings: true,
+            characters: false,
+            auto_pairs: vec![('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')],
+            prose: false,
+        }
+    }
+}
+
+// 定義ファイルの内容(省略したキーはDefinitionの既定値とする)
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DefinitionFile {
+    name: String,
+    extensions: Vec<String>,
+    #[serde(default)]
+    primary_keywords: Vec<String>,
+    #[serde(default)]
+    secondary_keywords: Vec<String>,
+    #[serde(default)]
+    line_comment: String,
+    #[serde(default)]
+    block_comments: bool,
+    #[serde(default = "enabled")]
+    numbers: bool,
+    #[serde(default = "enabled")]
+    strings: bool,
+    #[serde(default)]
+    characters: bool,
+    // "()"のように開き記号と閉じ記号を並べる
+    auto_pairs: Option<Vec<String>>,
+    #[serde(default)]
+    prose: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+// 定義ファイルの内容を読む(読めない場合は位置を含むメッセージを返す)
+pub fn parse(text: &str) -> Result<Definition, String> {
+    let file: DefinitionFile = toml::from_str(text).map_err(|error| {
+        let line = error.span().map_or(1, |span| {
+            let before = text.get(..span.start).unwrap_or_default();
+            before.matches('\n').count().saturating_add(1)
+        });
+        format!("line {line}: {}", error.message())
+    })?;
+    if file.name.is_empty() {
+        return Err("missing name".to_string());
+    }
+    if file.extensions.is_empty() {
+        return Err("missing extensions".to_string());
+    }
+    let auto_pairs = match file.auto_pairs {
+        Some(pairs) => pairs
+            .iter()
+            .map(|pair| {
+                let mut chars = pair.chars();
+                match (chars.next(), chars.next(), chars.next()) {
+                    (Some(open), Some(close), None) => Ok((open, close)),
+                    _ => Err(format!("invalid pair: {pair}")),
+                }
+            })
+            .collect::<Result<_, _>>()?,
+        None => Definition::default().auto_pairs,
+    };
+    Ok(Definition {
+        name: file.name,
+        extensions: file
+            .extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_lowercase())
+            .collect(),
+        primary_keywords: file.primary_keywords,
+        secondary_keywords: file.secondary_keywords,
+        line_comment: (!file.line_comment.is_empty()).then_some(file.line_comment),
+        block_comments: file.block_comments,
+        numbers: file.numbers,
+        strings: file.strings,
+        characters: file.characters,
+        auto_pairs,
+        prose: file.prose,
+    })
+}
+
+// エディタが読み込んだユーザ定義のファイルタイプ(後から登録したものを優先する)
+#[derive(Default, Clone)]
+pub struct Definitions(Vec<Definition>);
+
+impl Definitions {
+    // 設定ディレクトリの定義ファイルを全て読み込み、読めなかったファイルのメッセージと共に返す
+    pub fn load() -> (Self, Vec<String>) {
+        let mut definitions = Self::default();
+        let Some(dir) = config_dir().map(|dir| dir.join(SYNTAX_DIR)) else {
+            return (definitions, Vec::new());
+        };
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return (definitions, Vec::new());
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .collect();
+        paths.sort();
+        let mut errors = Vec::new();
+        for path in paths {
+            match fs::read_to_string(&path)
+                .map_err(|error| error.to_string())
+                .and_then(|text| parse(&text))
+            {
+                Ok(definition) => definitions.register(definition),
+                Err(message) => errors.push(format!("{}: {message}", path.display())),
+            }
+        }
+        (definitions, errors)
+    }
+    // 定義を登録する(同じ名前の定義は置き換える)
+    pub fn register(&mut self, definition: Definition) {
+        self.0
+            .retain(|registered| registered.name != definition.name);
+        self.0.push(definition);
+    }
+    // ファイル名の拡張子が対象の定義
+    pub fn find_by_file_name(&self, file_name: &str) -> Option<&Definition> {
+        let extension = Path::new(file_name).extension()?.to_str()?.to_lowercase();
+        self.0
+            .iter()
+            .rev()
+            .find(|definition| definition.extensions.contains(&extension))
+    }
+    // :set filetype=で指定する名前(大文字と小文字、空白を区別しない)の定義
+    pub fn find_by_name(&self, name: &str) -> Option<&Definition> {
+        let normalize = |name: &str| name.to_lowercase().replace(' ', "");
+        let name = normalize(name);
+        self.0
+            .iter()
+            .rev()
+            .find(|definition| normalize(&definition.name) == name)
+    }
+}
+
+// 設定ファイルを置くディレクトリ(ユーザの辞書などもここに置く)
+pub fn config_dir() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join(CONFIG_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_line_of_invalid_keys_and_values() {
+        let text = "name = \"INI\"\nextensions = [\"ini\"]\ncolor = true\n";
+        assert!(parse(text).is_err_and(|message| message.starts_with("line 3: ")));
+        let text = "name = \"INI\"\nextensions = [\"ini\"]\nauto_pairs = [\"(\"]\n";
+        assert!(parse(text).is_err_and(|message| message == "invalid pair: ("));
+        assert!(parse("name = \"INI\"").is_err());
+    }
+
+    #[test]
+    fn finds_the_definition_registered_last() {
+        let mut definitions = Definitions::default();
+        let text = "name = 'Conf'\nextensions = ['.CONF']\nnumbers = false # 数値は色を付けない\n";
+        let definition = parse(text).expect("definition should be valid");
+        assert!(definition.extensions == ["conf"] && !definition.numbers && definition.strings);
+        definitions.register(definition);
+        definitions.register(parse("name = 'Conf'\nextensions = ['cfg']").expect("valid"));
+        assert!(definitions.find_by_file_name("app.conf").is_none());
+        assert!(definitions
+            .find_by_name("conf")
+            .is_some_and(|found| found.extensions == ["cfg"]));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::width;
+use unicode_segmentation::UnicodeSegmentation;
+
+// 区切り行のセルに必要な最小の幅 (---)
+const MIN_CELL_WIDTH: usize = 3;
+
+// Markdownの表の行か判定する
+pub fn is_table_row(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+// ヘッダと本文の区切り行のセル(---, :---:など)か判定する
+fn is_separator_cell(cell: &str) -> bool {
+    cell.contains('-') && cell.chars().all(|c| c == '-' || c == ':')
+}
+
+// 全てのセルが区切りセルであれば区切り行とみなす
+fn is_separator_row(cells: &[String]) -> bool {
+    !cells.is_empty() && cells.iter().all(|cell| is_separator_cell(cell))
+}
+
+// 行頭の空白(表のインデント)
+fn indent(line: &str) -> &str {
+    line.strip_suffix(line.trim_start()).unwrap_or_default()
+}
+
+// 行を前後の空白を除いたセルに分割する
+fn cells(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|')
+        .map(|cell| cell.trim().to_string())
+        .collect()
+}
+
+// 行の表示幅を揃えるために、指定幅まで半角空白で埋める
+fn pad(cell: &str, width: usize) -> String {
+    let spaces = width.saturating_sub(width::str_width(cell));
+    format!("{cell}{}", " ".repeat(spaces))
+}
+
+// 区切りセルを指定幅に伸ばす(左右の:は寄せ方の指定として残す)
+fn separator(cell: &str, width: usize) -> String {
+    let left = cell.starts_with(':');
+    let right = cell.len() > 1 && cell.ends_with(':');
+    let colons = usize::from(left).saturating_add(usize::from(right));
+    format!(
+        "{}{}{}",
+        if left { ":" } else { "" },
+        "-".repeat(width.saturating_sub(colons)),
+        if right { ":" } else { "" }
+    )
+}
+
+// 表の各行を、全角文字の表示幅も考慮して縦線の位置を揃える
+pub fn format(lines: &[String]) -> Vec<String> {
+    // 最初の行のインデントを表全体のインデントとする
+    let indent = lines.first().map_or("", |line| indent(line));
+    let mut rows: Vec<Vec<String>> = lines.iter().map(|line| cells(line)).collect();
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    // 列数が足りない行は空のセルで埋める
+    for row in &mut rows {
+        row.resize(columns, String::new());
+    }
+    // 列ごとの最大表示幅を求める(区切り行は除く)
+    let mut widths = vec![MIN_CELL_WIDTH; columns];
+    for row in rows.iter().filter(|row| !is_separator_row(row)) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(width::str_width(cell));
+        }
+    }
+    rows.iter()
+        .map(|row| {
+            let separator_row = is_separator_row(row);
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| {
+                    if separator_row {
+                        separator(cell, *width)
+                    } else {
+                        pad(cell, *width)
+                    }
+                })
+                .collect();
+            format!("{indent}| {} |", cells.join(" | "))
+        })
+        .collect()
+}
+
+// 指定した行と同じ列数の空行を返す
+pub fn empty_row(line: &str) -> String {
+    let columns = cells(line).len();
+    format!("|{}", " |".repeat(columns))
+}
+
+// 指定した列の後ろに空の列を挿入する
+pub fn insert_column(lines: &[String], column: usize) -> Vec<String> {
+    lines
+        .iter()
+        .map(|line| {
+            let mut cells = cells(line);
+            let new_cell = if is_separator_row(&cells) {
+                "-".repeat(MIN_CELL_WIDTH)
+            } else {
+                String::new()
+            };
+            let at = column.saturating_add(1).min(cells.len());
+            cells.insert(at, new_cell);
+            format!("{}| {} |", indent(line), cells.join(" | "))
+        })
+        .collect()
+}
+
+// 全角文字単位の位置xが何番目のセルにあるかを返す
+pub fn cell_index(line: &str, x: usize) -> usize {
+    line.graphemes(true)
+        .take(x)
+        .filter(|grapheme| *grapheme == "|")
+        .count()
+        .saturating_sub(1)
+}
+
+// 行のセル数を返す
+pub fn cell_count(line: &str) -> usize {
+    cells(line).len()
+}
+
+// 指定したセルの内容の開始位置を全角文字単位で返す
+pub fn cell_start(line: &str, column: usize) -> Option<usize> {
+    line.graphemes(true)
+        .enumerate()
+        .filter(|(_, grapheme)| *grapheme == "|")
+        .nth(column)
+        .map(|(index, _)| index.saturating_add(2))
+}
+
+// 区切り行か判定する
+pub fn is_separator_line(line: &str) -> bool {
+    is_separator_row(&cells(line))
+}
@@ -1,45 +1,175 @@
 use crate::Position;
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
 use std::io::{self, stdout, Write};
+use std::panic;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
 use termion::color;
-use termion::event::Key;
-use termion::input::TermRead;
+use termion::event::Event;
+use termion::input::{MouseTerminal, TermRead};
 use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::{AlternateScreen, IntoAlternateScreen};
 
-// 行頭の行番号の最大表示桁数 4桁+半角スペース1桁
-const LINE_NUMBER_SPACES: usize = 5;
+thread_local! {
+    // Noneでなければ端末に出力せずにここに溜める(テストとベンチマークで画面を描画するために使う)
+    static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+    // Noneでなければ出力をここに溜める(画面の1行分の出力を前回と比べるために使う)
+    static RECORDING: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// 端末が表示できる色の種類
+static COLOR_SUPPORT: OnceLock<ColorSupport> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorSupport {
+    // 24ビットのRGB
+    TrueColor,
+    // 256色のパレット
+    Ansi256,
+    // 色を付けない
+    NoColor,
+}
 
-// 端末の縦横の半角文字単位のサイズ
+impl ColorSupport {
+    /// 環境変数から端末が表示できる色の種類を判定する
+    pub fn detect() -> Self {
+        let term = env::var("TERM").unwrap_or_default();
+        if env::var_os("NO_COLOR").is_some() || term == "dumb" {
+            return Self::NoColor;
+        }
+        match &env::var("COLORTERM").unwrap_or_default().to_lowercase()[..] {
+            "truecolor" | "24bit" => Self::TrueColor,
+            _ => Self::Ansi256,
+        }
+    }
+}
+
+/// 端末の縦横の半角文字単位のサイズ
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
+/// 端末への描画とキー入力の読み込み(テストなどでは端末を使わずに出力を溜める)
 pub struct Terminal {
     // 端末の縦横の半角文字単位のサイズ
-    // 幅は端末の画面幅から行番号の表示スペースを除いたサイズ
     size: Size,
-    _stdout: RawTerminal<std::io::Stdout>,
+    // マウス操作を受け付ける
+    // 代替スクリーンに表示して、終了時にシェルの画面を元に戻す
+    // 端末を使わずに出力を溜める場合はNone
+    stdout: Option<MouseTerminal<AlternateScreen<RawTerminal<std::io::Stdout>>>>,
+    // 別スレッドで読み込んだキー入力とマウス操作
+    events: Receiver<Result<Event, io::Error>>,
 }
 
 impl Terminal {
+    /// 端末をrawモードにして代替スクリーンに切り替え、キー入力を別スレッドで読み込み始める
+    ///
+    /// # Errors
+    ///
+    /// 端末のサイズの取得やrawモードへの切り替えに失敗した場合はエラーを返す
     pub fn default() -> Result<Self, std::io::Error> {
         let size = termion::terminal_size()?;
-        #[allow(clippy::cast_possible_truncation)]
+        let stdout = stdout().into_raw_mode()?.into_alternate_screen()?;
+        // パニックした場合はメッセージを表示する前に画面を元に戻す
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            Self::restore_screen();
+            default_hook(info);
+        }));
+        // 入力を待つ間も他の処理ができるように、入力は別スレッドで読み込む
+        let (sender, events) = mpsc::channel();
+        // 標準入力がパイプの場合(ページャとして使う場合や-で標準入力を開いた場合)はキー入力を端末から直接読む
+        let input: Box<dyn io::Read + Send> = if termion::is_tty(&io::stdin()) {
+            Box::new(io::stdin())
+        } else {
+            Box::new(termion::get_tty()?)
+        };
+        thread::spawn(move || {
+            for event in input.events() {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
         Ok(Self {
             size: Size {
-                width: size.0.saturating_sub(LINE_NUMBER_SPACES as u16),
+                width: size.0,
                 // 2行分空ける
                 height: size.1.saturating_sub(2),
             },
-            _stdout: stdout().into_raw_mode()?,
+            stdout: Some(MouseTerminal::from(stdout)),
+            events,
         })
     }
-    // サイズ情報を共有参照で返す
+    /// 端末の代わりに、与えられた入力を順に返して出力をこのスレッドに溜める
+    /// 全ての入力を読み終えたら`read_event`はErrを返す
+    pub fn headless(width: u16, height: u16, events: Vec<Event>) -> Self {
+        let _ = COLOR_SUPPORT.set(ColorSupport::TrueColor);
+        CAPTURED.with_borrow_mut(|captured| *captured = Some(String::new()));
+        let (sender, receiver) = mpsc::channel();
+        for event in events {
+            let _ = sender.send(Ok(event));
+        }
+        Self {
+            size: Size {
+                width,
+                height: height.saturating_sub(2),
+            },
+            stdout: None,
+            events: receiver,
+        }
+    }
+    /// 溜めた出力を取り出す
+    pub fn take_output() -> String {
+        CAPTURED
+            .with_borrow_mut(|captured| captured.as_mut().map(std::mem::take).unwrap_or_default())
+    }
+    /// drawで出力する内容を画面に出力せずに返す
+    pub fn record(draw: impl FnOnce()) -> String {
+        RECORDING.with_borrow_mut(|recording| *recording = Some(String::new()));
+        draw();
+        RECORDING.with_borrow_mut(Option::take).unwrap_or_default()
+    }
+    /// 画面に出力する(出力を溜めている場合は溜める)
+    pub fn print(args: fmt::Arguments) {
+        let recorded = RECORDING.with_borrow_mut(|recording| {
+            recording
+                .as_mut()
+                .map(|output| output.push_str(&args.to_string()))
+                .is_some()
+        });
+        if recorded {
+            return;
+        }
+        CAPTURED.with_borrow_mut(|captured| match captured {
+            Some(output) => output.push_str(&args.to_string()),
+            None => print!("{args}"),
+        });
+    }
+    // 色とカーソルを元に戻して、シェルの画面に切り替える
+    fn restore_screen() {
+        print!(
+            "{}{}{}{}",
+            color::Fg(color::Reset),
+            color::Bg(color::Reset),
+            termion::cursor::Show,
+            termion::screen::ToMainScreen
+        );
+        let _ = io::stdout().flush();
+    }
+    /// サイズ情報を共有参照で返す
     pub fn size(&self) -> &Size {
         &self.size
     }
+    /// 画面全体をクリアする
     pub fn clear_screen() {
-        print!("{}", termion::clear::All);
+        Self::print(format_args!("{}", termion::clear::All));
     }
+    /// カーソルを画面上の位置(原点は(0, 0))に移動する
     // usizeからu16への型変換に対する警告を表示しない
     #[allow(clippy::cast_possible_truncation)]
     pub fn cursor_position(position: &Position) {
@@ -49,40 +179,150 @@ impl Terminal {
         y = y.saturating_add(1);
         let x = x as u16;
         let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
+        Self::print(format_args!("{}", termion::cursor::Goto(x, y)));
     }
+    /// 出力を端末に書き出す
+    ///
+    /// # Errors
+    ///
+    /// 標準出力への書き込みに失敗した場合はエラーを返す
     pub fn flush() -> Result<(), std::io::Error> {
         io::stdout().flush()
     }
-    pub fn read_key() -> Result<Key, std::io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+    /// キー入力またはマウス操作を読み込む
+    /// timeoutの間に入力が無ければNoneを返す
+    ///
+    /// # Errors
+    ///
+    /// 入力の読み込みに失敗した場合や、入力が閉じられた場合はエラーを返す
+    pub fn read_event(&self, timeout: Duration) -> Result<Option<Event>, std::io::Error> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => event.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Input closed"))
             }
         }
     }
+    /// カーソルを隠す
     pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+        Self::print(format_args!("{}", termion::cursor::Hide));
     }
+    /// カーソルを表示する
     pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+        Self::print(format_args!("{}", termion::cursor::Show));
     }
-    // カーソルのある行のみクリアする
+    /// カーソルのある行のみクリアする
     pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+        Self::print(format_args!("{}", termion::clear::CurrentLine));
+    }
+    /// 端末が表示できる色の種類を決める(起動時に一度だけ呼び出す)
+    pub fn init_color_support(no_color: bool) {
+        let support = if no_color {
+            ColorSupport::NoColor
+        } else {
+            ColorSupport::detect()
+        };
+        // 既に決まっている場合はそのまま使う
+        let _ = COLOR_SUPPORT.set(support);
     }
-    // 背景色を指定した色に設定
+    fn color_support() -> ColorSupport {
+        *COLOR_SUPPORT.get_or_init(ColorSupport::detect)
+    }
+    /// 文字色を変える制御文字列を、端末が表示できる色に変換して返す
+    pub fn fg(color: color::Rgb) -> String {
+        match Self::color_support() {
+            ColorSupport::TrueColor => format!("{}", color::Fg(color)),
+            ColorSupport::Ansi256 => format!("{}", color::Fg(to_ansi256(color))),
+            ColorSupport::NoColor => String::new(),
+        }
+    }
+    /// 背景色を変える制御文字列を、端末が表示できる色に変換して返す
+    pub fn bg(color: color::Rgb) -> String {
+        match Self::color_support() {
+            ColorSupport::TrueColor => format!("{}", color::Bg(color)),
+            ColorSupport::Ansi256 => format!("{}", color::Bg(to_ansi256(color))),
+            ColorSupport::NoColor => String::new(),
+        }
+    }
+    /// 文字色をデフォルトの色に戻す制御文字列
+    pub fn fg_reset() -> String {
+        if Self::color_support() == ColorSupport::NoColor {
+            return String::new();
+        }
+        format!("{}", color::Fg(color::Reset))
+    }
+    /// 下線を引く(やめる)制御文字列
+    pub fn underline(on: bool) -> String {
+        if on {
+            format!("{}", termion::style::Underline)
+        } else {
+            format!("{}", termion::style::NoUnderline)
+        }
+    }
+    /// 背景色をデフォルトの色に戻す制御文字列
+    pub fn bg_reset() -> String {
+        if Self::color_support() == ColorSupport::NoColor {
+            return String::new();
+        }
+        format!("{}", color::Bg(color::Reset))
+    }
+    /// 背景色を指定した色に設定
     pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+        Self::print(format_args!("{}", Self::bg(color)));
     }
-    // 背景色をデフォルトの色に設定
+    /// 背景色をデフォルトの色に設定
     pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+        Self::print(format_args!("{}", Self::bg_reset()));
     }
+    /// 現在の行の指定した列(0から始まる)にカーソルを移動する
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn cursor_to_column(x: usize) {
+        Self::print(format_args!("\r"));
+        if x > 0 {
+            Self::print(format_args!("{}", termion::cursor::Right(x as u16)));
+        }
+    }
+    /// 文字色を設定する
     pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
+        Self::print(format_args!("{}", Self::fg(color)));
     }
+    /// 文字色をデフォルトに戻す
     pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
+        Self::print(format_args!("{}", Self::fg_reset()));
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.stdout.is_none() {
+            return;
+        }
+        // 代替スクリーンを抜ける前に色とカーソルを元に戻す
+        print!(
+            "{}{}{}",
+            color::Fg(color::Reset),
+            color::Bg(color::Reset),
+            termion::cursor::Show
+        );
+        let _ = io::stdout().flush();
+    }
+}
+
+// RGBの色を256色のパレットで最も近い色に変換する
+#[allow(clippy::arithmetic_side_effects, clippy::integer_division)]
+fn to_ansi256(color: color::Rgb) -> color::AnsiValue {
+    let color::Rgb(r, g, b) = color;
+    // 無彩色は24段階のグレースケールを使う
+    if r == g && g == b {
+        return match r {
+            0..=7 => color::AnsiValue(16),
+            249..=255 => color::AnsiValue(231),
+            _ => color::AnsiValue::grayscale(((r - 8) / 10).min(23)),
+        };
     }
+    // 0から255を6段階に丸める
+    let level = |c: u8| (u16::from(c) * 5 + 127) / 255;
+    let index = 16 + 36 * level(r) + 6 * level(g) + level(b);
+    color::AnsiValue(u8::try_from(index).unwrap_or(u8::MAX))
 }
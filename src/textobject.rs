@@ -0,0 +1,174 @@
+// テキストオブジェクト(diwやci"などで操作の対象にする範囲)
+#[derive(Clone, Copy)]
+pub enum TextObject {
+    // 単語(記号の並びや空白の並びも1つの単語とみなす)
+    Word,
+    // 空白で区切られた単語
+    BigWord,
+    // 引用符で囲まれた範囲
+    Quote(char),
+    // 括弧で囲まれた範囲
+    Pair(char, char),
+}
+
+impl TextObject {
+    // iwやa(のiやaに続く文字からテキストオブジェクトを返す
+    pub fn from_char(c: char) -> Option<Self> {
+        let object = match c {
+            'w' => Self::Word,
+            'W' => Self::BigWord,
+            '"' | '\'' | '`' => Self::Quote(c),
+            '(' | ')' | 'b' => Self::Pair('(', ')'),
+            '[' | ']' => Self::Pair('[', ']'),
+            '{' | '}' | 'B' => Self::Pair('{', '}'),
+            '<' | '>' => Self::Pair('<', '>'),
+            _ => return None,
+        };
+        Some(object)
+    }
+    // 行の書記素のat番目を含む範囲を書記素の位置で返す(endは範囲の次の位置)
+    // aroundがtrueの場合は囲む記号や後ろの空白も含める
+    pub fn range(self, graphemes: &[&str], at: usize, around: bool) -> Option<(usize, usize)> {
+        match self {
+            Self::Word => word_range(graphemes, at, around, word_class),
+            Self::BigWord => word_range(graphemes, at, around, |grapheme| {
+                u8::from(!is_blank(grapheme))
+            }),
+            Self::Quote(quote) => quote_range(graphemes, at, around, quote),
+            Self::Pair(open, close) => pair_range(graphemes, at, around, open, close),
+        }
+    }
+}
+
+fn is_blank(grapheme: &str) -> bool {
+    grapheme.chars().all(char::is_whitespace)
+}
+
+// 空白は0、単語の文字は1、それ以外の記号は2
+fn word_class(grapheme: &str) -> u8 {
+    if is_blank(grapheme) {
+        0
+    } else if grapheme
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_')
+    {
+        1
+    } else {
+        2
+    }
+}
+
+// 同じ種類の文字が続く範囲(aroundの場合は後ろの空白、無ければ前の空白も含める)
+fn word_range(
+    graphemes: &[&str],
+    at: usize,
+    around: bool,
+    class: impl Fn(&str) -> u8,
+) -> Option<(usize, usize)> {
+    let target = class(graphemes.get(at)?);
+    let run_start = |end: usize, class_of: u8| {
+        graphemes
+            .iter()
+            .take(end)
+            .rposition(|grapheme| class(grapheme) != class_of)
+            .map_or(0, |index| index.saturating_add(1))
+    };
+    let run_end = |start: usize, class_of: u8| {
+        graphemes
+            .iter()
+            .skip(start)
+            .position(|grapheme| class(grapheme) != class_of)
+            .map_or(graphemes.len(), |count| start.saturating_add(count))
+    };
+    let start = run_start(at, target);
+    let end = run_end(at, target);
+    if !around || target == 0 {
+        return Some((start, end));
+    }
+    let trailing_end = run_end(end, 0);
+    if trailing_end > end {
+        return Some((start, trailing_end));
+    }
+    Some((run_start(start, 0), end))
+}
+
+// 引用符で囲まれた範囲(カーソルが引用符の外にある場合は後ろの最初の組)
+fn quote_range(graphemes: &[&str], at: usize, around: bool, quote: char) -> Option<(usize, usize)> {
+    // \でエスケープされていない引用符の位置
+    let quote = quote.to_string();
+    let positions: Vec<usize> = (0..graphemes.len())
+        .filter(|index| {
+            graphemes.get(*index) == Some(&&quote[..])
+                && (*index == 0 || graphemes.get(index.saturating_sub(1)) != Some(&"\\"))
+        })
+        .collect();
+    let (open, close) = positions
+        .chunks_exact(2)
+        .filter_map(|pair| Some((*pair.first()?, *pair.get(1)?)))
+        .find(|(_, close)| at <= *close)?;
+    if !around {
+        return Some((open.saturating_add(1), close));
+    }
+    let end = close.saturating_add(1);
+    let trailing = graphemes
+        .iter()
+        .skip(end)
+        .take_while(|grapheme| is_blank(grapheme))
+        .count();
+    Some((open, end.saturating_add(trailing)))
+}
+
+// 括弧で囲まれた範囲(入れ子になっている場合はカーソルを囲む最も内側の組)
+fn pair_range(
+    graphemes: &[&str],
+    at: usize,
+    around: bool,
+    open: char,
+    close: char,
+) -> Option<(usize, usize)> {
+    let (open, close) = (open.to_string(), close.to_string());
+    // カーソル位置が閉じ括弧の場合はその括弧の組を対象にする
+    let search_from = if graphemes.get(at) == Some(&&close[..]) {
+        at.checked_sub(1)?
+    } else {
+        at
+    };
+    let mut depth = 0_usize;
+    let mut start = None;
+    for index in (0..=search_from.min(graphemes.len().checked_sub(1)?)).rev() {
+        let grapheme = graphemes.get(index)?;
+        if *grapheme == close {
+            depth = depth.saturating_add(1);
+        } else if *grapheme == open {
+            if depth == 0 {
+                start = Some(index);
+                break;
+            }
+            depth = depth.saturating_sub(1);
+        }
+    }
+    let start = start?;
+    let mut depth = 0_usize;
+    let end = graphemes
+        .iter()
+        .enumerate()
+        .skip(start.saturating_add(1))
+        .find(|(_, grapheme)| {
+            if **grapheme == open {
+                depth = depth.saturating_add(1);
+            } else if **grapheme == close {
+                if depth == 0 {
+                    return true;
+                }
+                depth = depth.saturating_sub(1);
+            }
+            false
+        })
+        .map(|(index, _)| index)?;
+    if around {
+        Some((start, end.saturating_add(1)))
+    } else {
+        Some((start.saturating_add(1), end))
+    }
+}
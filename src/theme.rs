@@ -0,0 +1,207 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use termion::color::Rgb;
+
+// 組み込みのテーマ名
+pub const THEME_NAMES: [&str; 4] = ["dark", "light", "solarized", "gruvbox"];
+
+// 画面の各部分とハイライトの色
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    // ステータスバーとウィンドウの区切り行
+    pub status_fg: Rgb,
+    pub status_bg: Rgb,
+    pub line_number_bg: Rgb,
+    // 行の途中で切れていることを示す印など
+    pub nontext_fg: Rgb,
+    pub cursorline_bg: Rgb,
+    // 縦の目安線(colorcolumn)
+    pub colorcolumn_bg: Rgb,
+    pub fold_bg: Rgb,
+    // シンタックスハイライト
+    pub number: Rgb,
+    pub search_match: Rgb,
+    pub string: Rgb,
+    pub character: Rgb,
+    pub comment: Rgb,
+    pub primary_keywords: Rgb,
+    pub secondary_keywords: Rgb,
+    pub matching_bracket: Rgb,
+    pub cursor_word_bg: Rgb,
+    // ビジュアルモードの選択範囲
+    pub visual_bg: Rgb,
+    // 差分
+    pub diff_add: Rgb,
+    pub diff_delete: Rgb,
+    pub diff_text_bg: Rgb,
+    // 行末の空白
+    pub trailing_whitespace_bg: Rgb,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: "dark".to_string(),
+            status_fg: Rgb(13, 13, 13),
+            status_bg: Rgb(239, 239, 239),
+            line_number_bg: Rgb(53, 53, 53),
+            nontext_fg: Rgb(128, 128, 128),
+            cursorline_bg: Rgb(48, 48, 48),
+            colorcolumn_bg: Rgb(58, 40, 40),
+            fold_bg: Rgb(40, 48, 64),
+            number: Rgb(220, 163, 163),
+            search_match: Rgb(38, 139, 210),
+            string: Rgb(211, 54, 130),
+            character: Rgb(108, 113, 196),
+            comment: Rgb(133, 153, 0),
+            primary_keywords: Rgb(181, 137, 0),
+            secondary_keywords: Rgb(42, 161, 152),
+            matching_bracket: Rgb(255, 85, 85),
+            cursor_word_bg: Rgb(68, 68, 68),
+            visual_bg: Rgb(38, 79, 120),
+            diff_add: Rgb(133, 200, 90),
+            diff_delete: Rgb(230, 95, 95),
+            diff_text_bg: Rgb(90, 70, 20),
+            trailing_whitespace_bg: Rgb(150, 40, 40),
+        }
+    }
+}
+
+impl Theme {
+    // 組み込みのテーマを名前から返す
+    pub fn named(name: &str) -> Option<Self> {
+        let theme = match name {
+            "dark" => Self::default(),
+            "light" => Self {
+                name: name.to_string(),
+                status_fg: Rgb(239, 239, 239),
+                status_bg: Rgb(60, 60, 60),
+                line_number_bg: Rgb(220, 220, 220),
+                nontext_fg: Rgb(160, 160, 160),
+                cursorline_bg: Rgb(235, 235, 235),
+                colorcolumn_bg: Rgb(245, 225, 225),
+                fold_bg: Rgb(220, 228, 240),
+                number: Rgb(0, 92, 197),
+                search_match: Rgb(227, 98, 9),
+                string: Rgb(10, 48, 105),
+                character: Rgb(111, 66, 193),
+                comment: Rgb(106, 115, 125),
+                primary_keywords: Rgb(215, 58, 73),
+                secondary_keywords: Rgb(0, 92, 197),
+                matching_bracket: Rgb(207, 34, 46),
+                cursor_word_bg: Rgb(221, 221, 221),
+                visual_bg: Rgb(173, 214, 255),
+                diff_add: Rgb(26, 127, 55),
+                diff_delete: Rgb(207, 34, 46),
+                diff_text_bg: Rgb(255, 223, 150),
+                trailing_whitespace_bg: Rgb(255, 200, 200),
+            },
+            "solarized" => Self {
+                name: name.to_string(),
+                status_fg: Rgb(0, 43, 54),
+                status_bg: Rgb(147, 161, 161),
+                line_number_bg: Rgb(7, 54, 66),
+                nontext_fg: Rgb(88, 110, 117),
+                cursorline_bg: Rgb(7, 54, 66),
+                colorcolumn_bg: Rgb(0, 52, 64),
+                fold_bg: Rgb(7, 54, 66),
+                number: Rgb(203, 75, 22),
+                search_match: Rgb(38, 139, 210),
+                string: Rgb(42, 161, 152),
+                character: Rgb(108, 113, 196),
+                comment: Rgb(88, 110, 117),
+                primary_keywords: Rgb(133, 153, 0),
+                secondary_keywords: Rgb(181, 137, 0),
+                matching_bracket: Rgb(220, 50, 47),
+                cursor_word_bg: Rgb(0, 60, 75),
+                visual_bg: Rgb(0, 80, 100),
+                diff_add: Rgb(133, 153, 0),
+                diff_delete: Rgb(220, 50, 47),
+                diff_text_bg: Rgb(88, 70, 0),
+                trailing_whitespace_bg: Rgb(220, 50, 47),
+            },
+            "gruvbox" => Self {
+                name: name.to_string(),
+                status_fg: Rgb(40, 40, 40),
+                status_bg: Rgb(168, 153, 132),
+                line_number_bg: Rgb(60, 56, 54),
+                nontext_fg: Rgb(146, 131, 116),
+                cursorline_bg: Rgb(60, 56, 54),
+                colorcolumn_bg: Rgb(70, 60, 55),
+                fold_bg: Rgb(80, 73, 69),
+                number: Rgb(211, 134, 155),
+                search_match: Rgb(131, 165, 152),
+                string: Rgb(184, 187, 38),
+                character: Rgb(211, 134, 155),
+                comment: Rgb(146, 131, 116),
+                primary_keywords: Rgb(251, 73, 52),
+                secondary_keywords: Rgb(250, 189, 47),
+                matching_bracket: Rgb(254, 128, 25),
+                cursor_word_bg: Rgb(80, 73, 69),
+                visual_bg: Rgb(69, 133, 136),
+                diff_add: Rgb(184, 187, 38),
+                diff_delete: Rgb(251, 73, 52),
+                diff_text_bg: Rgb(102, 92, 84),
+                trailing_whitespace_bg: Rgb(204, 36, 29),
+            },
+            _ => return None,
+        };
+        Some(theme)
+    }
+    // ファイルからテーマを読み込む
+    // 1行に「名前 = #rrggbb」の形式で色を書き、指定しなかった色はdarkテーマの色とする
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)?;
+        let mut theme = Self {
+            name: path.to_string(),
+            ..Self::default()
+        };
+        for line in contents.lines() {
+            let line = line.trim();
+            // 空行と#で始まるコメント行は無視する
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid line: {line}"));
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            let rgb = parse_rgb(value.trim()).ok_or_else(invalid)?;
+            *theme.color_mut(key.trim()).ok_or_else(invalid)? = rgb;
+        }
+        Ok(theme)
+    }
+    fn color_mut(&mut self, key: &str) -> Option<&mut Rgb> {
+        let color = match key {
+            "status_fg" => &mut self.status_fg,
+            "status_bg" => &mut self.status_bg,
+            "line_number_bg" => &mut self.line_number_bg,
+            "nontext_fg" => &mut self.nontext_fg,
+            "cursorline_bg" => &mut self.cursorline_bg,
+            "colorcolumn_bg" => &mut self.colorcolumn_bg,
+            "fold_bg" => &mut self.fold_bg,
+            "number" => &mut self.number,
+            "search_match" => &mut self.search_match,
+            "string" => &mut self.string,
+            "character" => &mut self.character,
+            "comment" => &mut self.comment,
+            "primary_keywords" => &mut self.primary_keywords,
+            "secondary_keywords" => &mut self.secondary_keywords,
+            "matching_bracket" => &mut self.matching_bracket,
+            "cursor_word_bg" => &mut self.cursor_word_bg,
+            "visual_bg" => &mut self.visual_bg,
+            "diff_add" => &mut self.diff_add,
+            "diff_delete" => &mut self.diff_delete,
+            "diff_text_bg" => &mut self.diff_text_bg,
+            "trailing_whitespace_bg" => &mut self.trailing_whitespace_bg,
+            _ => return None,
+        };
+        Some(color)
+    }
+}
+
+// #rrggbb形式の色を解析する
+fn parse_rgb(text: &str) -> Option<Rgb> {
+    let hex = text.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let channel = |range| u8::from_str_radix(hex.get(range)?, 16).ok();
+    Some(Rgb(channel(0..2)?, channel(2..4)?, channel(4..6)?))
+}
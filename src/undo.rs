@@ -0,0 +1,79 @@
+use crate::Position;
+use std::mem;
+
+// 保持する取り消し単位の最大数
+const MAX_STEPS: usize = 1000;
+
+// 行の範囲を置き換えた1回の変更
+pub struct Change {
+    // 変更した位置(at.y行目からの行を置き換えた)
+    pub at: Position,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+// 取り消しとやり直しの履歴
+// 変更は区切られるまでまとめられ、区切られた変更が1回の取り消しの単位になる
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+    // まだ区切っていない変更
+    current: Vec<Change>,
+    // trueの場合は次に区切る変更を直前の取り消し単位にまとめる
+    join: bool,
+}
+
+impl History {
+    pub fn record(&mut self, change: Change) {
+        self.redo.clear();
+        // 同じ行を続けて変更した場合は1つの変更にまとめる
+        if let Some(last) = self.current.last_mut() {
+            if last.at.y == change.at.y && last.new == change.old {
+                last.new = change.new;
+                return;
+            }
+        }
+        self.current.push(change);
+    }
+    // まだ区切っていない変更があるか
+    pub fn has_uncommitted(&self) -> bool {
+        !self.current.is_empty()
+    }
+    // ここまでの変更を1つの取り消し単位として区切る
+    pub fn commit(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        let changes = mem::take(&mut self.current);
+        if mem::take(&mut self.join) {
+            if let Some(last) = self.undo.last_mut() {
+                last.extend(changes);
+                return;
+            }
+        }
+        self.undo.push(changes);
+        if self.undo.len() > MAX_STEPS {
+            self.undo.remove(0);
+        }
+    }
+    // 次の変更を直前の取り消し単位にまとめる
+    pub fn join(&mut self) {
+        self.join = true;
+    }
+    // 取り消す変更を取り出す(取り消した後にpush_redoで戻す)
+    pub fn take_undo(&mut self) -> Option<Vec<Change>> {
+        self.commit();
+        self.undo.pop()
+    }
+    pub fn push_redo(&mut self, changes: Vec<Change>) {
+        self.redo.push(changes);
+    }
+    // やり直す変更を取り出す(やり直した後にpush_undoで戻す)
+    pub fn take_redo(&mut self) -> Option<Vec<Change>> {
+        self.redo.pop()
+    }
+    pub fn push_undo(&mut self, changes: Vec<Change>) {
+        self.undo.push(changes);
+    }
+}
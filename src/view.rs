@@ -0,0 +1,65 @@
+use std::env;
+
+// 表示の状態を保存するファイルタイプ名を,で区切って指定する(未設定の場合は全て、空の場合は保存しない)
+const FILETYPES_VAR: &str = "DECI_VIEW_FILETYPES";
+
+// ファイルを閉じるときに自動で記録し、次に開いたときに戻す表示の状態
+#[derive(Default, Clone, PartialEq)]
+pub struct View {
+    // 閉じていた折りたたみの(開始行, 終了行の次の行)
+    pub folds: Vec<(usize, usize)>,
+    // 横方向のスクロール位置
+    pub offset_x: usize,
+    // :setで指定したバッファごとの設定(expandtab、shiftwidth=2など)
+    pub options: Vec<String>,
+}
+
+impl View {
+    // 戻すものが何も無いか
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    // 横方向のスクロール位置<TAB>開始行-終了行,...<TAB>設定 設定...の形式にする
+    pub fn to_text(&self) -> String {
+        let folds: Vec<String> = self
+            .folds
+            .iter()
+            .map(|(start, end)| format!("{start}-{end}"))
+            .collect();
+        format!(
+            "{}\t{}\t{}",
+            self.offset_x,
+            folds.join(","),
+            self.options.join(" ")
+        )
+    }
+    // to_textの形式の3つの欄を読む
+    pub fn parse(offset_x: &str, folds: &str, options: &str) -> Option<Self> {
+        Some(Self {
+            folds: folds
+                .split(',')
+                .filter(|fold| !fold.is_empty())
+                .map(|fold| {
+                    let (start, end) = fold.split_once('-')?;
+                    Some((start.parse().ok()?, end.parse().ok()?))
+                })
+                .collect::<Option<_>>()?,
+            offset_x: offset_x.parse().ok()?,
+            options: options.split_whitespace().map(str::to_string).collect(),
+        })
+    }
+}
+
+// このファイルタイプのファイルの表示の状態を保存して戻すか
+// file_typesは:set viewfiletypesで設定した値(設定されていなければ環境変数の値を使う)
+pub fn is_enabled(file_types: Option<&str>, file_type: &str) -> bool {
+    let Some(file_types) = file_types
+        .map(str::to_string)
+        .or_else(|| env::var(FILETYPES_VAR).ok())
+    else {
+        return true;
+    };
+    file_types
+        .split(',')
+        .any(|name| name.trim().eq_ignore_ascii_case(file_type))
+}
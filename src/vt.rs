@@ -0,0 +1,323 @@
+// :terminalで実行したプログラムの出力を解釈して、端末の画面の内容を再現する
+// 文字色とカーソル移動、消去、挿入と削除、スクロールのみ扱い、それ以外の制御は無視する
+// (画面から流れた行は残さない)
+use crate::ansi::{self, Color};
+use crate::width;
+use std::mem;
+use std::str;
+
+// タブの間隔
+const TAB_STOP: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Cell {
+    c: char,
+    color: Color,
+}
+
+impl Cell {
+    const BLANK: Self = Self {
+        c: ' ',
+        color: None,
+    };
+    // 全角文字の右半分
+    const CONTINUATION: Self = Self {
+        c: '\0',
+        color: None,
+    };
+}
+
+// エスケープシーケンスの途中で出力が途切れた場合に続きを待つための状態
+enum State {
+    Ground,
+    Escape,
+    // ESC (などの文字集合の指定(続く1文字を捨てる)
+    Charset,
+    // CSIの引数
+    Csi(String),
+    // OSC(BELまたはESC \で終わる)
+    Osc,
+}
+
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<Cell>>,
+    x: usize,
+    y: usize,
+    color: Color,
+    state: State,
+    // UTF-8の途中で途切れたバイト列
+    pending: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+        Self {
+            width,
+            height,
+            cells: vec![vec![Cell::BLANK; width]; height],
+            x: 0,
+            y: 0,
+            color: None,
+            state: State::Ground,
+            pending: Vec::new(),
+        }
+    }
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+    // 画面の大きさを変える(はみ出した部分は捨て、カーソルが画面外に出る場合は下の行を残す)
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let width = width.max(1);
+        let height = height.max(1);
+        let excess = self.y.saturating_add(1).saturating_sub(height);
+        self.cells.drain(..excess.min(self.cells.len()));
+        self.y = self.y.saturating_sub(excess);
+        self.cells.resize(height, vec![Cell::BLANK; width]);
+        for row in &mut self.cells {
+            row.resize(width, Cell::BLANK);
+        }
+        self.width = width;
+        self.height = height;
+        self.x = self.x.min(width.saturating_sub(1));
+    }
+    // プログラムの出力を画面に反映する
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        // 末尾で途切れた文字は次の出力と合わせて解釈する
+        let valid = match str::from_utf8(&self.pending) {
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            _ => self.pending.len(),
+        };
+        let bytes: Vec<u8> = self.pending.drain(..valid).collect();
+        for c in String::from_utf8_lossy(&bytes).chars() {
+            self.process(c);
+        }
+    }
+    fn process(&mut self, c: char) {
+        match mem::replace(&mut self.state, State::Ground) {
+            State::Ground => match c {
+                '\u{1b}' => self.state = State::Escape,
+                '\r' => self.x = 0,
+                '\n' | '\u{0b}' | '\u{0c}' => self.line_feed(),
+                '\u{08}' => self.x = self.x.min(self.width.saturating_sub(1)).saturating_sub(1),
+                '\t' => {
+                    let next = self
+                        .x
+                        .saturating_add(TAB_STOP)
+                        .saturating_sub(self.x % TAB_STOP);
+                    self.x = next.min(self.width.saturating_sub(1));
+                }
+                c if c.is_control() => (),
+                c => self.put(c),
+            },
+            State::Escape => match c {
+                '[' => self.state = State::Csi(String::new()),
+                ']' => self.state = State::Osc,
+                '(' | ')' | '*' | '+' => self.state = State::Charset,
+                // 逆改行
+                'M' => {
+                    if self.y == 0 {
+                        self.scroll_down(1);
+                    } else {
+                        self.y = self.y.saturating_sub(1);
+                    }
+                }
+                'c' => *self = Self::new(self.width, self.height),
+                _ => (),
+            },
+            State::Charset => (),
+            State::Csi(mut params) => {
+                if ('\u{40}'..='\u{7e}').contains(&c) {
+                    self.control(&params, c);
+                } else {
+                    params.push(c);
+                    self.state = State::Csi(params);
+                }
+            }
+            State::Osc => match c {
+                '\u{07}' => (),
+                '\u{1b}' => self.state = State::Escape,
+                _ => self.state = State::Osc,
+            },
+        }
+    }
+    // CSIで始まる制御シーケンスを実行する
+    fn control(&mut self, params: &str, command: char) {
+        // ?で始まるモードの設定などは、代替画面への切り替えのみ画面の消去として扱う
+        if let Some(mode) = params.strip_prefix('?') {
+            if mode == "1049" && matches!(command, 'h' | 'l') {
+                self.erase(0, 0, self.height, self.width);
+                self.x = 0;
+                self.y = 0;
+            }
+            return;
+        }
+        let numbers: Vec<usize> = params
+            .split(';')
+            .map(|param| param.parse().unwrap_or_default())
+            .collect();
+        let arg = |index: usize| numbers.get(index).copied().unwrap_or_default();
+        // 移動量などは省略や0を1とする
+        let count = arg(0).max(1);
+        let bottom = self.height.saturating_sub(1);
+        let right = self.width.saturating_sub(1);
+        match command {
+            'A' => self.y = self.y.saturating_sub(count),
+            'B' => self.y = self.y.saturating_add(count).min(bottom),
+            'C' => self.x = self.x.saturating_add(count).min(right),
+            'D' => self.x = self.x.min(right).saturating_sub(count),
+            'E' => (self.x, self.y) = (0, self.y.saturating_add(count).min(bottom)),
+            'F' => (self.x, self.y) = (0, self.y.saturating_sub(count)),
+            'G' | '`' => self.x = count.saturating_sub(1).min(right),
+            'd' => self.y = count.saturating_sub(1).min(bottom),
+            'H' | 'f' => {
+                self.y = count.saturating_sub(1).min(bottom);
+                self.x = arg(1).max(1).saturating_sub(1).min(right);
+            }
+            'J' => match arg(0) {
+                0 => {
+                    self.erase(self.y, self.x, self.y.saturating_add(1), self.width);
+                    self.erase(self.y.saturating_add(1), 0, self.height, self.width);
+                }
+                1 => {
+                    self.erase(0, 0, self.y, self.width);
+                    self.erase(
+                        self.y,
+                        0,
+                        self.y.saturating_add(1),
+                        self.x.saturating_add(1),
+                    );
+                }
+                _ => self.erase(0, 0, self.height, self.width),
+            },
+            'K' => {
+                let (start, end) = match arg(0) {
+                    0 => (self.x, self.width),
+                    1 => (0, self.x.saturating_add(1)),
+                    _ => (0, self.width),
+                };
+                self.erase(self.y, start, self.y.saturating_add(1), end);
+            }
+            'X' => {
+                let end = self.x.saturating_add(count);
+                self.erase(self.y, self.x, self.y.saturating_add(1), end);
+            }
+            '@' | 'P' => {
+                let (x, width) = (self.x.min(right), self.width);
+                if let Some(row) = self.cells.get_mut(self.y) {
+                    let count = count.min(width.saturating_sub(x));
+                    if command == '@' {
+                        row.splice(x..x, vec![Cell::BLANK; count]);
+                        row.truncate(width);
+                    } else {
+                        row.drain(x..x.saturating_add(count));
+                        row.resize(width, Cell::BLANK);
+                    }
+                }
+            }
+            'L' | 'M' => {
+                let count = count.min(self.height.saturating_sub(self.y));
+                let blank = vec![Cell::BLANK; self.width];
+                if command == 'L' {
+                    self.cells.truncate(self.height.saturating_sub(count));
+                    self.cells.splice(self.y..self.y, vec![blank; count]);
+                } else {
+                    self.cells.drain(self.y..self.y.saturating_add(count));
+                    self.cells.resize(self.height, blank);
+                }
+            }
+            'S' => self.scroll_up(count),
+            'T' => self.scroll_down(count),
+            'm' => self.color = ansi::select_graphic_rendition(params, self.color),
+            _ => (),
+        }
+    }
+    fn put(&mut self, c: char) {
+        let char_width = width::str_width(c.encode_utf8(&mut [0; 4]));
+        // 結合文字などの幅の無い文字は捨てる
+        if char_width == 0 {
+            return;
+        }
+        // 右端まで書いた後の文字は次の行に書く
+        if self.x.saturating_add(char_width) > self.width {
+            self.x = 0;
+            self.line_feed();
+        }
+        let color = self.color;
+        if let Some(row) = self.cells.get_mut(self.y) {
+            if let Some(cell) = row.get_mut(self.x) {
+                *cell = Cell { c, color };
+            }
+            if char_width > 1 {
+                if let Some(cell) = row.get_mut(self.x.saturating_add(1)) {
+                    *cell = Cell::CONTINUATION;
+                }
+            }
+        }
+        self.x = self.x.saturating_add(char_width);
+    }
+    fn line_feed(&mut self) {
+        if self.y.saturating_add(1) >= self.height {
+            self.scroll_up(1);
+        } else {
+            self.y = self.y.saturating_add(1);
+        }
+    }
+    fn scroll_up(&mut self, count: usize) {
+        let count = count.min(self.height);
+        self.cells.drain(..count);
+        self.cells
+            .resize(self.height, vec![Cell::BLANK; self.width]);
+    }
+    fn scroll_down(&mut self, count: usize) {
+        let count = count.min(self.height);
+        self.cells.truncate(self.height.saturating_sub(count));
+        let blank = vec![Cell::BLANK; self.width];
+        self.cells.splice(0..0, vec![blank; count]);
+    }
+    // start_yからend_yの手前までの行の、start_xからend_xの手前までを消す
+    // (2行以上の場合、1行目以外は行頭から消す)
+    fn erase(&mut self, start_y: usize, start_x: usize, end_y: usize, end_x: usize) {
+        for (index, row) in self.cells.iter_mut().enumerate().take(end_y).skip(start_y) {
+            let start = if index == start_y { start_x } else { 0 };
+            for cell in row.iter_mut().take(end_x).skip(start) {
+                *cell = Cell::BLANK;
+            }
+        }
+    }
+    // 各行の文字列と文字ごとの色(カーソル行以外は末尾の空白を除く)
+    pub fn lines(&self) -> Vec<(String, Vec<Color>)> {
+        self.cells
+            .iter()
+            .enumerate()
+            .map(|(y, row)| {
+                let cells: Vec<&Cell> = row.iter().filter(|cell| cell.c != '\0').collect();
+                let keep = if y == self.y { self.cursor().0 } else { 0 };
+                let len = cells
+                    .iter()
+                    .rposition(|cell| cell.c != ' ' || cell.color.is_some())
+                    .map_or(0, |index| index.saturating_add(1))
+                    .max(keep);
+                let cells = cells.get(..len).unwrap_or_default();
+                (
+                    cells.iter().map(|cell| cell.c).collect(),
+                    cells.iter().map(|cell| cell.color).collect(),
+                )
+            })
+            .collect()
+    }
+    // カーソルの位置(linesの行の何文字目か)
+    pub fn cursor(&self) -> (usize, usize) {
+        let x = self.cells.get(self.y).map_or(0, |row| {
+            row.iter()
+                .take(self.x.min(self.width.saturating_sub(1)))
+                .filter(|cell| cell.c != '\0')
+                .count()
+        });
+        (x, self.y)
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+// 文字の表示幅の求め方(端末によって絵文字や新しい文字の幅の扱いが違うので選べるようにする)
+static SETTINGS: RwLock<Settings> = RwLock::new(Settings {
+    backend: Backend::Unicode,
+    overrides: None,
+});
+
+// 求め方を変えるたびに増やす(行ごとに覚えた表示幅を作り直すために使う)
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// :set widthで選べる求め方の名前
+pub const BACKEND_NAMES: [&str; 3] = ["unicode", "unicode-cjk", "wcwidth"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Backend {
+    // unicode-widthの幅
+    Unicode,
+    // unicode-widthの幅で、幅が曖昧な文字を全角とする
+    UnicodeCjk,
+    // Markus Kuhnのwcwidthと同じ表(古い端末やglibcの多くと同じく、絵文字を半角とする)
+    Wcwidth,
+}
+
+struct Settings {
+    backend: Backend,
+    // 文字ごとに指定した幅(求め方より優先する)
+    overrides: Option<HashMap<char, usize>>,
+}
+
+// 文字の表示幅を求める方法を名前で選ぶ
+pub fn set_backend(name: &str) -> Result<(), String> {
+    let backend = match name {
+        "unicode" => Backend::Unicode,
+        "unicode-cjk" => Backend::UnicodeCjk,
+        "wcwidth" => Backend::Wcwidth,
+        _ => {
+            return Err(format!(
+                "Unknown width: {name} (available: {})",
+                BACKEND_NAMES.join(", ")
+            ))
+        }
+    };
+    if let Ok(mut settings) = SETTINGS.write() {
+        settings.backend = backend;
+    }
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+// 1F600-1F64F:2,2014:1のように、16進数のコードポイント(または範囲)ごとに幅を指定する
+// 空の場合は指定を全て消す
+pub fn set_overrides(spec: &str) -> Result<(), String> {
+    let mut overrides = HashMap::new();
+    for entry in spec.split(',').filter(|entry| !entry.is_empty()) {
+        let invalid = || format!("Invalid width override: {entry}");
+        let (range, width) = entry.split_once(':').ok_or_else(invalid)?;
+        let width: usize = width.parse().map_err(|_| invalid())?;
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let code_point = |hex: &str| u32::from_str_radix(hex, 16).map_err(|_| invalid());
+        for c in (code_point(start)?..=code_point(end)?).filter_map(char::from_u32) {
+            overrides.insert(c, width);
+        }
+    }
+    if let Ok(mut settings) = SETTINGS.write() {
+        settings.overrides = (!overrides.is_empty()).then_some(overrides);
+    }
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+// 表示幅の求め方の世代
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+// 文字列の表示幅(制御文字は幅0とする)
+// 書記素ごとの幅の合計なので、カーソルが書記素の途中に置かれることはない
+pub fn str_width(text: &str) -> usize {
+    // ASCIIのみなら書記素に分けなくても制御文字以外は全て半角
+    if text.is_ascii() {
+        return text.bytes().filter(|b| !b.is_ascii_control()).count();
+    }
+    let Ok(settings) = SETTINGS.read() else {
+        return text.chars().filter_map(UnicodeWidthChar::width).sum();
+    };
+    text.graphemes(true)
+        .map(|grapheme| settings.grapheme_width(grapheme))
+        .fold(0, usize::saturating_add)
+}
+
+// 表示幅がmax_width以下に収まる先頭部分を返す(全角文字を半分で切らない)
+pub fn truncate(text: &str, max_width: usize) -> &str {
+    let mut width: usize = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        width = width.saturating_add(str_width(grapheme));
+        if width > max_width {
+            return text.get(..index).unwrap_or_default();
+        }
+    }
+    text
+}
+
+// 改行と表示幅max_widthで折り返した各行を返す(1文字も収まらない場合も1文字ずつ進める)
+pub fn wrap(text: &str, max_width: usize) -> Vec<&str> {
+    let mut lines = Vec::new();
+    for mut line in text.lines() {
+        loop {
+            let mut head = truncate(line, max_width);
+            if head.is_empty() {
+                let first = line.chars().next().map_or(0, char::len_utf8);
+                head = line.get(..first).unwrap_or_default();
+            }
+            lines.push(head);
+            line = line.get(head.len()..).unwrap_or_default();
+            if line.is_empty() {
+                break;
+            }
+        }
+    }
+    lines
+}
+
+impl Settings {
+    // 書記素(結合文字や、ZWJで繋いだ絵文字を含む1文字)の表示幅
+    // 端末は書記素を先頭の文字の幅で表示するので、続く文字の幅は数えない
+    // ただしVS16(U+FE0F)で絵文字として表示する文字と、2つの地域指示子で表す国旗は全角とする
+    // wcwidthの求め方では、古い端末と同じく各文字の幅の合計とする
+    fn grapheme_width(&self, grapheme: &str) -> usize {
+        let mut chars = grapheme.chars();
+        let Some(first) = chars.next() else {
+            return 0;
+        };
+        let width = self.char_width(first).unwrap_or_default();
+        if chars.as_str().is_empty() {
+            return width;
+        }
+        if self.backend == Backend::Wcwidth {
+            return chars
+                .filter_map(|c| self.char_width(c))
+                .fold(width, usize::saturating_add);
+        }
+        let regional_indicator = |c: char| ('\u{1F1E6}'..='\u{1F1FF}').contains(&c);
+        if chars.clone().any(|c| c == '\u{FE0F}')
+            || (regional_indicator(first) && chars.any(regional_indicator))
+        {
+            return width.max(2);
+        }
+        width
+    }
+    fn char_width(&self, c: char) -> Option<usize> {
+        if let Some(width) = self
+            .overrides
+            .as_ref()
+            .and_then(|overrides| overrides.get(&c))
+        {
+            return Some(*width);
+        }
+        match self.backend {
+            Backend::Unicode => c.width(),
+            Backend::UnicodeCjk => c.width_cjk(),
+            Backend::Wcwidth => c.width().map(|width| {
+                // 幅0の結合文字などはそのまま使い、それ以外は表にある文字のみ全角とする
+                if width == 0 {
+                    0
+                } else if is_wcwidth_wide(c) {
+                    2
+                } else {
+                    1
+                }
+            }),
+        }
+    }
+}
+
+// Markus Kuhnのwcwidth(Unicode 5.0)で全角とする文字
+fn is_wcwidth_wide(c: char) -> bool {
+    matches!(
+        u32::from(c),
+        0x1100..=0x115F
+            | 0x2329..=0x232A
+            | 0x2E80..=0x303E
+            | 0x3040..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE10..=0xFE19
+            | 0xFE30..=0xFE6F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x2_0000..=0x2_FFFD
+            | 0x3_0000..=0x3_FFFD
+    )
+}
@@ -0,0 +1,5 @@
+// 挨拶を表示する
+fn main() {
+    let name = "deci";
+    println!("Hello, {name}!");
+}
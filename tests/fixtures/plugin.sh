@@ -0,0 +1,31 @@
+#!/bin/sh
+# テスト用のプラグイン(:stampまたは<F5>で内容を置き換え、開いたときにメッセージを表示する)
+# :whereは通知されたカーソル位置とモードの変更回数を表示し、:jumpはカーソル、画面、モードを変更する
+# :selectedは渡された選択範囲の最後の位置を表示し、:selectは範囲を選択する
+changes=0
+while IFS= read -r line; do
+    id=$(printf '%s\n' "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+    case "$line" in
+    *'"method":"cursor_moved"'*)
+        cursor=$(printf '%s\n' "$line" | sed 's/.*"cursor":{"column":\([0-9]*\),"line":\([0-9]*\)}.*/\2:\1/')
+        result='null'
+        ;;
+    *'"method":"mode_changed"'*)
+        mode=$(printf '%s\n' "$line" | sed 's/.*"mode":"\([a-z]*\)".*/\1/')
+        changes=$((changes + 1))
+        result='null'
+        ;;
+    *'"method":"initialize"'*) result='{"commands":["stamp","where","jump","selected","select"],"keys":{"<F5>":"stamp"},"hooks":["open"],"events":["cursor_moved","mode_changed"]}' ;;
+    *'"method":"open"'*) result='{"message":"Opened by plugin."}' ;;
+    *'"name":"where"'*) result="{\"message\":\"At $cursor, $mode, $changes mode changes.\"}" ;;
+    *'"name":"selected"'*)
+        selection=$(printf '%s\n' "$line" | sed -n 's/.*"selection":{"end":{"column":\([0-9]*\),"line":\([0-9]*\)},"linewise":\([a-z]*\).*/\2:\1 linewise=\3/p')
+        result="{\"message\":\"Selected to $selection.\"}"
+        ;;
+    *'"name":"select"'*) result='{"selection":{"start":{"line":1,"column":4},"end":{"line":2,"column":7}},"message":"Selected."}' ;;
+    *'"name":"jump"'*) result='{"cursor":{"line":3,"column":4},"viewport":{"top":1},"mode":"insert","message":"Jumped."}' ;;
+    *'"method":"command"'*) result='{"lines":["// stamped","fn main() {}"],"message":"Stamped."}' ;;
+    *) result='null' ;;
+    esac
+    printf '{"jsonrpc":"2.0","id":%s,"result":%s}\n' "$id" "$result"
+done
@@ -0,0 +1,254 @@
+// 端末を使わずにライブラリとしてドキュメントの編集とハイライトを使う
+use deci::{
+    BufferKind, Cli, Document, Editor, FileType, HighlightType, Position, Row, SearchOptions,
+    Terminal,
+};
+use std::os::unix::fs::PermissionsExt;
+use std::{env, fs, process};
+
+#[test]
+fn highlights_row_with_file_type() {
+    let file_type = FileType::from("main.rs");
+    let mut row = Row::from("let x = 42; // answer");
+    row.highlight(
+        file_type.highlighting_options(),
+        None,
+        SearchOptions::default(),
+        false,
+    );
+    let highlighting = row.highlighting();
+    assert!(highlighting.first() == Some(&HighlightType::PrimaryKeywords));
+    assert!(highlighting.get(8) == Some(&HighlightType::Number));
+    assert!(highlighting.last() == Some(&HighlightType::Comment));
+}
+
+#[test]
+fn highlights_rows_and_document_alike_across_comments() {
+    let lines = ["let a = 1; /* start", "still comment", "end */ let b = 2;"];
+    let path = env::temp_dir().join(format!("deci-comment-{}.rs", process::id()));
+    let file_name = path.to_str().expect("temporary path should be UTF-8");
+    fs::write(&path, lines.join("\n")).expect("file should be writable");
+    let document = Document::open(file_name);
+    let _ = fs::remove_file(&path);
+    let mut document = document.expect("file should be readable");
+    while document.loading_progress().is_some() {
+        document.receive_rows().expect("file should be loaded");
+    }
+    document.highlight(None, SearchOptions::default(), 0, document.len());
+    // 前の行が返した複数行コメントの状態を次の行に渡すと、ドキュメント全体のハイライトと一致する
+    let file_type = FileType::from("main.rs");
+    let mut in_comment = false;
+    for (y, line) in lines.iter().enumerate() {
+        let mut row = Row::from(*line);
+        in_comment = row.highlight(
+            file_type.highlighting_options(),
+            None,
+            SearchOptions::default(),
+            in_comment,
+        );
+        let highlighted = document.row(y).expect("line should exist");
+        assert!(row.highlighting() == highlighted.highlighting());
+    }
+    assert!(!in_comment);
+    let last = document.row(2).expect("line should exist");
+    assert!(last.highlighting().first() == Some(&HighlightType::MultilineComment));
+    assert!(last.highlighting().get(8) == Some(&HighlightType::PrimaryKeywords));
+}
+
+#[test]
+fn edits_and_highlights_document() {
+    let mut document =
+        Document::open("tests/fixtures/hello.rs").expect("fixture should be readable");
+    assert!(document.file_type() == "Rust");
+    document.insert(&Position { x: 0, y: 1 }, 'p');
+    document.insert(&Position { x: 1, y: 1 }, 'u');
+    document.insert(&Position { x: 2, y: 1 }, 'b');
+    document.insert(&Position { x: 3, y: 1 }, ' ');
+    document.highlight(None, SearchOptions::default(), 0, document.len());
+    let row = document.row(1).expect("second line should exist");
+    assert!(row.as_str() == "pub fn main() {");
+    assert!(row.highlighting().first() == Some(&HighlightType::PrimaryKeywords));
+    assert!(document.is_dirty());
+
+    let lines = vec!["one".to_string(), "two".to_string()];
+    let output = Document::from_lines(&lines, BufferKind::Output("lines".to_string()));
+    assert!(output.len() == 2);
+    assert!(!output.is_editable());
+}
+
+#[test]
+fn converts_widths_after_edits() {
+    let mut row = Row::from("aあb");
+    assert!(row.full2half_width(0, 2) == 3);
+    assert!(row.half2full_width(3) == 2);
+    assert!(row.half2full_position(0, 2) == 1);
+    row.insert(1, 'い');
+    assert!(row.as_str() == "aいあb");
+    assert!(row.full2half_width(1, 3) == 4);
+    assert!(row.grapheme(2) == Some("あ"));
+    row.delete(0);
+    let rest = row.split(1);
+    assert!(row.as_str() == "い" && rest.as_str() == "あb");
+    assert!(rest.full2half_width(0, rest.len()) == 3);
+    assert!(rest.substring(1, 5) == "b");
+}
+
+#[test]
+fn measures_grapheme_clusters_in_display_cells() {
+    let row = Row::from("e\u{301}\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{1F1EF}\u{1F1F5}x");
+    assert!(row.len() == 4);
+    assert!(row.full2half_width(0, 1) == 1);
+    assert!(row.full2half_width(1, 2) == 2);
+    assert!(row.full2half_width(2, 3) == 2);
+    assert!(row.half2full_position(0, 2) == 1);
+    assert!(row.half2full_position(0, 4) == 2);
+}
+
+#[test]
+fn counts_grapheme_clusters_merged_across_joins_and_deletions() {
+    // 結合文字やZWJで始まる行を結合すると、前の行の最後の文字とまとまる
+    let mut row = Row::from("ae");
+    row.append(&Row::from("\u{301}b"));
+    assert!(row.len() == 3);
+    let mut row = Row::from("\u{1F468}\u{200D}");
+    row.append(&Row::from("\u{1F469}"));
+    assert!(row.len() == 1);
+    // 間の文字を削除すると、前後のハングルの字母が1文字にまとまる
+    let mut row = Row::from("\u{1100}x\u{1161}");
+    row.delete(1);
+    assert!(row.len() == 1);
+    assert!(row.as_str() == "\u{1100}\u{1161}");
+}
+
+#[test]
+fn keeps_grapheme_positions_and_widths_after_edits_in_the_middle() {
+    let mut row = Row::from("aあe\u{1F468}b");
+    // 位置を求めて索引を作ってから、途中を変更していく
+    assert!(row.full2half_width(0, row.len()) == 7);
+    row.insert(3, '\u{301}');
+    row.insert_str(1, "い\u{200D}");
+    row.delete(0);
+    row.append(&Row::from("\u{301}う"));
+    let fresh = Row::from(row.as_str());
+    assert!(row.len() == fresh.len());
+    for at in 0..=row.len() {
+        assert!(row.grapheme(at) == fresh.grapheme(at));
+        assert!(row.full2half_width(0, at) == fresh.full2half_width(0, at));
+    }
+}
+
+#[test]
+fn inserts_text_across_lines_at_once() {
+    let mut document = Document::default();
+    let end = document.insert_str(&Position { x: 0, y: 0 }, "fn main() {}");
+    assert!((end.x, end.y) == (12, 0));
+    document.commit_undo();
+    let end = document.insert_str(&Position { x: 11, y: 0 }, "\n    println!();\n");
+    assert!((end.x, end.y) == (0, 2));
+    assert!(document.all_lines() == ["fn main() {", "    println!();", "}"]);
+    document.commit_undo();
+    document.undo();
+    assert!(document.all_lines() == ["fn main() {}"]);
+}
+
+#[test]
+fn deletes_ranges_across_lines_at_once() {
+    let mut document = Document::default();
+    document.insert_str(&Position { x: 0, y: 0 }, "one\ntwo\nthree\nfour");
+    document.commit_undo();
+    let text = document.text_range(&Position { x: 4, y: 2 }, &Position { x: 1, y: 0 });
+    assert!(text == ["ne", "two", "thre"]);
+    let deleted = document.delete_range(&Position { x: 4, y: 2 }, &Position { x: 1, y: 0 });
+    assert!(deleted == "ne\ntwo\nthre");
+    assert!(document.all_lines() == ["oe", "four"]);
+    document.commit_undo();
+    assert!(document.delete_range(&Position { x: 1, y: 1 }, &Position { x: 0, y: 9 }) == "our");
+    assert!(document.all_lines() == ["oe", "f"]);
+    document.commit_undo();
+    document.undo();
+    document.undo();
+    assert!(document.all_lines() == ["one", "two", "three", "four"]);
+}
+
+#[test]
+fn runs_batch_commands() {
+    let path = env::temp_dir().join(format!("deci-batch-{}.txt", process::id()));
+    let file_name = path.to_str().expect("temporary path should be UTF-8");
+    fs::write(&path, "one foo\ntwo\nthree foo\nfour\n").expect("file should be writable");
+    assert!(Editor::batch(file_name, ":%s/foo/bar/\n2,3d\nw") == 0);
+    let contents = fs::read_to_string(&path).expect("file should be readable");
+    assert!(Editor::batch(file_name, "bogus") == 1);
+    let _ = fs::remove_file(&path);
+    assert!(contents == "one bar\nfour\n");
+}
+
+#[test]
+fn parses_command_line() {
+    let args = ["-R", "--config=init.vim", "+12", "--", "-p"].map(str::to_string);
+    let cli = Cli::parse(args).expect("arguments should be valid");
+    assert!(cli.readonly && !cli.pager);
+    assert!(cli.config.as_deref() == Some("init.vim"));
+    assert!(cli.line == Some(12));
+    assert!(cli.file.as_deref() == Some("-p"));
+    assert!(Cli::parse(["--bogus".to_string()]).is_err());
+    assert!(Cli::parse(["--batch".to_string()]).is_err());
+    let args = ["-x", "--recover", "deci-1-0.deci-recover"].map(str::to_string);
+    let cli = Cli::parse(args).expect("arguments should be valid");
+    assert!(cli.encrypt && cli.file.is_none());
+    assert!(cli.recover.as_deref() == Some("deci-1-0.deci-recover"));
+}
+
+#[test]
+fn writes_recovery_files_for_unsaved_changes() {
+    let path = env::temp_dir().join(format!("deci-recovery-{}.txt", process::id()));
+    let file_name = path.to_str().expect("temporary path should be UTF-8");
+    fs::write(&path, "saved\n").expect("file should be writable");
+    let mut document = Document::open(file_name).expect("file should be readable");
+    document.insert(&Position { x: 0, y: 0 }, '!');
+    let mut editor = Editor::headless(Terminal::headless(80, 24, Vec::new()), document);
+    let recovered = editor.write_recovery_files();
+    let contents = recovered.first().map(fs::read_to_string);
+    // 他のユーザには読めないようにする
+    let mode = recovered
+        .first()
+        .and_then(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.permissions().mode() & 0o777);
+    for file in recovered.iter().map(String::as_str).chain([file_name]) {
+        let _ = fs::remove_file(file);
+    }
+    assert!(recovered == [format!("{file_name}.deci-recover")]);
+    assert!(matches!(contents, Some(Ok(text)) if text == "!saved"));
+    assert!(mode == Some(0o600));
+}
+
+#[test]
+fn finishes_large_files_loaded_in_background_like_small_ones() {
+    // 別スレッドで読み込む大きさにして、拡張子の無いファイルをシバンで判定させる
+    let mut text = String::from("#!/usr/bin/env python3\n");
+    while text.len() <= 2 * 1024 * 1024 {
+        text.push_str("def answer():\n    return 42\n");
+    }
+    let path = env::temp_dir().join(format!("deci-large-{}", process::id()));
+    let file_name = path.to_str().expect("temporary path should be UTF-8");
+    fs::write(&path, &text).expect("file should be writable");
+    let mut document = Document::open(file_name).expect("file should be readable");
+    assert!(document.loading_progress().is_some());
+    let loaded = document.wait_rows();
+    // 途中にUTF-8として読めないバイトがあるファイル
+    let mut bytes = text.into_bytes();
+    bytes.extend_from_slice(b"\xff\xfe\n    pass\n");
+    fs::write(&path, bytes).expect("file should be writable");
+    let invalid = Document::open(file_name).map(|mut invalid| (invalid.wait_rows(), invalid));
+    let _ = fs::remove_file(&path);
+    loaded.expect("file should be loaded");
+    assert!(document.file_type() == "Python");
+    assert!(document.indent().describe() == "indent: spaces, width 4 (detected)");
+    // 途中までの内容を編集して元のファイルに保存できないように、読み取り専用の16進数の表示に置き換わる
+    let (loaded, invalid) = invalid.expect("file should be readable");
+    assert!(loaded.is_err());
+    assert!(invalid.file_name.is_none());
+    assert!(!invalid.is_editable());
+    assert!(invalid
+        .row(0)
+        .is_some_and(|row| row.as_str().starts_with("00000000")));
+}